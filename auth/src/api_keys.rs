@@ -0,0 +1,87 @@
+use crate::JWTError;
+use crate::Permission;
+use crate::UserRolesToken;
+use crate::Wrapper;
+
+use jsonwebtoken::Algorithm;
+use jsonwebtoken::Header;
+use jsonwebtoken::TokenData;
+
+use once_cell::sync::OnceCell;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use time::get_time;
+
+use std::future::Future;
+use std::pin::Pin;
+
+/// Name of the header API keys are passed in. Keys can also be passed in the `api_key` query
+/// parameter for clients which cannot set headers.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// What a valid API key resolves to.
+#[derive(Clone, Debug)]
+pub struct ApiKeyClaims {
+    /// Id of the key.
+    pub id: i64,
+    /// Username of the owner of the key.
+    pub user: String,
+    /// Roles currently held by the owner.
+    pub roles: Vec<String>,
+    /// Permissions the key is limited to. A key never grants permissions which the roles of its
+    /// owner dont grant. Scopes dont restrict reading, every key can read what its owner can.
+    pub scopes: Vec<Permission>,
+}
+
+type ValidatorFuture = Pin<Box<dyn Future<Output = Option<ApiKeyClaims>> + Send>>;
+type Validator = Box<dyn Fn(String) -> ValidatorFuture + Send + Sync>;
+
+static VALIDATOR: OnceCell<Validator> = OnceCell::new();
+
+/// Sets the function used to resolve API keys. Keys are stored by the server, so it has to tell us
+/// who a key belongs to. Until a validator is set every API key is rejected.
+pub fn set_api_key_validator<F, Fut>(validator: F)
+where
+    F: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Option<ApiKeyClaims>> + Send + 'static,
+{
+    let validator: Validator = Box::new(move |key| Box::pin(validator(key)));
+
+    if VALIDATOR.set(validator).is_err() {
+        panic!("API key validator has already been set");
+    }
+}
+
+/// Generates a new API key. Like refresh tokens, API keys are only shown once and the server
+/// should only store their hash.
+pub fn generate_api_key() -> String {
+    let key: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+
+    format!("dim_{}", key)
+}
+
+/// Function resolves `key` with the validator set through [`set_api_key_validator`] and returns a
+/// token scoped to the permissions of the key.
+pub async fn authenticate_api_key(key: &str) -> Result<Wrapper, JWTError> {
+    let validator = VALIDATOR.get().ok_or(JWTError::InvalidKey)?;
+    let claims = validator(key.to_string())
+        .await
+        .ok_or(JWTError::InvalidKey)?;
+
+    Ok(Wrapper(TokenData {
+        header: Header::new(Algorithm::HS512),
+        claims: UserRolesToken {
+            id: uuid::Uuid::new_v4().to_u128_le(),
+            iat: get_time().sec,
+            exp: i64::MAX,
+            user: claims.user,
+            roles: claims.roles,
+            session: String::new(),
+            scopes: Some(claims.scopes),
+        },
+    }))
+}
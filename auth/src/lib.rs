@@ -13,6 +13,8 @@ use serde::Deserialize;
 use serde::Serialize;
 use time::get_time;
 
+use std::collections::HashMap;
+
 use warp::filters::header::headers_cloned;
 use warp::http::header::HeaderMap;
 use warp::http::header::AUTHORIZATION;
@@ -20,13 +22,36 @@ use warp::reject;
 use warp::Filter;
 use warp::Rejection;
 
+mod api_keys;
+mod permissions;
+mod sessions;
+
+pub use api_keys::authenticate_api_key;
+pub use api_keys::generate_api_key;
+pub use api_keys::set_api_key_validator;
+pub use api_keys::ApiKeyClaims;
+pub use api_keys::API_KEY_HEADER;
+pub use permissions::Permission;
+pub use permissions::Role;
+pub use sessions::generate_refresh_token;
+pub use sessions::is_session_active;
+pub use sessions::is_session_valid;
+pub use sessions::revoke_session;
+pub use sessions::set_session_validator;
+pub use sessions::track_session;
+
 #[cfg(all(not(debug_assertions), feature = "null_auth"))]
 std::compile_error!("Cannot disable authentication for non-devel environments.");
 
 /// This is the secret key with which we sign the JWT tokens.
 // TODO: Generate this at first run to ensure security
 static KEY: OnceCell<[u8; 16]> = OnceCell::new();
-static ONE_WEEK: i64 = 60 * 60 * 24 * 7;
+
+/// Number of seconds a access token is valid for. Clients are expected to use their refresh token
+/// to get a new access token once it expires.
+pub const ACCESS_TOKEN_TTL: i64 = 60 * 15;
+/// Number of seconds a session can go unused before its refresh token expires.
+pub const REFRESH_TOKEN_TTL: i64 = 60 * 60 * 24 * 30;
 
 pub fn generate_key() -> [u8; 16] {
     rand::thread_rng().gen()
@@ -51,9 +76,15 @@ pub struct UserRolesToken {
     exp: i64,
     /// Username of the user to whom this token belongs to
     user: String,
-    /// The roles of the user, usually owner or user
-    // TODO: Use a enum here maybe considering theres like two possibilities lol?
+    /// The roles of the user, these are the names of [`Role`]s. Names which dont map to a role are
+    /// ignored when checking permissions.
     roles: Vec<String>,
+    /// Id of the session this token was issued for.
+    #[serde(default)]
+    session: String,
+    /// Permissions this token is limited to, only set for requests authenticated with a API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scopes: Option<Vec<Permission>>,
 }
 
 #[derive(Debug)]
@@ -75,6 +106,12 @@ pub enum JWTError {
     Invalid,
     InvalidKey,
     BadCount,
+    /// The token is valid but the session it was issued for has been revoked.
+    Revoked,
+    /// The token is valid but its roles dont grant the permission required.
+    MissingPermission(Permission),
+    /// The request was made with a API key but the route only accepts access tokens.
+    ApiKeyNotAllowed,
 }
 
 impl warp::reject::Reject for JWTError {}
@@ -96,6 +133,40 @@ impl UserRolesToken {
         self.roles.contains(&role.to_string())
     }
 
+    /// Method returns the roles held by the user holding this token.
+    pub fn get_roles(&self) -> Vec<Role> {
+        self.roles.iter().filter_map(|x| x.parse().ok()).collect()
+    }
+
+    /// Method checks if any of the roles held by the user holding this token grant `permission`,
+    /// and if the token is limited to some scopes, whether `permission` is one of them.
+    ///
+    /// # Example
+    /// ```
+    /// use auth::{jwt_generate, jwt_check, Permission};
+    ///
+    /// auth::set_jwt_key(auth::generate_key());
+    ///
+    /// let token = jwt_generate("test".into(), vec!["user".into()], "session".into());
+    /// let token = jwt_check(token).unwrap();
+    /// assert!(token.claims.has_permission(Permission::Stream));
+    /// assert!(!token.claims.has_permission(Permission::ManageLibraries));
+    /// ```
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        if matches!(&self.scopes, Some(x) if !x.contains(&permission)) {
+            return false;
+        }
+
+        self.get_roles()
+            .iter()
+            .any(|x| x.permissions().contains(&permission))
+    }
+
+    /// Method returns whether the request was authenticated with a API key rather than a login.
+    pub fn is_api_key(&self) -> bool {
+        self.scopes.is_some()
+    }
+
     /// Method returns the username from the token
     pub fn get_user(&self) -> String {
         self.user.clone()
@@ -110,6 +181,11 @@ impl UserRolesToken {
         self.id
     }
 
+    /// Method returns the id of the session this token was issued for.
+    pub fn get_session(&self) -> &str {
+        &self.session
+    }
+
     /// Method returns a clone of all roles.
     pub fn clone_roles(&self) -> Vec<String> {
         self.roles
@@ -123,10 +199,11 @@ impl UserRolesToken {
     }
 }
 
-/// Function generates a new JWT token and signs it with our KEY
+/// Function generates a new short lived access token and signs it with our KEY
 /// # Arguments
 /// * `user` - Username for whom we want to generate a token
 /// * `roles` - vector of roles we want to give to this user.
+/// * `session` - id of the session the token is issued for.
 ///
 /// # Example
 /// ```
@@ -134,17 +211,20 @@ impl UserRolesToken {
 ///
 /// auth::set_jwt_key(auth::generate_key());
 ///
-/// let token_1 = jwt_generate("test".into(), vec!["owner".into()]);
+/// let token_1 = jwt_generate("test".into(), vec!["owner".into()], "session".into());
 /// let check_token = jwt_check(token_1).unwrap();
+/// assert_eq!(check_token.claims.get_session(), "session");
 /// ```
-pub fn jwt_generate(user: String, roles: Vec<String>) -> String {
+pub fn jwt_generate(user: String, roles: Vec<String>, session: String) -> String {
     let now = get_time().sec;
     let payload = UserRolesToken {
         id: uuid::Uuid::new_v4().to_u128_le(),
         iat: now,
-        exp: now + ONE_WEEK,
+        exp: now + ACCESS_TOKEN_TTL,
         user,
         roles,
+        session,
+        scopes: None,
     };
 
     encode(
@@ -165,7 +245,7 @@ pub fn jwt_generate(user: String, roles: Vec<String>) -> String {
 ///
 /// auth::set_jwt_key(auth::generate_key());
 ///
-/// let token_1 = jwt_generate("test".into(), vec!["owner".into()]);
+/// let token_1 = jwt_generate("test".into(), vec!["owner".into()], "session".into());
 /// let check_token = jwt_check(token_1).unwrap();
 ///
 /// let check_token_2 = jwt_check("testtesttest".into());
@@ -193,24 +273,130 @@ pub fn jwt_check(_: String) -> Result<TokenData<UserRolesToken>, jsonwebtoken::e
             exp: i64::MAX,
             user: "Admin".into(),
             roles: vec!["owner".into()],
+            session: String::new(),
+            scopes: None,
         },
     })
 }
 
+/// Function validates the token supplied and checks that the session it was issued for hasnt been
+/// revoked.
+/// # Arguments
+/// * `token` - JWT token we want to validate
+///
+/// # Example
+/// ```
+/// use auth::{authenticate, jwt_generate, JWTError};
+///
+/// auth::set_jwt_key(auth::generate_key());
+/// auth::track_session("session");
+///
+/// let token = jwt_generate("test".into(), vec!["user".into()], "session".into());
+/// assert!(authenticate(&token).is_ok());
+///
+/// auth::revoke_session("session");
+/// assert!(matches!(authenticate(&token), Err(JWTError::Revoked)));
+/// ```
+pub fn authenticate(token: &str) -> Result<Wrapper, JWTError> {
+    let token = jwt_check(token.into()).map_err(|_| JWTError::InvalidKey)?;
+
+    if cfg!(not(feature = "null_auth")) && !is_session_active(token.claims.get_session()) {
+        return Err(JWTError::Revoked);
+    }
+
+    Ok(Wrapper(token))
+}
+
+/// Same as [`authenticate`] except the session the token was issued for is also looked up with
+/// the validator set through [`set_session_validator`], so that sessions deleted from the
+/// database are rejected too.
+pub async fn authenticate_session(token: &str) -> Result<Wrapper, JWTError> {
+    let token = authenticate(token)?;
+
+    if cfg!(not(feature = "null_auth")) && !is_session_valid(token.0.claims.get_session()).await {
+        return Err(JWTError::Revoked);
+    }
+
+    Ok(token)
+}
+
+/// Filter which authenticates requests carrying a access token in the `Authorization` header.
+/// Requests made with a API key are rejected with [`JWTError::ApiKeyNotAllowed`], routes which
+/// headless clients should be able to use opt into keys with [`with_api_key`].
 pub fn with_auth() -> impl Filter<Extract = (Wrapper,), Error = Rejection> + Clone {
-    headers_cloned().and_then(|x: HeaderMap| async move {
-        match x.get(AUTHORIZATION) {
-            Some(k) => match k.to_str().ok().and_then(|x| jwt_check(x.into()).ok()) {
-                Some(k) => Ok(Wrapper(k)),
-                None => Err(reject::custom(JWTError::InvalidKey)),
-            },
-            None => {
-                if cfg!(not(feature = "null_auth")) {
-                    Err(reject::custom(JWTError::Missing))
-                } else {
-                    Ok(Wrapper(jwt_check(String::new()).unwrap()))
+    authenticate_request(false)
+}
+
+/// Same as [`with_auth`] except requests can also carry a API key in the [`API_KEY_HEADER`]
+/// header or the `api_key` query parameter.
+///
+/// Scopes only restrict what a key can do on routes gated by a permission, see
+/// [`with_api_key_permission`]. Every key, whatever its scopes, can use the routes behind this
+/// filter with the library access of its owner, so these routes must not change anything.
+pub fn with_api_key() -> impl Filter<Extract = (Wrapper,), Error = Rejection> + Clone {
+    authenticate_request(true)
+}
+
+/// Same as [`with_auth`] except requests whose token doesnt grant `permission` are rejected with
+/// [`JWTError::MissingPermission`].
+pub fn with_permission(
+    permission: Permission,
+) -> impl Filter<Extract = (Wrapper,), Error = Rejection> + Clone {
+    with_auth().and_then(move |auth: Wrapper| require_permission(auth, permission))
+}
+
+/// Same as [`with_api_key`] except requests whose token or key doesnt grant `permission` are
+/// rejected with [`JWTError::MissingPermission`].
+pub fn with_api_key_permission(
+    permission: Permission,
+) -> impl Filter<Extract = (Wrapper,), Error = Rejection> + Clone {
+    with_api_key().and_then(move |auth: Wrapper| require_permission(auth, permission))
+}
+
+fn authenticate_request(
+    allow_api_key: bool,
+) -> impl Filter<Extract = (Wrapper,), Error = Rejection> + Clone {
+    headers_cloned()
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(
+            move |x: HeaderMap, query: HashMap<String, String>| async move {
+                let api_key = x
+                    .get(API_KEY_HEADER)
+                    .and_then(|k| k.to_str().ok())
+                    .map(ToString::to_string)
+                    .or_else(|| query.get("api_key").cloned());
+
+                if let Some(key) = api_key {
+                    if !allow_api_key {
+                        return Err(reject::custom(JWTError::ApiKeyNotAllowed));
+                    }
+
+                    return authenticate_api_key(&key).await.map_err(reject::custom);
                 }
-            }
-        }
-    })
+
+                match x.get(AUTHORIZATION) {
+                    Some(k) => match k.to_str() {
+                        Ok(k) => authenticate_session(k.strip_prefix("Bearer ").unwrap_or(k))
+                            .await
+                            .map_err(reject::custom),
+                        Err(_) => Err(reject::custom(JWTError::InvalidKey)),
+                    },
+                    None => {
+                        if cfg!(not(feature = "null_auth")) {
+                            Err(reject::custom(JWTError::Missing))
+                        } else {
+                            Ok(Wrapper(jwt_check(String::new()).unwrap()))
+                        }
+                    }
+                }
+            },
+        )
+}
+
+async fn require_permission(auth: Wrapper, permission: Permission) -> Result<Wrapper, Rejection> {
+    if auth.0.claims.has_permission(permission) {
+        Ok(auth)
+    } else {
+        Err(reject::custom(JWTError::MissingPermission(permission)))
+    }
 }
@@ -0,0 +1,144 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Actions a user can be allowed to take. Permissions are never granted to users directly,
+/// instead they come from the roles a user holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Create, delete, scan and edit libraries and the media in them.
+    ManageLibraries,
+    /// Invite users, delete users and assign roles.
+    ManageUsers,
+    /// Read and change the server settings.
+    ManageSettings,
+    /// Rematch media and mediafiles against a metadata provider.
+    RematchMetadata,
+    /// Stream media.
+    Stream,
+    /// Request transcoded streams, without this only direct play streams are offered.
+    Transcode,
+}
+
+impl Permission {
+    /// All the permissions which exist.
+    pub const ALL: &'static [Permission] = &[
+        Permission::ManageLibraries,
+        Permission::ManageUsers,
+        Permission::ManageSettings,
+        Permission::RematchMetadata,
+        Permission::Stream,
+        Permission::Transcode,
+    ];
+
+    /// Returns the name a permission is stored as, ie `manage_users`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ManageLibraries => "manage_libraries",
+            Self::ManageUsers => "manage_users",
+            Self::ManageSettings => "manage_settings",
+            Self::RematchMetadata => "rematch_metadata",
+            Self::Stream => "stream",
+            Self::Transcode => "transcode",
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Permission {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Self::ALL
+            .iter()
+            .find(|x| x.as_str().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or(())
+    }
+}
+
+/// Roles which can be assigned to users. In the database and in tokens roles are stored as their
+/// lowercase names, ie `owner`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// The user who set up the server, can do everything.
+    Owner,
+    /// Can do everything except change the server settings.
+    Admin,
+    /// Regular user who can only watch media.
+    User,
+}
+
+impl Role {
+    /// All the roles which exist.
+    pub const ALL: &'static [Role] = &[Role::Owner, Role::Admin, Role::User];
+
+    /// Returns the permissions granted by this role.
+    ///
+    /// # Example
+    /// ```
+    /// use auth::{Permission, Role};
+    ///
+    /// assert!(Role::Owner.permissions().contains(&Permission::ManageSettings));
+    /// assert!(!Role::User.permissions().contains(&Permission::ManageUsers));
+    /// ```
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+
+        match self {
+            Self::Owner => &[
+                ManageLibraries,
+                ManageUsers,
+                ManageSettings,
+                RematchMetadata,
+                Stream,
+                Transcode,
+            ],
+            Self::Admin => &[
+                ManageLibraries,
+                ManageUsers,
+                RematchMetadata,
+                Stream,
+                Transcode,
+            ],
+            Self::User => &[Stream, Transcode],
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Admin => "admin",
+            Self::User => "user",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "owner" => Ok(Self::Owner),
+            "admin" => Ok(Self::Admin),
+            "user" => Ok(Self::User),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+
+/// Ids of the sessions which havent been revoked. Access tokens are only accepted if they were
+/// issued for one of these sessions.
+static ACTIVE_SESSIONS: Lazy<RwLock<HashSet<String>>> = Lazy::new(Default::default);
+
+/// Marks the session `id` as active. The server must call this for every session stored in the
+/// database on boot and for every new session created afterwards.
+pub fn track_session(id: impl Into<String>) {
+    ACTIVE_SESSIONS.write().unwrap().insert(id.into());
+}
+
+/// Revokes the session `id`, access tokens issued for it will be rejected from now on even if they
+/// havent expired yet.
+pub fn revoke_session(id: &str) {
+    ACTIVE_SESSIONS.write().unwrap().remove(id);
+}
+
+type ValidatorFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+type Validator = Box<dyn Fn(String) -> ValidatorFuture + Send + Sync>;
+
+static VALIDATOR: OnceCell<Validator> = OnceCell::new();
+
+/// Sets the function used to check that a session is still stored by the server. Sessions which
+/// were deleted without going through [`revoke_session`], ie by another instance or because their
+/// owner was deleted, are rejected once the validator says so.
+pub fn set_session_validator<F, Fut>(validator: F)
+where
+    F: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    let validator: Validator = Box::new(move |id| Box::pin(validator(id)));
+
+    if VALIDATOR.set(validator).is_err() {
+        panic!("Session validator has already been set");
+    }
+}
+
+/// Returns whether the session `id` is active.
+pub fn is_session_active(id: &str) -> bool {
+    ACTIVE_SESSIONS.read().unwrap().contains(id)
+}
+
+/// Returns whether the session `id` is active and, if a validator was set through
+/// [`set_session_validator`], still stored by the server. Sessions the server no longer knows
+/// about are revoked.
+pub async fn is_session_valid(id: &str) -> bool {
+    if !is_session_active(id) {
+        return false;
+    }
+
+    match VALIDATOR.get() {
+        Some(validator) if !validator(id.to_string()).await => {
+            revoke_session(id);
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Generates a new opaque refresh token. Refresh tokens are only ever handed out once, the server
+/// should store a hash of them rather than the token itself.
+pub fn generate_refresh_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
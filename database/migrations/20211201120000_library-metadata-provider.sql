@@ -0,0 +1 @@
+ALTER TABLE library ADD COLUMN metadata_provider TEXT NOT NULL DEFAULT 'tmdb';
@@ -0,0 +1,42 @@
+-- Albums are grouped by artist rather than by name alone, thus two albums in the same library can
+-- share a name.
+DROP INDEX media_idx;
+CREATE UNIQUE INDEX media_idx ON _tblmedia(library_id, name, media_type) WHERE _tblmedia.media_type NOT IN ('episode', 'music');
+
+CREATE TABLE artist (
+    id INTEGER PRIMARY KEY NOT NULL,
+    library_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+
+    FOREIGN KEY (library_id) REFERENCES library(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX artist_idx ON artist(library_id, name);
+
+-- Albums inherit from _tblmedia the same way tv shows do, which lets them show up in the library
+-- views next to movies and shows.
+CREATE TABLE album (
+    id INTEGER,
+    artist_id INTEGER,
+    PRIMARY KEY (id),
+
+    FOREIGN KEY(id) REFERENCES _tblmedia (id) ON DELETE CASCADE,
+    FOREIGN KEY(artist_id) REFERENCES artist (id) ON DELETE SET NULL
+);
+
+-- Tracks are not media objects, the mediafile of a track is linked to its album.
+CREATE TABLE track (
+    id INTEGER PRIMARY KEY NOT NULL,
+    album_id INTEGER NOT NULL,
+    mediafile_id INTEGER NOT NULL UNIQUE,
+    artist_id INTEGER,
+    name TEXT NOT NULL,
+    track_number INTEGER,
+    disc_number INTEGER,
+
+    FOREIGN KEY(album_id) REFERENCES album (id) ON DELETE CASCADE,
+    FOREIGN KEY(mediafile_id) REFERENCES mediafile (id) ON DELETE CASCADE,
+    FOREIGN KEY(artist_id) REFERENCES artist (id) ON DELETE SET NULL
+);
+
+CREATE INDEX track_album_idx ON track(album_id);
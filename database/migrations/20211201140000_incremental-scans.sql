@@ -0,0 +1,8 @@
+-- Size and modification time of a file when it was last probed, used to detect changed files.
+ALTER TABLE mediafile ADD COLUMN file_size INTEGER;
+ALTER TABLE mediafile ADD COLUMN file_mtime INTEGER;
+
+-- Seconds between scheduled rescans of a library, NULL disables scheduled rescans.
+ALTER TABLE library ADD COLUMN scan_interval INTEGER;
+-- Unix timestamp of when the last full scan of a library finished.
+ALTER TABLE library ADD COLUMN last_scanned INTEGER;
@@ -0,0 +1,22 @@
+-- Users with `restrict_libraries` set can only see the libraries listed for them in
+-- `library_access`, everyone else can see every library.
+ALTER TABLE users ADD COLUMN restrict_libraries BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE library_access (
+    username TEXT NOT NULL,
+    library_id INTEGER NOT NULL,
+
+    PRIMARY KEY (username, library_id),
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (library_id) REFERENCES library(id) ON DELETE CASCADE
+);
+
+-- Every library each user is allowed to see. Libraries scheduled for deletion are never visible.
+CREATE VIEW visible_library AS
+    SELECT users.username, library.id AS library_id FROM users, library
+        WHERE NOT users.restrict_libraries AND NOT library.hidden
+    UNION
+    SELECT users.username, library.id AS library_id FROM users
+        INNER JOIN library_access ON library_access.username = users.username
+        INNER JOIN library ON library.id = library_access.library_id
+        WHERE users.restrict_libraries AND NOT library.hidden;
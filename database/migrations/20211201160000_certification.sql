@@ -0,0 +1,5 @@
+-- Certification (age rating) of media as reported by the metadata provider, ie `PG-13` or `TV-MA`.
+-- `certification_level` is the minimum age the certification is meant for and is what we filter on,
+-- media without a known level is visible to everyone. Episodes carry the certification of their show.
+ALTER TABLE _tblmedia ADD COLUMN certification TEXT;
+ALTER TABLE _tblmedia ADD COLUMN certification_level INTEGER;
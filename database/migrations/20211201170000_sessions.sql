@@ -0,0 +1,16 @@
+-- Every device a user is logged in on. Access tokens are short lived and carry the id of their
+-- session, revoking a session deletes its row which invalidates the refresh token and all the
+-- access tokens issued for it.
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL,
+    -- sha256 of the refresh token, the token itself is never stored.
+    refresh_token TEXT NOT NULL UNIQUE,
+    -- user agent of the device which logged in.
+    device TEXT,
+    created INTEGER NOT NULL,
+    last_used INTEGER NOT NULL,
+    expires INTEGER NOT NULL,
+
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,16 @@
+-- Long lived keys used by headless clients and scripts in place of a login. A key acts as its
+-- owner but is limited to the permissions listed in `scopes`.
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    -- name given to the key by its owner, ie "backup script".
+    name TEXT NOT NULL,
+    -- sha256 of the key, the key itself is only shown once on creation.
+    key_hash TEXT NOT NULL UNIQUE,
+    -- comma separated list of permissions.
+    scopes TEXT NOT NULL,
+    created INTEGER NOT NULL,
+    last_used INTEGER,
+
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE
+);
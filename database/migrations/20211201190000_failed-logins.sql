@@ -0,0 +1,13 @@
+-- Audit log of failed login attempts. Usernames are not foreign keys as attempts against users
+-- which dont exist are logged too.
+CREATE TABLE failed_logins (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    -- address the attempt was made from, if known.
+    ip TEXT,
+    -- why the attempt failed, ie `wrong_password`.
+    reason TEXT NOT NULL,
+    created INTEGER NOT NULL
+);
+
+CREATE INDEX failed_logins_created ON failed_logins(created);
@@ -0,0 +1,19 @@
+-- Invites can expire, be used more than once and decide the roles and libraries of the users who
+-- register with them. `users.claimed_invite` is unique, so every registration claims a row of its
+-- own which points at the invite that was used through `claim_of`.
+ALTER TABLE invites ADD COLUMN expires INTEGER;
+ALTER TABLE invites ADD COLUMN max_uses INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE invites ADD COLUMN note TEXT;
+ALTER TABLE invites ADD COLUMN roles TEXT NOT NULL DEFAULT 'user';
+ALTER TABLE invites ADD COLUMN restrict_libraries BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE invites ADD COLUMN claim_of TEXT REFERENCES invites(id);
+
+-- Libraries users registering with a invite which has `restrict_libraries` set can see.
+CREATE TABLE invite_library (
+    invite_id TEXT NOT NULL,
+    library_id INTEGER NOT NULL,
+
+    PRIMARY KEY (invite_id, library_id),
+    FOREIGN KEY (invite_id) REFERENCES invites(id) ON DELETE CASCADE,
+    FOREIGN KEY (library_id) REFERENCES library(id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- Disabled users cannot log in but keep their data, ie their watch history. Users with
+-- `password_reset` set have to pick a new password before they can log in again.
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN password_reset BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,23 @@
+-- `watched` is set once a media has been played through (or marked as watched by hand), it is
+-- kept when the user starts watching it again so that rewatches don't lose the flag.
+ALTER TABLE progress ADD COLUMN watched BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE progress ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE progress ADD COLUMN last_watched INTEGER;
+
+-- Every viewing session of a media, progress reported within a short gap of the previous report
+-- extends the session instead of starting a new one.
+CREATE TABLE watch_history (
+    id INTEGER NOT NULL,
+    user_id TEXT NOT NULL,
+    media_id INTEGER NOT NULL,
+    started INTEGER NOT NULL,
+    ended INTEGER NOT NULL,
+    delta INTEGER NOT NULL,
+    completed BOOLEAN NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (id),
+    FOREIGN KEY(media_id) REFERENCES _tblmedia (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY(user_id) REFERENCES users(username) ON DELETE CASCADE
+);
+
+CREATE INDEX watch_history_idx ON watch_history(user_id, media_id, ended);
@@ -0,0 +1,17 @@
+-- External services a user's playback gets reported to, ie a Trakt account or a webhook.
+CREATE TABLE scrobblers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    -- type of the sink, either `webhook` or `trakt`.
+    kind TEXT NOT NULL,
+    -- url events are posted to, for trakt this is the base url of the api.
+    url TEXT NOT NULL,
+    -- client id of the application registered with the service, only used by trakt.
+    client_id TEXT,
+    -- token sent as a bearer token with every request.
+    token TEXT,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    created INTEGER NOT NULL,
+
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,35 @@
+-- Urls server events are posted to, ie to hook dim into a notification system.
+CREATE TABLE webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url TEXT NOT NULL,
+    -- key payloads are signed with so that receivers can verify they come from us.
+    secret TEXT NOT NULL,
+    -- comma separated list of the events sent to this webhook, empty if all of them are.
+    events TEXT NOT NULL DEFAULT '',
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    created INTEGER NOT NULL
+);
+
+-- Every event sent to a webhook. Deliveries which failed are retried until they succeed or run out
+-- of attempts, `next_attempt` is only set while a delivery is pending.
+CREATE TABLE webhook_deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    -- one of `pending`, `delivered` or `failed`.
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- status code of the last response, if we got one.
+    response_code INTEGER,
+    -- why the last attempt failed.
+    error TEXT,
+    created INTEGER NOT NULL,
+    next_attempt INTEGER,
+    delivered INTEGER,
+
+    FOREIGN KEY (webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_next_attempt_idx ON webhook_deliveries(next_attempt);
+CREATE INDEX webhook_deliveries_webhook_idx ON webhook_deliveries(webhook_id, created);
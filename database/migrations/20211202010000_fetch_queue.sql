@@ -0,0 +1,22 @@
+-- Artwork waiting to be downloaded into the metadata directory. The queue is kept in the database
+-- so that downloads survive restarts and periods without network access.
+CREATE TABLE fetch_queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url TEXT NOT NULL UNIQUE,
+    -- name of the file the download is stored as, relative to the metadata directory.
+    local_path TEXT NOT NULL,
+    -- downloads with a higher priority are attempted first.
+    priority INTEGER NOT NULL DEFAULT 0,
+    -- one of `pending`, `done` or `failed`.
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- why the last attempt failed.
+    error TEXT,
+    -- hex encoded sha256 of the downloaded file.
+    checksum TEXT,
+    created INTEGER NOT NULL,
+    next_attempt INTEGER,
+    completed INTEGER
+);
+
+CREATE INDEX fetch_queue_due_idx ON fetch_queue(status, next_attempt);
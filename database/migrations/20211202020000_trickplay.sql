@@ -0,0 +1,22 @@
+-- Thumbnail sprite sheets used for seek previews. The sheets themselves are stored in
+-- `{metadata}/trickplay/{mediafile_id}`, this only keeps track of how they were laid out.
+CREATE TABLE trickplay (
+    mediafile_id INTEGER PRIMARY KEY,
+    -- seconds between two thumbnails.
+    interval INTEGER NOT NULL,
+    -- size of a single thumbnail.
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    -- number of thumbnails per row and column of a sheet.
+    tile_columns INTEGER NOT NULL,
+    tile_rows INTEGER NOT NULL,
+    thumbnails INTEGER NOT NULL,
+    sheets INTEGER NOT NULL,
+    -- mtime of the file when the sheets were generated, they're regenerated when it changes.
+    file_mtime INTEGER,
+    -- why generating the sheets failed, failed files arent retried until they change.
+    error TEXT,
+    created INTEGER NOT NULL,
+
+    FOREIGN KEY(mediafile_id) REFERENCES mediafile(id) ON DELETE CASCADE
+);
@@ -0,0 +1,26 @@
+-- Segments of a file players can offer to skip, ie intros and end credits.
+CREATE TABLE markers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    mediafile_id INTEGER NOT NULL,
+    -- one of `intro` or `credits`.
+    kind TEXT NOT NULL,
+    -- offsets into the file in seconds.
+    start_time REAL NOT NULL,
+    end_time REAL NOT NULL,
+
+    FOREIGN KEY(mediafile_id) REFERENCES mediafile(id) ON DELETE CASCADE
+);
+
+CREATE INDEX markers_mediafile_idx ON markers(mediafile_id);
+
+-- Files which were analyzed for markers, whether or not any were found.
+CREATE TABLE marker_analysis (
+    mediafile_id INTEGER PRIMARY KEY,
+    -- mtime of the file when it was analyzed, it is analyzed again when it changes.
+    file_mtime INTEGER,
+    -- why analyzing the file failed.
+    error TEXT,
+    analyzed INTEGER NOT NULL,
+
+    FOREIGN KEY(mediafile_id) REFERENCES mediafile(id) ON DELETE CASCADE
+);
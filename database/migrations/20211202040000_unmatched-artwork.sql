@@ -0,0 +1,4 @@
+-- Artwork extracted from files which couldnt be matched, as names of files in the metadata
+-- directory. Matched files use the artwork of their media instead.
+ALTER TABLE mediafile ADD COLUMN poster_file TEXT;
+ALTER TABLE mediafile ADD COLUMN backdrop_file TEXT;
@@ -0,0 +1,4 @@
+-- Whether the certification of a movie or tv show was looked up since certifications are stored.
+-- Media scanned before that, or whose certification the provider didnt know at the time, is looked
+-- up again once in the background.
+ALTER TABLE _tblmedia ADD COLUMN certification_checked BOOLEAN NOT NULL DEFAULT 0;
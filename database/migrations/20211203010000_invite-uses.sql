@@ -0,0 +1,8 @@
+-- Number of users who registered with a invite, kept as a counter so that deleting a user doesnt
+-- make the invite they used claimable again.
+ALTER TABLE invites ADD COLUMN uses INTEGER NOT NULL DEFAULT 0;
+
+UPDATE invites SET uses =
+    (SELECT COUNT(*) FROM invites claims WHERE claims.claim_of = invites.id)
+    + (SELECT COUNT(*) FROM users WHERE users.claimed_invite = invites.id)
+WHERE claim_of IS NULL;
@@ -0,0 +1,108 @@
+use crate::media::Media;
+use crate::DatabaseError;
+
+use serde::{Deserialize, Serialize};
+
+/// Struct represents a album entry in the database. Albums are media objects, this struct is used
+/// to link the media object to the artist of the album.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Album {
+    /// id of a media object we marked as a album.
+    pub id: i64,
+    /// id of the artist of this album, if known.
+    pub artist_id: Option<i64>,
+}
+
+impl Album {
+    /// Method returns the album with the id `id`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of a media object which is a album.
+    pub async fn get(conn: &mut crate::Transaction<'_>, id: i64) -> Result<Self, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", artist_id FROM album WHERE id = ?"#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns all the albums released by a artist which are certified at most `max_level`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `artist_id` - id of the artist.
+    /// * `max_level` - highest certification level to return.
+    pub async fn get_all_of_artist(
+        conn: &mut crate::Transaction<'_>,
+        artist_id: i64,
+        max_level: i64,
+    ) -> Result<Vec<Media>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Media,
+            r#"SELECT
+                media.id, media.library_id, media.name, media.description,
+                media.rating, media.year, media.added, media.poster_path,
+                media.backdrop_path, media.media_type as "media_type: _"
+                FROM media INNER JOIN album ON media.id = album.id
+                WHERE album.artist_id = ?
+                AND (media.certification_level IS NULL OR media.certification_level <= ?)
+                ORDER BY media.year, media.name"#,
+            artist_id,
+            max_level
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method looks up a album by its name and artist, returning its id if it exists.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `library_id` - id of the library the album is in.
+    /// * `name` - name of the album.
+    /// * `artist_id` - id of the artist of the album.
+    pub async fn get_by_name(
+        conn: &mut crate::Transaction<'_>,
+        library_id: i64,
+        name: &str,
+        artist_id: Option<i64>,
+    ) -> Result<Option<i64>, DatabaseError> {
+        Ok(sqlx::query!(
+            r#"SELECT album.id as "id!: i64" FROM album
+                INNER JOIN _tblmedia ON _tblmedia.id = album.id
+                WHERE _tblmedia.library_id = ?
+                AND _tblmedia.name = ?
+                AND album.artist_id IS ?"#,
+            library_id,
+            name,
+            artist_id
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .map(|x| x.id))
+    }
+
+    /// Method marks the media object `id` as a album.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of a media object that should be a album.
+    /// * `artist_id` - id of the artist of the album.
+    pub async fn insert(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        artist_id: Option<i64>,
+    ) -> Result<i64, DatabaseError> {
+        Ok(sqlx::query!(
+            "INSERT INTO album (id, artist_id) VALUES ($1, $2)",
+            id,
+            artist_id
+        )
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid())
+    }
+}
@@ -0,0 +1,161 @@
+use crate::utils::hash_token;
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Serialize;
+
+/// A API key used by a headless client or script to act on behalf of its owner.
+#[derive(Clone, Debug, Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub username: String,
+    /// Name given to the key by its owner.
+    pub name: String,
+    /// Names of the permissions this key is limited to.
+    pub scopes: Vec<String>,
+    /// Timestamp of when the key was created.
+    pub created: i64,
+    /// Timestamp of the last time the key was used, if ever.
+    pub last_used: Option<i64>,
+}
+
+impl ApiKey {
+    /// Method returns all the API keys of a user.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose keys we want.
+    pub async fn get_all_of_user(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query!(
+            r#"SELECT id as "id!", username, name, scopes, created, last_used FROM api_keys
+                WHERE username = ?
+                ORDER BY id ASC"#,
+            username
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|x| Self {
+            id: x.id,
+            username: x.username,
+            name: x.name,
+            scopes: split_scopes(&x.scopes),
+            created: x.created,
+            last_used: x.last_used,
+        })
+        .collect())
+    }
+
+    /// Method returns the API key matching `key`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `key` - the key supplied by the client.
+    pub async fn get_by_key(
+        conn: &mut crate::Transaction<'_>,
+        key: &str,
+    ) -> Result<Self, DatabaseError> {
+        let hash = hash_token(key);
+
+        let x = sqlx::query!(
+            r#"SELECT id as "id!", username, name, scopes, created, last_used FROM api_keys
+                WHERE key_hash = ?"#,
+            hash
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(Self {
+            id: x.id,
+            username: x.username,
+            name: x.name,
+            scopes: split_scopes(&x.scopes),
+            created: x.created,
+            last_used: x.last_used,
+        })
+    }
+
+    /// Method sets the last time a API key was used to now.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the key.
+    pub async fn touch(conn: &mut crate::Transaction<'_>, id: i64) -> Result<usize, DatabaseError> {
+        let now = now();
+
+        Ok(
+            sqlx::query!("UPDATE api_keys SET last_used = ? WHERE id = ?", now, id)
+                .execute(&mut *conn)
+                .await?
+                .rows_affected() as usize,
+        )
+    }
+
+    /// Method deletes a API key of a user, returns the number of rows deleted.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user who owns the key.
+    /// * `id` - id of the key.
+    pub async fn delete(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        id: i64,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "DELETE FROM api_keys WHERE username = ? AND id = ?",
+            username,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+}
+
+fn split_scopes(scopes: &str) -> Vec<String> {
+    scopes
+        .split(',')
+        .filter(|x| !x.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Struct used to create a new API key.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableApiKey {
+    pub username: String,
+    pub name: String,
+    /// The key handed out to the client, only its hash is stored.
+    pub key: String,
+    /// Names of the permissions the key is limited to.
+    pub scopes: Vec<String>,
+}
+
+impl InsertableApiKey {
+    /// Method inserts a new API key and returns its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        let hash = hash_token(&self.key);
+        let scopes = self.scopes.join(",");
+        let now = now();
+
+        Ok(sqlx::query!(
+            r#"INSERT INTO api_keys (username, name, key_hash, scopes, created)
+                VALUES ($1, $2, $3, $4, $5)"#,
+            self.username,
+            self.name,
+            hash,
+            scopes,
+            now
+        )
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid())
+    }
+}
@@ -0,0 +1,85 @@
+use crate::DatabaseError;
+
+use serde::{Deserialize, Serialize};
+
+/// Struct represents a artist entry in the database. Artists are scoped to a library.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Artist {
+    pub id: i64,
+    /// Id of the library this artist was scanned in.
+    pub library_id: i64,
+    /// Name of the artist as found in the tags of the tracks.
+    pub name: String,
+}
+
+impl Artist {
+    /// Method returns all artists of a library ordered by name. Artists whose albums are all
+    /// certified above `max_level` are left out.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `library_id` - id of the library we'd like to discriminate against.
+    /// * `max_level` - highest certification level to return albums for.
+    pub async fn get_all(
+        conn: &mut crate::Transaction<'_>,
+        library_id: i64,
+        max_level: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id, library_id, name FROM artist
+            WHERE library_id = ?
+            AND (NOT EXISTS (SELECT 1 FROM album WHERE album.artist_id = artist.id)
+                OR EXISTS (SELECT 1 FROM album INNER JOIN _tblmedia ON _tblmedia.id = album.id
+                    WHERE album.artist_id = artist.id
+                    AND (_tblmedia.certification_level IS NULL OR _tblmedia.certification_level <= ?)))
+            ORDER BY name COLLATE NOCASE"#,
+            library_id,
+            max_level
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the artist with the id `id`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the artist.
+    pub async fn get_one(conn: &mut crate::Transaction<'_>, id: i64) -> Result<Self, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            "SELECT id, library_id, name FROM artist WHERE id = ?",
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct InsertableArtist {
+    pub library_id: i64,
+    pub name: String,
+}
+
+impl InsertableArtist {
+    /// Method inserts a new artist into the database, if a artist with the same name already exists
+    /// in the library, its id is returned instead.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        Ok(sqlx::query!(
+            r#"INSERT INTO artist (library_id, name) VALUES ($1, $2)
+            ON CONFLICT DO UPDATE
+            SET name = $2
+            RETURNING artist.id as "id!: i64""#,
+            self.library_id,
+            self.name
+        )
+        .fetch_one(&mut *conn)
+        .await?
+        .id)
+    }
+}
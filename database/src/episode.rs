@@ -136,6 +136,23 @@ impl Episode {
         Ok(episodes)
     }
 
+    /// Method returns the number of episodes belonging to a season.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `season_id` - id of the season.
+    pub async fn count_of_season(
+        conn: &mut crate::Transaction<'_>,
+        season_id: i64,
+    ) -> Result<i64, DatabaseError> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM episode WHERE seasonid = ?"#,
+            season_id
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
     /// Method returns a episodes discriminated by episode number, season number and tv show id
     ///
     /// # Arguments
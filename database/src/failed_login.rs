@@ -0,0 +1,87 @@
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Serialize;
+
+/// A failed login attempt, kept so that admins can audit attempts to break into accounts.
+#[derive(Clone, Debug, Serialize)]
+pub struct FailedLogin {
+    pub id: i64,
+    /// Username the attempt was made against, the user might not exist.
+    pub username: String,
+    /// Address the attempt was made from.
+    pub ip: Option<String>,
+    /// Why the attempt failed, ie `wrong_password`.
+    pub reason: String,
+    /// Timestamp of the attempt.
+    pub created: i64,
+}
+
+impl FailedLogin {
+    /// Method returns the most recent failed login attempts, newest first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `limit` - max number of attempts to return.
+    pub async fn get_recent(
+        conn: &mut crate::Transaction<'_>,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            FailedLogin,
+            r#"SELECT id as "id!", username, ip, reason, created FROM failed_logins
+                ORDER BY created DESC, id DESC
+                LIMIT ?"#,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method deletes all the attempts made more than `age` seconds ago.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `age` - age in seconds after which attempts are deleted.
+    pub async fn delete_older_than(
+        conn: &mut crate::Transaction<'_>,
+        age: i64,
+    ) -> Result<usize, DatabaseError> {
+        let cutoff = now() - age;
+
+        Ok(
+            sqlx::query!("DELETE FROM failed_logins WHERE created < ?", cutoff)
+                .execute(&mut *conn)
+                .await?
+                .rows_affected() as usize,
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InsertableFailedLogin {
+    pub username: String,
+    pub ip: Option<String>,
+    pub reason: String,
+}
+
+impl InsertableFailedLogin {
+    /// Method logs a failed login attempt and returns its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            "INSERT INTO failed_logins (username, ip, reason, created) VALUES ($1, $2, $3, $4)",
+            self.username,
+            self.ip,
+            self.reason,
+            now
+        )
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid())
+    }
+}
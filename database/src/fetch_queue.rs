@@ -0,0 +1,241 @@
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// State of a download.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum FetchStatus {
+    /// The download hasnt succeeded yet but will be attempted again.
+    Pending,
+    Done,
+    /// The download ran out of attempts.
+    Failed,
+}
+
+/// A file queued to be downloaded into the metadata directory.
+#[derive(Clone, Debug, Serialize)]
+pub struct FetchJob {
+    pub id: i64,
+    pub url: String,
+    /// Name of the file the download is stored as, relative to the metadata directory.
+    pub local_path: String,
+    /// Downloads with a higher priority are attempted first.
+    pub priority: i64,
+    pub status: FetchStatus,
+    /// Number of times the download has been attempted.
+    pub attempts: i64,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    /// Hex encoded sha256 of the downloaded file.
+    pub checksum: Option<String>,
+    /// Timestamp of when the download was queued.
+    pub created: i64,
+    /// Timestamp after which the download should be attempted again, only set while pending.
+    pub next_attempt: Option<i64>,
+    /// Timestamp of when the download succeeded.
+    pub completed: Option<i64>,
+}
+
+/// Number of downloads in each state.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct FetchQueueStats {
+    pub pending: i64,
+    pub failed: i64,
+    pub done: i64,
+}
+
+impl FetchJob {
+    /// Method returns the download of `url`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `url` - url of the download.
+    pub async fn get_by_url(
+        conn: &mut crate::Transaction<'_>,
+        url: &str,
+    ) -> Result<Self, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", url, local_path, priority, status as "status: FetchStatus",
+                attempts, error, checksum, created, next_attempt, completed
+                FROM fetch_queue
+                WHERE url = ?"#,
+            url
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the downloads which havent succeeded, highest priority first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `limit` - max number of downloads to return.
+    pub async fn get_unfinished(
+        conn: &mut crate::Transaction<'_>,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", url, local_path, priority, status as "status: FetchStatus",
+                attempts, error, checksum, created, next_attempt, completed
+                FROM fetch_queue
+                WHERE status != 'done'
+                ORDER BY priority DESC, id ASC
+                LIMIT ?"#,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the pending downloads which are due to be attempted, highest priority first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `limit` - max number of downloads to return.
+    pub async fn get_due(
+        conn: &mut crate::Transaction<'_>,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", url, local_path, priority, status as "status: FetchStatus",
+                attempts, error, checksum, created, next_attempt, completed
+                FROM fetch_queue
+                WHERE status = 'pending' AND next_attempt <= ?
+                ORDER BY priority DESC, next_attempt ASC
+                LIMIT ?"#,
+            now,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the number of downloads in each state.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn stats(
+        conn: &mut crate::Transaction<'_>,
+    ) -> Result<FetchQueueStats, DatabaseError> {
+        Ok(sqlx::query_as!(
+            FetchQueueStats,
+            r#"SELECT
+                COALESCE(SUM(status = 'pending'), 0) as "pending!: i64",
+                COALESCE(SUM(status = 'failed'), 0) as "failed!: i64",
+                COALESCE(SUM(status = 'done'), 0) as "done!: i64"
+                FROM fetch_queue"#
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// Method records a successful download.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the download.
+    /// * `checksum` - hex encoded sha256 of the downloaded file.
+    pub async fn set_done(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        checksum: &str,
+    ) -> Result<usize, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            "UPDATE fetch_queue
+                SET status = 'done', attempts = attempts + 1, error = NULL, checksum = $1,
+                    next_attempt = NULL, completed = $2
+                WHERE id = $3",
+            checksum,
+            now,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method records a failed download. The download is attempted again at `next_attempt`, or
+    /// marked as failed if that is `None`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the download.
+    /// * `error` - why the attempt failed.
+    /// * `next_attempt` - timestamp of when to try again.
+    pub async fn set_failed(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        error: &str,
+        next_attempt: Option<i64>,
+    ) -> Result<usize, DatabaseError> {
+        let status = if next_attempt.is_some() {
+            FetchStatus::Pending
+        } else {
+            FetchStatus::Failed
+        };
+
+        Ok(sqlx::query!(
+            "UPDATE fetch_queue
+                SET status = $1, attempts = attempts + 1, error = $2, next_attempt = $3
+                WHERE id = $4",
+            status,
+            error,
+            next_attempt,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+}
+
+/// Struct used to queue a new download.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableFetchJob {
+    pub url: String,
+    pub local_path: String,
+    pub priority: i64,
+}
+
+impl InsertableFetchJob {
+    /// Method queues the download and returns its id. Urls which are already queued arent queued
+    /// twice, instead the existing download keeps the highest of both priorities. Downloads which
+    /// are done or failed are queued again so that missing files get fetched.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            r#"INSERT INTO fetch_queue (url, local_path, priority, created, next_attempt)
+                VALUES ($1, $2, $3, $4, $4)
+                ON CONFLICT(url) DO UPDATE SET
+                    priority = MAX(fetch_queue.priority, excluded.priority),
+                    status = 'pending',
+                    attempts = CASE WHEN fetch_queue.status = 'pending'
+                        THEN fetch_queue.attempts ELSE 0 END,
+                    next_attempt = CASE WHEN fetch_queue.status = 'pending'
+                        THEN fetch_queue.next_attempt ELSE excluded.next_attempt END
+                RETURNING id as "id!: i64""#,
+            self.url,
+            self.local_path,
+            self.priority,
+            now
+        )
+        .fetch_one(&mut *conn)
+        .await?
+        .id)
+    }
+}
@@ -0,0 +1,258 @@
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Serialize;
+
+/// A invite users can register with.
+#[derive(Clone, Debug, Serialize)]
+pub struct Invite {
+    pub id: String,
+    /// Timestamp of when the invite was created.
+    pub created: i64,
+    /// Timestamp after which the invite cannot be used anymore, `None` if it never expires.
+    pub expires: Option<i64>,
+    /// Number of users who can register with this invite.
+    pub max_uses: i64,
+    /// Number of users who registered with this invite, including users who were deleted since.
+    pub uses: i64,
+    /// Note left by the admin who created the invite, ie who it was meant for.
+    pub note: Option<String>,
+    /// Roles users registering with this invite get.
+    pub roles: Vec<String>,
+    /// Libraries users registering with this invite are restricted to, `None` if they can see
+    /// every library.
+    pub libraries: Option<Vec<i64>>,
+    /// Usernames of the users who registered with this invite and still exist.
+    pub claimed_by: Vec<String>,
+}
+
+struct InviteRow {
+    id: String,
+    created: i64,
+    expires: Option<i64>,
+    max_uses: i64,
+    uses: i64,
+    note: Option<String>,
+    roles: String,
+    restrict_libraries: bool,
+}
+
+impl InviteRow {
+    async fn into_invite(self, conn: &mut crate::Transaction<'_>) -> Result<Invite, DatabaseError> {
+        let libraries = if self.restrict_libraries {
+            Some(
+                sqlx::query_scalar!(
+                    "SELECT library_id FROM invite_library WHERE invite_id = ?",
+                    self.id
+                )
+                .fetch_all(&mut *conn)
+                .await?,
+            )
+        } else {
+            None
+        };
+
+        // invites claimed before they could be used more than once are referenced directly.
+        let claimed_by = sqlx::query_scalar!(
+            r#"SELECT username as "username!" FROM users
+                WHERE claimed_invite = ?1
+                OR claimed_invite IN (SELECT id FROM invites WHERE claim_of = ?1)"#,
+            self.id
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(Invite {
+            id: self.id,
+            created: self.created,
+            expires: self.expires,
+            max_uses: self.max_uses,
+            uses: self.uses,
+            note: self.note,
+            roles: self.roles.split(',').map(ToString::to_string).collect(),
+            libraries,
+            claimed_by,
+        })
+    }
+}
+
+impl Invite {
+    /// Method returns whether the invite has expired.
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires, Some(x) if x <= now())
+    }
+
+    /// Method returns whether the invite can still be used to register.
+    pub fn is_valid(&self) -> bool {
+        !self.is_expired() && self.uses < self.max_uses
+    }
+
+    /// Method returns a invite.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - the invite token.
+    pub async fn get(conn: &mut crate::Transaction<'_>, id: &str) -> Result<Self, DatabaseError> {
+        let row = sqlx::query_as!(
+            InviteRow,
+            r#"SELECT id, date_added as created, expires, max_uses, uses, note, roles,
+                restrict_libraries as "restrict_libraries: bool"
+                FROM invites
+                WHERE id = ? AND claim_of IS NULL"#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        row.into_invite(&mut *conn).await
+    }
+
+    /// Method returns all the invites, oldest first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn get_all(conn: &mut crate::Transaction<'_>) -> Result<Vec<Self>, DatabaseError> {
+        let rows = sqlx::query_as!(
+            InviteRow,
+            r#"SELECT id, date_added as created, expires, max_uses, uses, note, roles,
+                restrict_libraries as "restrict_libraries: bool"
+                FROM invites
+                WHERE claim_of IS NULL
+                ORDER BY date_added ASC"#
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut invites = Vec::with_capacity(rows.len());
+        for row in rows {
+            invites.push(row.into_invite(&mut *conn).await?);
+        }
+
+        Ok(invites)
+    }
+
+    /// Method records a use of a invite and returns the token the new user has to be inserted
+    /// with as their `claimed_invite`. Callers must check that the invite is valid first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - the invite token.
+    pub async fn claim(
+        conn: &mut crate::Transaction<'_>,
+        id: &str,
+    ) -> Result<String, DatabaseError> {
+        let token = uuid::Uuid::new_v4().to_hyphenated().to_string();
+        let now = now();
+
+        sqlx::query!(
+            "INSERT INTO invites (id, date_added, claim_of) VALUES ($1, $2, $3)",
+            token,
+            now,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Method deletes a invite if nobody has registered with it yet, otherwise the invite is
+    /// expired so that it cannot be used anymore. Returns the number of rows affected.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - the invite token.
+    pub async fn delete(
+        conn: &mut crate::Transaction<'_>,
+        id: &str,
+    ) -> Result<usize, DatabaseError> {
+        let deleted = sqlx::query!(
+            "DELETE FROM invites
+                WHERE id = ?1
+                AND claim_of IS NULL
+                AND id NOT IN (SELECT claimed_invite FROM users)
+                AND id NOT IN (SELECT claim_of FROM invites WHERE claim_of IS NOT NULL)",
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize;
+
+        if deleted > 0 {
+            return Ok(deleted);
+        }
+
+        let now = now();
+
+        Ok(sqlx::query!(
+            "UPDATE invites SET expires = $1 WHERE id = ?2 AND claim_of IS NULL",
+            now,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+}
+
+/// Struct used to create a new invite.
+#[derive(Clone, Debug)]
+pub struct InsertableInvite {
+    /// Timestamp after which the invite cannot be used anymore.
+    pub expires: Option<i64>,
+    pub max_uses: i64,
+    pub note: Option<String>,
+    pub roles: Vec<String>,
+    pub libraries: Option<Vec<i64>>,
+}
+
+impl Default for InsertableInvite {
+    fn default() -> Self {
+        Self {
+            expires: None,
+            max_uses: 1,
+            note: None,
+            roles: vec!["user".into()],
+            libraries: None,
+        }
+    }
+}
+
+impl InsertableInvite {
+    /// Method inserts a new invite and returns its token.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<String, DatabaseError> {
+        let token = uuid::Uuid::new_v4().to_hyphenated().to_string();
+        let roles = self.roles.join(",");
+        let restrict_libraries = self.libraries.is_some();
+        let now = now();
+
+        sqlx::query!(
+            r#"INSERT INTO invites (id, date_added, expires, max_uses, note, roles, restrict_libraries)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            token,
+            now,
+            self.expires,
+            self.max_uses,
+            self.note,
+            roles,
+            restrict_libraries
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        for library_id in self.libraries.iter().flatten() {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO invite_library (invite_id, library_id) VALUES ($1, $2)",
+                token,
+                library_id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(token)
+    }
+}
@@ -10,23 +10,36 @@ use std::sync::atomic::Ordering;
 use sqlx::ConnectOptions;
 use tracing::{info, instrument};
 
+pub mod album;
+pub mod api_key;
+pub mod artist;
 pub mod asset;
 pub mod episode;
 pub mod error;
+pub mod failed_login;
+pub mod fetch_queue;
 pub mod genre;
+pub mod invite;
 pub mod library;
+pub mod marker;
 pub mod media;
 pub mod mediafile;
 pub mod movie;
 pub mod progress;
 #[cfg(feature = "sqlite")]
 pub mod rw_pool;
+pub mod scrobbler;
 pub mod season;
+pub mod session;
 #[cfg(test)]
 pub mod tests;
+pub mod track;
+pub mod trickplay;
 pub mod tv;
 pub mod user;
 pub mod utils;
+pub mod watch_history;
+pub mod webhook;
 
 pub use crate::error::DatabaseError;
 /// Ugly hack because of a shitty deadlock in `Pool`
@@ -145,6 +158,32 @@ pub async fn get_conn_devel() -> sqlx::Result<crate::DbConnection> {
     Ok(pool)
 }
 
+/// Function returns a connection to a sqlite database stored at `path`, creating and migrating it
+/// if needed. Unlike the in-memory database of our own tests the reader and the writer see the same
+/// data, this is used by the route tests of dim.
+#[cfg(feature = "sqlite")]
+#[doc(hidden)]
+pub async fn get_conn_file(path: &std::path::Path) -> sqlx::Result<crate::DbConnection> {
+    let rw_only = sqlx::sqlite::SqliteConnectOptions::new()
+        .create_if_missing(true)
+        .filename(path)
+        .connect()
+        .await?;
+
+    let rd_only = sqlx::pool::PoolOptions::new()
+        .connect_with(
+            sqlx::sqlite::SqliteConnectOptions::new()
+                .read_only(true)
+                .filename(path),
+        )
+        .await?;
+
+    let pool = rw_pool::SqlitePool::new(rw_only, rd_only);
+    run_migrations(&pool).await?;
+
+    Ok(pool)
+}
+
 /// Function which returns a Result<T, E> where T is a new connection session or E is a connection
 /// error. It takes in a logger instance.
 ///
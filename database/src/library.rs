@@ -12,6 +12,10 @@ pub enum MediaType {
     Movie,
     Tv,
     Episode,
+    /// Used for music libraries as well as albums, tracks are stored separately.
+    Music,
+    /// Only used for libraries, which contain both movies and tv shows.
+    Mixed,
 }
 
 impl fmt::Display for MediaType {
@@ -23,6 +27,8 @@ impl fmt::Display for MediaType {
                 Self::Movie => "movie",
                 Self::Tv => "tv",
                 Self::Episode => "episode",
+                Self::Music => "music",
+                Self::Mixed => "mixed",
             }
         )
     }
@@ -34,6 +40,40 @@ impl Default for MediaType {
     }
 }
 
+/// Enum represents the source from which a library pulls its metadata.
+/// When returned in a http response, the fields are lowercase.
+#[derive(Copy, Serialize, Debug, Clone, Eq, PartialEq, Deserialize, Hash, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// Metadata is fetched from themoviedb.org.
+    Tmdb,
+    /// Metadata is generated locally from the parsed filenames. Useful for testing matching
+    /// without network access, only available with the `mock_provider` feature.
+    #[cfg(any(test, feature = "mock_provider"))]
+    Mock,
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Tmdb => "tmdb",
+                #[cfg(any(test, feature = "mock_provider"))]
+                Self::Mock => "mock",
+            }
+        )
+    }
+}
+
+impl Default for ProviderKind {
+    fn default() -> Self {
+        Self::Tmdb
+    }
+}
+
 /// Library struct which we can use to deserialize database queries into.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Library {
@@ -47,9 +87,20 @@ pub struct Library {
     pub locations: Vec<String>,
 
     /// Enum used to identify the media type that this library contains. At the
-    /// moment only `movie` and `tv` are supported
-    // TODO: support mixed content, music
+    /// moment `movie`, `tv`, `music` and `mixed` are supported
     pub media_type: MediaType,
+
+    /// The metadata provider used when matching media in this library.
+    #[serde(default)]
+    pub metadata_provider: ProviderKind,
+
+    /// Seconds between scheduled rescans of this library. If `None` the library is only scanned
+    /// on boot or when requested.
+    #[serde(default)]
+    pub scan_interval: Option<i64>,
+    /// Unix timestamp of when the last full scan of this library finished.
+    #[serde(default)]
+    pub last_scanned: Option<i64>,
 }
 
 impl Library {
@@ -59,18 +110,81 @@ impl Library {
     /// This method will not return the locations indexed for this library, if you need those you
     /// must query for them separately.
     pub async fn get_all(conn: &mut crate::Transaction<'_>) -> Vec<Self> {
-        sqlx::query!(r#"SELECT id, name, media_type as "media_type: MediaType" FROM library WHERE NOT hidden"#)
-            .fetch_all(&mut *conn)
-            .await
-            .unwrap_or_default()
-            .into_iter()
-            .map(|x| Self {
-                id: x.id,
-                name: x.name,
-                media_type: x.media_type,
-                locations: vec![],
-            })
-            .collect()
+        sqlx::query!(
+            r#"SELECT id, name, media_type as "media_type: MediaType",
+                metadata_provider as "metadata_provider: ProviderKind",
+                scan_interval, last_scanned
+            FROM library WHERE NOT hidden"#
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| Self {
+            id: x.id,
+            name: x.name,
+            media_type: x.media_type,
+            metadata_provider: x.metadata_provider,
+            scan_interval: x.scan_interval,
+            last_scanned: x.last_scanned,
+            locations: vec![],
+        })
+        .collect()
+    }
+
+    /// Method returns all the libraries the user `username` is allowed to see. Like
+    /// [`Library::get_all`] the locations of the libraries are not returned.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose libraries we want.
+    pub async fn get_all_for_user(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query!(
+            r#"SELECT library.id, name, media_type as "media_type: MediaType",
+                metadata_provider as "metadata_provider: ProviderKind",
+                scan_interval, last_scanned
+            FROM library
+            INNER JOIN visible_library ON visible_library.library_id = library.id
+            WHERE visible_library.username = ?"#,
+            username
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|x| Self {
+            id: x.id,
+            name: x.name,
+            media_type: x.media_type,
+            metadata_provider: x.metadata_provider,
+            scan_interval: x.scan_interval,
+            last_scanned: x.last_scanned,
+            locations: vec![],
+        })
+        .collect())
+    }
+
+    /// Method returns whether the user `username` is allowed to see the library `id`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the library.
+    /// * `username` - user we want to check.
+    pub async fn is_visible_to(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        username: &str,
+    ) -> Result<bool, DatabaseError> {
+        Ok(sqlx::query!(
+            "SELECT library_id FROM visible_library WHERE library_id = ? AND username = ?",
+            id,
+            username
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_some())
     }
 
     pub async fn get_locations(
@@ -97,8 +211,10 @@ impl Library {
         lib_id: i64,
     ) -> Result<Self, DatabaseError> {
         let library = sqlx::query!(
-            r#"SELECT id, name, media_type as "media_type: MediaType" FROM library
-            WHERE id = ?"#,
+            r#"SELECT id, name, media_type as "media_type: MediaType",
+                metadata_provider as "metadata_provider: ProviderKind",
+                scan_interval, last_scanned
+            FROM library WHERE id = ?"#,
             lib_id
         )
         .fetch_one(&mut *conn)
@@ -116,6 +232,9 @@ impl Library {
             id: library.id,
             name: library.name,
             media_type: library.media_type,
+            metadata_provider: library.metadata_provider,
+            scan_interval: library.scan_interval,
+            last_scanned: library.last_scanned,
             locations,
         })
     }
@@ -135,6 +254,49 @@ impl Library {
             .rows_affected() as usize)
     }
 
+    /// Method sets the interval in seconds between scheduled rescans of a library. Passing `None`
+    /// disables scheduled rescans.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the library.
+    /// * `interval` - seconds between scans.
+    pub async fn set_scan_interval(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        interval: Option<i64>,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "UPDATE library SET scan_interval = ? WHERE id = ?",
+            interval,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method records that a full scan of a library has finished at `timestamp`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the library.
+    /// * `timestamp` - unix timestamp of when the scan finished.
+    pub async fn set_last_scanned(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        timestamp: i64,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "UPDATE library SET last_scanned = ? WHERE id = ?",
+            timestamp,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
     pub async fn mark_hidden(
         conn: &mut crate::Transaction<'_>,
         id: i64,
@@ -154,6 +316,10 @@ pub struct InsertableLibrary {
     pub name: String,
     pub locations: Vec<String>,
     pub media_type: MediaType,
+    #[serde(default)]
+    pub metadata_provider: ProviderKind,
+    #[serde(default)]
+    pub scan_interval: Option<i64>,
 }
 
 impl InsertableLibrary {
@@ -163,9 +329,12 @@ impl InsertableLibrary {
     /// * `conn` - mutable reference to a sqlx transaction.
     pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
         let lib_id = sqlx::query!(
-            r#"INSERT INTO library (name, media_type) VALUES ($1, $2)"#,
+            r#"INSERT INTO library (name, media_type, metadata_provider, scan_interval)
+            VALUES ($1, $2, $3, $4)"#,
             self.name,
-            self.media_type
+            self.media_type,
+            self.metadata_provider,
+            self.scan_interval
         )
         .execute(&mut *conn)
         .await?
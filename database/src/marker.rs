@@ -0,0 +1,199 @@
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Kind of segment a marker covers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum MarkerKind {
+    /// The opening sequence shared by the episodes of a season.
+    Intro,
+    /// The end credits.
+    Credits,
+}
+
+/// A segment of a file players can offer to skip.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Marker {
+    pub id: i64,
+    pub mediafile_id: i64,
+    pub kind: MarkerKind,
+    /// Offset in seconds the segment starts at.
+    pub start_time: f64,
+    /// Offset in seconds the segment ends at.
+    pub end_time: f64,
+}
+
+/// A episode file which wasnt analyzed for markers yet, or which changed since.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarkerTarget {
+    pub mediafile_id: i64,
+    /// Season the episode belongs to, the episodes of a season are analyzed together.
+    pub seasonid: i64,
+    pub target_file: String,
+    pub duration: Option<i64>,
+    pub file_mtime: Option<i64>,
+}
+
+impl Marker {
+    /// Method returns the markers of a mediafile ordered by when they start.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `mediafile_id` - id of the mediafile.
+    pub async fn get_of_mediafile(
+        conn: &mut crate::Transaction<'_>,
+        mediafile_id: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", mediafile_id, kind as "kind: MarkerKind", start_time,
+                end_time
+                FROM markers
+                WHERE mediafile_id = ?
+                ORDER BY start_time ASC"#,
+            mediafile_id
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method removes the markers of a mediafile.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `mediafile_id` - id of the mediafile.
+    pub async fn delete_of_mediafile(
+        conn: &mut crate::Transaction<'_>,
+        mediafile_id: i64,
+    ) -> Result<usize, DatabaseError> {
+        Ok(
+            sqlx::query!("DELETE FROM markers WHERE mediafile_id = ?", mediafile_id)
+                .execute(&mut *conn)
+                .await?
+                .rows_affected() as usize,
+        )
+    }
+}
+
+impl MarkerTarget {
+    /// Method returns the episode files which werent analyzed yet or changed since they were,
+    /// grouped by season.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `limit` - max number of files to return.
+    pub async fn get_outdated(
+        conn: &mut crate::Transaction<'_>,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT mediafile.id as "mediafile_id!", episode.seasonid, mediafile.target_file,
+                mediafile.duration, mediafile.file_mtime
+                FROM mediafile
+                INNER JOIN episode ON episode.id = mediafile.media_id
+                LEFT JOIN marker_analysis ON marker_analysis.mediafile_id = mediafile.id
+                WHERE mediafile.duration > 0
+                    AND NOT COALESCE(mediafile.corrupt, 0)
+                    AND (marker_analysis.mediafile_id IS NULL
+                        OR marker_analysis.file_mtime IS NOT mediafile.file_mtime)
+                ORDER BY episode.seasonid ASC, mediafile.id ASC
+                LIMIT ?"#,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns all episode files of a season, ordered by episode number.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `seasonid` - id of the season.
+    pub async fn get_of_season(
+        conn: &mut crate::Transaction<'_>,
+        seasonid: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT mediafile.id as "mediafile_id!", episode.seasonid, mediafile.target_file,
+                mediafile.duration, mediafile.file_mtime
+                FROM mediafile
+                INNER JOIN episode ON episode.id = mediafile.media_id
+                WHERE episode.seasonid = ?
+                    AND mediafile.duration > 0
+                    AND NOT COALESCE(mediafile.corrupt, 0)
+                ORDER BY episode.episode_ ASC, mediafile.id ASC"#,
+            seasonid
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+}
+
+/// Struct used to add a marker to a mediafile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InsertableMarker {
+    pub mediafile_id: i64,
+    pub kind: MarkerKind,
+    pub start_time: f64,
+    pub end_time: f64,
+}
+
+impl InsertableMarker {
+    /// Method adds the marker and returns its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        Ok(sqlx::query!(
+            r#"INSERT INTO markers (mediafile_id, kind, start_time, end_time)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id as "id!: i64""#,
+            self.mediafile_id,
+            self.kind,
+            self.start_time,
+            self.end_time
+        )
+        .fetch_one(&mut *conn)
+        .await?
+        .id)
+    }
+}
+
+/// Struct used to record that a mediafile was analyzed for markers.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableMarkerAnalysis {
+    pub mediafile_id: i64,
+    /// Mtime of the mediafile when it was analyzed.
+    pub file_mtime: Option<i64>,
+    /// Why analyzing the mediafile failed.
+    pub error: Option<String>,
+}
+
+impl InsertableMarkerAnalysis {
+    /// Method records the analysis, replacing the previous one of the mediafile.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<(), DatabaseError> {
+        let now = now();
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO marker_analysis (mediafile_id, file_mtime, error, analyzed)
+                VALUES ($1, $2, $3, $4)",
+            self.mediafile_id,
+            self.file_mtime,
+            self.error,
+            now
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -262,6 +262,81 @@ impl Media {
                 .rows_affected() as usize,
         )
     }
+
+    /// Method returns the certification level of a media object, see
+    /// [`certification_level`](certification_level). Episodes share the level of their show.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of a media object
+    pub async fn get_certification_level(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+    ) -> Result<Option<i64>, DatabaseError> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT certification_level as "certification_level?: i64" FROM _tblmedia WHERE id = ?"#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the movies and tv shows without a certification whose certification wasnt
+    /// looked up yet, see [`Media::set_certification`](Media::set_certification).
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `limit` - max number of media to return.
+    pub async fn get_unchecked_certification(
+        conn: &mut crate::Transaction<'_>,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+                Media,
+                r#"SELECT id, library_id, name, description, rating, year, added, poster_path, backdrop_path, media_type as "media_type: _" FROM media
+                WHERE media_type IN ("movie", "tv") AND certification IS NULL AND NOT certification_checked
+                ORDER BY id ASC
+                LIMIT ?"#,
+                limit
+            )
+            .fetch_all(&mut *conn)
+            .await?)
+    }
+
+    /// Method stores the certification looked up for the media `id`, the episodes of a tv show get
+    /// the certification of the show. Media we dont know a certification for keeps the one it has,
+    /// either way it isnt returned by
+    /// [`Media::get_unchecked_certification`](Media::get_unchecked_certification) anymore.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of a movie or tv show.
+    /// * `certification` - the certification, if the provider knows it.
+    pub async fn set_certification(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        certification: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let level = certification.and_then(certification_level);
+
+        sqlx::query!(
+            r#"UPDATE _tblmedia
+            SET certification = COALESCE(?, certification),
+                certification_level = COALESCE(?, certification_level),
+                certification_checked = 1
+            WHERE id = ? OR id IN (SELECT episode.id FROM episode
+                INNER JOIN _tblseason ON _tblseason.id = episode.seasonid
+                WHERE _tblseason.tvshowid = ?)"#,
+            certification,
+            level,
+            id,
+            id
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
 }
 
 impl Into<super::tv::TVShow> for Media {
@@ -279,6 +354,8 @@ pub struct InsertableMedia {
     pub name: String,
     pub description: Option<String>,
     pub rating: Option<i64>,
+    /// Certification (age rating) of this media, ie `PG-13`.
+    pub certification: Option<String>,
     pub year: Option<i64>,
     pub added: String,
     pub poster: Option<i64>,
@@ -297,14 +374,18 @@ impl InsertableMedia {
             .fetch_optional(&mut *conn)
             .await?
         {
+            self.update_certification(&mut *conn, record.id).await?;
             return Ok(record.id);
         }
 
+        let certification_level = self.certification.as_deref().and_then(certification_level);
         let id = sqlx::query!(
-            r#"INSERT INTO _tblmedia (library_id, name, description, rating, year, added, poster, backdrop, media_type)
-            VALUES ($1, $2, $3, $4, $5, $6,$7, $8, $9)
+            r#"INSERT INTO _tblmedia (library_id, name, description, rating, year, added, poster, backdrop, media_type, certification, certification_level)
+            VALUES ($1, $2, $3, $4, $5, $6,$7, $8, $9, $10, $11)
             ON CONFLICT DO UPDATE
-            SET name = $2
+            SET name = $2,
+            certification = COALESCE($10, certification),
+            certification_level = COALESCE($11, certification_level)
             RETURNING _tblmedia.id as "id!: i64"
             "#,
             self.library_id,
@@ -315,7 +396,9 @@ impl InsertableMedia {
             self.added,
             self.poster,
             self.backdrop,
-            self.media_type
+            self.media_type,
+            self.certification,
+            certification_level
         ).fetch_one(&mut *conn).await?.id;
 
         Ok(id)
@@ -335,12 +418,14 @@ impl InsertableMedia {
             .fetch_optional(&mut *conn)
             .await?
         {
+            self.update_certification(&mut *conn, record.id).await?;
             return Ok(record.id);
         }
 
+        let certification_level = self.certification.as_deref().and_then(certification_level);
         sqlx::query!(
-            r#"INSERT INTO _tblmedia (id, library_id, name, description, rating, year, added, poster, backdrop, media_type)
-            VALUES ($1, $2, $3, $4, $5, $6,$7, $8, $9, $10)
+            r#"INSERT INTO _tblmedia (id, library_id, name, description, rating, year, added, poster, backdrop, media_type, certification, certification_level)
+            VALUES ($1, $2, $3, $4, $5, $6,$7, $8, $9, $10, $11, $12)
             "#,
             id,
             self.library_id,
@@ -351,12 +436,35 @@ impl InsertableMedia {
             self.added,
             self.poster,
             self.backdrop,
-            self.media_type
+            self.media_type,
+            self.certification,
+            certification_level
         ).execute(&mut *conn).await?;
 
         Ok(id)
     }
 
+    /// Method fills in or corrects the certification of the existing media `id` with the one in
+    /// `self`. Media we dont know a certification for is left alone.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the media object `self` was matched to.
+    async fn update_certification(
+        &self,
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+    ) -> Result<(), DatabaseError> {
+        UpdateMedia {
+            certification: self.certification.clone(),
+            ..Default::default()
+        }
+        .update(&mut *conn, id)
+        .await?;
+
+        Ok(())
+    }
+
     /// Method blindly inserts `self` into the database without checking whether a similar entry exists.
     /// This is especially useful for tv shows as they usually have similar metadata with key differences
     /// which are not indexed in the database.
@@ -364,9 +472,10 @@ impl InsertableMedia {
         &self,
         conn: &mut crate::Transaction<'_>,
     ) -> Result<i64, DatabaseError> {
+        let certification_level = self.certification.as_deref().and_then(certification_level);
         Ok(sqlx::query!(
-            r#"INSERT INTO _tblmedia (library_id, name, description, rating, year, added, poster, backdrop, media_type)
-            VALUES ($1, $2, $3, $4, $5, $6,$7, $8, $9)"#,
+            r#"INSERT INTO _tblmedia (library_id, name, description, rating, year, added, poster, backdrop, media_type, certification, certification_level)
+            VALUES ($1, $2, $3, $4, $5, $6,$7, $8, $9, $10, $11)"#,
             self.library_id,
             self.name,
             self.description,
@@ -375,7 +484,9 @@ impl InsertableMedia {
             self.added,
             self.poster,
             self.backdrop,
-            self.media_type
+            self.media_type,
+            self.certification,
+            certification_level
         ).execute(&mut *conn).await?.last_insert_rowid())
     }
 }
@@ -388,6 +499,7 @@ pub struct UpdateMedia {
     pub name: Option<String>,
     pub description: Option<String>,
     pub rating: Option<i64>,
+    pub certification: Option<String>,
     pub year: Option<i64>,
     pub added: Option<String>,
     pub poster: Option<i64>,
@@ -418,6 +530,59 @@ impl UpdateMedia {
             "UPDATE _tblmedia SET media_type = ? WHERE id = ?" => (self.media_type, id)
         );
 
+        if let Some(certification) = self.certification.as_deref() {
+            let level = certification_level(certification);
+            sqlx::query!(
+                "UPDATE _tblmedia SET certification = ?, certification_level = ? WHERE id = ?",
+                certification,
+                level,
+                id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
         Ok(1)
     }
 }
+
+/// Function maps a certification such as `PG-13` or `TV-MA` to the minimum age the media is meant
+/// for, so that certifications from different rating systems can be compared. Certifications
+/// which carry the age in them, ie `FSK 12`, map to that age. Returns `None` for certifications
+/// we dont know.
+///
+/// # Example
+/// ```
+/// use database::media::certification_level;
+///
+/// assert_eq!(certification_level("PG-13"), Some(13));
+/// assert_eq!(certification_level("TV-MA"), Some(17));
+/// assert_eq!(certification_level("FSK 16"), Some(16));
+/// assert_eq!(certification_level("NR"), None);
+/// ```
+pub fn certification_level(certification: &str) -> Option<i64> {
+    let certification = certification.trim().to_uppercase();
+    // kodi nfo files store certifications as `Rated PG-13` or `US:PG-13`.
+    let certification = certification.rsplit(':').next()?.trim();
+    let certification = certification.trim_start_matches("RATED ").trim();
+
+    let level = match certification {
+        "G" | "TV-Y" | "TV-G" | "U" => 0,
+        "TV-Y7" => 7,
+        "PG" | "TV-PG" => 10,
+        "PG-13" => 13,
+        "TV-14" => 14,
+        "R" | "TV-MA" => 17,
+        "NC-17" => 18,
+        _ => {
+            let digits = certification
+                .chars()
+                .filter(char::is_ascii_digit)
+                .collect::<String>();
+
+            return digits.parse().ok().filter(|x| *x <= 21);
+        }
+    };
+
+    Some(level)
+}
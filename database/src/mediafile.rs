@@ -54,6 +54,19 @@ pub struct MediaFile {
     pub profile: Option<String>,
     /// Primary audio language
     pub audio_language: Option<String>,
+
+    /// Size of the file in bytes when it was last probed.
+    pub file_size: Option<i64>,
+    /// Modification time of the file as a unix timestamp when it was last probed. Together with
+    /// `file_size` this lets the scanner tell whether a file has changed since.
+    pub file_mtime: Option<i64>,
+
+    /// Poster extracted from the file when it couldnt be matched, relative to the metadata
+    /// directory.
+    pub poster_file: Option<String>,
+    /// Backdrop extracted from the file when it couldnt be matched, relative to the metadata
+    /// directory.
+    pub backdrop_file: Option<String>,
 }
 
 impl MediaFile {
@@ -248,6 +261,9 @@ pub struct InsertableMediaFile {
     pub season: Option<i64>,
     /*** ***/
     pub corrupt: Option<bool>,
+
+    pub file_size: Option<i64>,
+    pub file_mtime: Option<i64>,
 }
 
 impl InsertableMediaFile {
@@ -259,8 +275,9 @@ impl InsertableMediaFile {
         let id = sqlx::query!(
             r#"
             INSERT INTO mediafile (media_id, library_id, target_file, raw_name, raw_year, quality,
-            codec, container, audio, original_resolution, duration, episode, season, corrupt, channels, profile, audio_language)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+            codec, container, audio, original_resolution, duration, episode, season, corrupt, channels, profile, audio_language,
+            file_size, file_mtime)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19)
         "#,
             self.media_id,
             self.library_id,
@@ -278,7 +295,9 @@ impl InsertableMediaFile {
             self.corrupt,
             self.channels,
             self.profile,
-            self.audio_language
+            self.audio_language,
+            self.file_size,
+            self.file_mtime
         )
         .execute(&mut *conn)
         .await?
@@ -313,6 +332,12 @@ pub struct UpdateMediaFile {
     pub season: Option<i64>,
     /*** ***/
     pub corrupt: Option<bool>,
+
+    pub file_size: Option<i64>,
+    pub file_mtime: Option<i64>,
+
+    pub poster_file: Option<String>,
+    pub backdrop_file: Option<String>,
 }
 
 impl UpdateMediaFile {
@@ -343,7 +368,11 @@ impl UpdateMediaFile {
             "UPDATE mediafile SET corrupt = ? WHERE id = ?" => (self.corrupt, id),
             "UPDATE mediafile SET channels = ? WHERE id = ?" => (self.channels, id),
             "UPDATE mediafile SET profile = ? WHERE id = ?" => (self.profile, id),
-            "UPDATE mediafile SET audio_language = ? WHERE id = ?" => (self.audio_language, id)
+            "UPDATE mediafile SET audio_language = ? WHERE id = ?" => (self.audio_language, id),
+            "UPDATE mediafile SET file_size = ? WHERE id = ?" => (self.file_size, id),
+            "UPDATE mediafile SET file_mtime = ? WHERE id = ?" => (self.file_mtime, id),
+            "UPDATE mediafile SET poster_file = ? WHERE id = ?" => (self.poster_file, id),
+            "UPDATE mediafile SET backdrop_file = ? WHERE id = ?" => (self.backdrop_file, id)
         );
 
         Ok(1)
@@ -1,10 +1,14 @@
 use crate::library::MediaType;
 use crate::media::Media;
+use crate::utils::now;
 use crate::DatabaseError as DieselError;
 
 use serde::Serialize;
 use std::time::SystemTime;
 
+/// Fraction of a media that has to be played for it to count as watched.
+pub const WATCHED_THRESHOLD: f64 = 0.90;
+
 #[derive(Debug, Clone, Serialize, Default)]
 pub struct Progress {
     pub id: i64,
@@ -12,6 +16,12 @@ pub struct Progress {
     pub media_id: i64,
     pub user_id: String,
     pub populated: i64,
+    /// Whether the user has watched this media to the end at least once.
+    pub watched: bool,
+    /// Number of times the user has watched this media.
+    pub play_count: i64,
+    /// Timestamp of the last time the user finished this media.
+    pub last_watched: Option<i64>,
 }
 
 impl Progress {
@@ -27,8 +37,10 @@ impl Progress {
             .as_secs() as i64;
 
         Ok(sqlx::query!(
-            "INSERT OR REPLACE INTO progress (delta, media_id, user_id, populated)
-            VALUES ($1, $2, $3, $4)",
+            "INSERT INTO progress (delta, media_id, user_id, populated)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, media_id) DO UPDATE
+            SET delta = excluded.delta, populated = excluded.populated",
             delta,
             mid,
             uid,
@@ -46,9 +58,11 @@ impl Progress {
     ) -> Result<Self, DieselError> {
         Ok(sqlx::query_as!(
             Progress,
-            "SELECT progress.* FROM progress
+            r#"SELECT id, delta, media_id, user_id, populated, watched as "watched: bool",
+                play_count, last_watched
+            FROM progress
             WHERE user_id = ?
-            AND media_id = ?",
+            AND media_id = ?"#,
             uid,
             mid
         )
@@ -61,6 +75,104 @@ impl Progress {
         }))
     }
 
+    /// Method records that a user has finished watching a media, this bumps the play count even
+    /// if the media was already watched before.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `uid` - the user who watched the media.
+    /// * `mid` - id of the media.
+    pub async fn mark_watched(
+        conn: &mut crate::Transaction<'_>,
+        uid: &str,
+        mid: i64,
+    ) -> Result<usize, DieselError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            "INSERT INTO progress (delta, media_id, user_id, populated, watched, play_count, last_watched)
+            VALUES (0, $1, $2, 0, 1, 1, $3)
+            ON CONFLICT (user_id, media_id) DO UPDATE
+            SET watched = 1, play_count = play_count + 1, last_watched = excluded.last_watched",
+            mid,
+            uid,
+            now
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method marks a media as watched or unwatched by hand. Marking a media that is already
+    /// watched as watched is a no-op, marking a media as unwatched resets its progress and play
+    /// count. Returns the number of rows affected.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `uid` - the user whose watched state we want to change.
+    /// * `mid` - id of the media.
+    /// * `watched` - whether the media should be marked as watched.
+    pub async fn set_watched(
+        conn: &mut crate::Transaction<'_>,
+        uid: &str,
+        mid: i64,
+        watched: bool,
+    ) -> Result<usize, DieselError> {
+        if !watched {
+            return Ok(sqlx::query!(
+                "UPDATE progress
+                SET watched = 0, play_count = 0, last_watched = NULL, delta = 0, populated = 0
+                WHERE user_id = ? AND media_id = ?",
+                uid,
+                mid
+            )
+            .execute(&mut *conn)
+            .await?
+            .rows_affected() as usize);
+        }
+
+        let now = now();
+
+        Ok(sqlx::query!(
+            "INSERT INTO progress (delta, media_id, user_id, populated, watched, play_count, last_watched)
+            VALUES (0, $1, $2, 0, 1, 1, $3)
+            ON CONFLICT (user_id, media_id) DO UPDATE
+            SET watched = 1, play_count = play_count + 1, last_watched = excluded.last_watched
+            WHERE NOT progress.watched",
+            mid,
+            uid,
+            now
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method returns the number of episodes of a season the user hasn't watched yet.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `uid` - the user whose watched state we want.
+    /// * `season_id` - id of the season.
+    pub async fn count_unwatched_of_season(
+        conn: &mut crate::Transaction<'_>,
+        uid: &str,
+        season_id: i64,
+    ) -> Result<i64, DieselError> {
+        Ok(sqlx::query_scalar!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM episode
+            WHERE episode.seasonid = ?
+            AND NOT EXISTS (
+                SELECT 1 FROM progress
+                WHERE progress.media_id = episode.id AND progress.user_id = ? AND progress.watched
+            )"#,
+            season_id,
+            uid
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
     pub async fn get_total_time_spent_watching(
         conn: &mut crate::Transaction<'_>,
         uid: String,
@@ -0,0 +1,165 @@
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Serialize;
+
+/// A external service the playback of a user gets reported to.
+#[derive(Clone, Debug, Serialize)]
+pub struct Scrobbler {
+    pub id: i64,
+    pub username: String,
+    /// Type of the service, ie `webhook` or `trakt`.
+    pub kind: String,
+    /// Url events are sent to.
+    pub url: String,
+    pub client_id: Option<String>,
+    /// Token used to authenticate with the service, never handed back out to clients.
+    #[serde(skip_serializing)]
+    pub token: Option<String>,
+    pub enabled: bool,
+    /// Timestamp of when the scrobbler was added.
+    pub created: i64,
+}
+
+impl Scrobbler {
+    /// Method returns all the scrobblers of a user.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose scrobblers we want.
+    pub async fn get_all_of_user(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", username, kind, url, client_id, token,
+                enabled as "enabled: bool", created
+                FROM scrobblers
+                WHERE username = ?
+                ORDER BY id ASC"#,
+            username
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the scrobblers of a user which are enabled.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose scrobblers we want.
+    pub async fn get_enabled_of_user(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", username, kind, url, client_id, token,
+                enabled as "enabled: bool", created
+                FROM scrobblers
+                WHERE username = ? AND enabled
+                ORDER BY id ASC"#,
+            username
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns a scrobbler by its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the scrobbler.
+    pub async fn get(conn: &mut crate::Transaction<'_>, id: i64) -> Result<Self, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", username, kind, url, client_id, token,
+                enabled as "enabled: bool", created
+                FROM scrobblers
+                WHERE id = ?"#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// Method enables or disables a scrobbler of a user, returns the number of rows affected.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user who owns the scrobbler.
+    /// * `id` - id of the scrobbler.
+    /// * `enabled` - whether events should be sent to the scrobbler.
+    pub async fn set_enabled(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        id: i64,
+        enabled: bool,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "UPDATE scrobblers SET enabled = ? WHERE username = ? AND id = ?",
+            enabled,
+            username,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method deletes a scrobbler of a user, returns the number of rows deleted.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user who owns the scrobbler.
+    /// * `id` - id of the scrobbler.
+    pub async fn delete(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        id: i64,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "DELETE FROM scrobblers WHERE username = ? AND id = ?",
+            username,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+}
+
+/// Struct used to add a new scrobbler.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableScrobbler {
+    pub username: String,
+    pub kind: String,
+    pub url: String,
+    pub client_id: Option<String>,
+    pub token: Option<String>,
+}
+
+impl InsertableScrobbler {
+    /// Method inserts a new scrobbler and returns its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            r#"INSERT INTO scrobblers (username, kind, url, client_id, token, created)
+                VALUES ($1, $2, $3, $4, $5, $6)"#,
+            self.username,
+            self.kind,
+            self.url,
+            self.client_id,
+            self.token,
+            now
+        )
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid())
+    }
+}
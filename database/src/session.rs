@@ -0,0 +1,229 @@
+use crate::utils::hash_token;
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Serialize;
+
+/// A device a user is logged in on. Sessions are created on login and live until their refresh
+/// token expires or they are revoked.
+#[derive(Clone, Debug, Serialize)]
+pub struct Session {
+    /// Unique id of the session, access tokens carry this id.
+    pub id: String,
+    pub username: String,
+    /// User agent of the device which created this session.
+    pub device: Option<String>,
+    /// Timestamp of when the session was created.
+    pub created: i64,
+    /// Timestamp of the last time the refresh token was used.
+    pub last_used: i64,
+    /// Timestamp after which the refresh token cannot be used anymore.
+    pub expires: i64,
+}
+
+impl Session {
+    /// Method returns all sessions which havent expired yet.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn get_all(conn: &mut crate::Transaction<'_>) -> Result<Vec<Self>, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query_as!(
+            Session,
+            r#"SELECT id as "id!", username, device, created, last_used, expires FROM sessions
+                WHERE expires > ?"#,
+            now
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns all sessions of a user which havent expired yet, most recently used first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose sessions we want.
+    pub async fn get_all_of_user(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query_as!(
+            Session,
+            r#"SELECT id as "id!", username, device, created, last_used, expires FROM sessions
+                WHERE username = ? AND expires > ?
+                ORDER BY last_used DESC"#,
+            username,
+            now
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns whether the session `id` exists and hasnt expired yet.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the session.
+    pub async fn is_active(
+        conn: &mut crate::Transaction<'_>,
+        id: &str,
+    ) -> Result<bool, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            "SELECT id FROM sessions WHERE id = ? AND expires > ?",
+            id,
+            now
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_some())
+    }
+
+    /// Method returns the session a refresh token belongs to, if the token hasnt expired.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `refresh_token` - refresh token handed out to the client.
+    pub async fn get_by_refresh_token(
+        conn: &mut crate::Transaction<'_>,
+        refresh_token: &str,
+    ) -> Result<Self, DatabaseError> {
+        let hash = hash_token(refresh_token);
+        let now = now();
+
+        Ok(sqlx::query_as!(
+            Session,
+            r#"SELECT id as "id!", username, device, created, last_used, expires FROM sessions
+                WHERE refresh_token = ? AND expires > ?"#,
+            hash,
+            now
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// Method replaces the refresh token of a session and extends its lifetime. Refresh tokens are
+    /// rotated every time they are used, so a stolen token stops working once the owner uses it.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the session.
+    /// * `refresh_token` - the new refresh token.
+    /// * `ttl` - number of seconds the new refresh token is valid for.
+    pub async fn rotate(
+        conn: &mut crate::Transaction<'_>,
+        id: &str,
+        refresh_token: &str,
+        ttl: i64,
+    ) -> Result<usize, DatabaseError> {
+        let hash = hash_token(refresh_token);
+        let now = now();
+        let expires = now + ttl;
+
+        Ok(sqlx::query!(
+            "UPDATE sessions SET refresh_token = ?, last_used = ?, expires = ? WHERE id = ?",
+            hash,
+            now,
+            expires,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method deletes a session of a user, returns the number of rows deleted.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user who owns the session.
+    /// * `id` - id of the session.
+    pub async fn delete(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        id: &str,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "DELETE FROM sessions WHERE username = ? AND id = ?",
+            username,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method deletes all the sessions of a user and returns their ids.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose sessions we want to delete.
+    pub async fn delete_all_of_user(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+    ) -> Result<Vec<String>, DatabaseError> {
+        Ok(sqlx::query_scalar!(
+            r#"DELETE FROM sessions WHERE username = ? RETURNING id AS "id!: String""#,
+            username
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method deletes all the sessions whose refresh token has expired.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn delete_expired(conn: &mut crate::Transaction<'_>) -> Result<usize, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!("DELETE FROM sessions WHERE expires <= ?", now)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected() as usize)
+    }
+}
+
+/// Struct used to create a new session when a user logs in.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableSession {
+    pub username: String,
+    /// Refresh token handed out to the client, only its hash is stored.
+    pub refresh_token: String,
+    pub device: Option<String>,
+    /// Number of seconds the refresh token is valid for.
+    pub ttl: i64,
+}
+
+impl InsertableSession {
+    /// Method inserts a new session and returns its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<String, DatabaseError> {
+        let id = uuid::Uuid::new_v4().to_hyphenated().to_string();
+        let hash = hash_token(&self.refresh_token);
+        let now = now();
+        let expires = now + self.ttl;
+
+        sqlx::query!(
+            r#"INSERT INTO sessions (id, username, refresh_token, device, created, last_used, expires)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            id,
+            self.username,
+            hash,
+            self.device,
+            now,
+            now,
+            expires
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(id)
+    }
+}
@@ -0,0 +1,40 @@
+use crate::api_key::ApiKey;
+use crate::api_key::InsertableApiKey;
+use crate::get_conn_memory;
+use crate::write_tx;
+
+use super::user_tests::insert_user;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_api_keys() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let username = insert_user(&mut tx).await;
+
+    let id = InsertableApiKey {
+        username: username.clone(),
+        name: "backup".into(),
+        key: "dim_secret".into(),
+        scopes: vec!["stream".into(), "manage_libraries".into()],
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let key = ApiKey::get_by_key(&mut tx, "dim_secret").await.unwrap();
+    assert_eq!(key.id, id);
+    assert_eq!(key.username, username);
+    assert_eq!(key.scopes, vec!["stream", "manage_libraries"]);
+    assert!(key.last_used.is_none());
+    assert!(ApiKey::get_by_key(&mut tx, "dim_wrong").await.is_err());
+
+    ApiKey::touch(&mut tx, id).await.unwrap();
+    let keys = ApiKey::get_all_of_user(&mut tx, &username).await.unwrap();
+    assert_eq!(keys.len(), 1);
+    assert!(keys[0].last_used.is_some());
+
+    // users can only delete their own keys.
+    assert_eq!(ApiKey::delete(&mut tx, "other", id).await.unwrap(), 0);
+    assert_eq!(ApiKey::delete(&mut tx, &username, id).await.unwrap(), 1);
+    assert!(ApiKey::get_by_key(&mut tx, "dim_secret").await.is_err());
+}
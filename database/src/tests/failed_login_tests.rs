@@ -0,0 +1,40 @@
+use crate::failed_login::FailedLogin;
+use crate::failed_login::InsertableFailedLogin;
+use crate::get_conn_memory;
+use crate::write_tx;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_failed_logins() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    assert!(FailedLogin::get_recent(&mut tx, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    for reason in &["unknown_user", "wrong_password", "locked"] {
+        InsertableFailedLogin {
+            username: "nobody".into(),
+            ip: Some("127.0.0.1".into()),
+            reason: reason.to_string(),
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+    }
+
+    let result = FailedLogin::get_recent(&mut tx, 2).await.unwrap();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[0].reason, "locked");
+    assert_eq!(result[1].reason, "wrong_password");
+
+    assert_eq!(
+        FailedLogin::delete_older_than(&mut tx, 60).await.unwrap(),
+        0
+    );
+    assert_eq!(
+        FailedLogin::delete_older_than(&mut tx, -60).await.unwrap(),
+        3
+    );
+}
@@ -0,0 +1,87 @@
+use crate::fetch_queue::FetchJob;
+use crate::fetch_queue::FetchStatus;
+use crate::fetch_queue::InsertableFetchJob;
+use crate::get_conn_memory;
+use crate::write_tx;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_fetch_queue() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    let poster = InsertableFetchJob {
+        url: "https://image.tmdb.org/t/p/original/poster.jpg".into(),
+        local_path: "poster.jpg".into(),
+        priority: 1,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let still = InsertableFetchJob {
+        url: "https://image.tmdb.org/t/p/original/still.jpg".into(),
+        local_path: "still.jpg".into(),
+        priority: 2,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    // queueing a url twice keeps a single download with the highest priority.
+    let dup = InsertableFetchJob {
+        url: "https://image.tmdb.org/t/p/original/poster.jpg".into(),
+        local_path: "poster.jpg".into(),
+        priority: 5,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+    assert_eq!(dup, poster);
+
+    let due = FetchJob::get_due(&mut tx, 10).await.unwrap();
+    assert_eq!(
+        due.iter().map(|x| x.id).collect::<Vec<_>>(),
+        vec![poster, still]
+    );
+    assert_eq!(due[0].priority, 5);
+
+    FetchJob::set_done(&mut tx, poster, "abcd").await.unwrap();
+    FetchJob::set_failed(&mut tx, still, "timed out", Some(i64::MAX))
+        .await
+        .unwrap();
+
+    assert!(FetchJob::get_due(&mut tx, 10).await.unwrap().is_empty());
+
+    let job = FetchJob::get_by_url(&mut tx, "https://image.tmdb.org/t/p/original/poster.jpg")
+        .await
+        .unwrap();
+    assert_eq!(job.status, FetchStatus::Done);
+    assert_eq!(job.checksum.as_deref(), Some("abcd"));
+    assert!(job.completed.is_some());
+
+    FetchJob::set_failed(&mut tx, still, "timed out", None)
+        .await
+        .unwrap();
+
+    let stats = FetchJob::stats(&mut tx).await.unwrap();
+    assert_eq!((stats.pending, stats.failed, stats.done), (0, 1, 1));
+
+    let unfinished = FetchJob::get_unfinished(&mut tx, 10).await.unwrap();
+    assert_eq!(unfinished.len(), 1);
+    assert_eq!(unfinished[0].attempts, 2);
+    assert_eq!(unfinished[0].error.as_deref(), Some("timed out"));
+
+    // failed downloads are attempted again once queued again.
+    InsertableFetchJob {
+        url: "https://image.tmdb.org/t/p/original/still.jpg".into(),
+        local_path: "still.jpg".into(),
+        priority: 2,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let due = FetchJob::get_due(&mut tx, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].attempts, 0);
+}
@@ -39,6 +39,7 @@ async fn test_get_by_media() {
         name: "TestMedia".into(),
         description: None,
         rating: Some(10),
+        certification: None,
         year: Some(2020),
         added: "Test".into(),
         poster: None,
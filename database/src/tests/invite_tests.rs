@@ -0,0 +1,98 @@
+use crate::get_conn_memory;
+use crate::invite::InsertableInvite;
+use crate::invite::Invite;
+use crate::user::hash;
+use crate::user::InsertableUser;
+use crate::user::Login;
+use crate::user::User;
+use crate::write_tx;
+
+use super::library_tests::create_test_library;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_invite_uses() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let library = create_test_library(&mut tx).await;
+
+    let id = InsertableInvite {
+        max_uses: 2,
+        note: Some("family".into()),
+        roles: vec!["user".into()],
+        libraries: Some(vec![library]),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let invite = Invite::get(&mut tx, &id).await.unwrap();
+    assert!(invite.is_valid());
+    assert_eq!(invite.note.as_deref(), Some("family"));
+    assert_eq!(invite.libraries, Some(vec![library]));
+
+    for username in &["first", "second"] {
+        let claimed_invite = Invite::claim(&mut tx, &id).await.unwrap();
+        InsertableUser {
+            username: username.to_string(),
+            password_hash: hash("test"),
+            roles: invite.roles.clone(),
+            prefs: Default::default(),
+            claimed_invite,
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+    }
+
+    let invite = Invite::get(&mut tx, &id).await.unwrap();
+    assert_eq!(invite.claimed_by.len(), 2);
+    assert_eq!(invite.uses, 2);
+    assert!(!invite.is_valid());
+
+    // deleting a user doesnt give the use back.
+    User::delete(&mut tx, "first".into()).await.unwrap();
+    let invite = Invite::get(&mut tx, &id).await.unwrap();
+    assert_eq!(invite.claimed_by, vec!["second".to_string()]);
+    assert!(!invite.is_valid());
+
+    // claims are not invites of their own.
+    let all = Invite::get_all(&mut tx).await.unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(
+        Login::get_all_invites(&mut tx).await.unwrap(),
+        vec![id.clone()]
+    );
+
+    // used invites are expired rather than deleted.
+    assert_eq!(Invite::delete(&mut tx, &id).await.unwrap(), 1);
+    assert!(Invite::get(&mut tx, &id).await.unwrap().is_expired());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_invite_expiry() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    let id = InsertableInvite {
+        expires: Some(0),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let invite = Invite::get(&mut tx, &id).await.unwrap();
+    assert!(invite.is_expired());
+    assert!(!Login {
+        invite_token: Some(id.clone()),
+        ..Default::default()
+    }
+    .invite_token_valid(&mut tx)
+    .await
+    .unwrap());
+
+    // unused invites are deleted.
+    assert_eq!(Invite::delete(&mut tx, &id).await.unwrap(), 1);
+    assert!(Invite::get(&mut tx, &id).await.is_err());
+}
@@ -11,6 +11,8 @@ pub async fn create_test_library(conn: &mut crate::Transaction<'_>) -> i64 {
         name: format!("test{}", _LIB.load(Ordering::Relaxed)),
         locations: vec![format!("/dev/null{}", _LIB.load(Ordering::Relaxed))],
         media_type: library::MediaType::Movie,
+        metadata_provider: Default::default(),
+        scan_interval: None,
     };
 
     _LIB.fetch_add(1, Ordering::SeqCst);
@@ -36,6 +38,51 @@ async fn test_get_one() {
     assert_eq!(result.media_type, library::MediaType::Movie);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_metadata_provider() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    let id = create_test_library(&mut tx).await;
+    let result = library::Library::get_one(&mut tx, id).await.unwrap();
+    assert_eq!(result.metadata_provider, library::ProviderKind::Tmdb);
+
+    let id = library::InsertableLibrary {
+        name: "offline".into(),
+        locations: vec![],
+        media_type: library::MediaType::Tv,
+        metadata_provider: library::ProviderKind::Mock,
+        scan_interval: None,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let result = library::Library::get_one(&mut tx, id).await.unwrap();
+    assert_eq!(result.metadata_provider, library::ProviderKind::Mock);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_mixed_library() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    let id = library::InsertableLibrary {
+        name: "everything".into(),
+        locations: vec!["/dev/null/mixed".into()],
+        media_type: library::MediaType::Mixed,
+        metadata_provider: Default::default(),
+        scan_interval: None,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let result = library::Library::get_one(&mut tx, id).await.unwrap();
+    assert_eq!(result.media_type, library::MediaType::Mixed);
+    assert_eq!(result.media_type.to_string(), "mixed");
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_get_all() {
     let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
@@ -60,3 +107,25 @@ async fn test_delete() {
     let rows = library::Library::delete(&mut tx, id).await.unwrap();
     assert_eq!(rows, 1);
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_scan_schedule() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    let id = create_test_library(&mut tx).await;
+    let result = library::Library::get_one(&mut tx, id).await.unwrap();
+    assert_eq!(result.scan_interval, None);
+    assert_eq!(result.last_scanned, None);
+
+    library::Library::set_scan_interval(&mut tx, id, Some(3600))
+        .await
+        .unwrap();
+    library::Library::set_last_scanned(&mut tx, id, 1638360000)
+        .await
+        .unwrap();
+
+    let result = library::Library::get_one(&mut tx, id).await.unwrap();
+    assert_eq!(result.scan_interval, Some(3600));
+    assert_eq!(result.last_scanned, Some(1638360000));
+}
@@ -0,0 +1,160 @@
+use crate::episode;
+use crate::get_conn_memory;
+use crate::marker::InsertableMarker;
+use crate::marker::InsertableMarkerAnalysis;
+use crate::marker::Marker;
+use crate::marker::MarkerKind;
+use crate::marker::MarkerTarget;
+use crate::media;
+use crate::mediafile::InsertableMediaFile;
+use crate::mediafile::UpdateMediaFile;
+use crate::season;
+use crate::tv;
+use crate::write_tx;
+
+use super::library_tests::create_test_library;
+use super::media_tests::insert_media;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_markers() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let library_id = create_test_library(&mut tx).await;
+    let tv = insert_media(&mut tx).await;
+    tv::TVShow::insert(&mut tx, tv).await.unwrap();
+
+    let season = season::InsertableSeason {
+        season_number: 1,
+        ..Default::default()
+    }
+    .insert(&mut tx, tv)
+    .await
+    .unwrap();
+
+    let mut files = vec![];
+
+    for i in 1..=2 {
+        let episode = episode::InsertableEpisode {
+            media: media::InsertableMedia {
+                library_id,
+                name: format!("TestEpisode{}", i),
+                ..Default::default()
+            },
+            seasonid: season,
+            episode: i,
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+
+        let file = InsertableMediaFile {
+            library_id,
+            media_id: Some(episode),
+            target_file: format!("/tv/s01e0{}.mkv", i),
+            raw_name: "Test".into(),
+            duration: Some(1400),
+            file_mtime: Some(1),
+            ..Default::default()
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+
+        files.push(file);
+    }
+
+    // files which arent matched to an episode have no siblings to compare against.
+    InsertableMediaFile {
+        library_id,
+        target_file: "/movies/a.mkv".into(),
+        raw_name: "Test".into(),
+        duration: Some(3600),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let ids = |x: Vec<MarkerTarget>| x.into_iter().map(|x| x.mediafile_id).collect::<Vec<_>>();
+
+    assert_eq!(
+        ids(MarkerTarget::get_outdated(&mut tx, 10).await.unwrap()),
+        files
+    );
+    assert_eq!(
+        ids(MarkerTarget::get_of_season(&mut tx, season).await.unwrap()),
+        files
+    );
+
+    InsertableMarker {
+        mediafile_id: files[0],
+        kind: MarkerKind::Credits,
+        start_time: 1320.0,
+        end_time: 1400.0,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    InsertableMarker {
+        mediafile_id: files[0],
+        kind: MarkerKind::Intro,
+        start_time: 30.5,
+        end_time: 90.0,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    for file in files.iter() {
+        InsertableMarkerAnalysis {
+            mediafile_id: *file,
+            file_mtime: Some(1),
+            error: None,
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+    }
+
+    assert!(MarkerTarget::get_outdated(&mut tx, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let markers = Marker::get_of_mediafile(&mut tx, files[0]).await.unwrap();
+    assert_eq!(markers.len(), 2);
+    assert_eq!(markers[0].kind, MarkerKind::Intro);
+    assert_eq!((markers[0].start_time, markers[0].end_time), (30.5, 90.0));
+    assert_eq!(markers[1].kind, MarkerKind::Credits);
+
+    assert!(Marker::get_of_mediafile(&mut tx, files[1])
+        .await
+        .unwrap()
+        .is_empty());
+
+    // files are analyzed again once they change.
+    UpdateMediaFile {
+        file_mtime: Some(2),
+        ..Default::default()
+    }
+    .update(&mut tx, files[1])
+    .await
+    .unwrap();
+
+    assert_eq!(
+        ids(MarkerTarget::get_outdated(&mut tx, 10).await.unwrap()),
+        vec![files[1]]
+    );
+
+    assert_eq!(
+        Marker::delete_of_mediafile(&mut tx, files[0])
+            .await
+            .unwrap(),
+        2
+    );
+    assert!(Marker::get_of_mediafile(&mut tx, files[0])
+        .await
+        .unwrap()
+        .is_empty());
+}
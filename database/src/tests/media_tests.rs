@@ -1,7 +1,10 @@
+use crate::episode;
 use crate::get_conn_memory;
 use crate::library;
 use crate::media;
 use crate::mediafile;
+use crate::season;
+use crate::tv;
 use crate::write_tx;
 
 use super::library_tests::create_test_library;
@@ -13,6 +16,7 @@ pub async fn insert_media(conn: &mut crate::Transaction<'_>) -> i64 {
         name: "TestMedia".into(),
         description: None,
         rating: Some(10),
+        certification: None,
         year: Some(2020),
         added: "Test".into(),
         poster: None,
@@ -30,6 +34,7 @@ pub async fn insert_many(conn: &mut crate::Transaction<'_>, n: usize) {
             name: format!("TestMedia{}", i),
             description: None,
             rating: Some(10),
+            certification: None,
             year: Some(2020),
             added: "Test".into(),
             poster: None,
@@ -145,6 +150,7 @@ async fn test_blind_insert() {
         name: "TestMedia".into(),
         description: None,
         rating: Some(10),
+        certification: None,
         year: Some(2020),
         added: "Test".into(),
         poster: None,
@@ -170,6 +176,7 @@ async fn test_update() {
         name: "TestMedia".into(),
         description: None,
         rating: Some(10),
+        certification: None,
         year: Some(2020),
         added: "Test".into(),
         poster: None,
@@ -191,3 +198,149 @@ async fn test_update() {
     assert_eq!(result.name, "TestMedia2".to_string());
     assert_eq!(result.rating, Some(5));
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_certification() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let _ = create_test_library(&mut tx).await;
+
+    let media = media::InsertableMedia {
+        library_id: 1,
+        name: "CertifiedMedia".into(),
+        certification: Some("PG-13".into()),
+        added: "Test".into(),
+        media_type: library::MediaType::Movie,
+        ..Default::default()
+    };
+
+    let media_id = media.insert(&mut tx).await.unwrap();
+    let result = media::Media::get_certification_level(&mut tx, media_id)
+        .await
+        .unwrap();
+    assert_eq!(result, Some(13));
+
+    let update = media::UpdateMedia {
+        certification: Some("TV-MA".into()),
+        ..Default::default()
+    };
+
+    let _ = update.update(&mut tx, media_id).await.unwrap();
+
+    let result = media::Media::get_certification_level(&mut tx, media_id)
+        .await
+        .unwrap();
+    assert_eq!(result, Some(17));
+
+    let media_id = insert_media(&mut tx).await;
+    let result = media::Media::get_certification_level(&mut tx, media_id)
+        .await
+        .unwrap();
+    assert_eq!(result, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_certification_rescan() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let _ = create_test_library(&mut tx).await;
+
+    let media_id = insert_media(&mut tx).await;
+
+    let media = media::InsertableMedia {
+        library_id: 1,
+        name: "TestMedia".into(),
+        certification: Some("R".into()),
+        added: "Test".into(),
+        media_type: library::MediaType::Movie,
+        ..Default::default()
+    };
+
+    let result = media.insert(&mut tx).await.unwrap();
+    assert_eq!(result, media_id);
+
+    let result = media::Media::get_certification_level(&mut tx, media_id)
+        .await
+        .unwrap();
+    assert_eq!(result, Some(17));
+
+    // a rescan which doesnt know the certification keeps the one we have.
+    let _ = insert_media(&mut tx).await;
+    let result = media::Media::get_certification_level(&mut tx, media_id)
+        .await
+        .unwrap();
+    assert_eq!(result, Some(17));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_certification_backfill() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let library_id = create_test_library(&mut tx).await;
+
+    let movie = insert_media(&mut tx).await;
+    let show = media::InsertableMedia {
+        library_id,
+        name: "TestShow".into(),
+        added: "Test".into(),
+        media_type: library::MediaType::Tv,
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+    tv::TVShow::insert(&mut tx, show).await.unwrap();
+
+    let season = season::InsertableSeason {
+        season_number: 1,
+        ..Default::default()
+    }
+    .insert(&mut tx, show)
+    .await
+    .unwrap();
+
+    let episode = episode::InsertableEpisode {
+        media: media::InsertableMedia {
+            library_id,
+            name: "TestEpisode".into(),
+            media_type: library::MediaType::Episode,
+            ..Default::default()
+        },
+        seasonid: season,
+        episode: 1,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    // episodes are looked up through their show.
+    let result = media::Media::get_unchecked_certification(&mut tx, 10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|x| x.id)
+        .collect::<Vec<_>>();
+    assert_eq!(result, vec![movie, show]);
+
+    media::Media::set_certification(&mut tx, show, Some("TV-MA"))
+        .await
+        .unwrap();
+    let result = media::Media::get_certification_level(&mut tx, episode)
+        .await
+        .unwrap();
+    assert_eq!(result, Some(17));
+
+    // media the provider has no certification for isnt looked up again.
+    media::Media::set_certification(&mut tx, movie, None)
+        .await
+        .unwrap();
+    let result = media::Media::get_certification_level(&mut tx, movie)
+        .await
+        .unwrap();
+    assert_eq!(result, None);
+
+    let result = media::Media::get_unchecked_certification(&mut tx, 10)
+        .await
+        .unwrap();
+    assert!(result.is_empty());
+}
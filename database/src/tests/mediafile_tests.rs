@@ -147,6 +147,7 @@ async fn test_update() {
     let update = mediafile::UpdateMediaFile {
         raw_name: Some("test2".into()),
         duration: Some(3),
+        poster_file: Some("local-1-poster.jpg".into()),
         ..Default::default()
     };
 
@@ -155,6 +156,8 @@ async fn test_update() {
     let mfile = mediafile::MediaFile::get_one(&mut tx, id).await.unwrap();
     assert_eq!(mfile.raw_name, "test2".to_string());
     assert_eq!(mfile.duration, Some(3));
+    assert_eq!(mfile.poster_file, Some("local-1-poster.jpg".into()));
+    assert_eq!(mfile.backdrop_file, None);
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -1,10 +1,21 @@
+pub mod api_key_tests;
 pub mod episode_tests;
+pub mod failed_login_tests;
+pub mod fetch_queue_tests;
 pub mod genre_tests;
+pub mod invite_tests;
 pub mod library_tests;
+pub mod marker_tests;
 pub mod media_tests;
 pub mod mediafile_tests;
 pub mod movie_tests;
+pub mod music_tests;
 pub mod progress_tests;
+pub mod scrobbler_tests;
 pub mod season_tests;
+pub mod session_tests;
+pub mod trickplay_tests;
 pub mod tv_tests;
 pub mod user_tests;
+pub mod watch_history_tests;
+pub mod webhook_tests;
@@ -0,0 +1,200 @@
+use crate::album;
+use crate::artist;
+use crate::get_conn_memory;
+use crate::library;
+use crate::media;
+use crate::mediafile;
+use crate::track;
+use crate::write_tx;
+
+use super::library_tests::create_test_library;
+
+async fn insert_album(conn: &mut crate::Transaction<'_>, name: &str, artist_id: i64) -> i64 {
+    let media = media::InsertableMedia {
+        library_id: 1,
+        name: name.into(),
+        year: Some(2003),
+        added: "Test".into(),
+        media_type: library::MediaType::Music,
+        ..Default::default()
+    };
+
+    let id = media.insert_blind(&mut *conn).await.unwrap();
+    album::Album::insert(&mut *conn, id, Some(artist_id))
+        .await
+        .unwrap();
+
+    id
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_artist_insert() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let _lib = create_test_library(&mut tx).await;
+
+    let artist = artist::InsertableArtist {
+        library_id: 1,
+        name: "Daft Punk".into(),
+    };
+
+    let id = artist.insert(&mut tx).await.unwrap();
+    assert_eq!(artist.insert(&mut tx).await.unwrap(), id);
+
+    let result = artist::Artist::get_all(&mut tx, 1, i64::MAX).await.unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].name, "Daft Punk");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_album_by_artist() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let _lib = create_test_library(&mut tx).await;
+
+    let a = artist::InsertableArtist {
+        library_id: 1,
+        name: "A".into(),
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let b = artist::InsertableArtist {
+        library_id: 1,
+        name: "B".into(),
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    // albums with the same name by different artists must not collide.
+    let album_a = insert_album(&mut tx, "Greatest Hits", a).await;
+    let album_b = insert_album(&mut tx, "Greatest Hits", b).await;
+    assert_ne!(album_a, album_b);
+
+    let result = album::Album::get_by_name(&mut tx, 1, "Greatest Hits", Some(b))
+        .await
+        .unwrap();
+    assert_eq!(result, Some(album_b));
+
+    let result = album::Album::get_by_name(&mut tx, 1, "Greatest Hits", None)
+        .await
+        .unwrap();
+    assert_eq!(result, None);
+
+    let result = album::Album::get_all_of_artist(&mut tx, a, i64::MAX)
+        .await
+        .unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].id, album_a);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_track_insert() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let _lib = create_test_library(&mut tx).await;
+
+    let artist = artist::InsertableArtist {
+        library_id: 1,
+        name: "A".into(),
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let album = insert_album(&mut tx, "Album", artist).await;
+
+    for (i, number) in [2, 1].iter().enumerate() {
+        let mediafile = mediafile::InsertableMediaFile {
+            library_id: 1,
+            media_id: Some(album),
+            target_file: format!("/dev/null/{}.flac", i),
+            raw_name: "Test".into(),
+            duration: Some(120),
+            ..Default::default()
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+
+        track::InsertableTrack {
+            album_id: album,
+            mediafile_id: mediafile,
+            artist_id: Some(artist),
+            name: format!("Track {}", number),
+            track_number: Some(*number),
+            disc_number: Some(1),
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+    }
+
+    let tracks = track::Track::get_all_of_album(&mut tx, album).await.unwrap();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(tracks[0].track_number, Some(1));
+    assert_eq!(tracks[0].duration, Some(120));
+
+    // re-inserting a track for the same mediafile updates it.
+    let id = track::InsertableTrack {
+        album_id: album,
+        mediafile_id: tracks[0].mediafile_id,
+        name: "Renamed".into(),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    assert_eq!(id, tracks[0].id);
+    assert_eq!(track::Track::get_one(&mut tx, id).await.unwrap().name, "Renamed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_album_certification() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let _lib = create_test_library(&mut tx).await;
+
+    let artist = artist::InsertableArtist {
+        library_id: 1,
+        name: "A".into(),
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let album = media::InsertableMedia {
+        library_id: 1,
+        name: "Explicit".into(),
+        certification: Some("R".into()),
+        added: "Test".into(),
+        media_type: library::MediaType::Music,
+        ..Default::default()
+    }
+    .insert_blind(&mut tx)
+    .await
+    .unwrap();
+    album::Album::insert(&mut tx, album, Some(artist))
+        .await
+        .unwrap();
+
+    let result = album::Album::get_all_of_artist(&mut tx, artist, 17)
+        .await
+        .unwrap();
+    assert_eq!(result.len(), 1);
+
+    let result = album::Album::get_all_of_artist(&mut tx, artist, 13)
+        .await
+        .unwrap();
+    assert!(result.is_empty());
+
+    let result = artist::Artist::get_all(&mut tx, 1, 13).await.unwrap();
+    assert!(result.is_empty());
+
+    let _ = insert_album(&mut tx, "Clean", artist).await;
+    let result = artist::Artist::get_all(&mut tx, 1, 13).await.unwrap();
+    assert_eq!(result.len(), 1);
+}
@@ -191,3 +191,123 @@ async fn test_get_continue_watching() {
     assert_eq!(result.len(), 2);
     assert_eq!(result[0], 2);
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_watched_state() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let _library = create_test_library(&mut tx).await;
+    let user = insert_user(&mut tx).await;
+    let media = insert_media(&mut tx).await;
+
+    progress::Progress::set(&mut tx, 100, user.clone(), media)
+        .await
+        .unwrap();
+    progress::Progress::mark_watched(&mut tx, &user, media)
+        .await
+        .unwrap();
+    progress::Progress::mark_watched(&mut tx, &user, media)
+        .await
+        .unwrap();
+
+    // setting progress again keeps the watched state around.
+    progress::Progress::set(&mut tx, 50, user.clone(), media)
+        .await
+        .unwrap();
+
+    let result = progress::Progress::get_for_media_user(&mut tx, user.clone(), media)
+        .await
+        .unwrap();
+    assert_eq!(result.delta, 50);
+    assert!(result.watched);
+    assert_eq!(result.play_count, 2);
+    assert!(result.last_watched.is_some());
+
+    // marking a watched media as watched by hand doesn't count as another play.
+    assert_eq!(
+        progress::Progress::set_watched(&mut tx, &user, media, true)
+            .await
+            .unwrap(),
+        0
+    );
+
+    progress::Progress::set_watched(&mut tx, &user, media, false)
+        .await
+        .unwrap();
+    let result = progress::Progress::get_for_media_user(&mut tx, user.clone(), media)
+        .await
+        .unwrap();
+    assert!(!result.watched);
+    assert_eq!(result.play_count, 0);
+    assert_eq!(result.delta, 0);
+    assert!(result.last_watched.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_count_unwatched_of_season() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let library = create_test_library(&mut tx).await;
+    let user = insert_user(&mut tx).await;
+
+    let tv = insert_media(&mut tx).await;
+    tv::TVShow::insert(&mut tx, tv).await.unwrap();
+
+    let season = season::InsertableSeason {
+        season_number: 1,
+        ..Default::default()
+    }
+    .insert(&mut tx, tv)
+    .await
+    .unwrap();
+
+    let mut episodes = vec![];
+    for i in 1..=3 {
+        let episode = episode::InsertableEpisode {
+            media: media::InsertableMedia {
+                library_id: library,
+                name: format!("TestEpisode{}", i),
+                ..Default::default()
+            },
+            seasonid: season,
+            episode: i,
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+
+        episodes.push(episode);
+    }
+
+    progress::Progress::set_watched(&mut tx, &user, episodes[0], true)
+        .await
+        .unwrap();
+
+    let result = progress::Progress::count_unwatched_of_season(&mut tx, &user, season)
+        .await
+        .unwrap();
+    assert_eq!(result, 2);
+
+    let result = episode::Episode::count_of_season(&mut tx, season)
+        .await
+        .unwrap();
+    assert_eq!(result, 3);
+
+    let empty = season::InsertableSeason {
+        season_number: 2,
+        ..Default::default()
+    }
+    .insert(&mut tx, tv)
+    .await
+    .unwrap();
+
+    let result = progress::Progress::count_unwatched_of_season(&mut tx, &user, empty)
+        .await
+        .unwrap();
+    assert_eq!(result, 0);
+
+    let result = episode::Episode::count_of_season(&mut tx, empty)
+        .await
+        .unwrap();
+    assert_eq!(result, 0);
+}
@@ -0,0 +1,60 @@
+use crate::get_conn_memory;
+use crate::scrobbler::InsertableScrobbler;
+use crate::scrobbler::Scrobbler;
+use crate::write_tx;
+
+use super::user_tests::insert_user;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_scrobblers() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let username = insert_user(&mut tx).await;
+
+    let id = InsertableScrobbler {
+        username: username.clone(),
+        kind: "trakt".into(),
+        url: "https://api.trakt.tv".into(),
+        client_id: Some("client".into()),
+        token: Some("secret".into()),
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let scrobbler = Scrobbler::get(&mut tx, id).await.unwrap();
+    assert_eq!(scrobbler.username, username);
+    assert_eq!(scrobbler.kind, "trakt");
+    assert_eq!(scrobbler.token.as_deref(), Some("secret"));
+    assert!(scrobbler.enabled);
+
+    // users can only change their own scrobblers.
+    assert_eq!(
+        Scrobbler::set_enabled(&mut tx, "other", id, false)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        Scrobbler::set_enabled(&mut tx, &username, id, false)
+            .await
+            .unwrap(),
+        1
+    );
+
+    assert_eq!(
+        Scrobbler::get_all_of_user(&mut tx, &username)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(Scrobbler::get_enabled_of_user(&mut tx, &username)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(Scrobbler::delete(&mut tx, "other", id).await.unwrap(), 0);
+    assert_eq!(Scrobbler::delete(&mut tx, &username, id).await.unwrap(), 1);
+    assert!(Scrobbler::get(&mut tx, id).await.is_err());
+}
@@ -0,0 +1,97 @@
+use crate::get_conn_memory;
+use crate::session::InsertableSession;
+use crate::session::Session;
+use crate::write_tx;
+
+use super::user_tests::insert_user;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_refresh_token() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let username = insert_user(&mut tx).await;
+
+    let id = InsertableSession {
+        username: username.clone(),
+        refresh_token: "first".into(),
+        device: Some("test".into()),
+        ttl: 60,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let session = Session::get_by_refresh_token(&mut tx, "first")
+        .await
+        .unwrap();
+    assert_eq!(session.id, id);
+    assert_eq!(session.username, username);
+
+    Session::rotate(&mut tx, &id, "second", 60).await.unwrap();
+    assert!(Session::get_by_refresh_token(&mut tx, "first")
+        .await
+        .is_err());
+    assert_eq!(
+        Session::get_by_refresh_token(&mut tx, "second")
+            .await
+            .unwrap()
+            .id,
+        id
+    );
+
+    Session::rotate(&mut tx, &id, "third", -1).await.unwrap();
+    assert!(Session::get_by_refresh_token(&mut tx, "third")
+        .await
+        .is_err());
+    assert!(Session::get_all(&mut tx).await.unwrap().is_empty());
+    assert_eq!(Session::delete_expired(&mut tx).await.unwrap(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_revoke() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let username = insert_user(&mut tx).await;
+
+    let mut ids = Vec::new();
+    for token in &["a", "b", "c"] {
+        let session = InsertableSession {
+            username: username.clone(),
+            refresh_token: token.to_string(),
+            device: None,
+            ttl: 60,
+        };
+
+        ids.push(session.insert(&mut tx).await.unwrap());
+    }
+
+    assert_eq!(
+        Session::get_all_of_user(&mut tx, &username)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+
+    // users can only delete their own sessions.
+    assert_eq!(Session::delete(&mut tx, "other", &ids[0]).await.unwrap(), 0);
+    assert!(Session::is_active(&mut tx, &ids[0]).await.unwrap());
+    assert_eq!(
+        Session::delete(&mut tx, &username, &ids[0]).await.unwrap(),
+        1
+    );
+    assert!(!Session::is_active(&mut tx, &ids[0]).await.unwrap());
+
+    let mut deleted = Session::delete_all_of_user(&mut tx, &username)
+        .await
+        .unwrap();
+    deleted.sort();
+    let mut expected = ids[1..].to_vec();
+    expected.sort();
+
+    assert_eq!(deleted, expected);
+    assert!(Session::get_all_of_user(&mut tx, &username)
+        .await
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,93 @@
+use crate::get_conn_memory;
+use crate::mediafile::InsertableMediaFile;
+use crate::mediafile::UpdateMediaFile;
+use crate::trickplay::InsertableTrickplay;
+use crate::trickplay::Trickplay;
+use crate::write_tx;
+
+use super::library_tests::create_test_library;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_trickplay() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let library_id = create_test_library(&mut tx).await;
+
+    let insert = |target_file: &str, duration: Option<i64>| InsertableMediaFile {
+        library_id,
+        target_file: target_file.into(),
+        raw_name: "Test".into(),
+        duration,
+        file_mtime: Some(1),
+        ..Default::default()
+    };
+
+    let movie = insert("/movies/a.mkv", Some(3600))
+        .insert(&mut tx)
+        .await
+        .unwrap();
+    let other = insert("/movies/b.mkv", Some(1200))
+        .insert(&mut tx)
+        .await
+        .unwrap();
+    // files we dont know the duration of cant be split into thumbnails.
+    insert("/movies/c.mkv", None).insert(&mut tx).await.unwrap();
+
+    let ids = |x: Vec<crate::trickplay::TrickplayTarget>| {
+        x.into_iter().map(|x| x.mediafile_id).collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        ids(Trickplay::get_outdated(&mut tx, 10).await.unwrap()),
+        vec![movie, other]
+    );
+
+    InsertableTrickplay {
+        mediafile_id: movie,
+        interval: 10,
+        width: 320,
+        height: 180,
+        tile_columns: 10,
+        tile_rows: 10,
+        thumbnails: 360,
+        sheets: 4,
+        file_mtime: Some(1),
+        error: None,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    InsertableTrickplay {
+        mediafile_id: other,
+        file_mtime: Some(1),
+        error: Some("ffmpeg failed".into()),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    assert!(Trickplay::get_outdated(&mut tx, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let sheets = Trickplay::get(&mut tx, movie).await.unwrap();
+    assert_eq!(sheets.sheets, 4);
+    assert_eq!((sheets.width, sheets.height), (320, 180));
+
+    // sheets are regenerated once the file changes.
+    UpdateMediaFile {
+        file_mtime: Some(2),
+        ..Default::default()
+    }
+    .update(&mut tx, other)
+    .await
+    .unwrap();
+
+    assert_eq!(
+        ids(Trickplay::get_outdated(&mut tx, 10).await.unwrap()),
+        vec![other]
+    );
+}
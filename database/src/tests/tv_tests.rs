@@ -13,6 +13,7 @@ pub async fn insert_tv(conn: &mut crate::Transaction<'_>) -> i64 {
         name: "TestMedia".into(),
         description: None,
         rating: Some(10),
+        certification: None,
         year: Some(2020),
         added: "Test".into(),
         poster: None,
@@ -1,13 +1,16 @@
 use crate::get_conn_memory;
+use crate::library;
 use crate::user;
 use crate::user::Login;
 use crate::write_tx;
 
+use super::library_tests::create_test_library;
+
 pub async fn insert_user(conn: &mut crate::Transaction<'_>) -> String {
     let invite = Login::new_invite(&mut *conn).await.unwrap();
     let user = user::InsertableUser {
         username: "test".into(),
-        password: "test".into(),
+        password_hash: user::hash("test"),
         roles: vec!["User".into()],
         prefs: Default::default(),
         claimed_invite: invite,
@@ -21,7 +24,7 @@ pub async fn insert_many(conn: &mut crate::Transaction<'_>, n: usize) {
         let invite = Login::new_invite(&mut *conn).await.unwrap();
         let user = user::InsertableUser {
             username: format!("test{}", i),
-            password: "test".into(),
+            password_hash: user::hash("test"),
             roles: vec!["User".into()],
             prefs: Default::default(),
             claimed_invite: invite,
@@ -47,6 +50,45 @@ async fn test_get_one() {
     assert_eq!(&result.roles, &["User".to_string()]);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_password_hash() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let uname = insert_user(&mut tx).await;
+
+    let result = user::User::get(&mut tx, &uname).await.unwrap();
+    assert!(result.password.starts_with("$pbkdf2-sha256$"));
+    assert!(!user::needs_rehash(&result.password));
+    // every hash gets its own salt.
+    assert_ne!(user::hash("test"), user::hash("test"));
+
+    // hash of `test` in the legacy format, salted with the username `test`.
+    let legacy = "V+MZKVQ38A3J3hqT6qNLdLe5hyXLp86lkl7aZsJio4A=";
+    sqlx::query!(
+        "UPDATE users SET password = ? WHERE username = ?",
+        legacy,
+        uname
+    )
+    .execute(&mut tx)
+    .await
+    .unwrap();
+
+    let result = user::User::get_one(&mut tx, uname.clone(), "test".into())
+        .await
+        .unwrap();
+    assert!(user::needs_rehash(&result.password));
+    assert!(user::User::get_one(&mut tx, uname.clone(), "wrong".into())
+        .await
+        .is_err());
+
+    let hash = user::hash_password("test".into()).await;
+    result.set_password(&mut tx, hash).await.unwrap();
+    let result = user::User::get_one(&mut tx, uname, "test".into())
+        .await
+        .unwrap();
+    assert!(!user::needs_rehash(&result.password));
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_get_all() {
     let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
@@ -78,6 +120,100 @@ async fn test_delete() {
     assert!(result.is_err());
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_set_roles() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let uname = insert_user(&mut tx).await;
+
+    let rows = user::User::set_roles(&mut tx, &uname, &["admin".into(), "user".into()])
+        .await
+        .unwrap();
+    assert_eq!(rows, 1);
+
+    let result = user::User::get(&mut tx, &uname).await.unwrap();
+    assert_eq!(&result.roles, &["admin".to_string(), "user".to_string()]);
+
+    let rows = user::User::set_roles(&mut tx, "nobody", &["admin".into()])
+        .await
+        .unwrap();
+    assert_eq!(rows, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_account_status() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let uname = insert_user(&mut tx).await;
+
+    let result = user::User::get(&mut tx, &uname).await.unwrap();
+    assert!(!result.disabled);
+    assert!(!result.password_reset);
+
+    user::User::set_disabled(&mut tx, &uname, true)
+        .await
+        .unwrap();
+    user::User::set_password_reset(&mut tx, &uname, true)
+        .await
+        .unwrap();
+
+    let result = user::User::get(&mut tx, &uname).await.unwrap();
+    assert!(result.disabled);
+    assert!(result.password_reset);
+
+    let rows = user::User::set_disabled(&mut tx, "nobody", true)
+        .await
+        .unwrap();
+    assert_eq!(rows, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_library_access() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let uname = insert_user(&mut tx).await;
+    let id = create_test_library(&mut tx).await;
+    let other = create_test_library(&mut tx).await;
+
+    let result = user::User::get_library_access(&mut tx, &uname)
+        .await
+        .unwrap();
+    assert_eq!(result, None);
+    assert!(library::Library::is_visible_to(&mut tx, id, &uname)
+        .await
+        .unwrap());
+
+    user::User::set_library_access(&mut tx, &uname, Some(&[]))
+        .await
+        .unwrap();
+    let result = library::Library::get_all_for_user(&mut tx, &uname)
+        .await
+        .unwrap();
+    assert!(result.is_empty());
+
+    user::User::set_library_access(&mut tx, &uname, Some(&[id]))
+        .await
+        .unwrap();
+    let result = user::User::get_library_access(&mut tx, &uname)
+        .await
+        .unwrap();
+    assert_eq!(result, Some(vec![id]));
+    assert!(library::Library::is_visible_to(&mut tx, id, &uname)
+        .await
+        .unwrap());
+    assert!(!library::Library::is_visible_to(&mut tx, other, &uname)
+        .await
+        .unwrap());
+
+    user::User::set_library_access(&mut tx, &uname, None)
+        .await
+        .unwrap();
+    let result = library::Library::get_all_for_user(&mut tx, &uname)
+        .await
+        .unwrap();
+    assert_eq!(result.len(), 2);
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_invites() {
     let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
@@ -129,3 +265,23 @@ async fn test_invites() {
     .unwrap();
     assert_eq!(result, 0);
 }
+
+#[test]
+fn test_certification_limit() {
+    let mut prefs = user::UserSettings::default();
+    let limit = prefs.certification_limit();
+    assert!(limit.allows(Some(18)));
+    assert!(limit.allows(None));
+
+    // unrated media is hidden by default once a limit is set.
+    prefs.max_certification = Some("PG-13".into());
+    let limit = prefs.certification_limit();
+    assert!(limit.allows(Some(13)));
+    assert!(!limit.allows(Some(17)));
+    assert!(!limit.allows(None));
+
+    prefs.hide_unrated = false;
+    let limit = prefs.certification_limit();
+    assert!(!limit.allows(Some(17)));
+    assert!(limit.allows(None));
+}
@@ -0,0 +1,54 @@
+use crate::get_conn_memory;
+use crate::media;
+use crate::watch_history::WatchHistory;
+use crate::write_tx;
+
+use super::library_tests::create_test_library;
+use super::media_tests::insert_media;
+use super::user_tests::insert_user;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_record_session() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+    let library = create_test_library(&mut tx).await;
+    let user = insert_user(&mut tx).await;
+    let media = insert_media(&mut tx).await;
+
+    assert!(!WatchHistory::record(&mut tx, &user, media, 10, 100)
+        .await
+        .unwrap());
+    assert!(!WatchHistory::record(&mut tx, &user, media, 50, 100)
+        .await
+        .unwrap());
+    // only the report crossing the threshold completes the session.
+    assert!(WatchHistory::record(&mut tx, &user, media, 95, 100)
+        .await
+        .unwrap());
+    assert!(!WatchHistory::record(&mut tx, &user, media, 99, 100)
+        .await
+        .unwrap());
+
+    let history = WatchHistory::get_of_media(&mut tx, &user, media)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].delta, 99);
+    assert!(history[0].completed);
+
+    // without a duration we can't tell whether the media was played through.
+    let other = media::InsertableMedia {
+        library_id: library,
+        name: "Other".into(),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+    assert!(!WatchHistory::record(&mut tx, &user, other, 1000, 0)
+        .await
+        .unwrap());
+
+    let history = WatchHistory::get_of_user(&mut tx, &user, 10).await.unwrap();
+    assert_eq!(history.len(), 2);
+}
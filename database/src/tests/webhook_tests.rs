@@ -0,0 +1,161 @@
+use crate::get_conn_memory;
+use crate::webhook::DeliveryStatus;
+use crate::webhook::InsertableWebhook;
+use crate::webhook::InsertableWebhookDelivery;
+use crate::webhook::UpdateWebhook;
+use crate::webhook::Webhook;
+use crate::webhook::WebhookDelivery;
+use crate::write_tx;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_webhooks() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    let all = InsertableWebhook {
+        url: "http://localhost/all".into(),
+        secret: "secret".into(),
+        events: vec![],
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let libraries = InsertableWebhook {
+        url: "http://localhost/libraries".into(),
+        secret: "secret".into(),
+        events: vec!["EventNewLibrary".into(), "EventRemoveLibrary".into()],
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let webhook = Webhook::get(&mut tx, libraries).await.unwrap();
+    assert_eq!(webhook.events.len(), 2);
+    assert!(webhook.enabled);
+    assert!(webhook.wants("EventNewLibrary"));
+    assert!(!webhook.wants("EventNewCard"));
+
+    let ids = |x: Vec<Webhook>| x.into_iter().map(|x| x.id).collect::<Vec<_>>();
+
+    assert_eq!(
+        ids(Webhook::get_for_event(&mut tx, "EventNewCard")
+            .await
+            .unwrap()),
+        vec![all]
+    );
+    assert_eq!(
+        ids(Webhook::get_for_event(&mut tx, "EventNewLibrary")
+            .await
+            .unwrap()),
+        vec![all, libraries]
+    );
+
+    UpdateWebhook {
+        enabled: Some(false),
+        ..Default::default()
+    }
+    .update(&mut tx, all)
+    .await
+    .unwrap();
+
+    UpdateWebhook {
+        events: Some(vec![]),
+        ..Default::default()
+    }
+    .update(&mut tx, libraries)
+    .await
+    .unwrap();
+
+    assert_eq!(
+        ids(Webhook::get_for_event(&mut tx, "EventNewCard")
+            .await
+            .unwrap()),
+        vec![libraries]
+    );
+
+    assert_eq!(Webhook::delete(&mut tx, all).await.unwrap(), 1);
+    assert_eq!(Webhook::get_all(&mut tx).await.unwrap().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_webhook_deliveries() {
+    let mut conn = get_conn_memory().await.unwrap().writer().lock_owned().await;
+    let mut tx = write_tx(&mut conn).await.unwrap();
+
+    let webhook = InsertableWebhook {
+        url: "http://localhost".into(),
+        secret: "secret".into(),
+        events: vec![],
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let mut deliveries = vec![];
+    for _ in 0..3 {
+        let id = InsertableWebhookDelivery {
+            webhook_id: webhook,
+            event: "EventNewCard".into(),
+            payload: "{}".into(),
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+
+        deliveries.push(id);
+    }
+
+    assert_eq!(
+        WebhookDelivery::get_due(&mut tx, 10).await.unwrap().len(),
+        3
+    );
+
+    WebhookDelivery::set_delivered(&mut tx, deliveries[0], 200)
+        .await
+        .unwrap();
+    WebhookDelivery::set_failed(&mut tx, deliveries[1], Some(500), "oops", Some(i64::MAX))
+        .await
+        .unwrap();
+    WebhookDelivery::set_failed(&mut tx, deliveries[2], None, "refused", None)
+        .await
+        .unwrap();
+
+    // nothing is due, the second delivery is retried in the future.
+    assert!(WebhookDelivery::get_due(&mut tx, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let history = WebhookDelivery::get_of_webhook(&mut tx, webhook, 10)
+        .await
+        .unwrap();
+    let status = history.iter().map(|x| x.status).collect::<Vec<_>>();
+    assert_eq!(
+        status,
+        vec![
+            DeliveryStatus::Failed,
+            DeliveryStatus::Pending,
+            DeliveryStatus::Delivered
+        ]
+    );
+    assert!(history.iter().all(|x| x.attempts == 1));
+    assert_eq!(history[1].response_code, Some(500));
+    assert_eq!(history[1].error.as_deref(), Some("oops"));
+    assert!(history[2].delivered.is_some());
+
+    // pending deliveries are kept regardless of their age.
+    assert_eq!(
+        WebhookDelivery::delete_older_than(&mut tx, -10)
+            .await
+            .unwrap(),
+        2
+    );
+
+    // deleting the webhook deletes its deliveries.
+    Webhook::delete(&mut tx, webhook).await.unwrap();
+    assert!(WebhookDelivery::get_of_webhook(&mut tx, webhook, 10)
+        .await
+        .unwrap()
+        .is_empty());
+}
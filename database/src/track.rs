@@ -0,0 +1,102 @@
+use crate::DatabaseError;
+
+use serde::{Deserialize, Serialize};
+
+/// Struct represents a single track of a album. Each track maps to exactly one mediafile, which is
+/// what gets streamed.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Track {
+    pub id: i64,
+    /// id of the album this track is on.
+    pub album_id: i64,
+    /// id of the mediafile of this track.
+    pub mediafile_id: i64,
+    /// id of the artist performing this track, this can differ from the album artist.
+    pub artist_id: Option<i64>,
+    /// Title of the track.
+    pub name: String,
+    pub track_number: Option<i64>,
+    pub disc_number: Option<i64>,
+    /// Duration of the track in seconds.
+    pub duration: Option<i64>,
+}
+
+impl Track {
+    /// Method returns all the tracks of a album ordered by disc and track number.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `album_id` - id of the album.
+    pub async fn get_all_of_album(
+        conn: &mut crate::Transaction<'_>,
+        album_id: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT track.id, track.album_id, track.mediafile_id, track.artist_id, track.name,
+                track.track_number, track.disc_number, mediafile.duration as "duration?"
+            FROM track
+            INNER JOIN mediafile ON mediafile.id = track.mediafile_id
+            WHERE track.album_id = ?
+            ORDER BY track.disc_number, track.track_number, track.name"#,
+            album_id
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the track with the id `id`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the track.
+    pub async fn get_one(conn: &mut crate::Transaction<'_>, id: i64) -> Result<Self, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT track.id, track.album_id, track.mediafile_id, track.artist_id, track.name,
+                track.track_number, track.disc_number, mediafile.duration as "duration?"
+            FROM track
+            INNER JOIN mediafile ON mediafile.id = track.mediafile_id
+            WHERE track.id = ?"#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct InsertableTrack {
+    pub album_id: i64,
+    pub mediafile_id: i64,
+    pub artist_id: Option<i64>,
+    pub name: String,
+    pub track_number: Option<i64>,
+    pub disc_number: Option<i64>,
+}
+
+impl InsertableTrack {
+    /// Method inserts a new track into the database. If the mediafile is already linked to a track,
+    /// the existing track is replaced.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        Ok(sqlx::query!(
+            r#"INSERT INTO track (album_id, mediafile_id, artist_id, name, track_number, disc_number)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (mediafile_id) DO UPDATE
+            SET album_id = $1, artist_id = $3, name = $4, track_number = $5, disc_number = $6
+            RETURNING track.id as "id!: i64""#,
+            self.album_id,
+            self.mediafile_id,
+            self.artist_id,
+            self.name,
+            self.track_number,
+            self.disc_number
+        )
+        .fetch_one(&mut *conn)
+        .await?
+        .id)
+    }
+}
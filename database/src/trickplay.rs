@@ -0,0 +1,139 @@
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Serialize;
+
+/// Thumbnail sprite sheets generated for a mediafile, used for seek previews.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Trickplay {
+    pub mediafile_id: i64,
+    /// Seconds between two thumbnails.
+    pub interval: i64,
+    /// Width of a single thumbnail.
+    pub width: i64,
+    /// Height of a single thumbnail.
+    pub height: i64,
+    /// Number of thumbnails per row of a sheet.
+    pub tile_columns: i64,
+    /// Number of thumbnails per column of a sheet.
+    pub tile_rows: i64,
+    /// Total number of thumbnails.
+    pub thumbnails: i64,
+    /// Number of sheets, the last one might not be full.
+    pub sheets: i64,
+    /// Mtime of the mediafile when the sheets were generated.
+    pub file_mtime: Option<i64>,
+    /// Why generating the sheets failed.
+    pub error: Option<String>,
+    pub created: i64,
+}
+
+/// A mediafile which has no sheets yet or whose sheets are out of date.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrickplayTarget {
+    pub mediafile_id: i64,
+    pub target_file: String,
+    pub duration: Option<i64>,
+    pub file_mtime: Option<i64>,
+}
+
+impl Trickplay {
+    /// Method returns the sheets of a mediafile.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `mediafile_id` - id of the mediafile.
+    pub async fn get(
+        conn: &mut crate::Transaction<'_>,
+        mediafile_id: i64,
+    ) -> Result<Self, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT mediafile_id as "mediafile_id!", interval, width, height, tile_columns,
+                tile_rows, thumbnails, sheets, file_mtime, error, created
+                FROM trickplay
+                WHERE mediafile_id = ?"#,
+            mediafile_id
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the video files which have no sheets, or whose file changed since the
+    /// sheets were generated. Files for which generating the sheets failed are only returned once
+    /// they change.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `limit` - max number of files to return.
+    pub async fn get_outdated(
+        conn: &mut crate::Transaction<'_>,
+        limit: i64,
+    ) -> Result<Vec<TrickplayTarget>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            TrickplayTarget,
+            r#"SELECT mediafile.id as "mediafile_id!", mediafile.target_file, mediafile.duration,
+                mediafile.file_mtime
+                FROM mediafile
+                INNER JOIN library ON library.id = mediafile.library_id
+                LEFT JOIN trickplay ON trickplay.mediafile_id = mediafile.id
+                WHERE library.media_type != 'music'
+                    AND mediafile.duration > 0
+                    AND NOT COALESCE(mediafile.corrupt, 0)
+                    AND (trickplay.mediafile_id IS NULL
+                        OR trickplay.file_mtime IS NOT mediafile.file_mtime)
+                ORDER BY mediafile.id ASC
+                LIMIT ?"#,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+}
+
+/// Struct used to record the sheets generated for a mediafile.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableTrickplay {
+    pub mediafile_id: i64,
+    pub interval: i64,
+    pub width: i64,
+    pub height: i64,
+    pub tile_columns: i64,
+    pub tile_rows: i64,
+    pub thumbnails: i64,
+    pub sheets: i64,
+    pub file_mtime: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl InsertableTrickplay {
+    /// Method records the sheets, replacing the sheets previously generated for the mediafile.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<(), DatabaseError> {
+        let now = now();
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO trickplay
+                (mediafile_id, interval, width, height, tile_columns, tile_rows, thumbnails,
+                    sheets, file_mtime, error, created)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+            self.mediafile_id,
+            self.interval,
+            self.width,
+            self.height,
+            self.tile_columns,
+            self.tile_rows,
+            self.thumbnails,
+            self.sheets,
+            self.file_mtime,
+            self.error,
+            now
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
+use crate::invite::Invite;
 use crate::DatabaseError;
 use std::collections::HashMap;
+use std::fmt;
 use std::num::NonZeroU32;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
 use std::time::SystemTime;
 
 use serde::Deserialize;
@@ -8,10 +12,51 @@ use serde::Serialize;
 
 use ring::digest;
 use ring::pbkdf2;
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
 
 static PBKDF2_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
 const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
-const HASH_ROUNDS: NonZeroU32 = unsafe { NonZeroU32::new_unchecked(1_000) };
+const SALT_LEN: usize = 16;
+/// Rounds used by the legacy hash format, which was salted with the username.
+const LEGACY_HASH_ROUNDS: NonZeroU32 = unsafe { NonZeroU32::new_unchecked(1_000) };
+/// Hashes are stored as `$pbkdf2-sha256$<iterations>$<salt>$<hash>`, anything without this prefix
+/// is a legacy hash.
+const HASH_PREFIX: &str = "$pbkdf2-sha256$";
+
+/// Default number of PBKDF2 iterations new passwords are hashed with.
+pub const DEFAULT_HASH_ITERATIONS: u32 = 310_000;
+static HASH_ITERATIONS: AtomicU32 = AtomicU32::new(DEFAULT_HASH_ITERATIONS);
+
+/// Passwords which are too common to be allowed regardless of the configured policy.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "password",
+    "password1",
+    "password123",
+    "qwerty",
+    "qwerty123",
+    "qwertyuiop",
+    "abc123",
+    "111111",
+    "000000",
+    "iloveyou",
+    "letmein",
+    "welcome",
+    "admin",
+    "admin123",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "trustno1",
+    "changeme",
+];
 
 pub type Credential = [u8; CREDENTIAL_LEN];
 
@@ -69,6 +114,60 @@ pub struct UserSettings {
     /// Whether hovercards are hidden or not
     #[serde(default)]
     show_hovercards: bool,
+    /// Highest certification, ie `PG-13`, of the media this user can see. Can only be changed by
+    /// admins.
+    #[serde(default)]
+    pub max_certification: Option<String>,
+    /// Whether media without a known certification is hidden from this user while
+    /// `max_certification` is set. Can only be changed by admins.
+    #[serde(default = "default_true")]
+    pub hide_unrated: bool,
+}
+
+impl UserSettings {
+    /// Returns the certification limit of this user.
+    pub fn certification_limit(&self) -> CertificationLimit {
+        match self
+            .max_certification
+            .as_deref()
+            .and_then(crate::media::certification_level)
+        {
+            Some(level) => CertificationLimit {
+                level,
+                hide_unrated: self.hide_unrated,
+            },
+            None => CertificationLimit::default(),
+        }
+    }
+}
+
+/// Limits the media a user can see by its certification, see
+/// [`certification_level`](crate::media::certification_level).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CertificationLimit {
+    /// Highest certification level the user can see.
+    pub level: i64,
+    /// Whether media without a known certification level is hidden.
+    pub hide_unrated: bool,
+}
+
+impl CertificationLimit {
+    /// Returns whether media with the certification level `level` is within the limit.
+    pub fn allows(&self, level: Option<i64>) -> bool {
+        match level {
+            Some(x) => x <= self.level,
+            None => !self.hide_unrated,
+        }
+    }
+}
+
+impl Default for CertificationLimit {
+    fn default() -> Self {
+        Self {
+            level: i64::MAX,
+            hide_unrated: false,
+        }
+    }
 }
 
 impl Default for UserSettings {
@@ -84,6 +183,8 @@ impl Default for UserSettings {
             external_args: HashMap::new(),
             show_hovercards: true,
             default_video_quality: DefaultVideoQuality::DirectPlay,
+            max_certification: None,
+            hide_unrated: true,
         }
     }
 }
@@ -102,6 +203,10 @@ pub struct User {
     pub password: String,
     pub prefs: UserSettings,
     pub picture: Option<i64>,
+    /// Disabled users cannot log in.
+    pub disabled: bool,
+    /// Whether the user has to pick a new password before they can log in again.
+    pub password_reset: bool,
 }
 
 impl User {
@@ -121,6 +226,8 @@ impl User {
                 password: user.password,
                 prefs: serde_json::from_slice(&user.prefs).unwrap_or_default(),
                 picture: user.picture,
+                disabled: user.disabled,
+                password_reset: user.password_reset,
             })
             .collect())
     }
@@ -142,6 +249,8 @@ impl User {
             password: u.password,
             prefs: serde_json::from_slice(&u.prefs).unwrap_or_default(),
             picture: u.picture,
+            disabled: u.disabled,
+            password_reset: u.password_reset,
         })?)
     }
 
@@ -156,22 +265,13 @@ impl User {
         uname: String,
         pw: String,
     ) -> Result<Self, DatabaseError> {
-        let hash = hash(uname.clone(), pw);
-        let user = sqlx::query!(
-            "SELECT * FROM users WHERE username = ? AND password = ?",
-            uname,
-            hash,
-        )
-        .fetch_one(&mut *conn)
-        .await?;
+        let user = Self::get(&mut *conn, &uname).await?;
 
-        Ok(Self {
-            username: user.username.unwrap(),
-            roles: user.roles.split(',').map(ToString::to_string).collect(),
-            password: user.password,
-            prefs: serde_json::from_slice(&user.prefs).unwrap_or_default(),
-            picture: user.picture,
-        })
+        if !verify_password(user.username.clone(), user.password.clone(), pw).await {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+
+        Ok(user)
     }
 
     /// Method deletes a entry from the table users and returns the number of rows deleted.
@@ -194,14 +294,12 @@ impl User {
     ///
     /// # Arguments
     /// * `&` - db &ection
-    /// * `password` - new password.
+    /// * `hash` - hash of the new password, see [`hash_password`].
     pub async fn set_password(
         &self,
         conn: &mut crate::Transaction<'_>,
-        password: String,
+        hash: String,
     ) -> Result<usize, DatabaseError> {
-        let hash = hash(self.username.clone(), password);
-
         Ok(sqlx::query!(
             "UPDATE users SET password = $1 WHERE username = ?2",
             hash,
@@ -212,6 +310,141 @@ impl User {
         .rows_affected() as usize)
     }
 
+    /// Method replaces the roles of a user.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - username of the user whose roles we want to set.
+    /// * `roles` - names of the new roles.
+    pub async fn set_roles(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        roles: &[String],
+    ) -> Result<usize, DatabaseError> {
+        let roles = roles.join(",");
+
+        Ok(sqlx::query!(
+            "UPDATE users SET roles = $1 WHERE users.username = ?2",
+            roles,
+            username
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method disables or enables a user.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - username of the user.
+    /// * `disabled` - whether the user should be disabled.
+    pub async fn set_disabled(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        disabled: bool,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "UPDATE users SET disabled = $1 WHERE users.username = ?2",
+            disabled,
+            username
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method sets whether a user has to pick a new password before they can log in again.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - username of the user.
+    /// * `password_reset` - whether a new password is required.
+    pub async fn set_password_reset(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        password_reset: bool,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!(
+            "UPDATE users SET password_reset = $1 WHERE users.username = ?2",
+            password_reset,
+            username
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method returns the ids of the libraries a user is restricted to, or `None` if the user can
+    /// see every library.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose restrictions we want.
+    pub async fn get_library_access(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+    ) -> Result<Option<Vec<i64>>, DatabaseError> {
+        let restricted = sqlx::query_scalar!(
+            r#"SELECT restrict_libraries as "restrict_libraries: bool" FROM users WHERE username = ?"#,
+            username
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if !restricted {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            sqlx::query_scalar!(
+                "SELECT library_id FROM library_access WHERE username = ?",
+                username
+            )
+            .fetch_all(&mut *conn)
+            .await?,
+        ))
+    }
+
+    /// Method restricts a user to only see the libraries `libraries`. Passing `None` lifts the
+    /// restriction, letting the user see every library.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `username` - user whose restrictions we want to set.
+    /// * `libraries` - ids of the libraries the user can see.
+    pub async fn set_library_access(
+        conn: &mut crate::Transaction<'_>,
+        username: &str,
+        libraries: Option<&[i64]>,
+    ) -> Result<usize, DatabaseError> {
+        let restricted = libraries.is_some();
+        let rows = sqlx::query!(
+            "UPDATE users SET restrict_libraries = $1 WHERE users.username = ?2",
+            restricted,
+            username
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize;
+
+        sqlx::query!("DELETE FROM library_access WHERE username = ?", username)
+            .execute(&mut *conn)
+            .await?;
+
+        for library_id in libraries.unwrap_or_default() {
+            sqlx::query!(
+                "INSERT OR IGNORE INTO library_access (username, library_id) VALUES ($1, $2)",
+                username,
+                library_id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(rows)
+    }
+
     pub async fn set_username(
         conn: &mut crate::Transaction<'_>,
         old_username: String,
@@ -246,7 +479,8 @@ impl User {
 #[derive(Deserialize)]
 pub struct InsertableUser {
     pub username: String,
-    pub password: String,
+    /// Hash of the password of the user, see [`hash_password`].
+    pub password_hash: String,
     pub roles: Vec<String>,
     pub prefs: UserSettings,
     pub claimed_invite: String,
@@ -262,20 +496,19 @@ impl InsertableUser {
     pub async fn insert(self, conn: &mut crate::Transaction<'_>) -> Result<String, DatabaseError> {
         let Self {
             username,
-            password,
+            password_hash,
             roles,
             prefs,
             claimed_invite,
         } = self;
 
-        let password = hash(username.clone(), password);
         let roles = roles.join(",");
         let prefs = serde_json::to_vec(&prefs).unwrap_or_default();
 
         sqlx::query!(
             "INSERT INTO users (username, password, prefs, claimed_invite, roles) VALUES ($1, $2, $3, $4, $5)",
             username,
-            password,
+            password_hash,
             prefs,
             claimed_invite,
             roles
@@ -283,6 +516,15 @@ impl InsertableUser {
         .execute(&mut *conn)
         .await?;
 
+        // claims count as a use of the invite they were made from.
+        sqlx::query!(
+            "UPDATE invites SET uses = uses + 1
+                WHERE id = COALESCE((SELECT claim_of FROM invites WHERE id = ?1), ?1)",
+            claimed_invite
+        )
+        .execute(&mut *conn)
+        .await?;
+
         Ok(username)
     }
 }
@@ -322,7 +564,7 @@ pub struct Login {
 }
 
 impl Login {
-    /// Will return whether the token is valid and hasnt been claimed yet.
+    /// Will return whether the token is valid, ie it exists, hasnt expired and hasnt been used up.
     pub async fn invite_token_valid(
         &self,
         conn: &mut crate::Transaction<'_>,
@@ -332,17 +574,11 @@ impl Login {
             Some(t) => t,
         };
 
-        Ok(sqlx::query!(
-            "SELECT id FROM invites
-                          WHERE id NOT IN (
-                              SELECT claimed_invite FROM users
-                          )
-                          AND id = ?",
-            tok
-        )
-        .fetch_optional(&mut *conn)
-        .await?
-        .is_some())
+        match Invite::get(&mut *conn, tok).await {
+            Ok(invite) => Ok(invite.is_valid()),
+            Err(DatabaseError::DatabaseError(sqlx::Error::RowNotFound)) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
     pub async fn invalidate_token(
@@ -379,52 +615,204 @@ impl Login {
     pub async fn get_all_invites(
         conn: &mut crate::Transaction<'_>,
     ) -> Result<Vec<String>, DatabaseError> {
-        Ok(sqlx::query!("SELECT id from invites")
-            .fetch_all(&mut *conn)
-            .await?
-            .into_iter()
-            .map(|t| t.id)
-            .collect())
+        Ok(
+            sqlx::query!("SELECT id from invites WHERE claim_of IS NULL")
+                .fetch_all(&mut *conn)
+                .await?
+                .into_iter()
+                .map(|t| t.id)
+                .collect(),
+        )
     }
 
+    /// Deletes a invite, or expires it if someone already registered with it.
     pub async fn delete_token(
         conn: &mut crate::Transaction<'_>,
         token: String,
     ) -> Result<usize, DatabaseError> {
-        Ok(sqlx::query!(
-            "DELETE FROM invites
-                WHERE id NOT IN (
-                    SELECT claimed_invite FROM users
-                ) AND id = ?",
-            token
-        )
-        .execute(&mut *conn)
-        .await?
-        .rows_affected() as usize)
+        Invite::delete(&mut *conn, &token).await
     }
 }
 
-pub fn hash(salt: String, s: String) -> String {
+/// Sets the number of PBKDF2 iterations new passwords are hashed with. Existing hashes with fewer
+/// iterations are upgraded the next time their owner logs in.
+pub fn set_hash_iterations(iterations: u32) {
+    HASH_ITERATIONS.store(iterations.max(1), Ordering::Relaxed);
+}
+
+fn hash_iterations() -> NonZeroU32 {
+    NonZeroU32::new(HASH_ITERATIONS.load(Ordering::Relaxed)).unwrap_or(LEGACY_HASH_ROUNDS)
+}
+
+/// Function hashes a password with a random salt.
+pub fn hash(password: &str) -> String {
+    let iterations = hash_iterations();
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("Failed to generate a salt.");
+
     let mut to_store: Credential = [0u8; CREDENTIAL_LEN];
     pbkdf2::derive(
         PBKDF2_ALG,
-        HASH_ROUNDS,
-        &salt.as_bytes(),
-        s.as_bytes(),
+        iterations,
+        &salt,
+        password.as_bytes(),
         &mut to_store,
     );
-    base64::encode(&to_store)
+
+    format!(
+        "{}{}${}${}",
+        HASH_PREFIX,
+        iterations,
+        base64::encode(&salt),
+        base64::encode(&to_store)
+    )
 }
 
-pub fn verify(salt: String, password: String, attempted_password: String) -> bool {
-    let real_pwd = base64::decode(&password).unwrap();
+/// Same as [`hash`] except the password is hashed on the blocking thread pool. Hashing takes a
+/// while on purpose, so it must not run on the async executor.
+pub async fn hash_password(password: String) -> String {
+    tokio::task::spawn_blocking(move || hash(&password))
+        .await
+        .expect("Failed to hash a password.")
+}
 
-    pbkdf2::verify(
-        PBKDF2_ALG,
-        HASH_ROUNDS,
-        &salt.as_bytes(),
-        attempted_password.as_bytes(),
-        real_pwd.as_slice(),
+/// Function splits a stored hash into the number of iterations, the salt and the derived key.
+fn parse_hash(username: &str, hash: &str) -> Option<(NonZeroU32, Vec<u8>, Vec<u8>)> {
+    let rest = match hash.strip_prefix(HASH_PREFIX) {
+        Some(x) => x,
+        None => {
+            return Some((
+                LEGACY_HASH_ROUNDS,
+                username.as_bytes().to_vec(),
+                base64::decode(hash).ok()?,
+            ))
+        }
+    };
+
+    let mut parts = rest.split('$');
+    let iterations = parts.next()?.parse().ok()?;
+    let salt = base64::decode(parts.next()?).ok()?;
+    let key = base64::decode(parts.next()?).ok()?;
+
+    Some((iterations, salt, key))
+}
+
+/// Function checks `attempted_password` against the stored `hash` of a user. Both the current and
+/// the legacy hash format are accepted.
+///
+/// # Arguments
+/// * `username` - username of the user, legacy hashes are salted with it.
+/// * `hash` - the stored hash.
+/// * `attempted_password` - the password we want to check.
+pub fn verify(username: &str, hash: &str, attempted_password: &str) -> bool {
+    matches!(
+        parse_hash(username, hash),
+        Some((iterations, salt, key)) if pbkdf2::verify(
+            PBKDF2_ALG,
+            iterations,
+            &salt,
+            attempted_password.as_bytes(),
+            &key,
+        )
+        .is_ok()
     )
-    .is_ok()
+}
+
+/// Same as [`verify`] except the password is checked on the blocking thread pool.
+pub async fn verify_password(username: String, hash: String, attempted_password: String) -> bool {
+    tokio::task::spawn_blocking(move || verify(&username, &hash, &attempted_password))
+        .await
+        .unwrap_or(false)
+}
+
+/// Function returns whether a stored hash is in the legacy format or uses fewer iterations than
+/// currently configured, in which case it should be replaced once we know the password.
+pub fn needs_rehash(hash: &str) -> bool {
+    let iterations = hash
+        .strip_prefix(HASH_PREFIX)
+        .and_then(|x| x.split('$').next())
+        .and_then(|x| x.parse::<u32>().ok());
+
+    match iterations {
+        Some(x) => x < hash_iterations().get(),
+        None => true,
+    }
+}
+
+/// Rules passwords have to follow when they are set.
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    /// Minimum number of characters.
+    pub min_length: usize,
+    /// Passwords which are rejected on top of a built in list of common passwords.
+    pub banned: Vec<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            banned: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordPolicyError {
+    /// The password is shorter than the minimum length.
+    TooShort(usize),
+    /// The password is a common password or has been banned.
+    Common,
+    /// The password is the username.
+    SameAsUsername,
+}
+
+impl fmt::Display for PasswordPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort(x) => write!(f, "Passwords must be at least {} characters long.", x),
+            Self::Common => f.write_str("This password is too common."),
+            Self::SameAsUsername => f.write_str("Passwords cannot be the same as the username."),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Method checks whether `password` is allowed as the password of `username`.
+    ///
+    /// # Example
+    /// ```
+    /// use database::user::{PasswordPolicy, PasswordPolicyError};
+    ///
+    /// let policy = PasswordPolicy::default();
+    ///
+    /// assert_eq!(policy.check("user", "short"), Err(PasswordPolicyError::TooShort(8)));
+    /// assert_eq!(policy.check("user", "Password1"), Err(PasswordPolicyError::Common));
+    /// assert_eq!(policy.check("username", "username"), Err(PasswordPolicyError::SameAsUsername));
+    /// assert!(policy.check("user", "correct horse battery").is_ok());
+    /// ```
+    pub fn check(&self, username: &str, password: &str) -> Result<(), PasswordPolicyError> {
+        if password.chars().count() < self.min_length {
+            return Err(PasswordPolicyError::TooShort(self.min_length));
+        }
+
+        if password.eq_ignore_ascii_case(username) {
+            return Err(PasswordPolicyError::SameAsUsername);
+        }
+
+        let banned = COMMON_PASSWORDS
+            .iter()
+            .copied()
+            .chain(self.banned.iter().map(String::as_str));
+
+        for x in banned {
+            if password.eq_ignore_ascii_case(x) {
+                return Err(PasswordPolicyError::Common);
+            }
+        }
+
+        Ok(())
+    }
 }
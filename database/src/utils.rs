@@ -1,3 +1,6 @@
+use ring::digest;
+use std::time::SystemTime;
+
 #[macro_export]
 macro_rules! opt_update {
     ($conn:ident, $query:expr => ($self:expr, $constraint:expr)) => {
@@ -17,6 +20,20 @@ macro_rules! opt_update {
     }
 }
 
+/// Returns the current unix timestamp in seconds.
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Secrets such as refresh tokens and API keys are stored as their sha256 hash so that a leaked
+/// database cant be used to log in.
+pub(crate) fn hash_token(token: &str) -> String {
+    base64::encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
 #[cfg(not(debug_assertions))]
 pub fn ffpath(bin: impl AsRef<str>) -> &'static str {
     let mut path = std::env::current_exe().expect("Failed to grab path to the `dim` binary.");
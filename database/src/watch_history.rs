@@ -0,0 +1,145 @@
+use crate::progress::WATCHED_THRESHOLD;
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Serialize;
+
+/// Number of seconds after the last progress report of a session after which further progress
+/// starts a new session.
+pub const SESSION_GAP: i64 = 30 * 60;
+
+/// A single viewing session of a media by a user.
+#[derive(Clone, Debug, Serialize)]
+pub struct WatchHistory {
+    pub id: i64,
+    pub user_id: String,
+    pub media_id: i64,
+    /// Timestamp of when the session started.
+    pub started: i64,
+    /// Timestamp of the last progress report of the session.
+    pub ended: i64,
+    /// Furthest position in seconds reached during the session.
+    pub delta: i64,
+    /// Whether the media was played through during the session.
+    pub completed: bool,
+}
+
+impl WatchHistory {
+    /// Method records progress made by a user, either extending their current session of the
+    /// media or starting a new one. Returns whether this progress completed the session, in
+    /// which case the caller should mark the media as watched.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `uid` - the user watching the media.
+    /// * `mid` - id of the media being watched.
+    /// * `delta` - current position in seconds.
+    /// * `duration` - duration of the media in seconds, `0` if unknown.
+    pub async fn record(
+        conn: &mut crate::Transaction<'_>,
+        uid: &str,
+        mid: i64,
+        delta: i64,
+        duration: i64,
+    ) -> Result<bool, DatabaseError> {
+        let now = now();
+        let cutoff = now - SESSION_GAP;
+        let completed = duration > 0 && delta as f64 / duration as f64 > WATCHED_THRESHOLD;
+
+        let session = sqlx::query!(
+            r#"SELECT id as "id!", completed as "completed: bool" FROM watch_history
+                WHERE user_id = ? AND media_id = ? AND ended >= ?
+                ORDER BY ended DESC
+                LIMIT 1"#,
+            uid,
+            mid,
+            cutoff
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        match session {
+            Some(x) => {
+                sqlx::query!(
+                    "UPDATE watch_history
+                    SET ended = $1, delta = MAX(delta, $2), completed = completed OR $3
+                    WHERE id = $4",
+                    now,
+                    delta,
+                    completed,
+                    x.id
+                )
+                .execute(&mut *conn)
+                .await?;
+
+                Ok(completed && !x.completed)
+            }
+            None => {
+                sqlx::query!(
+                    r#"INSERT INTO watch_history (user_id, media_id, started, ended, delta, completed)
+                    VALUES ($1, $2, $3, $3, $4, $5)"#,
+                    uid,
+                    mid,
+                    now,
+                    delta,
+                    completed
+                )
+                .execute(&mut *conn)
+                .await?;
+
+                Ok(completed)
+            }
+        }
+    }
+
+    /// Method returns the most recent viewing sessions of a user, newest first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `uid` - the user whose history we want.
+    /// * `limit` - max number of sessions to return.
+    pub async fn get_of_user(
+        conn: &mut crate::Transaction<'_>,
+        uid: &str,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", user_id, media_id, started, ended, delta,
+                completed as "completed: bool"
+                FROM watch_history
+                WHERE user_id = ?
+                ORDER BY ended DESC
+                LIMIT ?"#,
+            uid,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns all the viewing sessions of a media by a user, newest first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `uid` - the user whose history we want.
+    /// * `mid` - id of the media.
+    pub async fn get_of_media(
+        conn: &mut crate::Transaction<'_>,
+        uid: &str,
+        mid: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", user_id, media_id, started, ended, delta,
+                completed as "completed: bool"
+                FROM watch_history
+                WHERE user_id = ? AND media_id = ?
+                ORDER BY ended DESC"#,
+            uid,
+            mid
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+}
@@ -0,0 +1,388 @@
+use crate::opt_update;
+use crate::utils::now;
+use crate::DatabaseError;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A url server events are posted to.
+#[derive(Clone, Debug, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    /// Key payloads are signed with, only handed out when the webhook is created.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    /// Events sent to this webhook, empty if all events are.
+    pub events: Vec<String>,
+    pub enabled: bool,
+    /// Timestamp of when the webhook was created.
+    pub created: i64,
+}
+
+struct WebhookRow {
+    id: i64,
+    url: String,
+    secret: String,
+    events: String,
+    enabled: bool,
+    created: i64,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(x: WebhookRow) -> Self {
+        Self {
+            id: x.id,
+            url: x.url,
+            secret: x.secret,
+            events: x
+                .events
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+            enabled: x.enabled,
+            created: x.created,
+        }
+    }
+}
+
+impl Webhook {
+    /// Method returns whether `event` should be sent to this webhook.
+    pub fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|x| x == event)
+    }
+
+    /// Method returns all the webhooks.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn get_all(conn: &mut crate::Transaction<'_>) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            WebhookRow,
+            r#"SELECT id as "id!", url, secret, events, enabled as "enabled: bool", created
+                FROM webhooks
+                ORDER BY id ASC"#
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+    }
+
+    /// Method returns a webhook by its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the webhook.
+    pub async fn get(conn: &mut crate::Transaction<'_>, id: i64) -> Result<Self, DatabaseError> {
+        Ok(sqlx::query_as!(
+            WebhookRow,
+            r#"SELECT id as "id!", url, secret, events, enabled as "enabled: bool", created
+                FROM webhooks
+                WHERE id = ?"#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?
+        .into())
+    }
+
+    /// Method returns the enabled webhooks `event` should be sent to.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `event` - name of the event, ie `EventNewLibrary`.
+    pub async fn get_for_event(
+        conn: &mut crate::Transaction<'_>,
+        event: &str,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(Self::get_all(&mut *conn)
+            .await?
+            .into_iter()
+            .filter(|x| x.enabled && x.wants(event))
+            .collect())
+    }
+
+    /// Method deletes a webhook along with its deliveries, returns the number of rows deleted.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the webhook.
+    pub async fn delete(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+    ) -> Result<usize, DatabaseError> {
+        Ok(sqlx::query!("DELETE FROM webhooks WHERE id = ?", id)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected() as usize)
+    }
+}
+
+/// Struct used to create a new webhook.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableWebhook {
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+}
+
+impl InsertableWebhook {
+    /// Method inserts a new webhook and returns its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        let events = self.events.join(",");
+        let now = now();
+
+        Ok(sqlx::query!(
+            "INSERT INTO webhooks (url, secret, events, created) VALUES ($1, $2, $3, $4)",
+            self.url,
+            self.secret,
+            events,
+            now
+        )
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid())
+    }
+}
+
+/// Struct used to update a webhook, fields set to `None` are left untouched.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct UpdateWebhook {
+    pub url: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
+
+impl UpdateWebhook {
+    /// Method applies the update to the webhook `id`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the webhook.
+    pub async fn update(
+        self,
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+    ) -> Result<usize, DatabaseError> {
+        let events = self.events.map(|x| x.join(","));
+
+        opt_update!(conn,
+            "UPDATE webhooks SET url = $1 WHERE id = ?2" => (self.url, id),
+            "UPDATE webhooks SET events = $1 WHERE id = ?2" => (events, id),
+            "UPDATE webhooks SET enabled = $1 WHERE id = ?2" => (self.enabled, id)
+        );
+
+        Ok(1)
+    }
+}
+
+/// State of a delivery.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    /// The delivery hasnt succeeded yet but will be attempted again.
+    Pending,
+    Delivered,
+    /// The delivery ran out of attempts.
+    Failed,
+}
+
+/// A event sent, or to be sent, to a webhook.
+#[derive(Clone, Debug, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    /// Name of the event, ie `EventNewLibrary`.
+    pub event: String,
+    /// The json body posted to the webhook.
+    pub payload: String,
+    pub status: DeliveryStatus,
+    /// Number of times the delivery has been attempted.
+    pub attempts: i64,
+    /// Status code of the last response, if we got one.
+    pub response_code: Option<i64>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    /// Timestamp of when the event happened.
+    pub created: i64,
+    /// Timestamp after which the delivery should be attempted again, only set while pending.
+    pub next_attempt: Option<i64>,
+    /// Timestamp of when the delivery succeeded.
+    pub delivered: Option<i64>,
+}
+
+impl WebhookDelivery {
+    /// Method returns the most recent deliveries to a webhook, newest first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `webhook_id` - id of the webhook.
+    /// * `limit` - max number of deliveries to return.
+    pub async fn get_of_webhook(
+        conn: &mut crate::Transaction<'_>,
+        webhook_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", webhook_id, event, payload, status as "status: DeliveryStatus",
+                attempts, response_code, error, created, next_attempt, delivered
+                FROM webhook_deliveries
+                WHERE webhook_id = ?
+                ORDER BY id DESC
+                LIMIT ?"#,
+            webhook_id,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method returns the pending deliveries which are due to be attempted, oldest first.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `limit` - max number of deliveries to return.
+    pub async fn get_due(
+        conn: &mut crate::Transaction<'_>,
+        limit: i64,
+    ) -> Result<Vec<Self>, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query_as!(
+            Self,
+            r#"SELECT id as "id!", webhook_id, event, payload, status as "status: DeliveryStatus",
+                attempts, response_code, error, created, next_attempt, delivered
+                FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt <= ?
+                ORDER BY next_attempt ASC
+                LIMIT ?"#,
+            now,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await?)
+    }
+
+    /// Method records a successful attempt.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the delivery.
+    /// * `response_code` - status code the webhook responded with.
+    pub async fn set_delivered(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        response_code: i64,
+    ) -> Result<usize, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            "UPDATE webhook_deliveries
+                SET status = 'delivered', attempts = attempts + 1, response_code = $1,
+                    error = NULL, next_attempt = NULL, delivered = $2
+                WHERE id = $3",
+            response_code,
+            now,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method records a failed attempt. The delivery is attempted again at `next_attempt`, or
+    /// marked as failed if that is `None`.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `id` - id of the delivery.
+    /// * `response_code` - status code the webhook responded with, if it responded.
+    /// * `error` - why the attempt failed.
+    /// * `next_attempt` - timestamp of when to try again.
+    pub async fn set_failed(
+        conn: &mut crate::Transaction<'_>,
+        id: i64,
+        response_code: Option<i64>,
+        error: &str,
+        next_attempt: Option<i64>,
+    ) -> Result<usize, DatabaseError> {
+        let status = if next_attempt.is_some() {
+            DeliveryStatus::Pending
+        } else {
+            DeliveryStatus::Failed
+        };
+
+        Ok(sqlx::query!(
+            "UPDATE webhook_deliveries
+                SET status = $1, attempts = attempts + 1, response_code = $2, error = $3,
+                    next_attempt = $4
+                WHERE id = $5",
+            status,
+            response_code,
+            error,
+            next_attempt,
+            id
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
+    /// Method deletes deliveries which are no longer pending and older than `age` seconds.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    /// * `age` - age in seconds after which deliveries are deleted.
+    pub async fn delete_older_than(
+        conn: &mut crate::Transaction<'_>,
+        age: i64,
+    ) -> Result<usize, DatabaseError> {
+        let cutoff = now() - age;
+
+        Ok(sqlx::query!(
+            "DELETE FROM webhook_deliveries WHERE status != 'pending' AND created < ?",
+            cutoff
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+}
+
+/// Struct used to queue a new delivery.
+#[derive(Clone, Debug, Default)]
+pub struct InsertableWebhookDelivery {
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+}
+
+impl InsertableWebhookDelivery {
+    /// Method queues the delivery to be attempted right away and returns its id.
+    ///
+    /// # Arguments
+    /// * `conn` - mutable reference to a sqlx transaction.
+    pub async fn insert(&self, conn: &mut crate::Transaction<'_>) -> Result<i64, DatabaseError> {
+        let now = now();
+
+        Ok(sqlx::query!(
+            r#"INSERT INTO webhook_deliveries (webhook_id, event, payload, created, next_attempt)
+                VALUES ($1, $2, $3, $4, $4)"#,
+            self.webhook_id,
+            self.event,
+            self.payload,
+            now
+        )
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid())
+    }
+}
@@ -0,0 +1,99 @@
+//! Backfill of certifications. Movies and tv shows scanned before certifications were stored, or
+//! whose certification the provider didnt know at the time, have their certification looked up
+//! once by a background job.
+
+use crate::core::DbConnection;
+use crate::jobs;
+use crate::jobs::Next;
+use crate::scanners::base::library_provider;
+use crate::scanners::provider::ProviderError;
+
+use database::media::Media;
+
+use tracing::{instrument, warn};
+
+use std::time::Duration;
+
+/// How often we check for media whose certification wasnt looked up.
+const POLL_INTERVAL: Duration = Duration::from_secs(3600);
+/// How long we wait before trying again when the provider cant be reached.
+const RETRY_INTERVAL: Duration = Duration::from_secs(600);
+/// Number of media fetched from the database in one go.
+const BATCH_SIZE: i64 = 20;
+
+/// Function runs the job looking up certifications, it never returns.
+///
+/// # Arguments
+/// * `conn` - database connection
+#[instrument(skip(conn))]
+pub async fn run(conn: DbConnection) {
+    jobs::run(POLL_INTERVAL, None, || round(&conn)).await
+}
+
+/// Function looks up the certification of the next batch of media.
+async fn round(conn: &DbConnection) -> Next {
+    let batch = match conn.read().begin().await {
+        Ok(mut tx) => Media::get_unchecked_certification(&mut tx, BATCH_SIZE).await,
+        Err(e) => Err(e.into()),
+    };
+
+    let batch = match jobs::batch(batch) {
+        Ok(x) => x,
+        Err(next) => return next,
+    };
+
+    for media in batch {
+        let certification = match lookup(conn, &media).await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(media = %media.name, reason = %e, "Failed to look up certification.");
+                return Next::Sleep(RETRY_INTERVAL);
+            }
+        };
+
+        if let Err(e) = store(conn, media.id, certification.as_deref()).await {
+            warn!(reason = ?e, "Failed to store certification.");
+            return Next::Idle;
+        }
+    }
+
+    Next::Continue
+}
+
+/// Function looks up the certification of `media`. Returns `None` if the provider doesnt know the
+/// media or its certification, and an error if the provider couldnt be asked.
+async fn lookup(conn: &DbConnection, media: &Media) -> Result<Option<String>, ProviderError> {
+    let provider = match library_provider(conn, media.library_id, media.media_type).await {
+        Ok(x) => x,
+        // the library was removed in the meantime.
+        Err(_) => return Ok(None),
+    };
+
+    let mut result = match provider
+        .search(&media.name, media.year.map(|x| x as i32))
+        .await
+    {
+        Ok(x) => x,
+        Err(ProviderError::Tmdb(e)) => return Err(ProviderError::Tmdb(e)),
+        Err(_) => return Ok(None),
+    };
+
+    provider.populate_certification(&mut result).await;
+
+    Ok(result.certification)
+}
+
+async fn store(
+    conn: &DbConnection,
+    id: i64,
+    certification: Option<&str>,
+) -> Result<(), database::DatabaseError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    Media::set_certification(&mut tx, id, certification).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -1,15 +1,27 @@
 use crate::balanced_or_tree;
+use crate::certifications;
+use crate::fetcher;
+use crate::images;
 use crate::logger::RequestLogger;
+use crate::markers;
+use crate::rate_limit::LoginLimiter;
 use crate::routes;
 use crate::scanners;
+use crate::scrobbler::ScrobbleDispatcher;
 use crate::stream_tracking::StreamTracking;
+use crate::trickplay;
+use crate::webhooks;
 use crate::websocket;
 
+use chrono::prelude::Utc;
 use once_cell::sync::OnceCell;
 
+use std::path::PathBuf;
+use std::time::Duration;
+
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::UnboundedSender;
-use tracing::{info, instrument};
+use tracing::{error, info, instrument};
 
 use warp::http::status::StatusCode;
 use warp::Filter;
@@ -18,7 +30,7 @@ use crate::routes::*;
 
 pub type StateManager = nightfall::StateManager;
 pub type DbConnection = database::DbConnection;
-pub type EventTx = UnboundedSender<String>;
+pub type EventTx = UnboundedSender<events::Envelope>;
 
 /// Path to where metadata is stored and should be fetched to.
 pub static METADATA_PATH: OnceCell<String> = OnceCell::new();
@@ -59,7 +71,196 @@ pub async fn run_scanners(tx: EventTx) {
                 });
             }
         }
+
+        tokio::spawn(run_scan_scheduler(conn.clone(), tx));
+    }
+}
+
+/// Function periodically rescans libraries which have a `scan_interval` set and whose last scan
+/// is older than that interval.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `tx` - websocket channel over which scan events are dispatched.
+#[instrument(skip_all)]
+pub async fn run_scan_scheduler(conn: DbConnection, tx: EventTx) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        let libs = match conn.read().begin().await {
+            Ok(mut db_tx) => database::library::Library::get_all(&mut db_tx).await,
+            Err(_) => continue,
+        };
+
+        let now = Utc::now().timestamp();
+
+        for lib in libs {
+            let due = match (lib.scan_interval, lib.last_scanned) {
+                (Some(scan_interval), Some(last_scanned)) => now - last_scanned >= scan_interval,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+
+            if due && !scanners::is_scanning(lib.id) {
+                info!(
+                    "Starting scheduled scan for {} with id: {}",
+                    lib.name, lib.id
+                );
+                tokio::spawn(scanners::start(conn.clone(), lib.id, tx.clone()));
+            }
+        }
+    }
+}
+
+/// Function purges expired sessions and registers the remaining ones with the auth crate, so that
+/// access tokens issued before a restart keep working. Must succeed before the server starts.
+///
+/// # Arguments
+/// * `conn` - database connection
+pub async fn track_sessions(conn: &DbConnection) -> Result<(), database::DatabaseError> {
+    use database::session::Session;
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    Session::delete_expired(&mut tx).await?;
+    for session in Session::get_all(&mut tx).await? {
+        ::auth::track_session(session.id);
     }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Function lets the auth middleware check sessions against the database, so that sessions
+/// deleted from it stop working even if this instance never revoked them.
+pub fn set_session_validator(conn: DbConnection) {
+    use database::session::Session;
+
+    ::auth::set_session_validator(move |id| {
+        let conn = conn.clone();
+
+        async move {
+            let mut tx = match conn.read().begin().await {
+                Ok(x) => x,
+                Err(_) => return false,
+            };
+
+            Session::is_active(&mut tx, &id).await.unwrap_or(false)
+        }
+    });
+}
+
+/// Function deletes failed login attempts older than 30 days from the audit log.
+///
+/// # Arguments
+/// * `conn` - database connection
+pub async fn purge_failed_logins(conn: &DbConnection) -> Result<(), database::DatabaseError> {
+    use database::failed_login::FailedLogin;
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    FailedLogin::delete_older_than(&mut tx, 60 * 60 * 24 * 30).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Function deletes webhook deliveries older than 30 days.
+///
+/// # Arguments
+/// * `conn` - database connection
+pub async fn purge_webhook_deliveries(conn: &DbConnection) -> Result<(), database::DatabaseError> {
+    use database::webhook::WebhookDelivery;
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    WebhookDelivery::delete_older_than(&mut tx, 60 * 60 * 24 * 30).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Function purges old failed logins and webhook deliveries, and trims the image cache once a day.
+///
+/// # Arguments
+/// * `conn` - database connection
+#[instrument(skip_all)]
+pub async fn run_purge_scheduler(conn: DbConnection) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60 * 24));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = purge_failed_logins(&conn).await {
+            error!(reason = ?e, "Failed to purge old failed logins.");
+        }
+
+        if let Err(e) = purge_webhook_deliveries(&conn).await {
+            error!(reason = ?e, "Failed to purge old webhook deliveries.");
+        }
+
+        let meta_path = PathBuf::from(METADATA_PATH.get().unwrap());
+        let evicted = tokio::task::spawn_blocking(move || {
+            images::evict_cache(&meta_path, images::MAX_CACHE_SIZE)
+        })
+        .await;
+
+        match evicted {
+            Ok(Ok(count)) => info!(count, "Evicted images from the cache."),
+            Ok(Err(e)) => error!(reason = ?e, "Failed to evict images from the cache."),
+            Err(e) => error!(reason = ?e, "Image cache eviction panicked."),
+        }
+    }
+}
+
+/// Function lets the auth middleware resolve API keys against the database. Keys are looked up on
+/// every request they are used in, so the time a key was last used is only written once a minute.
+pub fn set_api_key_validator(conn: DbConnection) {
+    use database::api_key::ApiKey;
+    use database::user::User;
+
+    ::auth::set_api_key_validator(move |key| {
+        let conn = conn.clone();
+
+        async move {
+            let mut tx = conn.read().begin().await.ok()?;
+            let key = ApiKey::get_by_key(&mut tx, &key).await.ok()?;
+            let user = User::get(&mut tx, &key.username).await.ok()?;
+            drop(tx);
+
+            if user.disabled {
+                return None;
+            }
+
+            let stale = key
+                .last_used
+                .map_or(true, |x| Utc::now().timestamp() - x >= 60);
+            if stale {
+                let id = key.id;
+                tokio::spawn(async move {
+                    let mut lock = conn.writer().lock_owned().await;
+                    if let Ok(mut tx) = database::write_tx(&mut lock).await {
+                        if ApiKey::touch(&mut tx, id).await.is_ok() {
+                            let _ = tx.commit().await;
+                        }
+                    }
+                });
+            }
+
+            Some(::auth::ApiKeyClaims {
+                id: key.id,
+                user: user.username,
+                roles: user.roles,
+                scopes: key.scopes.iter().filter_map(|x| x.parse().ok()).collect(),
+            })
+        }
+    });
 }
 
 #[instrument(skip(stream_manager, event_tx, rt, event_rx))]
@@ -68,30 +269,88 @@ pub async fn warp_core(
     stream_manager: StateManager,
     rt: tokio::runtime::Handle,
     port: u16,
-    event_rx: UnboundedReceiver<String>,
+    event_rx: UnboundedReceiver<events::Envelope>,
 ) {
     let state = stream_manager;
     let stream_tracking = StreamTracking::default();
+    let login_limiter = LoginLimiter::default();
     let conn = database::get_conn()
         .await
         .expect("Failed to grab a handle to the connection pool.");
 
+    // without the sessions loaded every access token would be rejected, so we rather not start.
+    track_sessions(&conn)
+        .await
+        .expect("Failed to load the active sessions.");
+
+    tokio::spawn(run_purge_scheduler(conn.clone()));
+
+    set_api_key_validator(conn.clone());
+    set_session_validator(conn.clone());
+
+    let scrobbler = ScrobbleDispatcher::new(conn.clone());
+    let event_rx = webhooks::tap(conn.clone(), event_rx);
+
+    tokio::spawn(fetcher::run(
+        conn.clone(),
+        METADATA_PATH.get().unwrap().into(),
+    ));
+    tokio::spawn(trickplay::run(
+        conn.clone(),
+        METADATA_PATH.get().unwrap().into(),
+    ));
+    tokio::spawn(markers::run(conn.clone()));
+    tokio::spawn(certifications::run(conn.clone()));
+
     let request_logger = RequestLogger::new();
 
     let api_routes = balanced_or_tree![
         /* NOTE: v1 REST API routes start HERE */
         /* /api/v1/auth and /user routes */
-        auth::filters::login(conn.clone()),
+        auth::filters::login(conn.clone(), login_limiter.clone()),
+        auth::filters::refresh(conn.clone()),
+        auth::filters::get_sessions(conn.clone()),
+        auth::filters::revoke_session(conn.clone()),
+        auth::filters::revoke_all_sessions(conn.clone()),
+        auth::filters::get_api_keys(conn.clone()),
+        auth::filters::create_api_key(conn.clone()),
+        auth::filters::delete_api_key(conn.clone()),
         auth::filters::whoami(conn.clone()),
         auth::filters::admin_exists(conn.clone()),
-        auth::filters::register(conn.clone()),
+        auth::filters::register(conn.clone(), login_limiter.clone()),
+        auth::filters::reset_password(conn.clone(), login_limiter),
         auth::filters::get_all_invites(conn.clone()),
+        auth::filters::create_invite(conn.clone()),
         auth::filters::generate_invite(conn.clone()),
         auth::filters::user_change_password(conn.clone()),
         auth::filters::admin_delete_token(conn.clone()),
         auth::filters::user_delete_self(conn.clone()),
         auth::filters::user_change_username(conn.clone()),
         auth::filters::user_upload_avatar(conn.clone()),
+        auth::filters::user_history(conn.clone()),
+        routes::scrobbler::filters::get_scrobblers(conn.clone()),
+        routes::scrobbler::filters::add_scrobbler(conn.clone()),
+        routes::scrobbler::filters::patch_scrobbler(conn.clone()),
+        routes::scrobbler::filters::delete_scrobbler(conn.clone()),
+        /* admin routes */
+        routes::admin::filters::get_users(conn.clone()),
+        routes::admin::filters::get_user(conn.clone()),
+        routes::admin::filters::create_user(conn.clone()),
+        routes::admin::filters::update_user(conn.clone()),
+        routes::admin::filters::reset_user_password(conn.clone()),
+        routes::admin::filters::delete_user(conn.clone()),
+        routes::admin::filters::get_roles(),
+        routes::admin::filters::set_user_roles(conn.clone()),
+        routes::admin::filters::get_user_libraries(conn.clone()),
+        routes::admin::filters::set_user_libraries(conn.clone(), event_tx.clone()),
+        routes::admin::filters::set_user_certification(conn.clone()),
+        routes::admin::filters::get_failed_logins(conn.clone()),
+        routes::admin::filters::get_fetcher_status(conn.clone()),
+        routes::webhook::filters::get_webhooks(conn.clone()),
+        routes::webhook::filters::add_webhook(conn.clone()),
+        routes::webhook::filters::patch_webhook(conn.clone()),
+        routes::webhook::filters::delete_webhook(conn.clone()),
+        routes::webhook::filters::get_webhook_deliveries(conn.clone()),
         /* general routes */
         routes::general::filters::search(conn.clone()),
         routes::general::filters::get_directory_structure(),
@@ -100,6 +359,10 @@ pub async fn warp_core(
         routes::library::filters::library_post(conn.clone(), event_tx.clone()),
         routes::library::filters::library_delete(conn.clone(), event_tx.clone()),
         routes::library::filters::library_get_self(conn.clone()),
+        routes::library::filters::library_patch(conn.clone()),
+        routes::library::filters::library_scan(conn.clone(), event_tx.clone()),
+        routes::library::filters::library_scan_status(conn.clone()),
+        routes::library::filters::library_cancel_scan(conn.clone()),
         routes::library::filters::get_all_of_library(conn.clone()),
         routes::library::filters::get_all_unmatched_media(conn.clone()),
         /* dashboard routes */
@@ -110,18 +373,30 @@ pub async fn warp_core(
         routes::media::filters::get_media_files(conn.clone()),
         routes::media::filters::update_media_by_id(conn.clone()),
         routes::media::filters::delete_media_by_id(conn.clone()),
-        routes::media::filters::tmdb_search(),
-        routes::media::filters::map_progress(conn.clone()),
+        routes::media::filters::tmdb_search(conn.clone()),
+        routes::media::filters::map_progress(conn.clone(), scrobbler.clone()),
+        routes::media::filters::mark_watched(conn.clone()),
+        routes::media::filters::mark_unwatched(conn.clone()),
+        routes::media::filters::get_media_history(conn.clone()),
         routes::rematch_media::filters::rematch_media_by_id(conn.clone(), event_tx.clone()),
         /* tv routes */
         routes::tv::filters::get_tv_seasons(conn.clone()),
         routes::tv::filters::patch_episode_by_id(conn.clone()),
         routes::tv::filters::delete_season_by_id(conn.clone()),
         routes::tv::filters::get_season_episodes(conn.clone()),
+        routes::tv::filters::mark_season_watched(conn.clone()),
+        routes::tv::filters::mark_season_unwatched(conn.clone()),
         routes::tv::filters::patch_episode_by_id(conn.clone()),
         routes::tv::filters::delete_episode_by_id(conn.clone()),
+        /* music routes */
+        routes::music::filters::get_artists(conn.clone()),
+        routes::music::filters::get_artist_by_id(conn.clone()),
+        routes::music::filters::get_album_by_id(conn.clone()),
+        routes::music::filters::get_track_by_id(conn.clone()),
         /* mediafile routes */
         routes::mediafile::filters::get_mediafile_info(conn.clone()),
+        routes::mediafile::filters::get_trickplay(conn.clone()),
+        routes::mediafile::filters::get_trickplay_file(conn.clone()),
         routes::mediafile::filters::rematch_mediafile(conn.clone()),
         /* settings routes */
         routes::settings::filters::get_user_settings(conn.clone()),
@@ -132,19 +407,27 @@ pub async fn warp_core(
         routes::stream::filters::return_virtual_manifest(
             conn.clone(),
             state.clone(),
-            stream_tracking.clone()
+            stream_tracking.clone(),
+            scrobbler.clone()
         ),
         routes::stream::filters::return_manifest(
             conn.clone(),
             state.clone(),
             stream_tracking.clone()
         ),
+        routes::stream::filters::return_hls_master(stream_tracking.clone()),
+        routes::stream::filters::return_hls_playlist(stream_tracking.clone()),
         routes::stream::filters::get_init(state.clone())
             .recover(routes::global_filters::handle_rejection),
         routes::stream::filters::should_client_hard_seek(state.clone(), stream_tracking.clone()),
         routes::stream::filters::session_get_stderr(state.clone(), stream_tracking.clone()),
-        routes::stream::filters::kill_session(state.clone(), stream_tracking.clone()),
+        routes::stream::filters::kill_session(
+            state.clone(),
+            stream_tracking.clone(),
+            scrobbler.clone()
+        ),
         routes::stream::filters::get_subtitle(state.clone()),
+        routes::stream::filters::get_thumbnail_sheet(stream_tracking.clone()),
         routes::stream::filters::get_chunk(state.clone())
             .recover(routes::global_filters::handle_rejection),
         warp::path!("api" / "stream" / ..)
@@ -164,7 +447,7 @@ pub async fn warp_core(
         /* NOTE: This is a barrier to 404 any rest api calls that dont match till here */
         routes::global_filters::api_not_found(),
         /* websocket route */
-        websocket::event_socket(tokio::runtime::Handle::current(), event_rx)
+        websocket::event_socket(tokio::runtime::Handle::current(), conn.clone(), event_rx)
             .recover(routes::global_filters::handle_rejection),
         /* static routes */
         routes::statik::filters::dist_static(),
@@ -3,6 +3,7 @@ use err_derive::Error;
 use serde::Serialize;
 use serde_json::json;
 
+use crate::rate_limit::RateLimited;
 use crate::scanners::base::ScannerError;
 use nightfall::error::NightfallError;
 
@@ -43,6 +44,26 @@ pub enum DimError {
     UnsupportedFile,
     #[error(display = "Library does not exist.")]
     LibraryNotFound,
+    #[error(display = "A scan of this library is already running.")]
+    ScanInProgress,
+    #[error(display = "The server must have at least one owner.")]
+    LastOwner,
+    #[error(display = "Unknown certification.")]
+    InvalidCertification,
+    #[error(display = "Too many requests, try again in {} seconds.", retry_after)]
+    TooManyRequests { retry_after: u64 },
+    #[error(display = "Username taken.")]
+    UsernameTaken,
+    #[error(display = "{}", reason)]
+    WeakPassword { reason: String },
+}
+
+impl From<RateLimited> for DimError {
+    fn from(e: RateLimited) -> Self {
+        Self::TooManyRequests {
+            retry_after: e.retry_after,
+        }
+    }
 }
 
 impl From<sqlx::Error> for DimError {
@@ -66,9 +87,13 @@ impl warp::Reply for DimError {
             | Self::ScannerError(_)
             | Self::UploadFailed => StatusCode::INTERNAL_SERVER_ERROR,
             Self::AuthRequired | Self::Unauthorized => StatusCode::UNAUTHORIZED,
-            Self::UnsupportedFile | Self::InvalidMediaType | Self::MissingFieldInBody { .. } => {
-                StatusCode::NOT_ACCEPTABLE
-            }
+            Self::UnsupportedFile
+            | Self::InvalidMediaType
+            | Self::InvalidCertification
+            | Self::WeakPassword { .. }
+            | Self::MissingFieldInBody { .. } => StatusCode::NOT_ACCEPTABLE,
+            Self::ScanInProgress | Self::LastOwner | Self::UsernameTaken => StatusCode::CONFLICT,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
         };
 
         let resp = json!({
@@ -103,6 +128,26 @@ pub enum AuthError {
     UsernameTaken,
     #[error(display = "Requested user doesnt exist.")]
     UserDoesntExist,
+    #[error(display = "The session has expired or was revoked.")]
+    InvalidSession,
+    #[error(display = "{}", reason)]
+    WeakPassword { reason: String },
+    #[error(display = "Too many attempts, try again in {} seconds.", retry_after)]
+    TooManyAttempts { retry_after: u64 },
+    #[error(display = "The invite has expired or has already been used.")]
+    InvalidInvite,
+    #[error(display = "This account has been disabled.")]
+    AccountDisabled,
+    #[error(display = "A new password has to be set before logging in.")]
+    PasswordResetRequired,
+}
+
+impl From<RateLimited> for AuthError {
+    fn from(e: RateLimited) -> Self {
+        Self::TooManyAttempts {
+            retry_after: e.retry_after,
+        }
+    }
 }
 
 impl From<sqlx::Error> for AuthError {
@@ -118,8 +163,16 @@ impl warp::Reply for AuthError {
         let status = match self {
             Self::NoTokenError | Self::UsernameTaken => StatusCode::OK,
             Self::DatabaseError | Self::RawDatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::Unauthorized | Self::UserDoesntExist => StatusCode::UNAUTHORIZED,
-            Self::WrongPassword | Self::FailedAuth => StatusCode::FORBIDDEN,
+            Self::Unauthorized | Self::UserDoesntExist | Self::InvalidSession => {
+                StatusCode::UNAUTHORIZED
+            }
+            Self::WrongPassword
+            | Self::FailedAuth
+            | Self::InvalidInvite
+            | Self::AccountDisabled
+            | Self::PasswordResetRequired => StatusCode::FORBIDDEN,
+            Self::WeakPassword { .. } => StatusCode::NOT_ACCEPTABLE,
+            Self::TooManyAttempts { .. } => StatusCode::TOO_MANY_REQUESTS,
         };
 
         let resp = json!({
@@ -159,6 +212,8 @@ pub enum StreamingErrors {
     FFProbeCtxFailed,
     #[error(display = "Could not parse the gid")]
     GidParseError,
+    #[error(display = "Transcode permission required to play this file")]
+    TranscodePermissionRequired,
 }
 
 impl From<sqlx::Error> for StreamingErrors {
@@ -173,7 +228,8 @@ impl warp::Reply for StreamingErrors {
     fn into_response(self) -> warp::reply::Response {
         let status = match self {
             Self::OtherNightfall(NightfallError::ChunkNotDone) => StatusCode::PROCESSING,
-            Self::NoMediaFileFound(_) => StatusCode::NOT_FOUND,
+            Self::NoMediaFileFound(_) | Self::SessionDoesntExist => StatusCode::NOT_FOUND,
+            Self::TranscodePermissionRequired => StatusCode::FORBIDDEN,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
@@ -1,71 +1,435 @@
-use crate::core::*;
+//! The fetcher downloads artwork into the metadata directory. Downloads are queued in the
+//! database with [`insert_into_queue`] and attempted in the background by [`run`], failed ones
+//! are retried with a backoff until they run out of attempts. The network is considered
+//! unreachable when no download of a batch could connect, in which case the queue is left
+//! untouched until it comes back, artwork which was fetched before keeps being served from disk in
+//! the meantime.
 
-use tokio::sync::mpsc::unbounded_channel;
-use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::sync::mpsc::UnboundedSender;
+use crate::core::DbConnection;
+use crate::jobs;
+use crate::jobs::Next;
 
-use tracing::{debug, error, instrument};
+use database::fetch_queue::FetchJob;
+use database::fetch_queue::InsertableFetchJob;
 
-use std::fs::File;
-use std::io::copy;
-use std::io::Cursor;
+use chrono::Utc;
+use err_derive::Error;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
+use ring::digest;
+use tokio::sync::Notify;
+use tracing::{info, instrument, warn};
+
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// How long a single download may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Delay in seconds before the first retry of a failed download, doubled on every further attempt.
+const RETRY_BASE: i64 = 60;
+/// Number of times a download is attempted before it is marked as failed.
+pub const MAX_ATTEMPTS: i64 = 6;
+/// How often we check for downloads which are due to be retried.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// How long we wait before trying again when the network is unreachable.
+const OFFLINE_DELAY: Duration = Duration::from_secs(60);
+/// Number of downloads fetched from the queue in one go.
+const BATCH_SIZE: i64 = 20;
+/// Number of downloads running at the same time.
+const CONCURRENCY: usize = 4;
+
+/// Wakes the fetcher up when something is queued.
+static QUEUED: Lazy<Notify> = Lazy::new(Notify::new);
+/// Set while the network is unreachable.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error(display = "Failed to connect: {}", _0)]
+    Connect(String),
+    #[error(display = "The request failed: {}", _0)]
+    Request(String),
+    #[error(display = "The download is invalid: {}", _0)]
+    Invalid(String),
+    #[error(display = "Failed to store the download: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            Self::Connect(e.to_string())
+        } else {
+            Self::Request(e.to_string())
+        }
+    }
+}
+
+/// Function queues `url` to be downloaded into the metadata directory, the file is named after
+/// the last segment of the url. Urls which are already queued are only queued once.
+///
+/// # Arguments
+/// * `conn` - mutable reference to a sqlx transaction.
+/// * `url` - url of the file to download.
+/// * `priority` - downloads with a higher priority are attempted first.
+#[instrument(skip(conn))]
+pub async fn insert_into_queue(
+    conn: &mut database::Transaction<'_>,
+    url: String,
+    priority: usize,
+) -> Result<(), database::DatabaseError> {
+    let local_path = match file_name(&url) {
+        Some(x) => x,
+        None => {
+            warn!("Refusing to fetch {} as it doesnt point to a file.", url);
+            return Ok(());
+        }
+    };
+
+    InsertableFetchJob {
+        url,
+        local_path,
+        priority: priority as i64,
+    }
+    .insert(&mut *conn)
+    .await?;
+
+    QUEUED.notify_one();
+
+    Ok(())
+}
+
+/// Returns whether the fetcher currently cant reach the network.
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Returns the name a download of `url` is stored as.
+fn file_name(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()?
+        .path_segments()?
+        .next_back()
+        .filter(|x| !x.is_empty() && *x != "." && *x != "..")
+        .map(ToString::to_string)
+}
+
+/// Function returns the delay before a download which failed `attempts` times is retried, or
+/// `None` if it shouldnt be retried.
+fn retry_delay(attempts: i64) -> Option<i64> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+
+    Some(RETRY_BASE * 2i64.pow(attempts.max(1) as u32 - 1))
+}
+
+fn checksum(data: &[u8]) -> String {
+    digest::digest(&digest::SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
 
-use once_cell::sync::OnceCell;
+/// Function runs the fetcher, it never returns.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `meta_path` - the metadata directory downloads are stored in.
+#[instrument(skip(conn))]
+pub async fn run(conn: DbConnection, meta_path: PathBuf) {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap_or_default();
 
-#[instrument]
-pub async fn insert_into_queue(poster: String, priority: usize) {
-    // FIXME: We might want to figure out a way to make this a const generic param.
-    const PARTITIONS: usize = 5;
-    static SENDER_PARTITIONS: OnceCell<[UnboundedSender<(String, usize)>; PARTITIONS]> =
-        OnceCell::new();
+    jobs::run(POLL_INTERVAL, Some(&*QUEUED), || {
+        round(&conn, &client, &meta_path)
+    })
+    .await
+}
 
-    let partitions = SENDER_PARTITIONS.get_or_init(|| {
-        [(); PARTITIONS].map(|_| {
-            let (tx, rx) = unbounded_channel();
-            tokio::spawn(process_queue(rx));
+/// Function attempts the downloads which are due and keeps track of whether the network is
+/// reachable.
+async fn round(conn: &DbConnection, client: &reqwest::Client, meta_path: &Path) -> Next {
+    match fetch_due(conn, client, meta_path).await {
+        Ok(true) => {
+            if OFFLINE.swap(false, Ordering::Relaxed) {
+                info!("The network is reachable again, resuming downloads.");
+            }
 
-            tx
-        })
-    });
+            Next::Idle
+        }
+        Ok(false) => {
+            if !OFFLINE.swap(true, Ordering::Relaxed) {
+                warn!("The network is unreachable, pausing downloads.");
+            }
 
-    partitions[priority % PARTITIONS]
-        .send((poster.clone(), priority))
-        .expect("Failed to send poster request");
+            Next::Sleep(OFFLINE_DELAY)
+        }
+        Err(e) => {
+            warn!(reason = ?e, "Failed to process the fetch queue.");
+            Next::Idle
+        }
+    }
 }
 
-#[instrument]
-async fn process_queue(mut rx: UnboundedReceiver<(String, usize)>) {
-    while let Some((url, _priority)) = rx.recv().await {
-        debug!("Trying to cache {}", url);
-
-        match reqwest::get(url.as_str()).await {
-            Ok(resp) => {
-                if let Some(fname) = resp.url().path_segments().and_then(|segs| segs.last()) {
-                    let meta_path = METADATA_PATH.get().unwrap();
-                    let mut out_path = PathBuf::from(meta_path);
-                    out_path.push(fname);
-
-                    debug!("Caching {} -> {:?}", url, out_path);
-
-                    if let Ok(mut file) = File::create(out_path) {
-                        if let Ok(bytes) = resp.bytes().await {
-                            let mut content = Cursor::new(bytes);
-                            if copy(&mut content, &mut file).is_ok() {
-                                continue;
-                            }
-                        }
-                    }
+/// Function attempts the downloads which are due. Returns `false` if it stopped because the
+/// network is unreachable.
+async fn fetch_due(
+    conn: &DbConnection,
+    client: &reqwest::Client,
+    meta_path: &Path,
+) -> Result<bool, database::DatabaseError> {
+    loop {
+        let jobs = {
+            let mut tx = conn.read().begin().await?;
+            FetchJob::get_due(&mut tx, BATCH_SIZE).await?
+        };
+
+        if jobs.is_empty() {
+            return Ok(true);
+        }
+
+        let batch = jobs.len() as i64;
+
+        let results = futures::stream::iter(jobs)
+            .map(|job| async move {
+                let result = fetch(client, meta_path, &job).await;
+                (job, result)
+            })
+            .buffer_unordered(CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+
+        // a single host being down doesnt mean we're offline, only failing to reach any of them
+        // does. In that case the attempts dont count and are retried once we're back online.
+        if results
+            .iter()
+            .all(|(_, x)| matches!(x, Err(FetchError::Connect(_))))
+        {
+            return Ok(false);
+        }
+
+        let mut lock = conn.writer().lock_owned().await;
+        let mut tx = database::write_tx(&mut lock).await?;
+
+        for (job, result) in results {
+            match result {
+                Ok(checksum) => {
+                    FetchJob::set_done(&mut tx, job.id, &checksum).await?;
                 }
+                Err(e) => {
+                    let next_attempt =
+                        retry_delay(job.attempts + 1).map(|x| Utc::now().timestamp() + x);
 
-                error!(
-                    "Failed to cache {} locally, appending back into queue",
-                    &url
-                );
+                    warn!(
+                        url = %job.url,
+                        reason = %e,
+                        retrying = next_attempt.is_some(),
+                        "Failed to fetch a file."
+                    );
+
+                    FetchJob::set_failed(&mut tx, job.id, &e.to_string(), next_attempt).await?;
+                }
             }
-            Err(e) => {
-                error!(e = ?e, "Failed to cache URL locally: {}", url);
+        }
+
+        tx.commit().await?;
+
+        // a full batch means there might be more downloads due.
+        if batch < BATCH_SIZE {
+            return Ok(true);
+        }
+    }
+}
+
+/// Function downloads a file into the metadata directory and returns its checksum. Files which
+/// are already on disk with the checksum of an earlier download arent fetched again.
+async fn fetch(
+    client: &reqwest::Client,
+    meta_path: &Path,
+    job: &FetchJob,
+) -> Result<String, FetchError> {
+    let path = meta_path.join(&job.local_path);
+
+    if let Some(expected) = job.checksum.as_ref() {
+        if let Ok(data) = tokio::fs::read(&path).await {
+            if checksum(&data) == *expected {
+                return Ok(expected.clone());
             }
         }
     }
+
+    let response = client.get(&job.url).send().await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(FetchError::Request(format!(
+            "The server responded with {}",
+            status
+        )));
+    }
+
+    let expected_len = response.content_length();
+    let data = response.bytes().await?;
+
+    if let Some(len) = expected_len.filter(|x| *x != data.len() as u64) {
+        return Err(FetchError::Invalid(format!(
+            "Got {} out of {} bytes",
+            data.len(),
+            len
+        )));
+    }
+
+    // guards against storing error pages served with a success status.
+    if image::guess_format(&data).is_err() {
+        return Err(FetchError::Invalid("The file isnt an image".into()));
+    }
+
+    // write to a temporary file first so that a partial download never ends up being served.
+    let tmp = path.with_extension(format!("{}.part", uuid::Uuid::new_v4()));
+    if let Err(e) = tokio::fs::write(&tmp, &data).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e.into());
+    }
+
+    tokio::fs::rename(&tmp, &path).await?;
+
+    Ok(checksum(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+    use warp::Filter;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            file_name("https://image.tmdb.org/t/p/original/abc.jpg").as_deref(),
+            Some("abc.jpg")
+        );
+        assert_eq!(file_name("https://image.tmdb.org/"), None);
+        assert_eq!(file_name("not a url"), None);
+    }
+
+    #[test]
+    fn backoff() {
+        assert_eq!(retry_delay(1), Some(RETRY_BASE));
+        assert_eq!(retry_delay(3), Some(RETRY_BASE * 4));
+        assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetches_files() {
+        let mut png = Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        let served = png.clone();
+        let routes = warp::path!("poster.png")
+            .map(move || served.clone())
+            .or(warp::path!("error.png").map(|| "Not an image"));
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let meta_path = std::env::temp_dir().join(format!("dim-fetcher-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&meta_path).unwrap();
+
+        let client = reqwest::Client::new();
+        let mut job = FetchJob {
+            id: 1,
+            url: format!("http://{}/poster.png", addr),
+            local_path: "poster.png".into(),
+            priority: 0,
+            status: database::fetch_queue::FetchStatus::Pending,
+            attempts: 0,
+            error: None,
+            checksum: None,
+            created: 0,
+            next_attempt: Some(0),
+            completed: None,
+        };
+
+        let sum = fetch(&client, &meta_path, &job).await.unwrap();
+        assert_eq!(sum, checksum(&png));
+        assert_eq!(std::fs::read(meta_path.join("poster.png")).unwrap(), png);
+
+        // files which are already on disk arent fetched again.
+        job.url = "http://127.0.0.1:1/poster.png".into();
+        job.checksum = Some(sum);
+        assert!(fetch(&client, &meta_path, &job).await.is_ok());
+
+        job.checksum = None;
+        assert!(matches!(
+            fetch(&client, &meta_path, &job).await,
+            Err(FetchError::Connect(_))
+        ));
+
+        job.url = format!("http://{}/error.png", addr);
+        job.local_path = "error.png".into();
+        assert!(matches!(
+            fetch(&client, &meta_path, &job).await,
+            Err(FetchError::Invalid(_))
+        ));
+        assert!(!meta_path.join("error.png").exists());
+
+        std::fs::remove_dir_all(meta_path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn counts_unreachable_hosts() {
+        let db = crate::tests::test_db().await;
+        let conn = &db.conn;
+
+        let mut png = Cursor::new(Vec::new());
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        let routes = warp::path!("poster.png").map(move || png.clone());
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let meta_path = std::env::temp_dir().join(format!("dim-fetcher-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&meta_path).unwrap();
+
+        let client = reqwest::Client::new();
+        let dead = "http://127.0.0.1:1/dead.png".to_string();
+
+        let queue = |url: String| async move {
+            let mut lock = conn.writer().lock_owned().await;
+            let mut tx = database::write_tx(&mut lock).await.unwrap();
+            insert_into_queue(&mut tx, url, 0).await.unwrap();
+            tx.commit().await.unwrap();
+        };
+
+        let attempts = |url: String| async move {
+            let mut tx = conn.read().begin().await.unwrap();
+            FetchJob::get_by_url(&mut tx, &url).await.unwrap().attempts
+        };
+
+        // nothing could connect, so we're offline and the attempt doesnt count.
+        queue(dead.clone()).await;
+        assert!(!fetch_due(conn, &client, &meta_path).await.unwrap());
+        assert_eq!(attempts(dead.clone()).await, 0);
+
+        // other hosts are reachable, so only this host is down.
+        queue(format!("http://{}/poster.png", addr)).await;
+        assert!(fetch_due(conn, &client, &meta_path).await.unwrap());
+        assert_eq!(attempts(dead).await, 1);
+        assert!(meta_path.join("poster.png").exists());
+
+        std::fs::remove_dir_all(meta_path).unwrap();
+    }
 }
@@ -0,0 +1,467 @@
+use err_derive::Error;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use image::ImageOutputFormat;
+
+use std::fs;
+use std::fs::Metadata;
+use std::io::Cursor;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// Largest width or height images can be resized to.
+pub const MAX_DIMENSION: u32 = 4096;
+/// Widths and heights images can be resized to. Requested dimensions are rounded up to the next one
+/// so that clients asking for slightly different sizes share the same cached image, and so that
+/// only a handful of variants of every image can ever be cached.
+const SIZES: [u32; 6] = [128, 256, 512, 1024, 2048, MAX_DIMENSION];
+/// Size in bytes the image cache is trimmed down to by [`evict_cache`].
+pub const MAX_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+const JPEG_QUALITY: u8 = 85;
+const WEBP_QUALITY: f32 = 80.0;
+#[cfg(feature = "avif")]
+const AVIF_QUALITY: u8 = 70;
+#[cfg(feature = "avif")]
+const AVIF_SPEED: u8 = 8;
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error(display = "An io error has occured: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "Failed to process image: {}", _0)]
+    Image(#[error(source)] image::ImageError),
+    #[error(display = "Failed to encode image: {}", _0)]
+    Encode(String),
+}
+
+/// Formats we can encode images into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl Format {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// Method picks the best format the client accepts based on its `Accept` header. Returns
+    /// `None` if the image should be kept in its original format.
+    pub fn negotiate(accept: Option<&str>) -> Option<Self> {
+        let accept = accept?;
+
+        if cfg!(feature = "avif") && accepts(accept, Self::Avif.mime()) {
+            return Some(Self::Avif);
+        }
+
+        if accepts(accept, Self::Webp.mime()) {
+            return Some(Self::Webp);
+        }
+
+        None
+    }
+}
+
+/// Returns whether `mime` is explicitly listed in a `Accept` header with a non-zero quality.
+/// Wildcards are ignored as clients sending `*/*` rarely mean that they can decode any image.
+fn accepts(accept: &str, mime: &str) -> bool {
+    accept.split(',').any(|x| {
+        let mut params = x.split(';').map(str::trim);
+
+        params
+            .next()
+            .map_or(false, |x| x.eq_ignore_ascii_case(mime))
+            && params
+                .find_map(|x| x.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0)
+                > 0.0
+    })
+}
+
+/// Returns the mime type of an encoded image, sniffed from its content.
+pub fn mime_of(data: &[u8]) -> &'static str {
+    image::guess_format(data)
+        .map(|x| x.to_mime_type())
+        .unwrap_or("application/octet-stream")
+}
+
+/// A derived version of a image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Variant {
+    /// Max width of the image.
+    pub width: Option<u32>,
+    /// Max height of the image.
+    pub height: Option<u32>,
+    /// Format to encode the image into, the original format is kept if not set.
+    pub format: Option<Format>,
+}
+
+impl Variant {
+    /// Creates a new variant, the requested dimensions are rounded up to the next one of
+    /// [`SIZES`] and clamped to [`MAX_DIMENSION`]. Zero dimensions are ignored.
+    pub fn new(width: Option<u32>, height: Option<u32>, format: Option<Format>) -> Self {
+        let snap = |x: u32| {
+            SIZES
+                .iter()
+                .copied()
+                .find(|size| *size >= x)
+                .unwrap_or(MAX_DIMENSION)
+        };
+
+        Self {
+            width: width.filter(|x| *x > 0).map(snap),
+            height: height.filter(|x| *x > 0).map(snap),
+            format,
+        }
+    }
+
+    /// Whether this variant is the original image as is.
+    pub fn is_original(&self) -> bool {
+        self.width.is_none() && self.height.is_none() && self.format.is_none()
+    }
+
+    /// Name the variant is cached under, ie `256x-.webp`.
+    fn key(&self) -> String {
+        let dim = |x: Option<u32>| x.map(|x| x.to_string()).unwrap_or_else(|| "-".into());
+
+        format!(
+            "{}x{}.{}",
+            dim(self.width),
+            dim(self.height),
+            self.format.map_or("orig", |x| x.extension())
+        )
+    }
+
+    /// Returns the `ETag` of this variant of a image. It is derived from the size and mtime of
+    /// the source image so that it can be checked without touching the image itself.
+    pub fn etag(&self, source: &Metadata) -> String {
+        let mtime = source
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+
+        format!("\"{:x}-{:x}-{}\"", mtime, source.len(), self.key())
+    }
+}
+
+/// Returns whether a `If-None-Match` header matches `etag`.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|x| {
+        x == "*" || x.strip_prefix("W/").unwrap_or(x) == etag.strip_prefix("W/").unwrap_or(etag)
+    })
+}
+
+/// Returns the variant of a image, rendering it and storing it under `{meta_path}/cache` if it
+/// isnt cached or the source image changed since. This does blocking io and should be called
+/// with `spawn_blocking`.
+///
+/// # Arguments
+/// * `meta_path` - the metadata directory images are stored in.
+/// * `file` - path to the source image, relative to `meta_path`.
+/// * `variant` - the variant to return.
+pub fn render(meta_path: &Path, file: &Path, variant: Variant) -> Result<Vec<u8>, ImageError> {
+    let source = meta_path.join(file);
+
+    if variant.is_original() {
+        return Ok(fs::read(source)?);
+    }
+
+    let cached = cache_path(meta_path, file, &variant);
+    let modified = fs::metadata(&source)?.modified()?;
+
+    if let Ok(cached_at) = fs::metadata(&cached).and_then(|x| x.modified()) {
+        if cached_at >= modified {
+            if let Ok(data) = fs::read(&cached) {
+                return Ok(data);
+            }
+        }
+    }
+
+    let data = fs::read(&source)?;
+    let source_format = image::guess_format(&data)?;
+    let image = image::load_from_memory_with_format(&data, source_format)?;
+
+    let width = variant.width.unwrap_or(u32::MAX).min(image.width());
+    let height = variant.height.unwrap_or(u32::MAX).min(image.height());
+
+    // we never upscale, a image smaller than the requested size is only re-encoded.
+    let image = if width < image.width() || height < image.height() {
+        image.resize(width, height, FilterType::CatmullRom)
+    } else {
+        image
+    };
+
+    let format = variant.format.unwrap_or(match source_format {
+        image::ImageFormat::Jpeg => Format::Jpeg,
+        _ => Format::Png,
+    });
+
+    let encoded = encode(&image, format)?;
+
+    if let Some(parent) = cached.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // write to a temporary file first so that concurrent requests never read a partial image.
+    let tmp = cached.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    fs::write(&tmp, &encoded)?;
+    fs::rename(&tmp, &cached)?;
+
+    Ok(encoded)
+}
+
+/// Removes the least recently used variants from `{meta_path}/cache` until it is no larger than
+/// `max_size` bytes, along with every variant of images which no longer exist. Returns the number
+/// of variants removed. This does blocking io and should be called with `spawn_blocking`.
+///
+/// # Arguments
+/// * `meta_path` - the metadata directory images are stored in.
+/// * `max_size` - size in bytes the cache may take up.
+pub fn evict_cache(meta_path: &Path, max_size: u64) -> Result<usize, ImageError> {
+    let root = meta_path.join("cache");
+    let mut variants = Vec::new();
+    let mut removed = 0;
+    let mut dirs = vec![root.clone()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(x) => x,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let path = entry.path();
+
+            if metadata.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+
+            // variants are stored under `cache/{file}/{key}`, so their parent mirrors the source.
+            let source = path
+                .parent()
+                .and_then(|x| x.strip_prefix(&root).ok())
+                .map(|x| meta_path.join(x));
+
+            if !matches!(source, Some(x) if x.is_file()) {
+                if fs::remove_file(&path).is_ok() {
+                    removed += 1;
+                }
+
+                continue;
+            }
+
+            let used = metadata
+                .accessed()
+                .or_else(|_| metadata.modified())
+                .unwrap_or(UNIX_EPOCH);
+
+            variants.push((used, metadata.len(), path));
+        }
+    }
+
+    let mut size = variants.iter().map(|(_, len, _)| len).sum::<u64>();
+    variants.sort_by_key(|(used, _, _)| *used);
+
+    for (_, len, path) in variants {
+        if size <= max_size {
+            break;
+        }
+
+        if fs::remove_file(&path).is_ok() {
+            size -= len;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+fn cache_path(meta_path: &Path, file: &Path, variant: &Variant) -> PathBuf {
+    let mut path = meta_path.join("cache");
+    path.push(file);
+    path.push(variant.key());
+
+    path
+}
+
+fn encode(image: &DynamicImage, format: Format) -> Result<Vec<u8>, ImageError> {
+    let mut out = Cursor::new(Vec::new());
+
+    match format {
+        // the jpeg encoder doesnt support alpha channels.
+        Format::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut out, ImageOutputFormat::Jpeg(JPEG_QUALITY))?,
+        Format::Png => image.write_to(&mut out, ImageOutputFormat::Png)?,
+        Format::Webp => {
+            let rgba = image.to_rgba8();
+            let encoded =
+                webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(WEBP_QUALITY);
+
+            return Ok(encoded.to_vec());
+        }
+        Format::Avif => return encode_avif(image),
+    }
+
+    Ok(out.into_inner())
+}
+
+#[cfg(feature = "avif")]
+fn encode_avif(image: &DynamicImage) -> Result<Vec<u8>, ImageError> {
+    use image::codecs::avif::AvifEncoder;
+    use image::ColorType;
+    use image::ImageEncoder;
+
+    let rgba = image.to_rgba8();
+    let mut out = Vec::new();
+
+    AvifEncoder::new_with_speed_quality(&mut out, AVIF_SPEED, AVIF_QUALITY).write_image(
+        &rgba,
+        rgba.width(),
+        rgba.height(),
+        ColorType::Rgba8,
+    )?;
+
+    Ok(out)
+}
+
+#[cfg(not(feature = "avif"))]
+fn encode_avif(_: &DynamicImage) -> Result<Vec<u8>, ImageError> {
+    Err(ImageError::Encode(
+        "dim was built without avif support".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbaImage;
+
+    #[test]
+    fn negotiates_formats() {
+        let chrome = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        let expected = if cfg!(feature = "avif") {
+            Format::Avif
+        } else {
+            Format::Webp
+        };
+
+        assert_eq!(Format::negotiate(Some(chrome)), Some(expected));
+        assert_eq!(
+            Format::negotiate(Some("image/webp;q=0.9, image/avif;q=0")),
+            Some(Format::Webp)
+        );
+        assert_eq!(Format::negotiate(Some("*/*")), None);
+        assert_eq!(Format::negotiate(None), None);
+    }
+
+    #[test]
+    fn snaps_dimensions() {
+        let variant = Variant::new(Some(300), Some(0), None);
+        assert_eq!(variant.width, Some(512));
+        assert_eq!(variant.height, None);
+        assert!(!variant.is_original());
+
+        let variant = Variant::new(Some(100_000), Some(1), None);
+        assert_eq!(variant.width, Some(MAX_DIMENSION));
+        assert_eq!(variant.height, Some(128));
+
+        assert!(Variant::new(None, Some(0), None).is_original());
+    }
+
+    #[test]
+    fn matches_etags() {
+        assert!(etag_matches("\"a\", \"b\"", "\"b\""));
+        assert!(etag_matches("W/\"a\"", "\"a\""));
+        assert!(etag_matches("*", "\"a\""));
+        assert!(!etag_matches("\"a\"", "\"b\""));
+    }
+
+    #[test]
+    fn renders_variants() {
+        let meta_path = std::env::temp_dir().join(format!("dim-images-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&meta_path).unwrap();
+
+        RgbaImage::new(400, 200)
+            .save_with_format(meta_path.join("poster.png"), image::ImageFormat::Png)
+            .unwrap();
+
+        let file = Path::new("poster.png");
+        let variant = Variant::new(Some(100), None, Some(Format::Webp));
+
+        let data = render(&meta_path, file, variant).unwrap();
+        assert_eq!(mime_of(&data), "image/webp");
+
+        let image = image::load_from_memory(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (128, 64));
+        assert!(cache_path(&meta_path, file, &variant).exists());
+
+        // images are never upscaled.
+        let variant = Variant::new(Some(1000), Some(1000), None);
+        let data = render(&meta_path, file, variant).unwrap();
+        let image = image::load_from_memory(&data).unwrap();
+        assert_eq!(mime_of(&data), "image/png");
+        assert_eq!((image.width(), image.height()), (400, 200));
+
+        fs::remove_dir_all(meta_path).unwrap();
+    }
+
+    #[test]
+    fn evicts_variants() {
+        let meta_path = std::env::temp_dir().join(format!("dim-images-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&meta_path).unwrap();
+
+        for name in ["poster.png", "backdrop.png"] {
+            RgbaImage::new(400, 200)
+                .save_with_format(meta_path.join(name), image::ImageFormat::Png)
+                .unwrap();
+        }
+
+        let poster = Path::new("poster.png");
+        let backdrop = Path::new("backdrop.png");
+        let small = Variant::new(Some(128), None, Some(Format::Png));
+        let large = Variant::new(Some(256), None, Some(Format::Png));
+
+        render(&meta_path, poster, small).unwrap();
+        render(&meta_path, poster, large).unwrap();
+        render(&meta_path, backdrop, small).unwrap();
+
+        // variants of images which are gone are always removed.
+        fs::remove_file(meta_path.join(backdrop)).unwrap();
+        assert_eq!(evict_cache(&meta_path, u64::MAX).unwrap(), 1);
+        assert!(!cache_path(&meta_path, backdrop, &small).exists());
+        assert!(cache_path(&meta_path, poster, &small).exists());
+
+        assert_eq!(evict_cache(&meta_path, 0).unwrap(), 2);
+        assert!(!cache_path(&meta_path, poster, &large).exists());
+
+        fs::remove_dir_all(meta_path).unwrap();
+    }
+}
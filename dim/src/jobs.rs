@@ -0,0 +1,164 @@
+//! Background jobs working through a backlog kept in the database, ie the [`fetcher`], trickplay
+//! sheets and markers. A job does its work in rounds, each round picks up a batch of outstanding
+//! work and tells [`run`] what to do next.
+//!
+//! [`fetcher`]: crate::fetcher
+
+use tokio::sync::Notify;
+use tokio::task::spawn_blocking;
+use tracing::warn;
+
+use std::future::Future;
+use std::io;
+use std::io::Read;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often [`output`] checks whether the child exited.
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a job does after a round of work.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Next {
+    /// Runs the next round right away as there may be more work left.
+    Continue,
+    /// Waits for new work, either because there is none left or because the round failed.
+    Idle,
+    /// Waits for the given duration before running the next round.
+    Sleep(Duration),
+}
+
+/// Function runs a job, it never returns. `round` is called over and over again, idle jobs are
+/// woken up every `poll_interval` or as soon as `wake` is notified.
+///
+/// # Arguments
+/// * `poll_interval` - how often an idle job checks for new work.
+/// * `wake` - notified when new work is queued.
+/// * `round` - does a round of work.
+pub async fn run<F, Fut>(poll_interval: Duration, wake: Option<&Notify>, mut round: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Next>,
+{
+    loop {
+        match round().await {
+            Next::Continue => {}
+            Next::Idle => match wake {
+                Some(wake) => {
+                    let _ = tokio::time::timeout(poll_interval, wake.notified()).await;
+                }
+                None => tokio::time::sleep(poll_interval).await,
+            },
+            Next::Sleep(x) => tokio::time::sleep(x).await,
+        }
+    }
+}
+
+/// Function returns the batch of work a round fetched, or what the job should do if it has
+/// nothing to work on.
+pub fn batch<T>(batch: Result<Vec<T>, database::DatabaseError>) -> Result<Vec<T>, Next> {
+    match batch {
+        Ok(x) if !x.is_empty() => Ok(x),
+        Ok(_) => Err(Next::Idle),
+        Err(e) => {
+            warn!(reason = ?e, "Failed to fetch outstanding work.");
+            Err(Next::Idle)
+        }
+    }
+}
+
+/// Function runs `f` on the blocking thread pool. Returns `None` if it panicked.
+pub async fn blocking<T, F>(f: F) -> Option<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match spawn_blocking(f).await {
+        Ok(x) => Some(x),
+        Err(e) => {
+            warn!(reason = ?e, "A background job panicked.");
+            None
+        }
+    }
+}
+
+/// Function runs `cmd` and collects its output like [`Command::output`], except that the child
+/// is killed and an error of kind [`io::ErrorKind::TimedOut`] returned once `timeout` expires.
+/// This blocks and should be called with [`blocking`].
+pub fn output(cmd: &mut Command, timeout: Duration) -> io::Result<Output> {
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    // the pipes are drained while we wait, otherwise a child filling them up would never exit.
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("the process didnt exit within {:?}", timeout),
+            ));
+        }
+
+        thread::sleep(WAIT_INTERVAL);
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+
+        buf
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_output() {
+        let output = output(
+            Command::new("sh").args(["-c", "echo out; echo err >&2"]),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[test]
+    fn kills_on_timeout() {
+        let start = Instant::now();
+        let result = output(Command::new("sleep").arg("10"), Duration::from_millis(200));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -24,26 +24,42 @@ use tracing_subscriber::fmt;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::EnvFilter;
 
+/// Looks up the certifications of media scanned without one.
+pub mod certifications;
 /// Module contains our core initialization logic.
 pub mod core;
 /// Module contains all the error definitions used in dim, and returned by the web-service.
 pub mod errors;
 /// Contains the code for fetching assets like posters and stills.
 pub mod fetcher;
+/// Resizing and re-encoding of the images we serve.
+pub mod images;
+/// Runs the background jobs working through work queued in the database.
+pub mod jobs;
 /// Contains our custom logger for rocket
 pub mod logger;
+/// Detects intros and end credits players can offer to skip.
+pub mod markers;
+/// Rate limiting of login and register attempts.
+pub mod rate_limit;
 /// Contains all of the routes exposed by the webapi.
 pub mod routes;
 /// Contains our media scanners and so on.
 pub mod scanners;
+/// Reports what users are watching to external services such as Trakt.
+pub mod scrobbler;
 /// Contains the fairing which tracks streams across rest api
 pub mod stream_tracking;
 /// Contains all the logic needed for streaming and on-the-fly transcoding.
 pub mod streaming;
 #[cfg(test)]
 mod tests;
+/// Generates the thumbnail sprite sheets used for seek previews.
+pub mod trickplay;
 /// Various utilities
 pub mod utils;
+/// Delivery of server events to admin configured webhooks.
+pub mod webhooks;
 /// Websocket related logic.
 pub mod websocket;
 
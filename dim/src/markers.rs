@@ -0,0 +1,576 @@
+//! Detection of intros and end credits. A background job fingerprints the audio at the start and
+//! end of every episode and compares it against the episodes next to it in the same season, audio
+//! they share is taken to be the intro or the credits. Episodes whose end matches nothing fall back
+//! to looking for a long stretch of mostly black frames, ie credits scrolling over black.
+//!
+//! The fingerprints follow the scheme of Haitsma and Kalker: every frame of audio is split into
+//! [`BANDS`] frequency bands and each bit of the fingerprint records whether the energy
+//! difference between two neighbouring bands grew or shrank since the previous frame. This
+//! survives re-encoding and volume changes while being cheap to compare.
+
+use crate::core::DbConnection;
+use crate::jobs;
+use crate::jobs::Next;
+use crate::streaming::FFMPEG_BIN;
+
+use database::marker::InsertableMarker;
+use database::marker::InsertableMarkerAnalysis;
+use database::marker::Marker;
+use database::marker::MarkerKind;
+use database::marker::MarkerTarget;
+
+use err_derive::Error;
+use tracing::{debug, instrument, warn};
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::process::Command;
+use std::process::Stdio;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Sample rate audio is resampled to before fingerprinting.
+const SAMPLE_RATE: u32 = 5512;
+/// Number of samples in a frame.
+const FRAME_SIZE: usize = 2048;
+/// Number of samples between the starts of two frames.
+const HOP_SIZE: usize = 512;
+/// Number of frequency bands, every pair of neighbouring bands makes up one bit.
+const BANDS: usize = 33;
+/// Frequency of the lowest band in Hz.
+const MIN_FREQ: f32 = 300.0;
+/// Frequency of the highest band in Hz.
+const MAX_FREQ: f32 = 2000.0;
+/// Frames quieter than this have no fingerprint, otherwise silence would match silence.
+const SILENCE_RMS: f32 = 100.0;
+/// Max number of bits in which two fingerprints may differ to still match.
+const MAX_BIT_ERRORS: u32 = 8;
+/// Seconds of unmatched frames a shared segment may contain.
+const MAX_GAP: f64 = 1.0;
+/// Seconds at the start of a file we look for the intro in.
+const INTRO_WINDOW: f64 = 600.0;
+/// Seconds at the end of a file we look for the credits in.
+const CREDITS_WINDOW: f64 = 300.0;
+/// Shortest intro we accept in seconds.
+const MIN_INTRO: f64 = 15.0;
+/// Longest intro we accept in seconds.
+const MAX_INTRO: f64 = 150.0;
+/// Shortest credits we accept in seconds.
+const MIN_CREDITS: f64 = 15.0;
+/// Seconds between two black segments for them to still count as one.
+const MAX_BLACK_GAP: f64 = 2.0;
+/// Number of episodes each episode is compared against.
+const MAX_REFERENCES: usize = 2;
+/// How often we check for episodes which werent analyzed once all episodes are.
+const POLL_INTERVAL: Duration = Duration::from_secs(300);
+/// Number of files fetched from the database in one go.
+const BATCH_SIZE: i64 = 20;
+/// How long ffmpeg may take to decode a window of a file before it is killed.
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Error)]
+pub enum MarkerError {
+    #[error(display = "An io error has occured: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "ffmpeg failed: {}", _0)]
+    Ffmpeg(String),
+    #[error(display = "the job panicked")]
+    Panicked,
+}
+
+/// Fingerprints of the start and end of a file.
+#[derive(Clone, Debug)]
+struct Fingerprints {
+    intro: Vec<Option<u32>>,
+    credits: Vec<Option<u32>>,
+    /// Offset in seconds of the first frame of `credits`.
+    credits_offset: f64,
+}
+
+impl Fingerprints {
+    fn of(target: &MarkerTarget) -> Result<Self, MarkerError> {
+        let duration = target.duration.unwrap_or_default() as f64;
+        let credits_window = CREDITS_WINDOW.min(duration / 3.0);
+        let credits_offset = duration - credits_window;
+
+        Ok(Self {
+            intro: fingerprint(&decode(
+                &target.target_file,
+                0.0,
+                INTRO_WINDOW.min(duration / 2.0),
+            )?),
+            credits: fingerprint(&decode(
+                &target.target_file,
+                credits_offset,
+                credits_window,
+            )?),
+            credits_offset,
+        })
+    }
+}
+
+/// Function runs the job detecting markers, it never returns.
+///
+/// # Arguments
+/// * `conn` - database connection
+#[instrument(skip(conn))]
+pub async fn run(conn: DbConnection) {
+    jobs::run(POLL_INTERVAL, None, || round(&conn)).await
+}
+
+/// Function analyzes the next batch of episodes which werent analyzed yet.
+async fn round(conn: &DbConnection) -> Next {
+    let targets = match conn.read().begin().await {
+        Ok(mut tx) => MarkerTarget::get_outdated(&mut tx, BATCH_SIZE).await,
+        Err(e) => Err(e.into()),
+    };
+
+    let targets = match jobs::batch(targets) {
+        Ok(x) => x,
+        Err(next) => return next,
+    };
+
+    // targets are ordered by season, episodes of a season are analyzed together so that the
+    // fingerprints of their siblings are only computed once.
+    let mut seasons: Vec<Vec<MarkerTarget>> = vec![];
+    for target in targets {
+        match seasons.last_mut() {
+            Some(x) if x[0].seasonid == target.seasonid => x.push(target),
+            _ => seasons.push(vec![target]),
+        }
+    }
+
+    for targets in seasons {
+        if let Err(e) = process(conn, targets).await {
+            warn!(reason = ?e, "Failed to store markers.");
+            return Next::Idle;
+        }
+    }
+
+    Next::Continue
+}
+
+async fn process(
+    conn: &DbConnection,
+    targets: Vec<MarkerTarget>,
+) -> Result<(), database::DatabaseError> {
+    let siblings = {
+        let mut tx = conn.read().begin().await?;
+        MarkerTarget::get_of_season(&mut tx, targets[0].seasonid).await?
+    };
+
+    let results = {
+        let targets = targets.clone();
+
+        jobs::blocking(move || {
+            let mut cache = HashMap::new();
+
+            targets
+                .into_iter()
+                .map(|target| {
+                    let markers = analyze(&target, &siblings, &mut cache);
+                    (target, markers)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+    };
+
+    let results = results.unwrap_or_else(|| {
+        targets
+            .into_iter()
+            .map(|x| (x, Err(MarkerError::Panicked)))
+            .collect()
+    });
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    for (target, markers) in results {
+        Marker::delete_of_mediafile(&mut tx, target.mediafile_id).await?;
+
+        let error = match markers {
+            Ok(markers) => {
+                for marker in markers {
+                    marker.insert(&mut tx).await?;
+                }
+
+                None
+            }
+            Err(e) => {
+                warn!(file = %target.target_file, reason = %e, "Failed to analyze file.");
+                Some(e.to_string())
+            }
+        };
+
+        InsertableMarkerAnalysis {
+            mediafile_id: target.mediafile_id,
+            file_mtime: target.file_mtime,
+            error,
+        }
+        .insert(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Detects the markers of `target` by comparing it against the episodes closest to it in
+/// `siblings`. This does blocking io and should be called with `spawn_blocking`.
+fn analyze(
+    target: &MarkerTarget,
+    siblings: &[MarkerTarget],
+    cache: &mut HashMap<i64, Option<Rc<Fingerprints>>>,
+) -> Result<Vec<InsertableMarker>, MarkerError> {
+    let prints = match cache.get(&target.mediafile_id) {
+        Some(Some(x)) => x.clone(),
+        _ => {
+            let prints = Rc::new(Fingerprints::of(target)?);
+            cache.insert(target.mediafile_id, Some(prints.clone()));
+            prints
+        }
+    };
+
+    let position = siblings
+        .iter()
+        .position(|x| x.mediafile_id == target.mediafile_id)
+        .unwrap_or_default();
+
+    let mut others = siblings
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.mediafile_id != target.mediafile_id)
+        .collect::<Vec<_>>();
+
+    // the episodes right before and after are the most likely to share the intro.
+    others.sort_by_key(|(i, _)| (*i as isize - position as isize).abs());
+
+    let references = others
+        .into_iter()
+        .take(MAX_REFERENCES)
+        .filter_map(|(_, x)| {
+            cache
+                .entry(x.mediafile_id)
+                .or_insert_with(|| match Fingerprints::of(x) {
+                    Ok(x) => Some(Rc::new(x)),
+                    Err(e) => {
+                        debug!(file = %x.target_file, reason = %e, "Failed to fingerprint sibling.");
+                        None
+                    }
+                })
+                .clone()
+        })
+        .collect::<Vec<_>>();
+
+    let longest = |shared: Vec<Range<usize>>| shared.into_iter().max_by_key(|x| x.len());
+    let duration = target.duration.unwrap_or_default() as f64;
+    let mut markers = vec![];
+
+    let intro = longest(
+        references
+            .iter()
+            .filter_map(|x| find_shared(&prints.intro, &x.intro))
+            .collect(),
+    )
+    .map(|x| (frame_time(x.start), frame_time(x.end)))
+    .filter(|(start, end)| (MIN_INTRO..=MAX_INTRO).contains(&(end - start)));
+
+    if let Some((start_time, end_time)) = intro {
+        markers.push(InsertableMarker {
+            mediafile_id: target.mediafile_id,
+            kind: MarkerKind::Intro,
+            start_time,
+            end_time,
+        });
+    }
+
+    let credits = longest(
+        references
+            .iter()
+            .filter_map(|x| find_shared(&prints.credits, &x.credits))
+            .collect(),
+    )
+    .map(|x| prints.credits_offset + frame_time(x.start))
+    .filter(|start| duration - start >= MIN_CREDITS)
+    .or_else(|| {
+        let window = duration - prints.credits_offset;
+
+        black_segments(&target.target_file, prints.credits_offset, window)
+            .map_err(|e| debug!(file = %target.target_file, reason = %e, "blackdetect failed."))
+            .ok()
+            .and_then(|x| credits_from_black(&x))
+            .map(|x| prints.credits_offset + x)
+    });
+
+    if let Some(start_time) = credits {
+        markers.push(InsertableMarker {
+            mediafile_id: target.mediafile_id,
+            kind: MarkerKind::Credits,
+            start_time,
+            end_time: duration,
+        });
+    }
+
+    debug!(file = %target.target_file, ?markers, "Analyzed file");
+
+    Ok(markers)
+}
+
+/// Decodes `length` seconds of the first audio stream of `file` starting at `start` into mono
+/// samples at [`SAMPLE_RATE`].
+fn decode(file: &str, start: f64, length: f64) -> Result<Vec<f32>, MarkerError> {
+    let mut cmd = Command::new(*FFMPEG_BIN);
+    cmd.args(["-v", "error"])
+        .args(["-ss", &start.to_string(), "-t", &length.to_string()])
+        .arg("-i")
+        .arg(file)
+        .args(["-map", "0:a:0", "-vn", "-sn"])
+        .args(["-ac", "1", "-ar", &SAMPLE_RATE.to_string()])
+        .args(["-f", "s16le", "-"])
+        .stdin(Stdio::null());
+
+    let output = jobs::output(&mut cmd, FFMPEG_TIMEOUT)?;
+
+    if !output.status.success() {
+        return Err(MarkerError::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(output
+        .stdout
+        .chunks_exact(2)
+        .map(|x| i16::from_le_bytes([x[0], x[1]]) as f32)
+        .collect())
+}
+
+/// Returns the black segments in `length` seconds of `file` starting at `start`, relative to
+/// `start`.
+fn black_segments(file: &str, start: f64, length: f64) -> Result<Vec<(f64, f64)>, MarkerError> {
+    let mut cmd = Command::new(*FFMPEG_BIN);
+    cmd.args(["-hide_banner", "-nostats"])
+        .args(["-ss", &start.to_string(), "-t", &length.to_string()])
+        .arg("-i")
+        .arg(file)
+        // scrolling credits leave most of the picture black.
+        .args(["-map", "0:V:0", "-an", "-sn"])
+        .args(["-vf", "blackdetect=d=0.5:pic_th=0.90"])
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null());
+
+    let output = jobs::output(&mut cmd, FFMPEG_TIMEOUT)?;
+
+    if !output.status.success() {
+        return Err(MarkerError::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(parse_blackdetect(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Parses the segments reported by ffmpeg's `blackdetect` filter.
+pub fn parse_blackdetect(log: &str) -> Vec<(f64, f64)> {
+    let value = |line: &str, key: &str| -> Option<f64> {
+        line.split_whitespace()
+            .find_map(|x| x.strip_prefix(key))?
+            .parse()
+            .ok()
+    };
+
+    log.lines()
+        .filter_map(|line| Some((value(line, "black_start:")?, value(line, "black_end:")?)))
+        .collect()
+}
+
+/// Returns when the credits start given the black segments at the end of a file, ie the start of
+/// the first run of black segments long enough to be credits.
+pub fn credits_from_black(segments: &[(f64, f64)]) -> Option<f64> {
+    let mut runs: Vec<(f64, f64)> = vec![];
+
+    for &(start, end) in segments {
+        match runs.last_mut() {
+            Some(last) if start - last.1 <= MAX_BLACK_GAP => last.1 = last.1.max(end),
+            _ => runs.push((start, end)),
+        }
+    }
+
+    runs.into_iter()
+        .find(|(start, end)| end - start >= MIN_CREDITS)
+        .map(|(start, _)| start)
+}
+
+/// Returns the offset in seconds of the `n`th frame.
+fn frame_time(n: usize) -> f64 {
+    (n * HOP_SIZE) as f64 / SAMPLE_RATE as f64
+}
+
+/// Computes the fingerprint of every frame of `samples`. Silent frames, and the first frame
+/// which has nothing to be compared against, have none.
+pub fn fingerprint(samples: &[f32]) -> Vec<Option<u32>> {
+    let window = (0..FRAME_SIZE)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / (FRAME_SIZE - 1) as f32).cos())
+        .collect::<Vec<_>>();
+
+    // bands are spaced logarithmically, like our hearing.
+    let coefficients = (0..BANDS)
+        .map(|m| {
+            let freq = MIN_FREQ * (MAX_FREQ / MIN_FREQ).powf(m as f32 / (BANDS - 1) as f32);
+            2.0 * (2.0 * PI * freq / SAMPLE_RATE as f32).cos()
+        })
+        .collect::<Vec<_>>();
+
+    let mut prints = vec![];
+    let mut previous: Option<Vec<f32>> = None;
+
+    for frame in samples.windows(FRAME_SIZE).step_by(HOP_SIZE) {
+        let rms = (frame.iter().map(|x| x * x).sum::<f32>() / FRAME_SIZE as f32).sqrt();
+        let energies = coefficients
+            .iter()
+            .map(|x| goertzel(frame, &window, *x))
+            .collect::<Vec<_>>();
+
+        let print = previous.filter(|_| rms >= SILENCE_RMS).map(|previous| {
+            (0..BANDS - 1).fold(0u32, |acc, m| {
+                let diff = energies[m] - energies[m + 1] - (previous[m] - previous[m + 1]);
+                acc | ((diff > 0.0) as u32) << m
+            })
+        });
+
+        prints.push(print);
+        previous = Some(energies);
+    }
+
+    prints
+}
+
+/// Energy of a single frequency in `frame`, `coefficient` being `2cos(2πf/rate)`.
+fn goertzel(frame: &[f32], window: &[f32], coefficient: f32) -> f32 {
+    let (s1, s2) = frame
+        .iter()
+        .zip(window)
+        .fold((0.0, 0.0), |(s1, s2), (x, w)| {
+            (x * w + coefficient * s1 - s2, s1)
+        });
+
+    s1 * s1 + s2 * s2 - coefficient * s1 * s2
+}
+
+/// Finds the longest segment of audio `a` and `b` share and returns where it is in `a`, trying
+/// every offset between the two.
+pub fn find_shared(a: &[Option<u32>], b: &[Option<u32>]) -> Option<Range<usize>> {
+    let max_gap = (MAX_GAP / frame_time(1)).ceil() as usize;
+    let mut best: Option<Range<usize>> = None;
+
+    let mut keep = |run: Option<(usize, usize)>| {
+        if let Some((first, last)) = run {
+            if best.as_ref().map_or(true, |x| last + 1 - first > x.len()) {
+                best = Some(first..last + 1);
+            }
+        }
+    };
+
+    // `a[i]` is compared against `b[i - shift]`.
+    for shift in -(b.len() as isize)..a.len() as isize {
+        let start = shift.max(0) as usize;
+        let end = (b.len() as isize + shift).min(a.len() as isize).max(0) as usize;
+        let mut run: Option<(usize, usize)> = None;
+
+        for i in start..end {
+            let matches = match (a[i], b[(i as isize - shift) as usize]) {
+                (Some(x), Some(y)) => (x ^ y).count_ones() <= MAX_BIT_ERRORS,
+                _ => false,
+            };
+
+            if !matches {
+                continue;
+            }
+
+            run = match run {
+                Some((first, last)) if i - last <= max_gap => Some((first, i)),
+                _ => {
+                    keep(run);
+                    Some((i, i))
+                }
+            };
+        }
+
+        keep(run);
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise, so that tests dont depend on luck.
+    fn noise(seed: u64, n: usize) -> Vec<f32> {
+        let mut state = seed;
+
+        (0..n)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                ((state >> 33) as i32 % 8000) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shared_audio() {
+        let rate = SAMPLE_RATE as usize;
+        let theme = noise(1, 30 * rate);
+
+        // the intro starts at 12.3s in one episode and 40.1s in the other, neither of which is a
+        // multiple of the hop size.
+        let a = [
+            noise(2, rate * 123 / 10),
+            theme.clone(),
+            noise(3, 60 * rate),
+        ]
+        .concat();
+        let b = [noise(4, rate * 401 / 10), theme, noise(5, 20 * rate)].concat();
+
+        let shared = find_shared(&fingerprint(&a), &fingerprint(&b)).unwrap();
+        let (start, end) = (frame_time(shared.start), frame_time(shared.end));
+
+        assert!((start - 12.3).abs() < 1.0, "{}", start);
+        assert!((end - 42.3).abs() < 1.0, "{}", end);
+
+        assert!(
+            find_shared(&fingerprint(&noise(6, 60 * rate)), &fingerprint(&a))
+                .map_or(true, |x| frame_time(x.len()) < MIN_INTRO)
+        );
+    }
+
+    #[test]
+    fn silence_has_no_fingerprint() {
+        let prints = fingerprint(&vec![0.0; 10 * SAMPLE_RATE as usize]);
+
+        assert!(!prints.is_empty());
+        assert!(prints.iter().all(Option::is_none));
+        assert_eq!(find_shared(&prints, &prints), None);
+    }
+
+    #[test]
+    fn blackdetect() {
+        let log = "\
+[blackdetect @ 0x5581] black_start:1.2 black_end:3.5 black_duration:2.3
+frame= 100 fps=0.0 q=-0.0 size=N/A time=00:00:04.00
+[blackdetect @ 0x5581] black_start:120.04 black_end:131 black_duration:10.96
+[blackdetect @ 0x5581] black_start:132.5 black_end:160.25 black_duration:27.75";
+
+        let segments = parse_blackdetect(log);
+        assert_eq!(segments, vec![(1.2, 3.5), (120.04, 131.0), (132.5, 160.25)]);
+
+        // short fades to black are not credits, the last two segments are close enough to be one.
+        assert_eq!(credits_from_black(&segments), Some(120.04));
+        assert_eq!(credits_from_black(&segments[..2]), None);
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Number of login and register attempts a single IP can make per [`IP_WINDOW`].
+const MAX_ATTEMPTS_PER_IP: u32 = 20;
+const IP_WINDOW: Duration = Duration::from_secs(60);
+/// Number of consecutive failed logins after which an account gets locked.
+const MAX_FAILURES: u32 = 5;
+/// How long an account stays locked for. Failures older than this are forgotten.
+const LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// Max number of entries in each of the maps. Once reached stale entries are dropped, then the
+/// least recently used ones.
+const MAX_ENTRIES: usize = 4096;
+
+/// Error returned when a client has to back off before trying again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimited {
+    /// Number of seconds until the client can try again.
+    pub retry_after: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Start of the current window and the number of attempts made in it for every IP.
+    ips: HashMap<IpAddr, (Instant, u32)>,
+    /// Failed logins of every username.
+    users: HashMap<String, Failures>,
+    /// IP every username last logged in from and when. Locked accounts can still log in from it.
+    trusted: HashMap<String, (IpAddr, Instant)>,
+}
+
+struct Failures {
+    /// Number of consecutive failures.
+    count: u32,
+    /// When the last failure happened.
+    last: Instant,
+    /// When the lockout ends, if the account is locked.
+    until: Option<Instant>,
+}
+
+impl Failures {
+    /// Returns whether this entry can be forgotten, ie the account isnt locked and the last failure
+    /// is too old to count towards a lockout.
+    fn is_stale(&self, now: Instant) -> bool {
+        let locked = matches!(self.until, Some(x) if x > now);
+        !locked && now.duration_since(self.last) >= LOCKOUT
+    }
+}
+
+/// Tracks login and register attempts per IP and failed logins per username. Attempts are only
+/// tracked in memory, a restart lifts every limit.
+///
+/// Locking a account doesnt lock out its owner, the IP a account last logged in from successfully
+/// is exempt from its lockout.
+#[derive(Clone, Default)]
+pub struct LoginLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+fn retry_after(until: Instant, now: Instant) -> RateLimited {
+    RateLimited {
+        retry_after: until.saturating_duration_since(now).as_secs().max(1),
+    }
+}
+
+/// Function makes room for the new entry `key` in `map` if it is full. Stale entries are dropped
+/// first, if there are none the least recently used entry is.
+fn make_room<K, V>(
+    map: &mut HashMap<K, V>,
+    key: &K,
+    is_stale: impl Fn(&V) -> bool,
+    last_used: impl Fn(&V) -> Instant,
+) where
+    K: Clone + Eq + Hash,
+{
+    if map.len() < MAX_ENTRIES || map.contains_key(key) {
+        return;
+    }
+
+    map.retain(|_, v| !is_stale(v));
+
+    if map.len() < MAX_ENTRIES {
+        return;
+    }
+
+    let lru = map
+        .iter()
+        .min_by_key(|(_, v)| last_used(v))
+        .map(|(k, _)| k.clone());
+
+    if let Some(k) = lru {
+        map.remove(&k);
+    }
+}
+
+impl LoginLimiter {
+    /// Method records a attempt made from `ip` and returns a error if the IP has made too many
+    /// attempts recently.
+    pub fn check_ip(&self, ip: Option<IpAddr>) -> Result<(), RateLimited> {
+        let ip = match ip {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let now = Instant::now();
+        let mut lock = self.inner.lock().unwrap();
+
+        make_room(
+            &mut lock.ips,
+            &ip,
+            |(start, _)| now.duration_since(*start) >= IP_WINDOW,
+            |(start, _)| *start,
+        );
+
+        let (start, attempts) = lock.ips.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= IP_WINDOW {
+            *start = now;
+            *attempts = 0;
+        }
+
+        *attempts += 1;
+        if *attempts > MAX_ATTEMPTS_PER_IP {
+            return Err(retry_after(*start + IP_WINDOW, now));
+        }
+
+        Ok(())
+    }
+
+    /// Method returns a error if `username` is currently locked, unless `ip` is the IP the account
+    /// last logged in from.
+    pub fn check_user(&self, username: &str, ip: Option<IpAddr>) -> Result<(), RateLimited> {
+        let now = Instant::now();
+        let lock = self.inner.lock().unwrap();
+
+        if matches!((ip, lock.trusted.get(username)), (Some(ip), Some((x, _))) if ip == *x) {
+            return Ok(());
+        }
+
+        match lock.users.get(username).and_then(|x| x.until) {
+            Some(until) if until > now => Err(retry_after(until, now)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Method records a failed login for `username`. Returns whether the account got locked by
+    /// this failure. Only failures of accounts which exist should be recorded, otherwise made up
+    /// usernames crowd out the real ones.
+    pub fn failure(&self, username: &str) -> bool {
+        let now = Instant::now();
+        let mut lock = self.inner.lock().unwrap();
+
+        let username = username.to_string();
+        make_room(&mut lock.users, &username, |x| x.is_stale(now), |x| x.last);
+
+        let failures = lock.users.entry(username).or_insert(Failures {
+            count: 0,
+            last: now,
+            until: None,
+        });
+
+        if failures.is_stale(now) {
+            failures.count = 0;
+        }
+
+        failures.count += 1;
+        failures.last = now;
+
+        if failures.count >= MAX_FAILURES {
+            failures.count = 0;
+            failures.until = Some(now + LOCKOUT);
+            return true;
+        }
+
+        false
+    }
+
+    /// Method resets the failed logins of `username` after a successful login from `ip`.
+    pub fn success(&self, username: &str, ip: Option<IpAddr>) {
+        let now = Instant::now();
+        let mut lock = self.inner.lock().unwrap();
+        lock.users.remove(username);
+
+        if let Some(ip) = ip {
+            let username = username.to_string();
+            make_room(&mut lock.trusted, &username, |_| false, |(_, at)| *at);
+            lock.trusted.insert(username, (ip, now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockout() {
+        let limiter = LoginLimiter::default();
+
+        for _ in 1..MAX_FAILURES {
+            assert!(!limiter.failure("user"));
+        }
+
+        assert!(limiter.check_user("user", None).is_ok());
+        assert!(limiter.failure("user"));
+        assert!(limiter.check_user("user", None).is_err());
+        assert!(limiter.check_user("other", None).is_ok());
+
+        limiter.success("user", None);
+        assert!(limiter.check_user("user", None).is_ok());
+    }
+
+    #[test]
+    fn trusted_ip() {
+        let limiter = LoginLimiter::default();
+        let home = Some("10.0.0.1".parse().unwrap());
+        let other = Some("10.0.0.2".parse().unwrap());
+
+        limiter.success("user", home);
+        for _ in 0..MAX_FAILURES {
+            limiter.failure("user");
+        }
+
+        assert!(limiter.check_user("user", other).is_err());
+        assert!(limiter.check_user("user", None).is_err());
+        assert!(limiter.check_user("user", home).is_ok());
+    }
+
+    #[test]
+    fn bounded() {
+        let limiter = LoginLimiter::default();
+
+        for i in 0..MAX_ENTRIES + 100 {
+            limiter.failure(&format!("user{}", i));
+            assert!(limiter
+                .check_ip(Some(IpAddr::from((i as u128).to_be_bytes())))
+                .is_ok());
+        }
+
+        let lock = limiter.inner.lock().unwrap();
+        assert_eq!(lock.users.len(), MAX_ENTRIES);
+        assert_eq!(lock.ips.len(), MAX_ENTRIES);
+        // the most recent entries are kept.
+        let last = format!("user{}", MAX_ENTRIES + 99);
+        assert!(lock.users.contains_key(&last));
+    }
+
+    #[test]
+    fn stale_failures() {
+        let now = Instant::now();
+        let mut failures = Failures {
+            count: 1,
+            last: now,
+            until: None,
+        };
+
+        assert!(!failures.is_stale(now));
+        assert!(failures.is_stale(now + LOCKOUT));
+
+        failures.until = Some(now + LOCKOUT * 2);
+        assert!(!failures.is_stale(now + LOCKOUT));
+        assert!(failures.is_stale(now + LOCKOUT * 2));
+    }
+
+    #[test]
+    fn ip_limit() {
+        let limiter = LoginLimiter::default();
+        let ip = Some("127.0.0.1".parse().unwrap());
+
+        for _ in 0..MAX_ATTEMPTS_PER_IP {
+            assert!(limiter.check_ip(ip).is_ok());
+        }
+
+        let err = limiter.check_ip(ip).unwrap_err();
+        assert!(err.retry_after <= IP_WINDOW.as_secs());
+        assert!(limiter.check_ip(Some("127.0.0.2".parse().unwrap())).is_ok());
+        assert!(limiter.check_ip(None).is_ok());
+    }
+}
@@ -0,0 +1,825 @@
+use crate::core::DbConnection;
+use crate::core::EventTx;
+use crate::errors;
+use crate::fetcher;
+
+use auth::Role;
+use auth::Wrapper as Auth;
+
+use database::asset::Asset;
+use database::failed_login::FailedLogin;
+use database::fetch_queue::FetchJob;
+use database::library::Library;
+use database::media::certification_level;
+use database::session::Session;
+use database::user::hash_password;
+use database::user::InsertableUser;
+use database::user::Login;
+use database::user::UpdateableUser;
+use database::user::User;
+
+use events::Envelope;
+use events::Message;
+use events::PushEventType;
+
+use serde_json::json;
+
+use warp::http::StatusCode;
+use warp::reply;
+
+pub mod filters {
+    use warp::reject;
+    use warp::Filter;
+    use warp::Rejection;
+
+    use super::super::global_filters::with_state;
+    use auth::Permission;
+    use auth::Role;
+    use auth::Wrapper as Auth;
+    use database::DbConnection;
+
+    use serde::Deserialize;
+
+    pub fn get_users(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "users")
+            .and(warp::get())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|auth: Auth, conn: DbConnection| async move {
+                super::get_users(conn, auth).await.map_err(reject::custom)
+            })
+    }
+
+    pub fn get_user(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "users" / String)
+            .and(warp::get())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |username: String, auth: Auth, conn: DbConnection| async move {
+                    super::get_user(conn, auth, username)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn create_user(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            username: String,
+            password: String,
+            roles: Option<Vec<Role>>,
+            #[serde(default = "database::user::default_true")]
+            password_reset: bool,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "users")
+            .and(warp::post())
+            .and(warp::body::json::<Params>())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |Params {
+                     username,
+                     password,
+                     roles,
+                     password_reset,
+                 }: Params,
+                 auth: Auth,
+                 conn: DbConnection| async move {
+                    let roles = roles.unwrap_or_else(|| vec![Role::User]);
+
+                    super::create_user(conn, auth, username, password, roles, password_reset)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn update_user(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            disabled: Option<bool>,
+            password_reset: Option<bool>,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "users" / String)
+            .and(warp::patch())
+            .and(warp::body::json::<Params>())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |username: String,
+                 Params {
+                     disabled,
+                     password_reset,
+                 }: Params,
+                 auth: Auth,
+                 conn: DbConnection| async move {
+                    super::update_user(conn, auth, username, disabled, password_reset)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn reset_user_password(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            password: String,
+            #[serde(default = "database::user::default_true")]
+            password_reset: bool,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "users" / String / "password")
+            .and(warp::put())
+            .and(warp::body::json::<Params>())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |username: String,
+                 Params {
+                     password,
+                     password_reset,
+                 }: Params,
+                 auth: Auth,
+                 conn: DbConnection| async move {
+                    super::reset_user_password(conn, auth, username, password, password_reset)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn delete_user(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "users" / String)
+            .and(warp::delete())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |username: String, auth: Auth, conn: DbConnection| async move {
+                    super::delete_user(conn, auth, username)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn get_roles() -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "roles")
+            .and(warp::get())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and_then(
+                |auth: Auth| async move { super::get_roles(auth).await.map_err(reject::custom) },
+            )
+    }
+
+    pub fn set_user_roles(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            roles: Vec<Role>,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "users" / String / "roles")
+            .and(warp::put())
+            .and(warp::body::json::<Params>())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |username: String,
+                 Params { roles }: Params,
+                 auth: Auth,
+                 conn: DbConnection| async move {
+                    super::set_user_roles(conn, auth, username, roles)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn get_user_libraries(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "users" / String / "libraries")
+            .and(warp::get())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |username: String, auth: Auth, conn: DbConnection| async move {
+                    super::get_user_libraries(conn, auth, username)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn set_user_libraries(
+        conn: DbConnection,
+        event_tx: EventTx,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            libraries: Option<Vec<i64>>,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "users" / String / "libraries")
+            .and(warp::put())
+            .and(warp::body::json::<Params>())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and(with_state::<EventTx>(event_tx))
+            .and_then(
+                |username: String,
+                 Params { libraries }: Params,
+                 auth: Auth,
+                 conn: DbConnection,
+                 event_tx: EventTx| async move {
+                    super::set_user_libraries(conn, event_tx, auth, username, libraries)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn set_user_certification(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            max_certification: Option<String>,
+            hide_unrated: Option<bool>,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "users" / String / "certification")
+            .and(warp::put())
+            .and(warp::body::json::<Params>())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |username: String,
+                 Params {
+                     max_certification,
+                     hide_unrated,
+                 }: Params,
+                 auth: Auth,
+                 conn: DbConnection| async move {
+                    super::set_user_certification(
+                        conn,
+                        auth,
+                        username,
+                        max_certification,
+                        hide_unrated,
+                    )
+                    .await
+                    .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn get_failed_logins(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            limit: Option<i64>,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "failed_logins")
+            .and(warp::get())
+            .and(warp::query::<Params>())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |Params { limit }: Params, auth: Auth, conn: DbConnection| async move {
+                    super::get_failed_logins(conn, auth, limit.unwrap_or(100))
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn get_fetcher_status(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            limit: Option<i64>,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "fetcher")
+            .and(warp::get())
+            .and(warp::query::<Params>())
+            .and(auth::with_permission(Permission::ManageSettings))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |Params { limit }: Params, auth: Auth, conn: DbConnection| async move {
+                    super::get_fetcher_status(conn, auth, limit.unwrap_or(100))
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+}
+
+fn is_owner(roles: &[String]) -> bool {
+    roles.iter().any(|x| x.parse::<Role>() == Ok(Role::Owner))
+}
+
+/// Function returns the number of users holding the owner role.
+async fn count_owners(tx: &mut database::Transaction<'_>) -> Result<usize, errors::DimError> {
+    Ok(User::get_all(tx)
+        .await?
+        .into_iter()
+        .filter(|x| is_owner(&x.roles))
+        .count())
+}
+
+/// Function fetches the user `username` and checks that the caller can manage them. Only owners
+/// can manage other owners.
+async fn get_managed_user(
+    tx: &mut database::Transaction<'_>,
+    user: &Auth,
+    username: &str,
+) -> Result<User, errors::DimError> {
+    let target = User::get(tx, username)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    if is_owner(&target.roles) && !user.0.claims.get_roles().contains(&Role::Owner) {
+        return Err(errors::DimError::Unauthorized);
+    }
+
+    Ok(target)
+}
+
+async fn user_json(
+    tx: &mut database::Transaction<'_>,
+    user: User,
+) -> Result<serde_json::Value, errors::DimError> {
+    let picture = Asset::get_of_user(tx, &user.username)
+        .await
+        .ok()
+        .map(|x| format!("/images/{}", x.local_path));
+
+    Ok(json!({
+        "username": user.username,
+        "roles": user.roles,
+        "picture": picture,
+        "disabled": user.disabled,
+        "password_reset": user.password_reset,
+        "libraries": User::get_library_access(tx, &user.username).await?,
+        "max_certification": user.prefs.max_certification,
+        "hide_unrated": user.prefs.hide_unrated,
+    }))
+}
+
+/// Method mapped to `GET /api/v1/admin/users` returns all the users.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+pub async fn get_users(
+    conn: DbConnection,
+    _user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+
+    let mut users = Vec::new();
+    for user in User::get_all(&mut tx).await? {
+        users.push(user_json(&mut tx, user).await?);
+    }
+
+    Ok(reply::json(&users))
+}
+
+/// Method mapped to `GET /api/v1/admin/users/<username>` returns a user.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `username` - user we want
+pub async fn get_user(
+    conn: DbConnection,
+    _user: Auth,
+    username: String,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    let user = User::get(&mut tx, &username)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    Ok(reply::json(&user_json(&mut tx, user).await?))
+}
+
+/// Method mapped to `POST /api/v1/admin/users` creates a user without a invite. Unless
+/// `password_reset` is `false` the user has to pick a new password the first time they log in.
+/// Only owners can create other owners.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `username` - username of the new user
+/// * `password` - initial password of the new user
+/// * `roles` - roles of the new user
+/// * `password_reset` - whether the user has to change their password on their first login
+pub async fn create_user(
+    conn: DbConnection,
+    user: Auth,
+    username: String,
+    password: String,
+    roles: Vec<Role>,
+    password_reset: bool,
+) -> Result<impl warp::Reply, errors::DimError> {
+    if roles.contains(&Role::Owner) && !user.0.claims.get_roles().contains(&Role::Owner) {
+        return Err(errors::DimError::Unauthorized);
+    }
+
+    super::auth::password_policy()
+        .check(&username, &password)
+        .map_err(|e| errors::DimError::WeakPassword {
+            reason: e.to_string(),
+        })?;
+
+    let password_hash = hash_password(password).await;
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    if User::get(&mut tx, &username).await.is_ok() {
+        return Err(errors::DimError::UsernameTaken);
+    }
+
+    let username = InsertableUser {
+        username,
+        password_hash,
+        roles: roles.iter().map(ToString::to_string).collect(),
+        prefs: Default::default(),
+        claimed_invite: Login::new_invite(&mut tx).await?,
+    }
+    .insert(&mut tx)
+    .await?;
+
+    User::set_password_reset(&mut tx, &username, password_reset).await?;
+    tx.commit().await?;
+
+    Ok(reply::json(&json!({ "username": username })))
+}
+
+/// Method mapped to `PATCH /api/v1/admin/users/<username>` disables or enables a user, and sets
+/// whether they have to pick a new password on their next login. Disabling a user or requiring a
+/// new password logs them out everywhere, disabling keeps their data. Users cannot disable
+/// themselves, and the last owner cannot be disabled.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `username` - user we want to update
+/// * `disabled` - whether the user should be disabled
+/// * `password_reset` - whether the user has to change their password on their next login
+pub async fn update_user(
+    conn: DbConnection,
+    user: Auth,
+    username: String,
+    disabled: Option<bool>,
+    password_reset: Option<bool>,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let target = get_managed_user(&mut tx, &user, &username).await?;
+    let mut revoked = Vec::new();
+
+    if let Some(disabled) = disabled {
+        if disabled && !target.disabled {
+            if target.username == user.user_ref() {
+                return Err(errors::DimError::Unauthorized);
+            }
+
+            if is_owner(&target.roles) && count_owners(&mut tx).await? <= 1 {
+                return Err(errors::DimError::LastOwner);
+            }
+
+            revoked = Session::delete_all_of_user(&mut tx, &username).await?;
+        }
+
+        User::set_disabled(&mut tx, &username, disabled).await?;
+    }
+
+    if let Some(password_reset) = password_reset {
+        if password_reset && !target.password_reset {
+            revoked.extend(Session::delete_all_of_user(&mut tx, &username).await?);
+        }
+
+        User::set_password_reset(&mut tx, &username, password_reset).await?;
+    }
+
+    tx.commit().await?;
+
+    for id in revoked {
+        ::auth::revoke_session(&id);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `PUT /api/v1/admin/users/<username>/password` sets the password of a user and
+/// logs them out everywhere. Unless `password_reset` is `false` the user has to pick a new password
+/// the next time they log in.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `username` - user whose password we want to reset
+/// * `password` - the new password
+/// * `password_reset` - whether the user has to change their password on their next login
+pub async fn reset_user_password(
+    conn: DbConnection,
+    user: Auth,
+    username: String,
+    password: String,
+    password_reset: bool,
+) -> Result<impl warp::Reply, errors::DimError> {
+    super::auth::password_policy()
+        .check(&username, &password)
+        .map_err(|e| errors::DimError::WeakPassword {
+            reason: e.to_string(),
+        })?;
+
+    let hash = hash_password(password).await;
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let target = get_managed_user(&mut tx, &user, &username).await?;
+    target.set_password(&mut tx, hash).await?;
+    User::set_password_reset(&mut tx, &username, password_reset).await?;
+    let revoked = Session::delete_all_of_user(&mut tx, &username).await?;
+
+    tx.commit().await?;
+
+    for id in revoked {
+        ::auth::revoke_session(&id);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `DELETE /api/v1/admin/users/<username>` deletes a user along with their watch
+/// history. Use `PATCH /api/v1/admin/users/<username>` to disable a user while keeping their data.
+/// The last owner cannot be deleted.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `username` - user we want to delete
+pub async fn delete_user(
+    conn: DbConnection,
+    user: Auth,
+    username: String,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let target = get_managed_user(&mut tx, &user, &username).await?;
+
+    if is_owner(&target.roles) && count_owners(&mut tx).await? <= 1 {
+        return Err(errors::DimError::LastOwner);
+    }
+
+    let revoked = Session::delete_all_of_user(&mut tx, &username).await?;
+    User::delete(&mut tx, username).await?;
+    tx.commit().await?;
+
+    for id in revoked {
+        ::auth::revoke_session(&id);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `GET /api/v1/admin/roles` returns all the roles which can be assigned to users
+/// along with the permissions they grant.
+///
+/// # Arguments
+/// * `_user` - Auth middleware
+pub async fn get_roles(_user: Auth) -> Result<impl warp::Reply, errors::DimError> {
+    let roles = Role::ALL
+        .iter()
+        .map(|x| {
+            json!({
+                "role": x,
+                "permissions": x.permissions(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(reply::json(&roles))
+}
+
+/// Method mapped to `PUT /api/v1/admin/users/<username>/roles` replaces the roles of a user. Only
+/// owners can grant or revoke the owner role, and the last owner cannot be demoted. Users pick up
+/// their new roles the next time their access token is refreshed.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `username` - user whose roles we want to set
+/// * `roles` - the new roles of the user
+pub async fn set_user_roles(
+    conn: DbConnection,
+    user: Auth,
+    username: String,
+    roles: Vec<Role>,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let roles = roles.into_iter().fold(Vec::new(), |mut acc, x| {
+        if !acc.contains(&x) {
+            acc.push(x);
+        }
+        acc
+    });
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let target = User::get(&mut tx, &username)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    let was_owner = is_owner(&target.roles);
+    let becomes_owner = roles.contains(&Role::Owner);
+
+    if was_owner != becomes_owner && !user.0.claims.get_roles().contains(&Role::Owner) {
+        return Err(errors::DimError::Unauthorized);
+    }
+
+    if was_owner && !becomes_owner && count_owners(&mut tx).await? <= 1 {
+        return Err(errors::DimError::LastOwner);
+    }
+
+    let roles = roles.iter().map(ToString::to_string).collect::<Vec<_>>();
+    User::set_roles(&mut tx, &username, &roles).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `GET /api/v1/admin/users/<username>/libraries` returns the ids of the libraries
+/// a user is restricted to. `libraries` is `null` if the user can see every library.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `username` - user whose restrictions we want
+pub async fn get_user_libraries(
+    conn: DbConnection,
+    _user: Auth,
+    username: String,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    let libraries = User::get_library_access(&mut tx, &username)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    Ok(reply::json(&json!({ "libraries": libraries })))
+}
+
+/// Method mapped to `PUT /api/v1/admin/users/<username>/libraries` restricts a user to only see
+/// the libraries supplied, or lifts the restriction if `libraries` is `null`. Only owners can
+/// restrict other owners. The clients of the user are notified so that they stop receiving events
+/// of libraries they cant see anymore.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `event_tx` - channel over which events are dispatched
+/// * `user` - Auth middleware
+/// * `username` - user whose restrictions we want to set
+/// * `libraries` - ids of the libraries the user can see
+pub async fn set_user_libraries(
+    conn: DbConnection,
+    event_tx: EventTx,
+    user: Auth,
+    username: String,
+    libraries: Option<Vec<i64>>,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    get_managed_user(&mut tx, &user, &username).await?;
+
+    for id in libraries.iter().flatten() {
+        Library::get_one(&mut tx, *id)
+            .await
+            .map_err(|_| errors::DimError::LibraryNotFound)?;
+    }
+
+    if User::set_library_access(&mut tx, &username, libraries.as_deref()).await? < 1 {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    tx.commit().await?;
+
+    let event = Message {
+        id: -1,
+        event_type: PushEventType::EventLibraryAccessChanged { libraries },
+    };
+
+    let _ = event_tx.send(Envelope::user(username, event));
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `PUT /api/v1/admin/users/<username>/certification` sets the highest
+/// certification, ie `PG-13`, of the media a user can see and stream. A `null` certification lifts
+/// the limit. Media without a known certification is hidden from users with a limit unless
+/// `hide_unrated` is set to `false`. Only owners can limit other owners.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `username` - user whose limit we want to set
+/// * `max_certification` - highest certification the user can see
+/// * `hide_unrated` - whether media without a known certification is hidden, unchanged if `None`
+pub async fn set_user_certification(
+    conn: DbConnection,
+    user: Auth,
+    username: String,
+    max_certification: Option<String>,
+    hide_unrated: Option<bool>,
+) -> Result<impl warp::Reply, errors::DimError> {
+    if let Some(x) = max_certification.as_deref() {
+        certification_level(x).ok_or(errors::DimError::InvalidCertification)?;
+    }
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let mut prefs = get_managed_user(&mut tx, &user, &username).await?.prefs;
+    prefs.max_certification = max_certification;
+    prefs.hide_unrated = hide_unrated.unwrap_or(prefs.hide_unrated);
+
+    UpdateableUser { prefs: Some(prefs) }
+        .update(&mut tx, &username)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `GET /api/v1/admin/failed_logins` returns the most recent failed login
+/// attempts, newest first. Attempts which caused an account to be locked are logged with the reason
+/// `locked`.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `limit` - max number of attempts to return, defaults to 100
+pub async fn get_failed_logins(
+    conn: DbConnection,
+    _user: Auth,
+    limit: i64,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+
+    Ok(reply::json(&FailedLogin::get_recent(&mut tx, limit).await?))
+}
+
+/// Method mapped to `GET /api/v1/admin/fetcher` returns the state of the artwork fetcher: the
+/// number of downloads in each state, whether the network is reachable, and the downloads which
+/// are pending or failed, highest priority first.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `limit` - max number of downloads to return, defaults to 100
+pub async fn get_fetcher_status(
+    conn: DbConnection,
+    _user: Auth,
+    limit: i64,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    let stats = FetchJob::stats(&mut tx).await?;
+
+    Ok(reply::json(&json!({
+        "offline": fetcher::is_offline(),
+        "pending": stats.pending,
+        "failed": stats.failed,
+        "done": stats.done,
+        "queue": FetchJob::get_unfinished(&mut tx, limit).await?,
+    })))
+}
@@ -1,16 +1,31 @@
 use crate::core::DbConnection;
 use crate::errors;
-use auth::{jwt_generate, Wrapper as Auth};
+use crate::rate_limit::LoginLimiter;
+use auth::{jwt_generate, Permission, Role, Wrapper as Auth};
+use auth::{ACCESS_TOKEN_TTL, REFRESH_TOKEN_TTL};
 use bytes::BufMut;
 
+use database::api_key::ApiKey;
+use database::api_key::InsertableApiKey;
 use database::asset::Asset;
 use database::asset::InsertableAsset;
+use database::failed_login::InsertableFailedLogin;
+use database::invite::InsertableInvite;
+use database::invite::Invite;
+use database::library::Library;
 use database::progress::Progress;
-use database::user::verify;
+use database::session::InsertableSession;
+use database::session::Session;
+use database::user::hash_password;
+use database::user::needs_rehash;
+use database::user::verify_password;
 use database::user::InsertableUser;
 use database::user::Login;
+use database::user::PasswordPolicy;
 use database::user::User;
+use database::watch_history::WatchHistory;
 
+use serde::Deserialize;
 use serde_json::json;
 
 use warp::reply;
@@ -20,31 +35,168 @@ use http::StatusCode;
 use futures::TryStreamExt;
 use uuid::Uuid;
 
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+
 pub mod filters {
     use crate::core::DbConnection;
+    use crate::rate_limit::LoginLimiter;
     use serde::Deserialize;
 
+    use std::net::SocketAddr;
+
     use warp::reject;
     use warp::Filter;
 
+    use auth::Permission;
     use database::user::Login;
 
     use super::super::global_filters::with_db;
+    use super::super::global_filters::with_state;
 
     pub fn login(
         conn: DbConnection,
+        limiter: LoginLimiter,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "auth" / "login")
             .and(warp::post())
             .and(warp::body::json::<Login>())
+            .and(warp::header::optional::<String>("user-agent"))
+            .and(warp::filters::addr::remote())
+            .and(with_state(limiter))
+            .and(with_db(conn))
+            .and_then(
+                |new_login: Login,
+                 device: Option<String>,
+                 addr: Option<SocketAddr>,
+                 limiter: LoginLimiter,
+                 conn: DbConnection| async move {
+                    super::login(new_login, device, addr, limiter, conn)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
+    pub fn refresh(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        #[derive(Deserialize)]
+        pub struct Params {
+            refresh_token: String,
+        }
+
+        warp::path!("api" / "v1" / "auth" / "refresh")
+            .and(warp::post())
+            .and(warp::body::json::<Params>())
             .and(with_db(conn))
-            .and_then(|new_login: Login, conn: DbConnection| async move {
-                super::login(new_login, conn)
+            .and_then(
+                |Params { refresh_token }: Params, conn: DbConnection| async move {
+                    super::refresh(refresh_token, conn)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
+    pub fn get_sessions(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "auth" / "sessions")
+            .and(warp::get())
+            .and(auth::with_auth())
+            .and(with_db(conn))
+            .and_then(|auth: auth::Wrapper, conn: DbConnection| async move {
+                super::get_sessions(conn, auth)
                     .await
                     .map_err(|e| reject::custom(e))
             })
     }
 
+    pub fn revoke_session(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "auth" / "sessions" / String)
+            .and(warp::delete())
+            .and(auth::with_auth())
+            .and(with_db(conn))
+            .and_then(
+                |id: String, auth: auth::Wrapper, conn: DbConnection| async move {
+                    super::revoke_session(conn, auth, id)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
+    pub fn revoke_all_sessions(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "auth" / "sessions")
+            .and(warp::delete())
+            .and(auth::with_auth())
+            .and(with_db(conn))
+            .and_then(|auth: auth::Wrapper, conn: DbConnection| async move {
+                super::revoke_all_sessions(conn, auth)
+                    .await
+                    .map_err(|e| reject::custom(e))
+            })
+    }
+
+    pub fn get_api_keys(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "auth" / "keys")
+            .and(warp::get())
+            .and(auth::with_auth())
+            .and(with_db(conn))
+            .and_then(|auth: auth::Wrapper, conn: DbConnection| async move {
+                super::get_api_keys(conn, auth)
+                    .await
+                    .map_err(|e| reject::custom(e))
+            })
+    }
+
+    pub fn create_api_key(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        #[derive(Deserialize)]
+        pub struct Params {
+            name: String,
+            scopes: Vec<Permission>,
+        }
+
+        warp::path!("api" / "v1" / "auth" / "keys")
+            .and(warp::post())
+            .and(auth::with_auth())
+            .and(warp::body::json::<Params>())
+            .and(with_db(conn))
+            .and_then(
+                |auth: auth::Wrapper, Params { name, scopes }: Params, conn: DbConnection| async move {
+                    super::create_api_key(conn, auth, name, scopes)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
+    pub fn delete_api_key(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "auth" / "keys" / i64)
+            .and(warp::delete())
+            .and(auth::with_auth())
+            .and(with_db(conn))
+            .and_then(
+                |id: i64, auth: auth::Wrapper, conn: DbConnection| async move {
+                    super::delete_api_key(conn, auth, id)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
     pub fn whoami(
         conn: DbConnection,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -74,16 +226,24 @@ pub mod filters {
 
     pub fn register(
         conn: DbConnection,
+        limiter: LoginLimiter,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "auth" / "register")
             .and(warp::post())
             .and(warp::body::json::<Login>())
+            .and(warp::filters::addr::remote())
+            .and(with_state(limiter))
             .and(with_db(conn))
-            .and_then(|new_login: Login, conn: DbConnection| async move {
-                super::register(new_login, conn)
-                    .await
-                    .map_err(|e| reject::custom(e))
-            })
+            .and_then(
+                |new_login: Login,
+                 addr: Option<SocketAddr>,
+                 limiter: LoginLimiter,
+                 conn: DbConnection| async move {
+                    super::register(new_login, addr, limiter, conn)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
     }
 
     pub fn get_all_invites(
@@ -91,7 +251,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "auth" / "invites")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageUsers))
             .and(with_db(conn))
             .and_then(|user: auth::Wrapper, conn: DbConnection| async move {
                 super::get_all_invites(conn, user)
@@ -100,12 +260,29 @@ pub mod filters {
             })
     }
 
+    pub fn create_invite(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "auth" / "invites")
+            .and(warp::post())
+            .and(auth::with_permission(Permission::ManageUsers))
+            .and(warp::body::json::<super::NewInvite>())
+            .and(with_db(conn))
+            .and_then(
+                |user: auth::Wrapper, invite: super::NewInvite, conn: DbConnection| async move {
+                    super::create_invite(conn, user, invite)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
     pub fn generate_invite(
         conn: DbConnection,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "auth" / "new_invite")
             .and(warp::post())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageUsers))
             .and(with_db(conn))
             .and_then(|user: auth::Wrapper, conn: DbConnection| async move {
                 super::generate_invite(conn, user)
@@ -142,12 +319,45 @@ pub mod filters {
             )
     }
 
+    pub fn reset_password(
+        conn: DbConnection,
+        limiter: LoginLimiter,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        #[derive(Deserialize)]
+        pub struct Params {
+            username: String,
+            old_password: String,
+            new_password: String,
+        }
+
+        warp::path!("api" / "v1" / "auth" / "password" / "reset")
+            .and(warp::post())
+            .and(warp::body::json::<Params>())
+            .and(warp::filters::addr::remote())
+            .and(with_state(limiter))
+            .and(with_db(conn))
+            .and_then(
+                |Params {
+                     username,
+                     old_password,
+                     new_password,
+                 }: Params,
+                 addr: Option<SocketAddr>,
+                 limiter: LoginLimiter,
+                 conn: DbConnection| async move {
+                    super::reset_password(conn, limiter, addr, username, old_password, new_password)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
     pub fn admin_delete_token(
         conn: DbConnection,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "auth" / "token" / String)
             .and(warp::delete())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageUsers))
             .and(with_db(conn))
             .and_then(
                 |token: String, auth: auth::Wrapper, conn: DbConnection| async move {
@@ -217,32 +427,366 @@ pub mod filters {
                     .map_err(|e| reject::custom(e))
             })
     }
+
+    pub fn user_history(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            limit: Option<i64>,
+        }
+
+        warp::path!("api" / "v1" / "user" / "history")
+            .and(warp::get())
+            .and(auth::with_auth())
+            .and(warp::query::<Params>())
+            .and(with_db(conn))
+            .and_then(
+                |user: auth::Wrapper, Params { limit }: Params, conn: DbConnection| async move {
+                    super::user_history(conn, user, limit.unwrap_or(100))
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+}
+
+/// Function logs a failed login attempt and counts it towards locking the account, if the account
+/// exists.
+async fn failed_login(
+    tx: &mut database::Transaction<'_>,
+    limiter: &LoginLimiter,
+    username: &str,
+    ip: Option<IpAddr>,
+    reason: &str,
+) -> Result<(), errors::AuthError> {
+    let mut attempt = InsertableFailedLogin {
+        username: username.to_string(),
+        ip: ip.map(|x| x.to_string()),
+        reason: reason.to_string(),
+    };
+
+    attempt.insert(&mut *tx).await?;
+
+    if User::get(&mut *tx, username).await.is_ok() && limiter.failure(username) {
+        attempt.reason = "locked".into();
+        attempt.insert(&mut *tx).await?;
+    }
+
+    Ok(())
 }
 
+/// Method mapped to `POST /api/v1/auth/login` checks the credentials of a user and starts a new
+/// session for the device logging in. Returns a short lived access token along with the refresh
+/// token used to get new access tokens.
+///
+/// Attempts are rate limited per IP, and accounts are locked for a while after too many failed
+/// attempts in a row. A locked account can still log in from the IP it last logged in from.
+///
+/// # Arguments
+/// * `new_login` - credentials of the user
+/// * `device` - user agent of the device logging in
+/// * `addr` - address of the client
+/// * `limiter` - login rate limiter
+/// * `conn` - database connection
 pub async fn login(
     new_login: Login,
+    device: Option<String>,
+    addr: Option<SocketAddr>,
+    limiter: LoginLimiter,
     conn: DbConnection,
 ) -> Result<impl warp::Reply, errors::AuthError> {
-    let mut tx = conn.read().begin().await?;
-    let user = User::get(&mut tx, &new_login.username)
-        .await
-        .map_err(|_| errors::AuthError::UserDoesntExist)?;
+    let ip = addr.map(|x| x.ip());
+    limiter.check_ip(ip)?;
+    limiter.check_user(&new_login.username, ip)?;
+
+    let user = {
+        let mut tx = conn.read().begin().await?;
+        User::get(&mut tx, &new_login.username).await
+    };
+
+    let user = match user {
+        Ok(x) => x,
+        Err(_) => {
+            let mut lock = conn.writer().lock_owned().await;
+            let mut tx = database::write_tx(&mut lock).await?;
+            failed_login(&mut tx, &limiter, &new_login.username, ip, "unknown_user").await?;
+            tx.commit().await?;
 
-    if verify(
+            return Err(errors::AuthError::UserDoesntExist);
+        }
+    };
+
+    let password = new_login.password;
+    let verified = verify_password(
         user.username.clone(),
         user.password.clone(),
-        new_login.password.clone(),
-    ) {
-        let token = jwt_generate(user.username, user.roles.clone());
+        password.clone(),
+    )
+    .await;
+
+    if verified {
+        limiter.success(&user.username, ip);
+
+        if user.disabled {
+            return Err(errors::AuthError::AccountDisabled);
+        }
+
+        if user.password_reset {
+            return Err(errors::AuthError::PasswordResetRequired);
+        }
+
+        // hashes in an older format or with fewer iterations are upgraded while we have the password.
+        let rehashed = if needs_rehash(&user.password) {
+            Some(hash_password(password).await)
+        } else {
+            None
+        };
+
+        let mut lock = conn.writer().lock_owned().await;
+        let mut tx = database::write_tx(&mut lock).await?;
+
+        if let Some(hash) = rehashed {
+            user.set_password(&mut tx, hash).await?;
+        }
+
+        let refresh_token = auth::generate_refresh_token();
+        let session = InsertableSession {
+            username: user.username.clone(),
+            refresh_token: refresh_token.clone(),
+            device,
+            ttl: REFRESH_TOKEN_TTL,
+        }
+        .insert(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        auth::track_session(session.clone());
+
+        let token = jwt_generate(user.username, user.roles.clone(), session);
 
         return Ok(reply::json(&json!({
             "token": token,
+            "refresh_token": refresh_token,
+            "expires_in": ACCESS_TOKEN_TTL,
         })));
     }
 
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+    failed_login(&mut tx, &limiter, &user.username, ip, "wrong_password").await?;
+    tx.commit().await?;
+
     Err(errors::AuthError::WrongPassword)
 }
 
+/// Method mapped to `POST /api/v1/auth/refresh` exchanges a refresh token for a new access token.
+/// The refresh token is rotated, clients must use the one returned from now on.
+///
+/// # Arguments
+/// * `refresh_token` - refresh token of the session
+/// * `conn` - database connection
+pub async fn refresh(
+    refresh_token: String,
+    conn: DbConnection,
+) -> Result<impl warp::Reply, errors::AuthError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let session = Session::get_by_refresh_token(&mut tx, &refresh_token)
+        .await
+        .map_err(|_| errors::AuthError::InvalidSession)?;
+
+    if !auth::is_session_active(&session.id) {
+        return Err(errors::AuthError::InvalidSession);
+    }
+
+    let user = User::get(&mut tx, &session.username)
+        .await
+        .map_err(|_| errors::AuthError::UserDoesntExist)?;
+
+    if user.disabled {
+        return Err(errors::AuthError::AccountDisabled);
+    }
+
+    let refresh_token = auth::generate_refresh_token();
+    Session::rotate(&mut tx, &session.id, &refresh_token, REFRESH_TOKEN_TTL).await?;
+    tx.commit().await?;
+
+    // roles are read again so that changes made by admins apply once the access token is refreshed.
+    let token = jwt_generate(user.username, user.roles, session.id);
+
+    Ok(reply::json(&json!({
+        "token": token,
+        "refresh_token": refresh_token,
+        "expires_in": ACCESS_TOKEN_TTL,
+    })))
+}
+
+/// Method mapped to `GET /api/v1/auth/sessions` returns all the devices the user is logged in on.
+/// The session the request was made with is marked as `current`.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+pub async fn get_sessions(
+    conn: DbConnection,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    let current = user.0.claims.get_session();
+
+    let sessions = Session::get_all_of_user(&mut tx, user.user_ref())
+        .await?
+        .into_iter()
+        .map(|x| {
+            json!({
+                "id": x.id,
+                "device": x.device,
+                "created": x.created,
+                "last_used": x.last_used,
+                "expires": x.expires,
+                "current": x.id == current,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(reply::json(&sessions))
+}
+
+/// Method mapped to `DELETE /api/v1/auth/sessions/<id>` revokes one of the sessions of the user,
+/// logging that device out.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `id` - id of the session to revoke
+pub async fn revoke_session(
+    conn: DbConnection,
+    user: Auth,
+    id: String,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    if Session::delete(&mut tx, user.user_ref(), &id).await? < 1 {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    tx.commit().await?;
+    auth::revoke_session(&id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `DELETE /api/v1/auth/sessions` revokes every session of the user, including
+/// the one used to make this request.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+pub async fn revoke_all_sessions(
+    conn: DbConnection,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let revoked = Session::delete_all_of_user(&mut tx, user.user_ref()).await?;
+    tx.commit().await?;
+
+    for id in revoked {
+        auth::revoke_session(&id);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `GET /api/v1/auth/keys` returns all the API keys of the user. The keys
+/// themselves are never returned again after they are created.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+pub async fn get_api_keys(
+    conn: DbConnection,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+
+    Ok(reply::json(
+        &ApiKey::get_all_of_user(&mut tx, user.user_ref()).await?,
+    ))
+}
+
+/// Method mapped to `POST /api/v1/auth/keys` creates a new API key for the user, limited to
+/// `scopes`. Users can only scope keys to permissions their roles grant, and keys cannot be used
+/// to create more keys. Scopes only limit the routes which require a permission, a key without
+/// scopes can still read everything its owner can. The key is only ever returned in this response.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `name` - name of the key, ie the name of the script using it
+/// * `scopes` - permissions the key is limited to
+pub async fn create_api_key(
+    conn: DbConnection,
+    user: Auth,
+    name: String,
+    scopes: Vec<Permission>,
+) -> Result<impl warp::Reply, errors::DimError> {
+    if !scopes.iter().all(|x| user.0.claims.has_permission(*x)) {
+        return Err(errors::DimError::Unauthorized);
+    }
+
+    let key = auth::generate_api_key();
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let id = InsertableApiKey {
+        username: user.user_ref().to_string(),
+        name: name.clone(),
+        key: key.clone(),
+        scopes: scopes.iter().map(ToString::to_string).collect(),
+    }
+    .insert(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(reply::json(&json!({
+        "id": id,
+        "name": name,
+        "scopes": scopes,
+        "key": key,
+    })))
+}
+
+/// Method mapped to `DELETE /api/v1/auth/keys/<id>` deletes one of the API keys of the user, it
+/// stops working immediately.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `id` - id of the key
+pub async fn delete_api_key(
+    conn: DbConnection,
+    user: Auth,
+    id: i64,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    if ApiKey::delete(&mut tx, user.user_ref(), id).await? < 1 {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 pub async fn whoami(user: Auth, conn: DbConnection) -> Result<impl warp::Reply, errors::DimError> {
     let username = user.0.claims.get_user();
     let mut tx = conn.read().begin().await?;
@@ -264,41 +808,63 @@ pub async fn admin_exists(conn: DbConnection) -> Result<impl warp::Reply, errors
     })))
 }
 
+/// Function returns the password policy configured in the global settings.
+pub(crate) fn password_policy() -> PasswordPolicy {
+    let settings = crate::get_global_settings();
+
+    PasswordPolicy {
+        min_length: settings.password_min_length,
+        banned: settings.banned_passwords,
+    }
+}
+
 pub async fn register(
     new_user: Login,
+    addr: Option<SocketAddr>,
+    limiter: LoginLimiter,
     conn: DbConnection,
 ) -> Result<impl warp::Reply, errors::AuthError> {
+    limiter.check_ip(addr.map(|x| x.ip()))?;
+
+    password_policy()
+        .check(&new_user.username, &new_user.password)
+        .map_err(|e| errors::AuthError::WeakPassword {
+            reason: e.to_string(),
+        })?;
+
+    let password_hash = hash_password(new_user.password.clone()).await;
+
     // FIXME: Return INTERNAL SERVER ERROR maybe with a traceback?
     let mut lock = conn.writer().lock_owned().await;
     let mut tx = database::write_tx(&mut lock).await?;
     // NOTE: I doubt this method can faily all the time, we should map server error here too.
     let users_empty = User::get_all(&mut tx).await?.is_empty();
 
-    if !users_empty
-        && (new_user.invite_token.is_none()
-            || !new_user.invite_token_valid(&mut tx).await.unwrap_or(false))
-    {
-        return Err(errors::AuthError::NoTokenError);
-    }
-
-    let roles = if !users_empty {
-        vec!["user".to_string()]
-    } else {
-        vec!["owner".to_string()]
-    };
-
-    let claimed_invite = if users_empty {
+    let (roles, libraries, claimed_invite) = if users_empty {
         // NOTE: Double check what we are returning here.
-        Login::new_invite(&mut tx).await?
+        let claimed_invite = Login::new_invite(&mut tx).await?;
+        (vec![Role::Owner.to_string()], None, claimed_invite)
     } else {
-        new_user
+        let token = new_user
             .invite_token
-            .ok_or(errors::AuthError::NoTokenError)?
+            .as_deref()
+            .ok_or(errors::AuthError::NoTokenError)?;
+
+        let invite = Invite::get(&mut tx, token)
+            .await
+            .map_err(|_| errors::AuthError::NoTokenError)?;
+
+        if !invite.is_valid() {
+            return Err(errors::AuthError::InvalidInvite);
+        }
+
+        let claimed_invite = Invite::claim(&mut tx, &invite.id).await?;
+        (invite.roles, invite.libraries, claimed_invite)
     };
 
     let res = InsertableUser {
         username: new_user.username.clone(),
-        password: new_user.password.clone(),
+        password_hash,
         roles,
         claimed_invite,
         prefs: Default::default(),
@@ -306,60 +872,131 @@ pub async fn register(
     .insert(&mut tx)
     .await?;
 
+    if let Some(libraries) = libraries {
+        User::set_library_access(&mut tx, &res, Some(&libraries)).await?;
+    }
+
     // FIXME: Return internal server error.
     tx.commit().await?;
 
     Ok(reply::json(&json!({ "username": res })))
 }
 
+/// Method mapped to `GET /api/v1/auth/invites` returns all the invites. `claimed_by` only holds
+/// the first user who registered with a invite, `claimants` holds all of them who still exist.
+/// `uses` counts deleted users too.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
 pub async fn get_all_invites(
     conn: DbConnection,
     user: Auth,
 ) -> Result<impl warp::Reply, errors::AuthError> {
     let mut tx = conn.read().begin().await?;
-    if user.0.claims.has_role("owner") {
-        #[derive(serde::Serialize)]
-        struct Row {
-            id: String,
-            created: i64,
-            claimed_by: Option<String>,
+    if user.0.claims.has_permission(Permission::ManageUsers) {
+        let invites = Invite::get_all(&mut tx)
+            .await?
+            .into_iter()
+            .map(|x| {
+                json!({
+                    "id": x.id,
+                    "created": x.created,
+                    "claimed_by": x.claimed_by.first(),
+                    "claimants": x.claimed_by,
+                    "uses": x.uses,
+                    "expires": x.expires,
+                    "max_uses": x.max_uses,
+                    "note": x.note,
+                    "roles": x.roles,
+                    "libraries": x.libraries,
+                    "valid": x.is_valid(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        return Ok(reply::json(&invites));
+    }
+
+    Err(errors::AuthError::Unauthorized)
+}
+
+/// Body of a request creating a invite.
+#[derive(Deserialize)]
+pub struct NewInvite {
+    /// Number of seconds the invite is valid for, `None` if it never expires.
+    pub expires_in: Option<NonZeroU32>,
+    /// Number of users who can register with the invite, defaults to one.
+    pub max_uses: Option<NonZeroU32>,
+    pub note: Option<String>,
+    /// Roles users registering with the invite get, defaults to [`Role::User`].
+    pub roles: Option<Vec<Role>>,
+    /// Libraries users registering with the invite are restricted to.
+    pub libraries: Option<Vec<i64>>,
+}
+
+impl From<NewInvite> for InsertableInvite {
+    fn from(invite: NewInvite) -> Self {
+        Self {
+            expires: invite
+                .expires_in
+                .map(|x| chrono::Utc::now().timestamp() + i64::from(x.get())),
+            max_uses: invite.max_uses.map_or(1, |x| i64::from(x.get())),
+            note: invite.note,
+            roles: invite
+                .roles
+                .unwrap_or_else(|| vec![Role::User])
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            libraries: invite.libraries,
         }
+    }
+}
 
-        // FIXME: LEFT JOINs cause sqlx::query! to panic, thus we must get tokens in two queries.
-        let mut row = sqlx::query_as!(
-            Row,
-            r#"SELECT invites.id, invites.date_added as created, NULL as "claimed_by: _"
-                FROM invites
-                WHERE invites.id NOT IN (SELECT users.claimed_invite FROM users)
-                ORDER BY created ASC"#
-        )
-        .fetch_all(&mut tx)
-        .await
-        .unwrap_or_default();
-
-        row.append(
-            &mut sqlx::query_as!(
-                Row,
-                r#"SELECT invites.id, invites.date_added as created, users.username as claimed_by
-            FROM  invites
-            INNER JOIN users ON users.claimed_invite = invites.id"#
-            )
-            .fetch_all(&mut tx)
-            .await
-            .unwrap_or_default(),
-        );
+/// Method mapped to `POST /api/v1/auth/invites` creates a invite which can be used `max_uses`
+/// times until it expires. Users registering with it get the roles and library restrictions of
+/// the invite. Only owners can create invites which grant the owner role.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `invite` - the invite to create
+pub async fn create_invite(
+    conn: DbConnection,
+    user: Auth,
+    invite: NewInvite,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let invite = InsertableInvite::from(invite);
+    let grants_owner = invite
+        .roles
+        .iter()
+        .any(|x| x.parse::<Role>() == Ok(Role::Owner));
+
+    if grants_owner && !user.0.claims.get_roles().contains(&Role::Owner) {
+        return Err(errors::DimError::Unauthorized);
+    }
 
-        return Ok(reply::json(&row));
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    for id in invite.libraries.iter().flatten() {
+        Library::get_one(&mut tx, *id)
+            .await
+            .map_err(|_| errors::DimError::LibraryNotFound)?;
     }
 
-    Err(errors::AuthError::Unauthorized)
+    let token = invite.insert(&mut tx).await?;
+    tx.commit().await?;
+
+    Ok(reply::json(&json!({ "token": token })))
 }
 
 pub async fn generate_invite(
     conn: DbConnection,
     user: Auth,
 ) -> Result<impl warp::Reply, errors::AuthError> {
-    if !user.0.claims.has_role("owner") {
+    if !user.0.claims.has_permission(Permission::ManageUsers) {
         return Err(errors::AuthError::Unauthorized);
     }
 
@@ -378,7 +1015,7 @@ pub async fn delete_invite(
     user: Auth,
     token: String,
 ) -> Result<impl warp::Reply, errors::AuthError> {
-    if !user.0.claims.has_role("owner") {
+    if !user.0.claims.has_permission(Permission::ManageUsers) {
         return Err(errors::AuthError::Unauthorized);
     }
 
@@ -396,13 +1033,101 @@ pub async fn user_change_password(
     old_password: String,
     new_password: String,
 ) -> Result<impl warp::Reply, errors::AuthError> {
+    password_policy()
+        .check(user.user_ref(), &new_password)
+        .map_err(|e| errors::AuthError::WeakPassword {
+            reason: e.to_string(),
+        })?;
+
+    let current = user.0.claims.get_session().to_string();
+    let user = {
+        let mut tx = conn.read().begin().await?;
+        User::get_one(&mut tx, user.0.claims.get_user(), old_password)
+            .await
+            .map_err(|_| errors::AuthError::WrongPassword)?
+    };
+    let hash = hash_password(new_password).await;
+
     let mut lock = conn.writer().lock_owned().await;
     let mut tx = database::write_tx(&mut lock).await?;
-    let user = User::get_one(&mut tx, user.0.claims.get_user(), old_password)
-        .await
-        .map_err(|_| errors::AuthError::WrongPassword)?;
-    user.set_password(&mut tx, new_password).await?;
+    user.set_password(&mut tx, hash).await?;
+    User::set_password_reset(&mut tx, &user.username, false).await?;
+
+    // every other device has to log in again with the new password.
+    let mut revoked = Vec::new();
+    for session in Session::get_all_of_user(&mut tx, &user.username).await? {
+        if session.id != current {
+            Session::delete(&mut tx, &user.username, &session.id).await?;
+            revoked.push(session.id);
+        }
+    }
+
+    tx.commit().await?;
+
+    for id in revoked {
+        auth::revoke_session(&id);
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Method mapped to `POST /api/v1/auth/password/reset` lets users who have to pick a new password
+/// before logging in, ie after a admin reset their password, set one without being logged in.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `limiter` - login rate limiter
+/// * `addr` - address of the client
+/// * `username` - username of the user
+/// * `old_password` - current password of the user
+/// * `new_password` - the new password
+pub async fn reset_password(
+    conn: DbConnection,
+    limiter: LoginLimiter,
+    addr: Option<SocketAddr>,
+    username: String,
+    old_password: String,
+    new_password: String,
+) -> Result<impl warp::Reply, errors::AuthError> {
+    let ip = addr.map(|x| x.ip());
+    limiter.check_ip(ip)?;
+    limiter.check_user(&username, ip)?;
+
+    password_policy()
+        .check(&username, &new_password)
+        .map_err(|e| errors::AuthError::WeakPassword {
+            reason: e.to_string(),
+        })?;
+
+    let user = {
+        let mut tx = conn.read().begin().await?;
+        User::get_one(&mut tx, username.clone(), old_password).await
+    };
+
+    let user = match user {
+        Ok(x) => x,
+        Err(_) => {
+            let mut lock = conn.writer().lock_owned().await;
+            let mut tx = database::write_tx(&mut lock).await?;
+            failed_login(&mut tx, &limiter, &username, ip, "wrong_password").await?;
+            tx.commit().await?;
 
+            return Err(errors::AuthError::WrongPassword);
+        }
+    };
+
+    limiter.success(&username, ip);
+
+    if user.disabled {
+        return Err(errors::AuthError::AccountDisabled);
+    }
+
+    let hash = hash_password(new_password).await;
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+    user.set_password(&mut tx, hash).await?;
+    User::set_password_reset(&mut tx, &username, false).await?;
     tx.commit().await?;
 
     Ok(StatusCode::OK)
@@ -413,16 +1138,25 @@ pub async fn user_delete_self(
     user: Auth,
     password: String,
 ) -> Result<impl warp::Reply, errors::AuthError> {
+    {
+        let mut tx = conn.read().begin().await?;
+        let _ = User::get_one(&mut tx, user.0.claims.get_user(), password)
+            .await
+            .map_err(|_| errors::AuthError::WrongPassword)?;
+    }
+
     let mut lock = conn.writer().lock_owned().await;
     let mut tx = database::write_tx(&mut lock).await?;
-    let _ = User::get_one(&mut tx, user.0.claims.get_user(), password)
-        .await
-        .map_err(|_| errors::AuthError::WrongPassword)?;
 
+    let revoked = Session::delete_all_of_user(&mut tx, user.user_ref()).await?;
     User::delete(&mut tx, user.0.claims.get_user()).await?;
 
     tx.commit().await?;
 
+    for id in revoked {
+        auth::revoke_session(&id);
+    }
+
     Ok(StatusCode::OK)
 }
 
@@ -509,3 +1243,22 @@ pub async fn process_part(
     .insert(conn)
     .await?)
 }
+
+/// Method mapped to `GET /api/v1/user/history` returns the most recent viewing sessions of the
+/// user, newest first.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `limit` - max number of sessions to return, defaults to 100
+pub async fn user_history(
+    conn: DbConnection,
+    user: Auth,
+    limit: i64,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+
+    Ok(reply::json(
+        &WatchHistory::get_of_user(&mut tx, user.user_ref(), limit).await?,
+    ))
+}
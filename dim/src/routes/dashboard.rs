@@ -1,11 +1,13 @@
 use crate::core::DbConnection;
 use crate::errors;
 use crate::json;
+use crate::routes::library::certification_limit;
 
 use auth::Wrapper as Auth;
 
 use database::episode::Episode;
 use database::genre::*;
+use database::library::Library;
 use database::library::MediaType;
 use database::media::Media;
 use database::mediafile::MediaFile;
@@ -13,6 +15,8 @@ use database::progress::Progress;
 
 use serde_json::Value;
 
+use std::collections::HashSet;
+
 use warp::reply;
 
 pub mod filters {
@@ -33,7 +37,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "dashboard")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and(with_state::<TokioHandle>(rt))
             .and_then(
@@ -50,7 +54,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "dashboard" / "banner")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|user: Auth, conn: DbConnection| async move {
                 super::banners(conn, user)
@@ -66,13 +70,21 @@ pub async fn dashboard(
     _rt: tokio::runtime::Handle,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
+    let username = user.0.claims.get_user();
+    let cert_limit = certification_limit(&mut tx, &user).await?;
 
     let mut top_rated = Vec::new();
     for media in Media::get_top_rated(&mut tx, 10).await? {
         let item = match sqlx::query!(
             "SELECT _tblmedia.name, assets.local_path FROM _tblmedia LEFT JOIN assets ON assets.id = _tblmedia.poster
-            WHERE _tblmedia.id = ?",
-            media
+            WHERE _tblmedia.id = ?
+            AND _tblmedia.library_id IN (SELECT library_id FROM visible_library WHERE username = ?)
+            AND (_tblmedia.certification_level <= ?
+                OR (_tblmedia.certification_level IS NULL AND (_tblmedia.media_type = 'music' OR NOT ?)))",
+            media,
+            username,
+            cert_limit.level,
+            cert_limit.hide_unrated
         ).fetch_one(&mut tx).await {
             Ok(x) => x,
             Err(_) => continue,
@@ -89,8 +101,14 @@ pub async fn dashboard(
     for media in Media::get_recently_added(&mut tx, 10).await? {
         let item = match sqlx::query!(
             "SELECT _tblmedia.name, assets.local_path FROM _tblmedia LEFT JOIN assets ON assets.id = _tblmedia.poster
-            WHERE _tblmedia.id = ?",
-            media
+            WHERE _tblmedia.id = ?
+            AND _tblmedia.library_id IN (SELECT library_id FROM visible_library WHERE username = ?)
+            AND (_tblmedia.certification_level <= ?
+                OR (_tblmedia.certification_level IS NULL AND (_tblmedia.media_type = 'music' OR NOT ?)))",
+            media,
+            username,
+            cert_limit.level,
+            cert_limit.hide_unrated
         ).fetch_one(&mut tx).await {
             Ok(x) => x,
             Err(_) => continue,
@@ -107,8 +125,14 @@ pub async fn dashboard(
     for media in Progress::get_continue_watching(&mut tx, user.0.claims.get_user(), 10).await? {
         let item = match sqlx::query!(
             "SELECT _tblmedia.name, assets.local_path FROM _tblmedia LEFT JOIN assets ON assets.id = _tblmedia.poster
-            WHERE _tblmedia.id = ?",
-            media
+            WHERE _tblmedia.id = ?
+            AND _tblmedia.library_id IN (SELECT library_id FROM visible_library WHERE username = ?)
+            AND (_tblmedia.certification_level <= ?
+                OR (_tblmedia.certification_level IS NULL AND (_tblmedia.media_type = 'music' OR NOT ?)))",
+            media,
+            username,
+            cert_limit.level,
+            cert_limit.hide_unrated
         ).fetch_one(&mut tx).await {
             Ok(x) => x,
             Err(_) => continue,
@@ -138,12 +162,28 @@ pub async fn dashboard(
 
 pub async fn banners(conn: DbConnection, user: Auth) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
+    let visible = Library::get_all_for_user(&mut tx, user.user_ref())
+        .await?
+        .into_iter()
+        .map(|x| x.id)
+        .collect::<HashSet<_>>();
+    let cert_limit = certification_limit(&mut tx, &user).await?;
+
     let mut banners = Vec::new();
     for media in Media::get_random_with(&mut tx, 10).await? {
+        if !visible.contains(&media.library_id) {
+            continue;
+        }
+
+        if !cert_limit.allows(Media::get_certification_level(&mut tx, media.id).await?) {
+            continue;
+        }
+
         if let Ok(x) = match media.media_type {
             MediaType::Tv => banner_for_show(&mut tx, &user, &media).await,
             MediaType::Movie => banner_for_movie(&mut tx, &user, &media).await,
-            _ => unreachable!(),
+            // albums have no backdrops to build a banner with.
+            _ => continue,
         } {
             banners.push(x);
         }
@@ -206,12 +246,12 @@ async fn banner_for_show(
     let episode = if let Ok(Some(ep)) =
         Episode::get_last_watched_episode(&mut *conn, media.id, user.0.claims.get_user()).await
     {
-        let (delta, duration) =
-            Progress::get_progress_for_media(&mut *conn, ep.id, user.0.claims.get_user())
-                .await
-                .unwrap_or((0, 1));
+        let watched = Progress::get_for_media_user(&mut *conn, user.0.claims.get_user(), ep.id)
+            .await
+            .map(|x| x.watched)
+            .unwrap_or(false);
 
-        if (delta as f64 / duration as f64) > 0.90 {
+        if watched {
             ep.get_next_episode(&mut *conn)
                 .await
                 .unwrap_or(ep)
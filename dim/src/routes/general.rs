@@ -1,10 +1,12 @@
 use crate::core::DbConnection;
 use crate::errors;
+use crate::routes::library::certification_limit;
 
 use auth::Wrapper as Auth;
 use serde::Serialize;
 
 use database::genre::*;
+use database::user::CertificationLimit;
 
 use tokio::task::spawn_blocking;
 
@@ -17,6 +19,7 @@ use warp::reply;
 pub mod filters {
     use database::DbConnection;
 
+    use auth::Permission;
     use auth::Wrapper as Auth;
 
     use warp::reject;
@@ -30,7 +33,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "filebrowser" / ..)
             .and(warp::path::tail())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and_then(|tail: warp::path::Tail, user: Auth| async move {
                 let decoded_path = percent_encoding::percent_decode(tail.as_str().as_bytes())
                     .decode_utf8()
@@ -57,7 +60,7 @@ pub mod filters {
 
         warp::path!("api" / "v1" / "search")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and(warp::query::query::<SearchArgs>())
             .and_then(
@@ -138,9 +141,11 @@ pub async fn search(
     _library_id: Option<i32>,
     genre: Option<String>,
     _quick: Option<bool>,
-    _user: Auth,
+    user: Auth,
 ) -> Result<warp::reply::Json, errors::DimError> {
     let mut tx = conn.read().begin().await?;
+    let cert_limit = certification_limit(&mut tx, &user).await?;
+
     if let Some(query_string) = query {
         let query_string = query_string
             .split(' ')
@@ -149,16 +154,16 @@ pub async fn search(
             .as_slice()
             .join(" ");
 
-        return search_by_name(&mut tx, &query_string, 15).await;
+        return search_by_name(&mut tx, user.user_ref(), cert_limit, &query_string, 15).await;
     }
 
     if let Some(x) = genre {
         let genre_id = Genre::get_by_name(&mut tx, x).await?.id;
-        return search_by_genre(&mut tx, genre_id).await;
+        return search_by_genre(&mut tx, user.user_ref(), cert_limit, genre_id).await;
     }
 
     if let Some(x) = year {
-        return search_by_release_year(&mut tx, x as i64).await;
+        return search_by_release_year(&mut tx, user.user_ref(), cert_limit, x as i64).await;
     }
 
     Err(errors::DimError::NotFoundError)
@@ -166,6 +171,8 @@ pub async fn search(
 
 async fn search_by_name(
     conn: &mut database::Transaction<'_>,
+    username: &str,
+    cert_limit: CertificationLimit,
     query: &str,
     limit: i64,
 ) -> Result<warp::reply::Json, errors::DimError> {
@@ -182,8 +189,13 @@ async fn search_by_name(
         r#"SELECT _tblmedia.id, library_id, name, assets.local_path as poster_path FROM _tblmedia
            LEFT JOIN assets on _tblmedia.poster = assets.id
            WHERE NOT media_type = "episode"
+           AND library_id IN (SELECT library_id FROM visible_library WHERE username = ?)
+           AND (certification_level <= ? OR (certification_level IS NULL AND (media_type = "music" OR NOT ?)))
            AND UPPER(name) LIKE ?
            LIMIT ?"#,
+        username,
+        cert_limit.level,
+        cert_limit.hide_unrated,
         query,
         limit
     )
@@ -196,6 +208,8 @@ async fn search_by_name(
 
 async fn search_by_genre(
     conn: &mut database::Transaction<'_>,
+    username: &str,
+    cert_limit: CertificationLimit,
     genre_id: i64,
 ) -> Result<warp::reply::Json, errors::DimError> {
     #[derive(Serialize)]
@@ -213,8 +227,13 @@ async fn search_by_genre(
                 LEFT JOIN assets on _tblmedia.poster = assets.id
                 INNER JOIN genre_media ON genre_media.media_id = _tblmedia.id
                 WHERE NOT media_type = "episode"
+                AND library_id IN (SELECT library_id FROM visible_library WHERE username = ?)
+                AND (certification_level <= ? OR (certification_level IS NULL AND (media_type = "music" OR NOT ?)))
                 AND genre_media.genre_id = ?
                 "#,
+        username,
+        cert_limit.level,
+        cert_limit.hide_unrated,
         genre_id,
     )
     .fetch_all(conn)
@@ -226,6 +245,8 @@ async fn search_by_genre(
 
 async fn search_by_release_year(
     conn: &mut database::Transaction<'_>,
+    username: &str,
+    cert_limit: CertificationLimit,
     year: i64,
 ) -> Result<warp::reply::Json, errors::DimError> {
     #[derive(Serialize)]
@@ -242,8 +263,13 @@ async fn search_by_release_year(
                 FROM _tblmedia
             LEFT JOIN assets on _tblmedia.poster = assets.id
                 WHERE NOT media_type = "episode"
+                AND library_id IN (SELECT library_id FROM visible_library WHERE username = ?)
+                AND (certification_level <= ? OR (certification_level IS NULL AND (media_type = "music" OR NOT ?)))
                 AND year = ?
                 "#,
+        username,
+        cert_limit.level,
+        cert_limit.hide_unrated,
         year,
     )
     .fetch_all(conn)
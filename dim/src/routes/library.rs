@@ -7,9 +7,13 @@ use auth::Wrapper as Auth;
 
 use database::library::InsertableLibrary;
 use database::library::Library;
+use database::library::MediaType;
 use database::media::Media;
 use database::mediafile::MediaFile;
+use database::user::CertificationLimit;
+use database::user::User;
 
+use events::Envelope;
 use events::Message;
 use events::PushEventType;
 
@@ -19,7 +23,9 @@ use std::path::Path;
 use warp::http::StatusCode;
 use warp::reply;
 
+use serde::Deserialize;
 use serde::Serialize;
+use serde_json::json;
 
 use tracing::error;
 use tracing::info;
@@ -31,6 +37,7 @@ pub mod filters {
 
     use super::super::global_filters::with_db;
 
+    use auth::Permission;
     use auth::Wrapper as Auth;
 
     use database::DbConnection;
@@ -46,7 +53,7 @@ pub mod filters {
         warp::path!("api" / "v1" / "library")
             .and(warp::get())
             .and(with_db(conn))
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and_then(|conn, auth| async move {
                 super::library_get(conn, auth)
                     .await
@@ -61,7 +68,7 @@ pub mod filters {
         warp::path!("api" / "v1" / "library")
             .and(warp::post())
             .and(warp::body::json::<InsertableLibrary>())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<EventTx>(event_tx))
             .and(with_state::<DbConnection>(conn))
             .and_then(
@@ -82,7 +89,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "library" / i64)
             .and(warp::delete())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<DbConnection>(conn))
             .and(with_state::<EventTx>(event_tx))
             .and_then(
@@ -94,12 +101,75 @@ pub mod filters {
             )
     }
 
+    pub fn library_patch(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "library" / i64)
+            .and(warp::patch())
+            .and(warp::body::json::<LibraryPatch>())
+            .and(auth::with_permission(Permission::ManageLibraries))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |id: i64, patch: LibraryPatch, user: Auth, conn: DbConnection| async move {
+                    super::library_patch(conn, id, patch, user)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
+    pub fn library_scan(
+        conn: DbConnection,
+        event_tx: EventTx,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "library" / i64 / "scan")
+            .and(warp::post())
+            .and(auth::with_api_key_permission(Permission::ManageLibraries))
+            .and(with_state::<DbConnection>(conn))
+            .and(with_state::<EventTx>(event_tx))
+            .and_then(
+                |id: i64, user: Auth, conn: DbConnection, event_tx: EventTx| async move {
+                    super::library_scan(conn, id, event_tx, user)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
+    pub fn library_scan_status(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "library" / i64 / "scan_status")
+            .and(warp::get())
+            .and(auth::with_api_key())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, user: Auth, conn: DbConnection| async move {
+                super::library_scan_status(conn, id, user)
+                    .await
+                    .map_err(|e| reject::custom(e))
+            })
+    }
+
+    pub fn library_cancel_scan(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "library" / i64 / "scan")
+            .and(warp::delete())
+            .and(auth::with_api_key_permission(Permission::ManageLibraries))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, user: Auth, conn: DbConnection| async move {
+                super::library_cancel_scan(conn, id, user)
+                    .await
+                    .map_err(|e| reject::custom(e))
+            })
+    }
+
     pub fn library_get_self(
         conn: DbConnection,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "library" / i64)
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, user: Auth, conn: DbConnection| async move {
                 super::get_self(conn, id, user)
@@ -113,7 +183,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "library" / i64 / "media")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, user: Auth, conn: DbConnection| async move {
                 super::get_all_library(conn, id, user)
@@ -127,7 +197,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "library" / i64 / "unmatched")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, user: Auth, conn: DbConnection| async move {
                 super::get_all_unmatched_media(conn, id, user)
@@ -137,28 +207,97 @@ pub mod filters {
     }
 }
 
-/// Method maps to `GET /api/v1/library` and returns a list of all libraries in te database.
-/// This method can only be accessed by authenticated users.
+/// Method maps to `GET /api/v1/library` and returns a list of all libraries in te database which
+/// the user is allowed to see. This method can only be accessed by authenticated users.
 ///
 /// # Arguments
 /// * `conn` - database connection
 /// * `_log` - logger
-/// * `_user` - Authentication middleware
+/// * `user` - Authentication middleware
 pub async fn library_get(
     conn: DbConnection,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
     Ok(reply::json(&{
-        let mut x = Library::get_all(&mut tx).await;
+        let mut x = Library::get_all_for_user(&mut tx, user.user_ref()).await?;
         x.sort_by(|a, b| a.name.cmp(&b.name));
         x
     }))
 }
 
+/// Function checks whether `user` is allowed to see the library `id`. Libraries a user cant see
+/// are reported as not found so that their existence isnt leaked.
+pub async fn check_library_access(
+    tx: &mut database::Transaction<'_>,
+    id: i64,
+    user: &Auth,
+) -> Result<(), errors::DimError> {
+    if Library::is_visible_to(&mut *tx, id, user.user_ref()).await? {
+        Ok(())
+    } else {
+        Err(errors::DimError::LibraryNotFound)
+    }
+}
+
+/// Function checks whether `user` is allowed to see the media `id`, ie whether they can see the
+/// library it was scanned into. Media which doesnt exist or which the user cant see is reported as
+/// not found.
+pub async fn check_media_access(
+    tx: &mut database::Transaction<'_>,
+    id: i64,
+    user: &Auth,
+) -> Result<(), errors::DimError> {
+    let media = Media::get(&mut *tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    check_library_access(&mut *tx, media.library_id, user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)
+}
+
+/// Function returns the certification limit of `user`, see
+/// [`UserSettings::certification_limit`](database::user::UserSettings::certification_limit).
+pub async fn certification_limit(
+    tx: &mut database::Transaction<'_>,
+    user: &Auth,
+) -> Result<CertificationLimit, errors::DimError> {
+    Ok(User::get(&mut *tx, user.user_ref())
+        .await?
+        .prefs
+        .certification_limit())
+}
+
+/// Function checks whether the certification of the media `id` is within the limit set for
+/// `user`. Media above the limit, or without a known certification if `user` has unrated media
+/// hidden, is reported as not found. Music is never rated, so albums are only checked against the
+/// limit if they carry a certification.
+pub async fn check_certification(
+    tx: &mut database::Transaction<'_>,
+    id: i64,
+    user: &Auth,
+) -> Result<(), errors::DimError> {
+    let level = Media::get_certification_level(&mut *tx, id).await?;
+    let limit = certification_limit(&mut *tx, user).await?;
+
+    let allowed = match level {
+        None if limit.hide_unrated => {
+            matches!(Media::get(&mut *tx, id).await?.media_type, MediaType::Music)
+        }
+        level => limit.allows(level),
+    };
+
+    if !allowed {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    Ok(())
+}
+
 /// Method maps to `POST /api/v1/library`, it adds a new library to the database, starts a new
-/// scanner for it, then dispatches a event to all clients notifying them that a new library has
-/// been created. This method can only be accessed by authenticated users. Method returns 200 OK
+/// scanner for it, then dispatches a event to the clients of owners notifying them that a new library
+/// has been created. This method can only be accessed by authenticated users. Method returns 200 OK
 ///
 /// # Arguments
 /// * `conn` - database connection
@@ -187,14 +326,14 @@ pub async fn library_post(
         event_type: PushEventType::EventNewLibrary,
     };
 
-    let _ = event_tx.send(serde_json::to_string(&event).unwrap());
+    let _ = event_tx.send(Envelope::owner(event));
 
     Ok(StatusCode::CREATED)
 }
 
 /// Method mapped to `DELETE /api/v1/library/<id>` is used to delete a library from the database.
-/// It deletes the database based on the parameter `id`, then dispatches a event notifying all
-/// clients that the database with this id has been removed. Method can only be accessed by
+/// It deletes the database based on the parameter `id`, then dispatches a event notifying the
+/// clients of owners that the database with this id has been removed. Method can only be accessed by
 /// authenticated users.
 ///
 /// # Arguments:
@@ -245,26 +384,148 @@ pub async fn library_delete(
         event_type: PushEventType::EventRemoveLibrary,
     };
 
-    let _ = event_tx.send(serde_json::to_string(&event).unwrap());
+    let _ = event_tx.send(Envelope::owner(event));
 
     tokio::spawn(delete_lib_fut);
 
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Fields of a library which can be changed after it has been created.
+#[derive(Deserialize)]
+pub struct LibraryPatch {
+    /// Seconds between scheduled rescans, `null` disables scheduled rescans.
+    scan_interval: Option<i64>,
+}
+
+/// Method mapped to `PATCH /api/v1/library/<id>` updates the settings of a library. Currently only
+/// the scan schedule can be changed. Method can only be accessed by authenticated users.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `id` - id of the library we want to update
+/// * `patch` - new settings of the library
+/// * `_user` - Auth middleware
+pub async fn library_patch(
+    conn: DbConnection,
+    id: i64,
+    patch: LibraryPatch,
+    _user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    if matches!(patch.scan_interval, Some(x) if x <= 0) {
+        return Err(errors::DimError::MissingFieldInBody {
+            description: "scan_interval must be a positive number of seconds".into(),
+        });
+    }
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+    if Library::set_scan_interval(&mut tx, id, patch.scan_interval).await? < 1 {
+        return Err(errors::DimError::LibraryNotFound);
+    }
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `POST /api/v1/library/<id>/scan` starts a full rescan of a library in the
+/// background. Files which are new get mounted and matched, files which changed are re-probed and
+/// files which no longer exist are purged. Method returns 202 Accepted, or 409 Conflict if the
+/// library is already being scanned.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `id` - id of the library we want to scan
+/// * `event_tx` - channel over which to dispatch events
+/// * `_user` - Auth middleware
+pub async fn library_scan(
+    conn: DbConnection,
+    id: i64,
+    event_tx: EventTx,
+    _user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    {
+        let mut tx = conn.read().begin().await?;
+        Library::get_one(&mut tx, id)
+            .await
+            .map_err(|_| errors::DimError::LibraryNotFound)?;
+    }
+
+    if scanners::is_scanning(id) {
+        return Err(errors::DimError::ScanInProgress);
+    }
+
+    tokio::spawn(scanners::start(conn, id, event_tx));
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Method mapped to `GET /api/v1/library/<id>/scan_status` returns whether a full scan of the
+/// library is running and if so how far along it is, see [`scanners::ScanStatus`].
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `id` - id of the library
+/// * `user` - Auth middleware
+pub async fn library_scan_status(
+    conn: DbConnection,
+    id: i64,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    check_library_access(&mut tx, id, &user).await?;
+    let library = Library::get_one(&mut tx, id).await?;
+
+    let status = scanners::scan_status(id);
+
+    Ok(reply::json(&json!({
+        "scanning": status.is_some(),
+        "last_scanned": library.last_scanned,
+        "progress": status,
+    })))
+}
+
+/// Method mapped to `DELETE /api/v1/library/<id>/scan` cancels the running full scan of a
+/// library. Files which are being matched are finished, the remaining ones are skipped until the
+/// next scan. Method returns 404 if the library isnt being scanned.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `id` - id of the library
+/// * `_user` - Auth middleware
+pub async fn library_cancel_scan(
+    conn: DbConnection,
+    id: i64,
+    _user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    {
+        let mut tx = conn.read().begin().await?;
+        Library::get_one(&mut tx, id)
+            .await
+            .map_err(|_| errors::DimError::LibraryNotFound)?;
+    }
+
+    if !scanners::cancel_scan(id) {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Method mapped to `GET /api/v1/library/<id>` returns info about the library with the supplied
 /// id. Method can only be accessed by authenticated users.
 ///
 /// # Arguments
 /// * `conn` - database connection
 /// * `id` - id of the library we want info of
-/// * `_user` - Auth middleware
+/// * `user` - Auth middleware
 pub async fn get_self(
     conn: DbConnection,
     id: i64,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
+    check_library_access(&mut tx, id, &user).await?;
     Ok(reply::json(&Library::get_one(&mut tx, id).await?))
 }
 
@@ -274,29 +535,37 @@ pub async fn get_self(
 /// # Arguments
 /// * `conn` - database connection
 /// * `id` - id of the library we want media of
-/// * `_user` - Auth middleware
+/// * `user` - Auth middleware
 pub async fn get_all_library(
     conn: DbConnection,
     id: i64,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut result = HashMap::new();
     let mut tx = conn.read().begin().await?;
+    check_library_access(&mut tx, id, &user).await?;
     let lib = Library::get_one(&mut tx, id).await?;
+    let cert_limit = certification_limit(&mut tx, &user).await?;
 
     #[derive(Serialize)]
     struct Record {
         id: i64,
         name: String,
         poster_path: Option<String>,
+        media_type: MediaType,
     }
 
+    // NOTE: mixed libraries contain both movies and shows, `media_type` lets clients tell them apart.
     let mut data = sqlx::query_as!(
         Record,
-        r#"SELECT _tblmedia.id, name, assets.local_path as poster_path FROM _tblmedia
+        r#"SELECT _tblmedia.id, name, assets.local_path as poster_path,
+        _tblmedia.media_type as "media_type: MediaType" FROM _tblmedia
         LEFT JOIN assets ON _tblmedia.poster = assets.id
-        WHERE library_id = ? AND NOT media_type = "episode""#,
-        id
+        WHERE library_id = ? AND NOT media_type = "episode"
+        AND (certification_level <= ? OR (certification_level IS NULL AND (media_type = "music" OR NOT ?)))"#,
+        id,
+        cert_limit.level,
+        cert_limit.hide_unrated
     )
     .fetch_all(&mut tx)
     .await
@@ -310,20 +579,26 @@ pub async fn get_all_library(
 }
 
 /// Method mapped to `GET` /api/v1/library/<id>/unmatched` returns a list of all unmatched medias
-/// to be displayed in the library pages.
+/// to be displayed in the library pages, along with the artwork extracted from them if any.
 ///
 /// # Arguments
 /// * `conn` - database connection
 /// * `id` - id of the library
-/// * `_user` - auth middleware
+/// * `user` - auth middleware
 // NOTE: construct_standard on a mediafile will yield buggy deltas
 pub async fn get_all_unmatched_media(
     conn: DbConnection,
     id: i64,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut result = HashMap::new();
     let mut tx = conn.read().begin().await?;
+    check_library_access(&mut tx, id, &user).await?;
+
+    // unmatched files have no certification.
+    if certification_limit(&mut tx, &user).await?.hide_unrated {
+        return Ok(reply::json(&result));
+    }
 
     #[derive(Serialize)]
     struct Record {
@@ -331,11 +606,16 @@ pub async fn get_all_unmatched_media(
         name: String,
         duration: Option<i64>,
         target_file: String,
+        poster: Option<String>,
+        backdrop: Option<String>,
     }
 
     sqlx::query_as!(
         Record,
-        r#"SELECT id, raw_name as name, duration, target_file FROM mediafile
+        r#"SELECT id, raw_name as name, duration, target_file,
+            'images/' || poster_file as "poster?: String",
+            'images/' || backdrop_file as "backdrop?: String"
+        FROM mediafile
         WHERE library_id = ? AND media_id IS NULL"#,
         id
     )
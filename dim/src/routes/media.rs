@@ -1,6 +1,10 @@
 use crate::core::DbConnection;
 use crate::errors;
 use crate::json;
+use crate::routes::library::check_certification;
+use crate::routes::library::check_library_access;
+use crate::scrobbler::ScrobbleDispatcher;
+use crate::scrobbler::ScrobbleKind;
 
 use auth::Wrapper as Auth;
 
@@ -11,6 +15,9 @@ use database::media::Media;
 use database::media::UpdateMedia;
 use database::mediafile::MediaFile;
 use database::progress::Progress;
+use database::watch_history::WatchHistory;
+
+use serde::Deserialize;
 
 use warp::http::status::StatusCode;
 use warp::reply;
@@ -22,9 +29,11 @@ pub mod filters {
     use warp::Filter;
 
     use super::super::global_filters::with_state;
+    use auth::Permission;
     use auth::Wrapper as Auth;
     use serde::Deserialize;
 
+    use crate::scrobbler::ScrobbleDispatcher;
     use database::media::UpdateMedia;
     use database::DbConnection;
 
@@ -34,7 +43,7 @@ pub mod filters {
         warp::path!("api" / "v1" / "media" / i64)
             .and(warp::get())
             .and(with_state::<DbConnection>(conn))
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and_then(|id: i64, conn: DbConnection, user: Auth| async move {
                 super::get_media_by_id(conn, id, user)
                     .await
@@ -48,9 +57,9 @@ pub mod filters {
         warp::path!("api" / "v1" / "media" / i64 / "files")
             .and(warp::get())
             .and(with_state::<DbConnection>(conn))
-            .and(auth::with_auth())
-            .and_then(|id: i64, conn: DbConnection, _user: Auth| async move {
-                super::get_media_files(conn, id)
+            .and(auth::with_api_key())
+            .and_then(|id: i64, conn: DbConnection, user: Auth| async move {
+                super::get_media_files(conn, id, user)
                     .await
                     .map_err(|e| reject::custom(e))
             })
@@ -62,7 +71,7 @@ pub mod filters {
         warp::path!("api" / "v1" / "media" / i64)
             .and(warp::patch())
             .and(warp::body::json::<UpdateMedia>())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<DbConnection>(conn))
             .and_then(|id, body, auth, conn| async move {
                 super::update_media_by_id(id, body, auth, conn)
@@ -76,7 +85,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "media" / i64)
             .and(warp::delete())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
                 super::delete_media_by_id(conn, id, auth)
@@ -85,27 +94,32 @@ pub mod filters {
             })
     }
 
-    pub fn tmdb_search() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
-    {
+    pub fn tmdb_search(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         #[derive(Deserialize)]
         struct RouteArgs {
             query: String,
             year: Option<i32>,
             media_type: String,
+            library_id: Option<i64>,
         }
 
         warp::path!("api" / "v1" / "media" / "tmdb_search")
             .and(warp::get())
             .and(warp::query::query::<RouteArgs>())
-            .and(auth::with_auth())
+            .and(with_state::<DbConnection>(conn))
+            .and(auth::with_permission(Permission::RematchMetadata))
             .and_then(
                 |RouteArgs {
                      query,
                      year,
                      media_type,
+                     library_id,
                  }: RouteArgs,
+                 conn: DbConnection,
                  auth: Auth| async move {
-                    super::tmdb_search(query, year, media_type, auth)
+                    super::tmdb_search(conn, query, year, media_type, library_id, auth)
                         .await
                         .map_err(|e| reject::custom(e))
                 },
@@ -114,10 +128,12 @@ pub mod filters {
 
     pub fn map_progress(
         conn: DbConnection,
+        scrobbler: ScrobbleDispatcher,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         #[derive(Deserialize)]
         struct RouteArgs {
             offset: i64,
+            state: Option<super::PlaybackState>,
         }
 
         warp::path!("api" / "v1" / "media" / i64 / "progress")
@@ -125,10 +141,59 @@ pub mod filters {
             .and(warp::query::query::<RouteArgs>())
             .and(with_state::<DbConnection>(conn))
             .and(auth::with_auth())
-            .and_then(|id: i64, RouteArgs { offset }: RouteArgs, conn: DbConnection, auth: Auth| async move {
-                super::map_progress(conn, id, offset, auth)
+            .and(with_state::<ScrobbleDispatcher>(scrobbler))
+            .and_then(
+                |id: i64,
+                 RouteArgs { offset, state }: RouteArgs,
+                 conn: DbConnection,
+                 auth: Auth,
+                 scrobbler: ScrobbleDispatcher| async move {
+                    super::map_progress(conn, id, offset, state, auth, scrobbler)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
+    pub fn mark_watched(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "media" / i64 / "watched")
+            .and(warp::post())
+            .and(with_state::<DbConnection>(conn))
+            .and(auth::with_auth())
+            .and_then(|id: i64, conn: DbConnection, auth: Auth| async move {
+                super::set_watched(conn, id, true, auth)
                     .await
-                    .map_err(|e| reject::custom(e))
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn mark_unwatched(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "media" / i64 / "watched")
+            .and(warp::delete())
+            .and(with_state::<DbConnection>(conn))
+            .and(auth::with_auth())
+            .and_then(|id: i64, conn: DbConnection, auth: Auth| async move {
+                super::set_watched(conn, id, false, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn get_media_history(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "media" / i64 / "history")
+            .and(warp::get())
+            .and(with_state::<DbConnection>(conn))
+            .and(auth::with_api_key())
+            .and_then(|id: i64, conn: DbConnection, auth: Auth| async move {
+                super::get_media_history(conn, id, auth)
+                    .await
+                    .map_err(reject::custom)
             })
     }
 }
@@ -169,15 +234,21 @@ pub async fn get_media_by_id(
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
     let media = Media::get(&mut tx, id).await?;
+    check_library_access(&mut tx, media.library_id, &user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_certification(&mut tx, id, &user).await?;
 
     let media_id = match media.media_type {
-        MediaType::Movie | MediaType::Episode => id,
         MediaType::Tv => Episode::get_first_for_show(&mut tx, id).await?.id,
+        _ => id,
     };
 
     // TODO: at some point we want to issue a warning to the UI that none of the mediafiles with
     // this media have a duration (maybe because of corruption).
     let duration = match MediaFile::get_of_media(&mut tx, media_id).await {
+        // the duration of a album is the length of all of its tracks.
+        Ok(x) if media.media_type == MediaType::Music => x.iter().filter_map(|x| x.duration).sum(),
         Ok(x) => x
             .iter()
             .filter_map(|x| x.duration)
@@ -197,19 +268,27 @@ pub async fn get_media_by_id(
         MediaType::Episode | MediaType::Movie => {
             Progress::get_for_media_user(&mut tx, user.0.claims.get_user(), id)
                 .await
-                .map(|x| json!({"progress": x.delta}))
+                .map(|x| {
+                    json!({
+                        "progress": x.delta,
+                        "watched": x.watched,
+                        "play_count": x.play_count,
+                        "last_watched": x.last_watched,
+                    })
+                })
                 .ok()
         }
         MediaType::Tv => {
             if let Ok(Some(ep)) =
                 Episode::get_last_watched_episode(&mut tx, id, user.0.claims.get_user()).await
             {
-                let (delta, duration) =
-                    Progress::get_progress_for_media(&mut tx, ep.id, user.0.claims.get_user())
+                let progress =
+                    Progress::get_for_media_user(&mut tx, user.0.claims.get_user(), ep.id)
                         .await
-                        .unwrap_or((0, 1));
+                        .unwrap_or_default();
+                let delta = progress.delta;
 
-                if (delta as f64 / duration as f64) > 0.90 {
+                if progress.watched {
                     if let Ok(next_episode) = ep.get_next_episode(&mut tx).await {
                         let (delta, _duration) = Progress::get_progress_for_media(
                             &mut tx,
@@ -246,6 +325,8 @@ pub async fn get_media_by_id(
                 }))
             }
         }
+        // `mixed` is only ever used for libraries.
+        MediaType::Music | MediaType::Mixed => None,
     };
 
     fn mediafile_tags(x: &MediaFile) -> serde_json::Value {
@@ -282,6 +363,11 @@ pub async fn get_media_by_id(
             .iter()
             .map(|x| (x.media_id.unwrap(), mediafile_tags(x)))
             .collect::<HashMap<_, _>>()),
+        MediaType::Music | MediaType::Mixed => json!(MediaFile::get_of_media(&mut tx, media.id)
+            .await?
+            .iter()
+            .map(|x| (x.id, mediafile_tags(x)))
+            .collect::<HashMap<_, _>>()),
     };
 
     let season_episode_tag = match media.media_type {
@@ -315,11 +401,24 @@ pub async fn get_media_by_id(
     })))
 }
 
+/// Method mapped to `GET /api/v1/media/<id>/files` returns all the mediafiles of a media.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `id` - id of the media whose files we want
+/// * `user` - Auth middleware
 pub async fn get_media_files(
     conn: DbConnection,
     id: i64,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
+    let media = Media::get(&mut tx, id).await?;
+    check_library_access(&mut tx, media.library_id, &user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_certification(&mut tx, id, &user).await?;
+
     let mediafiles = MediaFile::get_of_media(&mut tx, id).await?;
     Ok(reply::json(&mediafiles))
 }
@@ -370,40 +469,51 @@ pub async fn delete_media_by_id(
     Ok(StatusCode::OK)
 }
 
-/// Method mapped to `GET /api/v1/media/tmdb_search` is used to quickly search TMDB based on 3
-/// params, one of which is optional. This is used client side in the rematch utility
+/// Method mapped to `GET /api/v1/media/tmdb_search` is used to quickly search for external
+/// metadata based on 3 params, one of which is optional. This is used client side in the rematch
+/// utility, so it requires the `rematch_metadata` permission.
 ///
 /// # Arguments
-/// * `query` - the query we want to send to tmdb, ie movie title, tv show title
+/// * `query` - the query we want to send to the provider, ie movie title, tv show title
 /// * `year` - optional parameter specifying the release year of the media we want to look up
 /// * `media_type` - parameter that tells us what media type we are querying, ie movie or tv show
+/// * `library_id` - optional library whose metadata provider should be queried, defaults to tmdb.
 pub async fn tmdb_search(
+    conn: DbConnection,
     query: String,
     year: Option<i32>,
     media_type: String,
+    library_id: Option<i64>,
     _user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
-    use crate::scanners::tmdb::Tmdb;
+    use crate::scanners::base::library_provider;
+    use crate::scanners::provider::parse_media_type;
+    use crate::scanners::provider::provider_for;
 
-    let media_type = match media_type.as_ref() {
-        "movie" => MediaType::Movie,
-        "tv" => MediaType::Tv,
-        _ => return Err(errors::DimError::InvalidMediaType),
-    };
+    let media_type = parse_media_type(&media_type).ok_or(errors::DimError::InvalidMediaType)?;
 
-    let mut tmdb_session = Tmdb::new("38c372f5bc572c8aadde7a802638534e".to_string(), media_type);
+    let provider = match library_id {
+        Some(id) => library_provider(&conn, id, media_type).await?,
+        None => provider_for(Default::default(), media_type),
+    };
 
     Ok(reply::json(
-        &tmdb_session
-            .search_by_name(query, year, None)
+        &provider
+            .search_many(&query, year)
             .await
-            .map_err(|_| errors::DimError::NotFoundError)?
-            .into_iter()
-            .map(Into::<crate::scanners::ApiMedia>::into)
-            .collect::<Vec<_>>(),
+            .map_err(|_| errors::DimError::NotFoundError)?,
     ))
 }
 
+/// Playback state a client can report along with its progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
 /// Method mapped to `POST /api/v1/media/<id>/progress` is used to map progress for a certain media
 /// to the user. This is useful for remembering progress for a movie etc.
 ///
@@ -412,15 +522,112 @@ pub async fn tmdb_search(
 ///
 /// # Query params
 /// * `offset` - offset in seconds
+/// * `state` - optional playback state (`playing`, `paused` or `stopped`) which gets reported to
+/// the scrobblers of the user
 pub async fn map_progress(
     conn: DbConnection,
     id: i64,
     offset: i64,
+    state: Option<PlaybackState>,
     user: Auth,
+    scrobbler: ScrobbleDispatcher,
 ) -> Result<impl warp::Reply, errors::DimError> {
+    let uid = user.0.claims.get_user();
     let mut lock = conn.writer().lock_owned().await;
     let mut tx = database::write_tx(&mut lock).await?;
-    Progress::set(&mut tx, offset, user.0.claims.get_user(), id).await?;
+
+    let media = Media::get(&mut tx, id).await?;
+    check_library_access(&mut tx, media.library_id, &user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_certification(&mut tx, id, &user).await?;
+
+    Progress::set(&mut tx, offset, uid.clone(), id).await?;
+
+    let duration = MediaFile::get_largest_duration(&mut tx, id)
+        .await
+        .unwrap_or(0);
+    let completed = WatchHistory::record(&mut tx, &uid, id, offset, duration).await?;
+    if completed {
+        Progress::mark_watched(&mut tx, &uid, id).await?;
+    }
+
     tx.commit().await?;
+
+    let kind = match state {
+        _ if completed => Some(ScrobbleKind::Complete),
+        Some(PlaybackState::Playing) => Some(ScrobbleKind::Start),
+        Some(PlaybackState::Paused) => Some(ScrobbleKind::Pause),
+        Some(PlaybackState::Stopped) => Some(ScrobbleKind::Stop),
+        None => None,
+    };
+
+    if let Some(kind) = kind {
+        scrobbler.report(kind, &uid, id, Some(offset));
+    }
+
     Ok(StatusCode::OK)
 }
+
+/// Method mapped to `POST /api/v1/media/<id>/watched` marks a movie or episode as watched,
+/// marking a tv show marks all of its episodes. `DELETE /api/v1/media/<id>/watched` does the
+/// opposite and resets the progress of the media.
+///
+/// # Arguments
+/// * `id` - id of a movie, episode or tv show.
+/// * `watched` - whether to mark the media as watched or unwatched.
+pub async fn set_watched(
+    conn: DbConnection,
+    id: i64,
+    watched: bool,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let uid = user.0.claims.get_user();
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let media = Media::get(&mut tx, id).await?;
+    check_library_access(&mut tx, media.library_id, &user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_certification(&mut tx, id, &user).await?;
+
+    let ids = match media.media_type {
+        MediaType::Movie | MediaType::Episode => vec![id],
+        MediaType::Tv => Episode::get_all_of_tv(&mut tx, id)
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect(),
+        MediaType::Music | MediaType::Mixed => return Err(errors::DimError::InvalidMediaType),
+    };
+
+    for id in ids {
+        Progress::set_watched(&mut tx, &uid, id, watched).await?;
+    }
+
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `GET /api/v1/media/<id>/history` returns every time the user has watched a
+/// media, newest first.
+///
+/// # Arguments
+/// * `id` - id of the media.
+pub async fn get_media_history(
+    conn: DbConnection,
+    id: i64,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    let media = Media::get(&mut tx, id).await?;
+    check_library_access(&mut tx, media.library_id, &user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_certification(&mut tx, id, &user).await?;
+
+    Ok(reply::json(
+        &WatchHistory::get_of_media(&mut tx, &user.0.claims.get_user(), id).await?,
+    ))
+}
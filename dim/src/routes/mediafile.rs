@@ -1,8 +1,16 @@
 use crate::core::DbConnection;
 use crate::errors;
+use crate::routes::library::certification_limit;
+use crate::routes::library::check_certification;
+use crate::routes::library::check_library_access;
+use crate::trickplay;
 
 use auth::Wrapper as Auth;
+use database::marker::Marker;
 use database::mediafile::MediaFile;
+use database::trickplay::Trickplay;
+
+use std::path::Path;
 
 use serde_json::json;
 use warp::http::status::StatusCode;
@@ -13,6 +21,7 @@ pub mod filters {
     use warp::Filter;
 
     use super::super::global_filters::with_state;
+    use auth::Permission;
     use auth::Wrapper as Auth;
     use database::DbConnection;
 
@@ -23,7 +32,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "mediafile" / i64)
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
                 super::get_mediafile_info(conn, id, auth)
@@ -32,6 +41,36 @@ pub mod filters {
             })
     }
 
+    pub fn get_trickplay(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "mediafile" / i64 / "trickplay")
+            .and(warp::get())
+            .and(auth::with_api_key())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::get_trickplay(conn, id, auth)
+                    .await
+                    .map_err(|e| reject::custom(e))
+            })
+    }
+
+    pub fn get_trickplay_file(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "mediafile" / i64 / "trickplay" / String)
+            .and(warp::get())
+            .and(auth::with_api_key())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |id: i64, file: String, auth: Auth, conn: DbConnection| async move {
+                    super::get_trickplay_file(conn, id, file, auth)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
     pub fn rematch_mediafile(
         conn: DbConnection,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -43,7 +82,7 @@ pub mod filters {
 
         warp::path!("api" / "v1" / "mediafile" / i64 / "match")
             .and(warp::patch())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::RematchMetadata))
             .and(with_state::<DbConnection>(conn))
             .and(warp::query::query::<RouteArgs>())
             .and_then(
@@ -64,27 +103,146 @@ pub mod filters {
 }
 
 /// Method mapped to `GET /api/v1/mediafile/<id>` is used to get information about a mediafile by its id.
+/// Mediafiles the user isnt allowed to see are reported as not found.
 ///
 /// # Arguments
 /// * `id` - id of the mediafile we want info about
 pub async fn get_mediafile_info(
     conn: DbConnection,
     id: i64,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
-    let mediafile = MediaFile::get_one(&mut tx, id)
+    let mediafile = visible_mediafile(&mut tx, id, &user).await?;
+
+    // intros and credits players can offer to skip, empty until the file was analyzed.
+    let markers = Marker::get_of_mediafile(&mut tx, id)
         .await
-        .map_err(|_| errors::DimError::NotFoundError)?;
+        .unwrap_or_default();
 
     Ok(reply::json(&json!({
         "id": mediafile.id,
         "media_id": mediafile.media_id,
         "library_id": mediafile.library_id,
         "raw_name": mediafile.raw_name,
+        "markers": markers,
     })))
 }
 
+/// Function returns the mediafile `id` if `user` is allowed to see it, ie if they can see its
+/// library and its media is within their certification limit.
+async fn visible_mediafile(
+    tx: &mut database::Transaction<'_>,
+    id: i64,
+    user: &Auth,
+) -> Result<MediaFile, errors::DimError> {
+    let mediafile = MediaFile::get_one(&mut *tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    check_library_access(&mut *tx, mediafile.library_id, user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    match mediafile.media_id {
+        Some(media_id) => check_certification(&mut *tx, media_id, user)
+            .await
+            .map_err(|_| errors::DimError::NotFoundError)?,
+        // unmatched files have no certification.
+        None if certification_limit(&mut *tx, user).await?.hide_unrated => {
+            return Err(errors::DimError::NotFoundError)
+        }
+        None => {}
+    }
+
+    Ok(mediafile)
+}
+
+/// Function returns the thumbnail sheets of the mediafile `id` if they were generated and `user`
+/// is allowed to see the mediafile.
+async fn visible_trickplay(
+    conn: &DbConnection,
+    id: i64,
+    user: &Auth,
+) -> Result<Trickplay, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    visible_mediafile(&mut tx, id, user).await?;
+
+    match Trickplay::get(&mut tx, id).await {
+        Ok(x) if x.error.is_none() && x.sheets > 0 => Ok(x),
+        _ => Err(errors::DimError::NotFoundError),
+    }
+}
+
+/// Method mapped to `GET /api/v1/mediafile/<id>/trickplay` returns the layout of the thumbnail
+/// sheets used for seek previews, along with the url of the WebVTT thumbnail track. Returns 404
+/// if the sheets werent generated yet.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `id` - id of the mediafile
+/// * `user` - Auth middleware
+pub async fn get_trickplay(
+    conn: DbConnection,
+    id: i64,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let sheets = visible_trickplay(&conn, id, &user).await?;
+    let base = format!("/api/v1/mediafile/{}/trickplay", id);
+
+    Ok(reply::json(&json!({
+        "interval": sheets.interval,
+        "width": sheets.width,
+        "height": sheets.height,
+        "tile_columns": sheets.tile_columns,
+        "tile_rows": sheets.tile_rows,
+        "thumbnails": sheets.thumbnails,
+        "sheets": (0..sheets.sheets)
+            .map(|x| format!("{}/{}", base, trickplay::sheet_name(x)))
+            .collect::<Vec<_>>(),
+        "vtt": format!("{}/{}", base, trickplay::VTT_FILE),
+    })))
+}
+
+/// Method mapped to `GET /api/v1/mediafile/<id>/trickplay/<file>` returns the WebVTT thumbnail
+/// track or one of the thumbnail sheets of a mediafile.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `id` - id of the mediafile
+/// * `file` - either `thumbnails.vtt` or the name of a sheet
+/// * `user` - Auth middleware
+pub async fn get_trickplay_file(
+    conn: DbConnection,
+    id: i64,
+    file: String,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let sheets = visible_trickplay(&conn, id, &user).await?;
+
+    let content_type = match trickplay::parse_sheet_name(&file) {
+        Some(x) if x < sheets.sheets => "image/jpeg",
+        Some(_) => return Err(errors::DimError::NotFoundError),
+        None if file == trickplay::VTT_FILE => "text/vtt",
+        None => return Err(errors::DimError::NotFoundError),
+    };
+
+    let meta_path = crate::core::METADATA_PATH
+        .get()
+        .ok_or(errors::DimError::InternalServerError)?;
+    let path = trickplay::sheets_dir(Path::new(meta_path), id).join(&file);
+
+    let data = tokio::fs::read(path)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    warp::http::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .body(data)
+        .map_err(|_| errors::DimError::InternalServerError)
+}
+
 /// Method mapped to `PATCH /api/v1/mediafile/<id>/match` used to match a unmatched(orphan)
 /// mediafile to a tmdb id.
 ///
@@ -102,31 +260,32 @@ pub async fn rematch_mediafile(
     tmdb_id: i32,
     media_type: String,
 ) -> Result<impl warp::Reply, errors::DimError> {
-    use crate::scanners::tmdb::Tmdb;
-    use database::library::MediaType;
+    use crate::scanners::base::library_provider;
+    use crate::scanners::provider::parse_media_type;
 
     let mut tx = conn.read().begin().await?;
     let mediafile = MediaFile::get_one(&mut tx, id).await?;
+    drop(tx);
+
     let matcher = crate::scanners::get_matcher_unchecked();
 
-    let mut tmdb = match media_type.to_lowercase().as_ref() {
-        "movie" => Tmdb::new("38c372f5bc572c8aadde7a802638534e".into(), MediaType::Movie),
-        "tv" => Tmdb::new("38c372f5bc572c8aadde7a802638534e".into(), MediaType::Tv),
-        _ => return Err(errors::DimError::InvalidMediaType),
-    };
+    let target_type =
+        parse_media_type(&media_type).ok_or(errors::DimError::InvalidMediaType)?;
+
+    let provider = library_provider(&conn, mediafile.library_id, target_type).await?;
 
-    let result = tmdb
-        .search_by_id(tmdb_id)
+    let result = provider
+        .search_by_id(tmdb_id as u64)
         .await
         .map_err(|_| errors::DimError::NotFoundError)?;
 
     match media_type.to_lowercase().as_ref() {
         "movie" => {
             matcher
-                .match_movie_to_result(mediafile, result.into())
+                .match_movie_to_result(mediafile, result)
                 .await?
         }
-        "tv" => matcher.match_tv_to_result(mediafile, result.into()).await?,
+        "tv" => matcher.match_tv_to_result(mediafile, result).await?,
         _ => unreachable!(),
     }
 
@@ -1,14 +1,18 @@
+pub mod admin;
 pub mod auth;
 pub mod dashboard;
 pub mod general;
 pub mod library;
 pub mod media;
 pub mod mediafile;
+pub mod music;
 pub mod rematch_media;
+pub mod scrobbler;
 pub mod settings;
 pub mod statik;
 pub mod stream;
 pub mod tv;
+pub mod webhook;
 
 pub mod global_filters {
     use crate::errors;
@@ -38,6 +42,11 @@ pub mod global_filters {
             return Ok(e.clone().into_response());
         } else if let Some(e) = err.find::<errors::DimError>() {
             return Ok(e.clone().into_response());
+        } else if let Some(
+            auth::JWTError::MissingPermission(_) | auth::JWTError::ApiKeyNotAllowed,
+        ) = err.find::<auth::JWTError>()
+        {
+            return Ok(errors::DimError::Unauthorized.into_response());
         } else if err.find::<auth::JWTError>().is_some() {
             return Ok(errors::DimError::AuthRequired.into_response());
         } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
@@ -0,0 +1,197 @@
+use crate::core::DbConnection;
+use crate::errors;
+use crate::routes::library::certification_limit;
+use crate::routes::library::check_certification;
+use crate::routes::library::check_library_access;
+use crate::routes::library::check_media_access;
+
+use auth::Wrapper as Auth;
+
+use database::album::Album;
+use database::artist::Artist;
+use database::media::Media;
+use database::track::Track;
+
+use serde_json::json;
+use warp::reply;
+
+pub mod filters {
+    use warp::reject;
+    use warp::Filter;
+    use warp::Rejection;
+
+    use super::super::global_filters::with_state;
+    use auth::Wrapper as Auth;
+    use database::DbConnection;
+
+    use serde::Deserialize;
+
+    pub fn get_artists(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct QueryArgs {
+            library_id: i64,
+        }
+
+        warp::path!("api" / "v1" / "music" / "artists")
+            .and(warp::get())
+            .and(warp::query::query::<QueryArgs>())
+            .and(auth::with_api_key())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |QueryArgs { library_id }: QueryArgs, auth: Auth, conn: DbConnection| async move {
+                    super::get_artists(conn, library_id, auth)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn get_artist_by_id(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "music" / "artist" / i64)
+            .and(warp::get())
+            .and(auth::with_api_key())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::get_artist_by_id(conn, id, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn get_album_by_id(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "music" / "album" / i64)
+            .and(warp::get())
+            .and(auth::with_api_key())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::get_album_by_id(conn, id, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn get_track_by_id(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "music" / "track" / i64)
+            .and(warp::get())
+            .and(auth::with_api_key())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::get_track_by_id(conn, id, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+}
+
+/// Method mapped to `GET /api/v1/music/artists?<library_id>` returns all artists of a music
+/// library.
+///
+/// # Arguments
+/// * `library_id` - id of the music library.
+pub async fn get_artists(
+    conn: DbConnection,
+    library_id: i64,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    check_library_access(&mut tx, library_id, &user).await?;
+    let max_level = certification_limit(&mut tx, &user).await?.level;
+
+    Ok(reply::json(
+        &Artist::get_all(&mut tx, library_id, max_level).await?,
+    ))
+}
+
+/// Method mapped to `GET /api/v1/music/artist/<id>` returns a artist and all of their albums.
+///
+/// # Arguments
+/// * `id` - id of the artist.
+pub async fn get_artist_by_id(
+    conn: DbConnection,
+    id: i64,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    let artist = Artist::get_one(&mut tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_library_access(&mut tx, artist.library_id, &user)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    let max_level = certification_limit(&mut tx, &user).await?.level;
+    let albums = Album::get_all_of_artist(&mut tx, id, max_level).await?;
+
+    Ok(reply::json(&json!({
+        "id": artist.id,
+        "library_id": artist.library_id,
+        "name": artist.name,
+        "albums": albums,
+    })))
+}
+
+/// Method mapped to `GET /api/v1/music/album/<id>` returns a album, its artist and its tracks
+/// ordered by disc and track number. Tracks can be streamed with the stream routes using their
+/// `mediafile_id`.
+///
+/// # Arguments
+/// * `id` - id of the album.
+pub async fn get_album_by_id(
+    conn: DbConnection,
+    id: i64,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    check_media_access(&mut tx, id, &user).await?;
+    check_certification(&mut tx, id, &user).await?;
+
+    let album = Album::get(&mut tx, id).await?;
+    let media = Media::get(&mut tx, id).await?;
+
+    let artist = match album.artist_id {
+        Some(artist_id) => Some(Artist::get_one(&mut tx, artist_id).await?),
+        None => None,
+    };
+
+    let tracks = Track::get_all_of_album(&mut tx, id).await?;
+    let duration: i64 = tracks.iter().filter_map(|x| x.duration).sum();
+
+    Ok(reply::json(&json!({
+        "id": media.id,
+        "library_id": media.library_id,
+        "name": media.name,
+        "year": media.year,
+        "poster_path": media.poster_path,
+        "artist": artist,
+        "duration": duration,
+        "tracks": tracks,
+    })))
+}
+
+/// Method mapped to `GET /api/v1/music/track/<id>` returns info about a single track.
+///
+/// # Arguments
+/// * `id` - id of the track.
+pub async fn get_track_by_id(
+    conn: DbConnection,
+    id: i64,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+    let track = Track::get_one(&mut tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    // tracks are visible to whoever can see the album they are on.
+    check_media_access(&mut tx, track.album_id, &user).await?;
+    check_certification(&mut tx, track.album_id, &user).await?;
+
+    Ok(reply::json(&track))
+}
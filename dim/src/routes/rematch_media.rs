@@ -1,10 +1,10 @@
 use crate::core::DbConnection;
 use crate::core::EventTx;
 use crate::errors::*;
+use crate::scanners::base::library_provider;
 use crate::scanners::base::patch_tv_metadata;
-use crate::scanners::tmdb::MediaType as ExternalMediaType;
-use crate::scanners::tmdb::Tmdb;
 use crate::scanners::movie::MovieMatcher;
+use crate::scanners::provider::parse_media_type;
 use crate::scanners::tv_show::TvShowMatcher;
 
 use database::library::MediaType;
@@ -13,11 +13,10 @@ use database::mediafile::MediaFile;
 
 use http::status::StatusCode;
 
-const API_KEY: &str = "38c372f5bc572c8aadde7a802638534e";
-
 pub mod filters {
     use crate::core::EventTx;
     use crate::routes::global_filters::with_state;
+    use auth::Permission;
     use auth::Wrapper as Auth;
     use database::DbConnection;
     use serde::Deserialize;
@@ -40,7 +39,7 @@ pub mod filters {
             .and(warp::query::query::<RouteArgs>())
             .and(with_state(conn))
             .and(with_state(event_tx))
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::RematchMetadata))
             .and_then(
                 |id,
                  RouteArgs {
@@ -65,40 +64,29 @@ pub async fn rematch_media(
     external_id: i32,
     media_type: String,
 ) -> Result<impl warp::Reply, DimError> {
-    // first fetch the data from tmdb
-    let target_type = match media_type.to_lowercase().as_ref() {
-        "movie" => ExternalMediaType::Movie,
-        "tv" => ExternalMediaType::Tv,
-        _ => return Err(DimError::InvalidMediaType),
+    // first fetch the data from the metadata provider of the library this media belongs to.
+    let target_type = parse_media_type(&media_type).ok_or(DimError::InvalidMediaType)?;
+
+    let library_id = {
+        let mut tx = conn.read().begin().await?;
+        let media = Media::get(&mut tx, id).await?;
+
+        // albums are built from the tags of their tracks, there is nothing to rematch against.
+        if let MediaType::Music = media.media_type {
+            return Err(DimError::InvalidMediaType);
+        }
+
+        media.library_id
     };
 
-    let mut tmdb = Tmdb::new(API_KEY.into(), target_type);
-    let mut result: crate::scanners::ApiMedia = tmdb
-        .search_by_id(external_id)
+    let provider = library_provider(&conn, library_id, target_type).await?;
+    let mut result = provider
+        .search_by_id(external_id as u64)
         .await
-        .map_err(|_| DimError::NotFoundError)?
-        .into();
-
-    if let ExternalMediaType::Tv = target_type {
-        let mut seasons: Vec<crate::scanners::ApiSeason> = tmdb
-            .get_seasons_for(result.id)
-            .await
-            .unwrap_or_default()
-            .into_iter()
-            .map(Into::into)
-            .collect();
-
-        for season in seasons.iter_mut() {
-            season.episodes = tmdb
-                .get_episodes_for(result.id, season.season_number)
-                .await
-                .unwrap_or_default()
-                .into_iter()
-                .map(Into::into)
-                .collect();
-        }
+        .map_err(|_| DimError::NotFoundError)?;
 
-        result.seasons = seasons;
+    if let MediaType::Tv = target_type {
+        provider.populate_seasons(&mut result).await;
     }
 
     // second decouple the media and its mediafiles.
@@ -110,7 +98,9 @@ pub async fn rematch_media(
     use database::episode::Episode;
 
     let orphans = match target.media_type {
-        MediaType::Movie | MediaType::Episode => Media::decouple_mediafiles(&mut tx, id).await?,
+        MediaType::Movie | MediaType::Episode | MediaType::Music | MediaType::Mixed => {
+            Media::decouple_mediafiles(&mut tx, id).await?
+        }
         MediaType::Tv => {
             let mut orphans = vec![];
             for episode in Episode::get_all_of_tv(&mut tx, id).await? {
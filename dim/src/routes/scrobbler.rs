@@ -0,0 +1,237 @@
+use crate::core::DbConnection;
+use crate::errors;
+use crate::scrobbler;
+use crate::scrobbler::trakt;
+use crate::scrobbler::SinkKind;
+
+use auth::Permission;
+use auth::Wrapper as Auth;
+
+use database::scrobbler::InsertableScrobbler;
+use database::scrobbler::Scrobbler;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use warp::http::status::StatusCode;
+use warp::reply;
+
+pub mod filters {
+    use warp::reject;
+    use warp::Filter;
+    use warp::Rejection;
+
+    use super::super::global_filters::with_state;
+    use auth::Wrapper as Auth;
+    use database::DbConnection;
+    use serde::Deserialize;
+
+    pub fn get_scrobblers(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "user" / "scrobblers")
+            .and(warp::get())
+            .and(auth::with_auth())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|auth: Auth, conn: DbConnection| async move {
+                super::get_scrobblers(conn, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn add_scrobbler(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "user" / "scrobblers")
+            .and(warp::post())
+            .and(auth::with_auth())
+            .and(warp::body::json::<super::NewScrobbler>())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |auth: Auth, data: super::NewScrobbler, conn: DbConnection| async move {
+                    super::add_scrobbler(conn, auth, data)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn patch_scrobbler(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            enabled: bool,
+        }
+
+        warp::path!("api" / "v1" / "user" / "scrobblers" / i64)
+            .and(warp::patch())
+            .and(auth::with_auth())
+            .and(warp::body::json::<Params>())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |id: i64, auth: Auth, Params { enabled }: Params, conn: DbConnection| async move {
+                    super::patch_scrobbler(conn, auth, id, enabled)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn delete_scrobbler(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "user" / "scrobblers" / i64)
+            .and(warp::delete())
+            .and(auth::with_auth())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::delete_scrobbler(conn, auth, id)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+}
+
+/// Body of a request adding a scrobbler.
+#[derive(Deserialize)]
+pub struct NewScrobbler {
+    /// Either `webhook` or `trakt`.
+    pub kind: String,
+    /// Url events are posted to, defaults to the Trakt api for trakt scrobblers.
+    pub url: Option<String>,
+    /// Client id of the application registered with Trakt.
+    pub client_id: Option<String>,
+    /// Token sent as a bearer token, for Trakt this is the OAuth access token of the user.
+    pub token: Option<String>,
+}
+
+fn invalid(description: &str) -> errors::DimError {
+    errors::DimError::MissingFieldInBody {
+        description: description.into(),
+    }
+}
+
+/// Method mapped to `GET /api/v1/user/scrobblers` returns the scrobblers of the user. Tokens are
+/// never returned.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+pub async fn get_scrobblers(
+    conn: DbConnection,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+
+    Ok(reply::json(
+        &Scrobbler::get_all_of_user(&mut tx, user.user_ref()).await?,
+    ))
+}
+
+/// Method mapped to `POST /api/v1/user/scrobblers` adds a scrobbler the playback of the user gets
+/// reported to. Only users who can manage the server settings may add scrobblers pointing at
+/// loopback, private or link-local addresses.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `data` - the scrobbler to add
+pub async fn add_scrobbler(
+    conn: DbConnection,
+    user: Auth,
+    data: NewScrobbler,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let kind = data
+        .kind
+        .parse::<SinkKind>()
+        .map_err(|_| invalid("kind must be one of [webhook, trakt]"))?;
+
+    let url = match (kind, data.url) {
+        (_, Some(url)) => url,
+        (SinkKind::Trakt, None) => trakt::DEFAULT_URL.to_string(),
+        (SinkKind::Webhook, None) => return Err(invalid("webhooks require a url")),
+    };
+
+    let parsed = match reqwest::Url::parse(&url) {
+        Ok(x) if matches!(x.scheme(), "http" | "https") => x,
+        _ => return Err(invalid("url must be a valid http(s) url")),
+    };
+
+    if !user.0.claims.has_permission(Permission::ManageSettings)
+        && !scrobbler::is_public_url(&parsed).await
+    {
+        return Err(invalid("url must point to a public host"));
+    }
+
+    if kind == SinkKind::Trakt && (data.client_id.is_none() || data.token.is_none()) {
+        return Err(invalid("trakt scrobblers require a client_id and token"));
+    }
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let id = InsertableScrobbler {
+        username: user.get_user(),
+        kind: kind.as_str().to_string(),
+        url,
+        client_id: data.client_id,
+        token: data.token,
+    }
+    .insert(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(reply::json(&json!({ "id": id })))
+}
+
+/// Method mapped to `PATCH /api/v1/user/scrobblers/<id>` enables or disables a scrobbler of the
+/// user.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `id` - id of the scrobbler
+/// * `enabled` - whether events should be sent to the scrobbler
+pub async fn patch_scrobbler(
+    conn: DbConnection,
+    user: Auth,
+    id: i64,
+    enabled: bool,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    if Scrobbler::set_enabled(&mut tx, user.user_ref(), id, enabled).await? < 1 {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `DELETE /api/v1/user/scrobblers/<id>` removes a scrobbler of the user.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `user` - Auth middleware
+/// * `id` - id of the scrobbler
+pub async fn delete_scrobbler(
+    conn: DbConnection,
+    user: Auth,
+    id: i64,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    if Scrobbler::delete(&mut tx, user.user_ref(), id).await? < 1 {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -6,6 +6,7 @@ use database::user::UpdateableUser;
 use database::user::User;
 use database::user::UserSettings;
 
+use auth::Permission;
 use auth::Wrapper as Auth;
 use serde::Deserialize;
 use serde::Serialize;
@@ -23,6 +24,7 @@ use once_cell::sync::OnceCell;
 use warp::reply;
 
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct GlobalSettings {
     pub enable_ssl: bool,
     pub port: u16,
@@ -38,6 +40,16 @@ pub struct GlobalSettings {
     pub verbose: bool,
     pub secret_key: Option<[u8; 16]>,
     pub enable_hwaccel: bool,
+
+    /// API key used to query tmdb, if unset a default key is used.
+    pub tmdb_api_key: Option<String>,
+
+    /// Number of PBKDF2 iterations passwords are hashed with.
+    pub password_hash_iterations: u32,
+    /// Minimum length of new passwords.
+    pub password_min_length: usize,
+    /// Passwords which cannot be used, on top of a built in list of common passwords.
+    pub banned_passwords: Vec<String>,
 }
 
 impl Default for GlobalSettings {
@@ -62,6 +74,10 @@ impl Default for GlobalSettings {
             verbose: false,
             secret_key: None,
             enable_hwaccel: true,
+            tmdb_api_key: None,
+            password_hash_iterations: database::user::DEFAULT_HASH_ITERATIONS,
+            password_min_length: 8,
+            banned_passwords: Vec::new(),
         }
     }
 }
@@ -102,6 +118,8 @@ pub fn set_global_settings(settings: GlobalSettings) -> Result<(), Box<dyn Error
         .cloned()
         .unwrap_or(ffpath("config/config.toml").into());
 
+    database::user::set_hash_iterations(settings.password_hash_iterations);
+
     {
         let mut lock = GLOBAL_SETTINGS.lock().unwrap();
         *lock = settings;
@@ -119,6 +137,7 @@ pub mod filters {
     use database::user::UserSettings;
     use database::DbConnection;
 
+    use auth::Permission;
     use auth::Wrapper as Auth;
 
     use warp::reject;
@@ -163,7 +182,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "host" / "settings")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageSettings))
             .and_then(|auth: Auth| async move {
                 super::http_get_global_settings(auth)
                     .await
@@ -176,7 +195,7 @@ pub mod filters {
         warp::path!("api" / "v1" / "host" / "settings")
             .and(warp::post())
             .and(warp::body::json::<super::GlobalSettings>())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageSettings))
             .and_then(|settings: super::GlobalSettings, auth: Auth| async move {
                 super::http_set_global_settings(auth, settings)
                     .await
@@ -200,8 +219,15 @@ pub async fn post_user_settings(
     user: Auth,
     new_settings: UserSettings,
 ) -> Result<impl warp::Reply, errors::DimError> {
+    let mut new_settings = new_settings;
     let mut lock = db.writer().lock_owned().await;
     let mut tx = database::write_tx(&mut lock).await?;
+
+    // the certification limit is set by admins, users cannot lift it themselves.
+    let prefs = User::get(&mut tx, &user.0.claims.get_user()).await?.prefs;
+    new_settings.max_certification = prefs.max_certification;
+    new_settings.hide_unrated = prefs.hide_unrated;
+
     let update_user = UpdateableUser {
         prefs: Some(new_settings.clone()),
     };
@@ -226,7 +252,7 @@ pub async fn http_set_global_settings(
     user: Auth,
     new_settings: GlobalSettings,
 ) -> Result<impl warp::Reply, errors::DimError> {
-    if user.0.claims.has_role("owner") {
+    if user.0.claims.has_permission(Permission::ManageSettings) {
         set_global_settings(new_settings).unwrap();
         return Ok(reply::json(&get_global_settings()));
     }
@@ -1,14 +1,22 @@
 use database::asset;
+use database::fetch_queue::FetchJob;
+use database::fetch_queue::FetchStatus;
 use http::StatusCode;
 use rust_embed::RustEmbed;
+use tokio::task::spawn_blocking;
+use tracing::warn;
 use warp::path;
 use warp::Reply;
 
-use std::path::Path;
+use std::path::Component;
 use std::path::PathBuf;
 
 use crate::errors;
 use crate::fetcher::insert_into_queue;
+use crate::images;
+
+/// Images are revalidated with their `ETag` after a day.
+const IMAGE_CACHE_CONTROL: &str = "public, max-age=86400";
 
 pub mod filters {
     use super::super::global_filters::with_state;
@@ -39,11 +47,18 @@ pub mod filters {
             .and(warp::get())
             .and(warp::path::tail())
             .and(warp::query::query::<QueryArgs>())
+            .and(warp::header::optional::<String>("accept"))
+            .and(warp::header::optional::<String>("if-none-match"))
             .and(with_state(metadata_path.clone()))
             .and(with_state(conn))
             .and_then(
-                |x, QueryArgs { w, h }: QueryArgs, meta_path, conn| async move {
-                    super::get_image(x, w, h, meta_path, conn)
+                |x,
+                 QueryArgs { w, h }: QueryArgs,
+                 accept: Option<String>,
+                 if_none_match: Option<String>,
+                 meta_path,
+                 conn| async move {
+                    super::get_image(x, w, h, accept, if_none_match, meta_path, conn)
                         .await
                         .map_err(|e| reject::custom(e))
                 },
@@ -111,43 +126,116 @@ pub async fn react_routes() -> Result<impl warp::Reply, warp::Rejection> {
     }
 }
 
+/// Method mapped to `GET /images/<path>` returns a image from the metadata directory. If `w` or
+/// `h` are set the image is scaled down to fit within them, and it is converted to WebP or AVIF if
+/// the client accepts it. Derived images are cached under `{metadata_dir}/cache`. Images which are
+/// missing are queued to be fetched unless their download is already pending or has failed.
+///
+/// # Arguments
+/// * `path` - path of the image relative to the metadata directory
+/// * `resize_w` - max width of the image
+/// * `resize_h` - max height of the image
+/// * `accept` - `Accept` header sent by the client
+/// * `if_none_match` - `If-None-Match` header sent by the client
+/// * `meta_path` - the metadata directory
+/// * `conn` - database connection
 pub async fn get_image(
     path: path::Tail,
-    _resize_w: Option<u32>,
-    _resize_h: Option<u32>,
+    resize_w: Option<u32>,
+    resize_h: Option<u32>,
+    accept: Option<String>,
+    if_none_match: Option<String>,
     meta_path: String,
     conn: database::DbConnection,
 ) -> Result<impl warp::Reply, errors::DimError> {
+    let file = PathBuf::from(path.as_str());
+
+    if !file.components().all(|x| matches!(x, Component::Normal(_))) {
+        return Err(errors::DimError::NotFoundError);
+    }
+
     let mut file_path = PathBuf::from(&meta_path);
-    file_path.push(path.as_str());
+    file_path.push(&file);
 
     let mut url_path = PathBuf::from("images/");
-    url_path.push(path.as_str());
+    url_path.push(&file);
+
+    let metadata = match tokio::fs::metadata(&file_path).await {
+        Ok(x) => x,
+        Err(_) => {
+            let mut tx = conn.read().begin().await?;
+            let url = asset::Asset::get_url_by_file(&mut tx, &url_path).await;
+            let queued = match url.as_ref() {
+                Ok(x) => matches!(
+                    FetchJob::get_by_url(&mut tx, x).await,
+                    Ok(job) if job.status != FetchStatus::Done
+                ),
+                Err(_) => false,
+            };
+            drop(tx);
+
+            // failed downloads are queued again by rescans, not by anyone requesting the image.
+            if let (Ok(x), false) = (url, queued) {
+                let mut lock = conn.writer().lock_owned().await;
+                let mut tx = database::write_tx(&mut lock).await?;
+                insert_into_queue(&mut tx, x, 5).await?;
+                tx.commit().await?;
+            }
 
-    /*
-    let image = if let (Some(w), Some(h)) = (resize_w, resize_h) {
-        spawn_blocking(move ||  { resize_image(file_path, w, h).ok() }).await.unwrap()
-    } else {
-        tokio::fs::read(file_path).await.ok()
+            return Err(errors::DimError::NotFoundError);
+        }
     };
-    */
 
-    let mut tx = conn.read().begin().await?;
-    if !Path::new(&file_path).exists() {
-        if let Ok(x) = asset::Asset::get_url_by_file(&mut tx, &url_path).await {
-            insert_into_queue(x, 5).await;
+    let mut variant = images::Variant::new(
+        resize_w,
+        resize_h,
+        images::Format::negotiate(accept.as_deref()),
+    );
+
+    if let Some(x) = if_none_match {
+        if images::etag_matches(&x, &variant.etag(&metadata)) {
+            return warp::http::Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header("ETag", variant.etag(&metadata))
+                .header("Cache-Control", IMAGE_CACHE_CONTROL)
+                .header("Vary", "Accept")
+                .body(Vec::new())
+                .map_err(|_| errors::DimError::NotFoundError);
         }
     }
 
-    let image = tokio::fs::read(file_path).await.ok();
+    let render = {
+        let meta_path = PathBuf::from(&meta_path);
+        let file = file.clone();
+        spawn_blocking(move || images::render(&meta_path, &file, variant)).await
+    };
 
-    if let Some(data) = image {
-        return warp::http::Response::builder()
-            .status(StatusCode::OK)
-            .header("ContentType", "image/jpeg")
-            .body(data)
-            .map_err(|_| errors::DimError::NotFoundError);
-    }
+    let data = match render {
+        Ok(Ok(x)) => x,
+        Ok(Err(e)) => {
+            // serve the image as is rather than not at all, ie if it is in a format we cant decode.
+            warn!(reason = ?e, "Failed to render {:?}", file);
+            variant = images::Variant::new(None, None, None);
+            tokio::fs::read(&file_path)
+                .await
+                .map_err(|_| errors::DimError::NotFoundError)?
+        }
+        Err(_) => return Err(errors::DimError::InternalServerError),
+    };
 
-    Err(errors::DimError::NotFoundError)
+    // not every format we encode into can be sniffed, ie avif, so only originals and resized
+    // images, which keep a jpeg or png format, are.
+    let mime = variant
+        .format
+        .map(|x| x.mime())
+        .unwrap_or_else(|| images::mime_of(&data));
+
+    warp::http::Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", mime)
+        .header("ETag", variant.etag(&metadata))
+        .header("Cache-Control", IMAGE_CACHE_CONTROL)
+        .header("Vary", "Accept")
+        .body(data)
+        .map_err(|_| errors::DimError::NotFoundError)
 }
@@ -1,8 +1,10 @@
+use auth::Permission;
 use auth::Wrapper as Auth;
 
 use crate::core::DbConnection;
 use crate::core::StateManager;
 use crate::errors;
+use crate::scrobbler::ScrobbleDispatcher;
 use crate::stream_tracking::ContentType;
 use crate::stream_tracking::StreamTracking;
 use crate::stream_tracking::VirtualManifest;
@@ -11,9 +13,14 @@ use crate::streaming::ffprobe::FFProbeCtx;
 use crate::streaming::get_avc1_tag;
 use crate::streaming::get_qualities;
 use crate::streaming::level_to_tag;
+use crate::trickplay;
 use crate::utils::quality_to_label;
 
+use database::library::Library;
+use database::marker::Marker;
+use database::media::Media;
 use database::mediafile::MediaFile;
+use database::trickplay::Trickplay;
 use database::user::DefaultVideoQuality;
 use database::user::User;
 use database::user::UserSettings;
@@ -22,6 +29,7 @@ use nightfall::error::NightfallError;
 use nightfall::profiles::*;
 
 use std::future::Future;
+use std::path::Path;
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -45,9 +53,11 @@ pub mod filters {
     use crate::core::DbConnection;
     use crate::core::StateManager;
     use crate::errors::StreamingErrors;
+    use crate::scrobbler::ScrobbleDispatcher;
     use crate::stream_tracking::StreamTracking;
     use crate::warp_unwrap;
 
+    use auth::Permission;
     use auth::Wrapper as Auth;
     use uuid::Uuid;
 
@@ -58,6 +68,7 @@ pub mod filters {
         conn: DbConnection,
         state: StateManager,
         stream_tracking: StreamTracking,
+        scrobbler: ScrobbleDispatcher,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         #[derive(Deserialize)]
         struct QueryArgs {
@@ -67,22 +78,32 @@ pub mod filters {
         warp::path!("api" / "v1" / "stream" / i64 / "manifest")
             .and(warp::get())
             .and(warp::query::query::<QueryArgs>())
-            .and(auth::with_auth())
+            .and(auth::with_api_key_permission(Permission::Stream))
             .and(with_state::<DbConnection>(conn))
             .and(with_state::<StateManager>(state))
             .and(with_state::<StreamTracking>(stream_tracking))
+            .and(with_state::<ScrobbleDispatcher>(scrobbler))
             .and_then(
                 |id: i64,
                  QueryArgs { gid }: QueryArgs,
                  auth: Auth,
                  conn: DbConnection,
                  state: StateManager,
-                 stream_tracking: StreamTracking| async move {
+                 stream_tracking: StreamTracking,
+                 scrobbler: ScrobbleDispatcher| async move {
                     let gid = gid.and_then(|x| Uuid::parse_str(x.as_str()).ok());
 
                     warp_unwrap!(
-                        super::return_virtual_manifest(state, stream_tracking, auth, conn, id, gid)
-                            .await
+                        super::return_virtual_manifest(
+                            state,
+                            stream_tracking,
+                            scrobbler,
+                            auth,
+                            conn,
+                            id,
+                            gid
+                        )
+                        .await
                     )
                 },
             )
@@ -103,7 +124,7 @@ pub mod filters {
         warp::path!("api" / "v1" / "stream" / String / "manifest.mpd")
             .and(warp::get())
             .and(warp::query::query::<QueryArgs>())
-            .and(auth::with_auth())
+            .and(auth::with_api_key_permission(Permission::Stream))
             .and(with_state::<DbConnection>(conn))
             .and(with_state::<StateManager>(state))
             .and(with_state::<StreamTracking>(stream_tracking))
@@ -139,6 +160,53 @@ pub mod filters {
             )
     }
 
+    pub fn return_hls_master(
+        stream_tracking: StreamTracking,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "stream" / String / "master.m3u8")
+            .and(warp::get())
+            .and(with_state::<StreamTracking>(stream_tracking))
+            .and_then(|id: String, stream_tracking: StreamTracking| async move {
+                let gid = match Uuid::parse_str(id.as_str()) {
+                    Ok(x) => x,
+                    Err(_) => return Err(reject::custom(StreamingErrors::GidParseError)),
+                };
+
+                super::return_hls_master(stream_tracking, gid)
+                    .await
+                    .map_err(|e| reject::custom(e))
+            })
+    }
+
+    pub fn return_hls_playlist(
+        stream_tracking: StreamTracking,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct QueryArgs {
+            start_num: Option<u64>,
+        }
+
+        warp::path!("api" / "v1" / "stream" / String / String / "playlist.m3u8")
+            .and(warp::get())
+            .and(warp::query::query::<QueryArgs>())
+            .and(with_state::<StreamTracking>(stream_tracking))
+            .and_then(
+                |gid: String,
+                 id: String,
+                 QueryArgs { start_num }: QueryArgs,
+                 stream_tracking: StreamTracking| async move {
+                    let gid = match Uuid::parse_str(gid.as_str()) {
+                        Ok(x) => x,
+                        Err(_) => return Err(reject::custom(StreamingErrors::GidParseError)),
+                    };
+
+                    super::return_hls_playlist(stream_tracking, gid, id, start_num)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
     pub fn get_init(
         state: StateManager,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -189,6 +257,29 @@ pub mod filters {
             })
     }
 
+    pub fn get_thumbnail_sheet(
+        stream_tracking: StreamTracking,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+        warp::path!("api" / "v1" / "stream" / String / "thumbnails" / i64 / String)
+            .and(warp::get())
+            .and(with_state::<StreamTracking>(stream_tracking))
+            .and_then(
+                |gid: String,
+                 mediafile_id: i64,
+                 sheet: String,
+                 stream_tracking: StreamTracking| async move {
+                    let gid = match Uuid::parse_str(gid.as_str()) {
+                        Ok(x) => x,
+                        Err(_) => return Err(reject::custom(StreamingErrors::GidParseError)),
+                    };
+
+                    super::get_thumbnail_sheet(stream_tracking, gid, mediafile_id, sheet)
+                        .await
+                        .map_err(|e| reject::custom(e))
+                },
+            )
+    }
+
     pub fn should_client_hard_seek(
         state: StateManager,
         stream_tracking: StreamTracking,
@@ -238,19 +329,24 @@ pub mod filters {
     pub fn kill_session(
         state: StateManager,
         stream_tracking: StreamTracking,
+        scrobbler: ScrobbleDispatcher,
     ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
         warp::path!("api" / "v1" / "stream" / String / "state" / "kill")
             .and(warp::get())
             .and(with_state(state))
             .and(with_state(stream_tracking))
+            .and(with_state(scrobbler))
             .and_then(
-                |id: String, state: StateManager, stream_tracking: StreamTracking| async move {
+                |id: String,
+                 state: StateManager,
+                 stream_tracking: StreamTracking,
+                 scrobbler: ScrobbleDispatcher| async move {
                     let gid = match Uuid::parse_str(id.as_str()) {
                         Ok(x) => x,
                         Err(_) => return Err(reject::custom(StreamingErrors::GidParseError)),
                     };
 
-                    super::kill_session(state, stream_tracking, gid)
+                    super::kill_session(state, stream_tracking, scrobbler, gid)
                         .await
                         .map_err(|e| reject::custom(e))
                 },
@@ -259,15 +355,19 @@ pub mod filters {
 }
 
 /// Method mapped to `GET /api/v1/stream/<id>/manifest?<gid>` returns or creates a virtual
-/// manifest.
+/// manifest. Users without the `transcode` permission get a 403 for videos which cant be direct
+/// played.
 pub async fn return_virtual_manifest(
     state: StateManager,
     stream_tracking: StreamTracking,
+    scrobbler: ScrobbleDispatcher,
     auth: Auth,
     conn: DbConnection,
     id: i64,
     gid: Option<Uuid>,
 ) -> Result<impl warp::Reply, errors::StreamingErrors> {
+    let mut tx = conn.read().begin().await?;
+
     if let Some(gid) = gid {
         return Ok(reply::json(&json!({
             "tracks": stream_tracking.get_for_gid(&gid).await,
@@ -275,19 +375,43 @@ pub async fn return_virtual_manifest(
         })));
     }
 
-    let mut tx = conn.read().begin().await?;
-
-    let user_prefs = User::get(&mut tx, auth.0.claims.get_user_ref())
+    let mut user_prefs = User::get(&mut tx, auth.0.claims.get_user_ref())
         .await
         .map(|x| x.prefs)
         .unwrap_or_default();
 
+    // users who arent allowed to transcode only get offered the direct play stream.
+    let can_transcode = auth.0.claims.has_permission(Permission::Transcode);
+    if !can_transcode {
+        user_prefs.default_video_quality = DefaultVideoQuality::DirectPlay;
+    }
+
     let gid = uuid::Uuid::new_v4();
 
     let media = MediaFile::get_one(&mut tx, id)
         .await
         .map_err(|e| errors::StreamingErrors::NoMediaFileFound(e.to_string()))?;
 
+    let certification_level = match media.media_id {
+        Some(id) => Media::get_certification_level(&mut tx, id)
+            .await
+            .ok()
+            .flatten(),
+        None => None,
+    };
+
+    // files in libraries the user cant see, or of media outside the users certification limit, are
+    // treated as if they dont exist.
+    let is_visible = Library::is_visible_to(&mut tx, media.library_id, auth.user_ref())
+        .await
+        .unwrap_or(false);
+
+    if !is_visible || !user_prefs.certification_limit().allows(certification_level) {
+        return Err(errors::StreamingErrors::NoMediaFileFound(
+            "No mediafile with this id".into(),
+        ));
+    }
+
     let target_file = media.target_file.clone();
     let info = spawn_blocking(move || {
         FFProbeCtx::new(crate::streaming::FFPROBE_BIN.as_ref()).get_meta(target_file)
@@ -303,27 +427,49 @@ pub async fn return_virtual_manifest(
 
     ms.truncate(4);
 
-    let should_stream_default =
-        try_create_dstream(&info, &media, &stream_tracking, &gid, &state, &user_prefs).await?;
-
-    create_video(
-        &info,
-        &media,
-        &stream_tracking,
-        &gid,
-        &state,
-        &user_prefs,
-        should_stream_default,
-    )
-    .await?;
+    // audio-only files, such as music, only get audio tracks.
+    if info.get_primary("video").is_some() {
+        let should_stream_default =
+            try_create_dstream(&info, &media, &stream_tracking, &gid, &state, &user_prefs).await?;
+
+        // the file cant be direct played, so without transcoding there would be no video at all.
+        if !can_transcode && should_stream_default {
+            return Err(errors::StreamingErrors::TranscodePermissionRequired);
+        }
+
+        if can_transcode {
+            create_video(
+                &info,
+                &media,
+                &stream_tracking,
+                &gid,
+                &state,
+                &user_prefs,
+                should_stream_default,
+            )
+            .await?;
+        }
+    }
+
     create_audio(&info, &media, &stream_tracking, &gid, &state).await?;
     create_subtitles(&info, &media, &stream_tracking, &gid, &state).await?;
+    create_thumbnails(&info, &media, &stream_tracking, &gid, &mut tx).await;
 
     stream_tracking.generate_sids(&gid).await;
 
+    if let Some(media_id) = media.media_id {
+        scrobbler.stream_started(gid, auth.user_ref(), media_id);
+    }
+
+    // lets players offer to skip the intro and move on to the next episode once the credits roll.
+    let markers = Marker::get_of_mediafile(&mut tx, id)
+        .await
+        .unwrap_or_default();
+
     Ok(reply::json(&json!({
         "tracks": stream_tracking.get_for_gid(&gid).await,
         "gid": gid.to_hyphenated().to_string(),
+        "markers": markers,
     })))
 }
 
@@ -564,6 +710,7 @@ pub async fn create_audio(
         let virtual_manifest =
             VirtualManifest::new(audio.clone(), chunk_path, init_seg, ContentType::Audio)
                 .set_mime("audio/mp4")
+                .set_duration(info.get_duration())
                 .set_codecs("mp4a.40.2")
                 .set_bandwidth(bitrate)
                 .set_is_default(is_default)
@@ -640,6 +787,65 @@ pub async fn create_subtitles(
     Ok(())
 }
 
+/// Adds the thumbnail sheets of `media` as a image track, if they were generated already.
+pub async fn create_thumbnails(
+    info: &FFPWrapper,
+    media: &MediaFile,
+    stream_tracking: &StreamTracking,
+    gid: &Uuid,
+    tx: &mut database::Transaction<'_>,
+) {
+    let sheets = match Trickplay::get(tx, media.id).await {
+        Ok(x) if x.error.is_none() && x.sheets > 0 => x,
+        _ => return,
+    };
+
+    let sheet_duration = sheets.interval * sheets.tile_columns * sheets.tile_rows;
+
+    // estimated from the size of the first sheet, the others are about the same size.
+    let sheet_size = match crate::core::METADATA_PATH.get() {
+        Some(x) => {
+            let path = trickplay::sheets_dir(Path::new(x), media.id).join(trickplay::sheet_name(0));
+            tokio::fs::metadata(path)
+                .await
+                .map(|x| x.len())
+                .unwrap_or(0)
+        }
+        None => 0,
+    };
+    let bandwidth = sheet_size * 8 / sheet_duration.max(1) as u64;
+
+    let chunk_path = format!(
+        "{}/thumbnails/{}/sheet-$Number$.jpg",
+        gid.to_hyphenated(),
+        media.id
+    );
+
+    let virtual_manifest = VirtualManifest::new(
+        thumbnails_id(media.id),
+        chunk_path,
+        None,
+        ContentType::Image,
+    )
+    .set_mime("image/jpeg")
+    .set_duration(info.get_duration())
+    .set_bandwidth(bandwidth.max(1))
+    .set_args([
+        ("width", sheets.width * sheets.tile_columns),
+        ("height", sheets.height * sheets.tile_rows),
+    ])
+    .set_tiles(sheets.tile_columns, sheets.tile_rows)
+    .set_target_duration(sheet_duration as u32)
+    .set_label("Thumbnails".into());
+
+    stream_tracking.insert(gid, virtual_manifest).await;
+}
+
+/// Id of the image track holding the thumbnail sheets of a mediafile.
+fn thumbnails_id(mediafile_id: i64) -> String {
+    format!("thumbnails-{}", mediafile_id)
+}
+
 /// Method mapped to `/api/v1/stream/<gid>/manifest.mpd` compiles a virtual manifest into a
 /// mpeg-dash manifest.
 ///
@@ -663,7 +869,12 @@ pub async fn return_manifest(
             .get_for_gid(&gid)
             .await
             .into_iter()
-            .filter(|x| !matches!(x.content_type, ContentType::Video | ContentType::Audio))
+            .filter(|x| {
+                !matches!(
+                    x.content_type,
+                    ContentType::Video | ContentType::Audio | ContentType::Image
+                )
+            })
             .map(|x| x.id)
             .collect::<Vec<_>>();
         stream_tracking.kill(&state, &gid, ids, true).await;
@@ -693,6 +904,51 @@ pub async fn return_manifest(
     ))
 }
 
+/// Method mapped to `GET /api/v1/stream/<gid>/master.m3u8` compiles a virtual manifest into a HLS
+/// master playlist.
+///
+/// This route, like the chunk routes, is not authenticated as native HLS players cannot attach
+/// custom headers to their requests. The gid can only be obtained through the authenticated
+/// virtual manifest route.
+pub async fn return_hls_master(
+    stream_tracking: StreamTracking,
+    gid: Uuid,
+) -> Result<impl warp::Reply, errors::StreamingErrors> {
+    let playlist = stream_tracking
+        .compile_hls(&gid)
+        .await
+        .ok_or(errors::StreamingErrors::SessionDoesntExist)?;
+
+    Ok(warp::reply::with_header(
+        playlist,
+        "Content-Type",
+        "application/vnd.apple.mpegurl",
+    ))
+}
+
+/// Method mapped to `GET /api/v1/stream/<gid>/<id>/playlist.m3u8` returns the HLS media playlist
+/// for the track `id` of the virtual manifest `gid`.
+///
+/// # Query args
+/// * `start_num` - first chunk number
+pub async fn return_hls_playlist(
+    stream_tracking: StreamTracking,
+    gid: Uuid,
+    id: String,
+    start_num: Option<u64>,
+) -> Result<impl warp::Reply, errors::StreamingErrors> {
+    let playlist = stream_tracking
+        .compile_hls_playlist(&gid, &id, start_num.unwrap_or(0))
+        .await
+        .ok_or(errors::StreamingErrors::SessionDoesntExist)?;
+
+    Ok(warp::reply::with_header(
+        playlist,
+        "Content-Type",
+        "application/vnd.apple.mpegurl",
+    ))
+}
+
 /// Repeatedly invoke a nightfall routine until a timeout occurs waiting for a chunk to be "ready".
 ///
 /// `tick_dur` will the the duration amount that gets passed into `std::thread::sleep` and it will
@@ -801,6 +1057,42 @@ pub async fn get_subtitle(
     Ok(reply_with_file(path, ("Content-Type", "text/vtt")).await)
 }
 
+/// Method mapped to `/api/v1/stream/<gid>/thumbnails/<mediafile_id>/<sheet>` returns a thumbnail
+/// sheet of the image track of `gid`. Like the chunk routes this isnt authenticated as players
+/// fetch thumbnails without attaching headers.
+pub async fn get_thumbnail_sheet(
+    stream_tracking: StreamTracking,
+    gid: Uuid,
+    mediafile_id: i64,
+    sheet: String,
+) -> Result<impl warp::Reply, errors::StreamingErrors> {
+    let id = thumbnails_id(mediafile_id);
+    let has_track = stream_tracking
+        .get_for_gid(&gid)
+        .await
+        .iter()
+        .any(|x| x.content_type == ContentType::Image && x.id == id);
+
+    if !has_track {
+        return Err(errors::StreamingErrors::SessionDoesntExist);
+    }
+
+    let sheet =
+        trickplay::parse_sheet_name(&sheet).ok_or(errors::StreamingErrors::InvalidRequest)?;
+    let meta_path = crate::core::METADATA_PATH
+        .get()
+        .ok_or(errors::StreamingErrors::InternalServerError)?;
+
+    let path = trickplay::sheets_dir(Path::new(meta_path), mediafile_id)
+        .join(trickplay::sheet_name(sheet));
+
+    Ok(reply_with_file(
+        path.to_string_lossy().into_owned(),
+        ("Content-Type", "image/jpeg"),
+    )
+    .await)
+}
+
 /// Method mapped to `/api/v1/stream/<gid>/state/should_hard_seek/<chunk_num>` returns whether the
 /// client should hard seek in order to play the video at `chunk_num`. This is really only useful
 /// on web platforms.
@@ -814,7 +1106,11 @@ pub async fn should_client_hard_seek(
 
     let mut should_client_hard_seek = false;
 
-    for manifest in ids {
+    // thumbnail tracks arent backed by a stream.
+    for manifest in ids
+        .into_iter()
+        .filter(|x| x.content_type != ContentType::Image)
+    {
         should_client_hard_seek |= state.should_hard_seek(manifest.id, chunk_num).await?;
     }
 
@@ -843,12 +1139,15 @@ pub async fn session_get_stderr(
 pub async fn kill_session(
     state: StateManager,
     stream_tracking: StreamTracking,
+    scrobbler: ScrobbleDispatcher,
     gid: Uuid,
 ) -> Result<impl warp::Reply, errors::StreamingErrors> {
     for manifest in stream_tracking.get_for_gid(&gid).await {
         let _ = state.die(manifest.id).await;
     }
 
+    scrobbler.stream_stopped(&gid);
+
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -1,11 +1,16 @@
 use crate::core::DbConnection;
 use crate::errors;
+use crate::routes::library::check_certification;
+use crate::routes::library::check_media_access;
 
 use auth::Wrapper as Auth;
 
 use database::episode::{Episode, UpdateEpisode};
+use database::progress::Progress;
 use database::season::{Season, UpdateSeason};
 
+use serde::Serialize;
+
 use warp::http::status::StatusCode;
 use warp::reply;
 
@@ -15,6 +20,7 @@ pub mod filters {
     use warp::Rejection;
 
     use super::super::global_filters::with_state;
+    use auth::Permission;
     use auth::Wrapper as Auth;
     use database::episode::UpdateEpisode;
     use database::season::UpdateSeason;
@@ -25,7 +31,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "tv" / i64 / "season")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
                 super::get_tv_seasons(conn, id, auth)
@@ -39,7 +45,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "season" / i64)
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
                 super::get_season_by_id(conn, id, auth)
@@ -54,7 +60,7 @@ pub mod filters {
         warp::path!("api" / "v1" / "season" / i64)
             .and(warp::patch())
             .and(warp::body::json::<UpdateSeason>())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<DbConnection>(conn))
             .and_then(
                 |id: i64, data: UpdateSeason, auth: Auth, conn: DbConnection| async move {
@@ -70,7 +76,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "season" / i64)
             .and(warp::delete())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
                 super::delete_season_by_id(conn, id, auth)
@@ -84,7 +90,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "season" / i64 / "episodes")
             .and(warp::get())
-            .and(auth::with_auth())
+            .and(auth::with_api_key())
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
                 super::get_season_episodes(conn, id, auth)
@@ -93,13 +99,41 @@ pub mod filters {
             })
     }
 
+    pub fn mark_season_watched(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "season" / i64 / "watched")
+            .and(warp::post())
+            .and(auth::with_auth())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::set_season_watched(conn, id, true, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn mark_season_unwatched(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "season" / i64 / "watched")
+            .and(warp::delete())
+            .and(auth::with_auth())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::set_season_watched(conn, id, false, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
     pub fn patch_episode_by_id(
         conn: DbConnection,
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "episode" / i64)
             .and(warp::patch())
             .and(warp::body::json::<UpdateEpisode>())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<DbConnection>(conn))
             .and_then(
                 |id: i64, data: UpdateEpisode, auth: Auth, conn: DbConnection| async move {
@@ -115,7 +149,7 @@ pub mod filters {
     ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
         warp::path!("api" / "v1" / "episode" / i64)
             .and(warp::delete())
-            .and(auth::with_auth())
+            .and(auth::with_permission(Permission::ManageLibraries))
             .and(with_state::<DbConnection>(conn))
             .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
                 super::delete_episode_by_id(conn, id, auth)
@@ -133,10 +167,19 @@ pub mod filters {
 pub async fn get_tv_seasons(
     conn: DbConnection,
     id: i64,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
-    Ok(reply::json(&Season::get_all(&mut tx, id).await?))
+    check_media_access(&mut tx, id, &user).await?;
+    check_certification(&mut tx, id, &user).await?;
+
+    let mut seasons = vec![];
+
+    for season in Season::get_all(&mut tx, id).await? {
+        seasons.push(with_watched(&mut tx, season, &user).await?);
+    }
+
+    Ok(reply::json(&seasons))
 }
 
 /// Method mapped to `GET /api/v1/tv/<id>/season/<season_num>` returns info about the season
@@ -148,10 +191,43 @@ pub async fn get_tv_seasons(
 pub async fn get_season_by_id(
     conn: DbConnection,
     id: i64,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
-    Ok(reply::json(&Season::get_by_id(&mut tx, id).await?))
+    let season = Season::get_by_id(&mut tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_media_access(&mut tx, season.tvshowid, &user).await?;
+    check_certification(&mut tx, season.tvshowid, &user).await?;
+
+    Ok(reply::json(&with_watched(&mut tx, season, &user).await?))
+}
+
+/// A season along with how much of it the user has watched.
+#[derive(Serialize)]
+pub struct SeasonWithWatched {
+    #[serde(flatten)]
+    season: Season,
+    /// Whether the user has watched every episode of the season, seasons without episodes are
+    /// never watched.
+    watched: bool,
+    unwatched_episodes: i64,
+}
+
+async fn with_watched(
+    tx: &mut database::Transaction<'_>,
+    season: Season,
+    user: &Auth,
+) -> Result<SeasonWithWatched, errors::DimError> {
+    let uid = user.0.claims.get_user();
+    let unwatched_episodes = Progress::count_unwatched_of_season(&mut *tx, &uid, season.id).await?;
+    let episodes = Episode::count_of_season(&mut *tx, season.id).await?;
+
+    Ok(SeasonWithWatched {
+        season,
+        watched: episodes > 0 && unwatched_episodes == 0,
+        unwatched_episodes,
+    })
 }
 
 /// Method mapped to `PATCH /api/v1/tv/<id>/season/<season_num>` allows you to patch in info about
@@ -203,29 +279,76 @@ pub async fn delete_season_by_id(
 pub async fn get_season_episodes(
     conn: DbConnection,
     season_id: i64,
-    _user: Auth,
+    user: Auth,
 ) -> Result<impl warp::Reply, errors::DimError> {
     let mut tx = conn.read().begin().await?;
+    let season = Season::get_by_id(&mut tx, season_id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_media_access(&mut tx, season.tvshowid, &user).await?;
+    check_certification(&mut tx, season.tvshowid, &user).await?;
+
     #[derive(serde::Serialize)]
     pub struct Record {
         pub id: i64,
         pub name: String,
         pub thumbnail_url: Option<String>,
         pub episode: i64,
+        pub progress: i64,
+        pub watched: bool,
+        pub play_count: i64,
+        pub last_watched: Option<i64>,
     }
 
+    let uid = user.0.claims.get_user();
     let result = sqlx::query_as!(Record,
-        r#"SELECT episode.id as "id!", _tblmedia.name, assets.local_path as thumbnail_url, episode.episode_ as "episode!"
+        r#"SELECT episode.id as "id!", _tblmedia.name, assets.local_path as thumbnail_url, episode.episode_ as "episode!",
+            COALESCE(progress.delta, 0) as "progress!: i64",
+            COALESCE(progress.watched, 0) as "watched!: bool",
+            COALESCE(progress.play_count, 0) as "play_count!: i64",
+            progress.last_watched
         FROM episode
         INNER JOIN _tblmedia on _tblmedia.id = episode.id
         LEFT JOIN assets ON assets.id = _tblmedia.backdrop
+        LEFT JOIN progress ON progress.media_id = episode.id AND progress.user_id = ?
         WHERE episode.seasonid = ?"#,
+        uid,
         season_id
     ).fetch_all(&mut tx).await.map_err(|_| errors::DimError::DatabaseError)?;
 
     Ok(reply::json(&result))
 }
 
+/// Method mapped to `POST /api/v1/season/<id>/watched` marks every episode of a season as
+/// watched, `DELETE /api/v1/season/<id>/watched` marks them as unwatched.
+///
+/// # Arguments
+/// * `id` - id of the season.
+/// * `watched` - whether to mark the episodes as watched or unwatched.
+pub async fn set_season_watched(
+    conn: DbConnection,
+    id: i64,
+    watched: bool,
+    user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let uid = user.0.claims.get_user();
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let season = Season::get_by_id(&mut tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+    check_media_access(&mut tx, season.tvshowid, &user).await?;
+    check_certification(&mut tx, season.tvshowid, &user).await?;
+
+    for episode in Episode::get_all_of_season(&mut tx, id).await? {
+        Progress::set_watched(&mut tx, &uid, episode.id, watched).await?;
+    }
+
+    tx.commit().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// TODO: Move all of these into a unified update interface for media items
 /// Method mapped to `PATCH /api/v1/episode/<id>` lets you patch
 /// information about a episode.
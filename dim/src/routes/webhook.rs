@@ -0,0 +1,284 @@
+use crate::core::DbConnection;
+use crate::errors;
+use crate::webhooks;
+
+use auth::Wrapper as Auth;
+
+use database::webhook::InsertableWebhook;
+use database::webhook::UpdateWebhook;
+use database::webhook::Webhook;
+use database::webhook::WebhookDelivery;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use warp::http::status::StatusCode;
+use warp::reply;
+
+pub mod filters {
+    use warp::reject;
+    use warp::Filter;
+    use warp::Rejection;
+
+    use super::super::global_filters::with_state;
+    use auth::Permission;
+    use auth::Wrapper as Auth;
+    use database::webhook::UpdateWebhook;
+    use database::DbConnection;
+    use serde::Deserialize;
+
+    pub fn get_webhooks(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "webhooks")
+            .and(warp::get())
+            .and(auth::with_permission(Permission::ManageSettings))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|auth: Auth, conn: DbConnection| async move {
+                super::get_webhooks(conn, auth)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn add_webhook(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "webhooks")
+            .and(warp::post())
+            .and(auth::with_permission(Permission::ManageSettings))
+            .and(warp::body::json::<super::NewWebhook>())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |auth: Auth, data: super::NewWebhook, conn: DbConnection| async move {
+                    super::add_webhook(conn, auth, data)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn patch_webhook(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "webhooks" / i64)
+            .and(warp::patch())
+            .and(auth::with_permission(Permission::ManageSettings))
+            .and(warp::body::json::<UpdateWebhook>())
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |id: i64, auth: Auth, data: UpdateWebhook, conn: DbConnection| async move {
+                    super::patch_webhook(conn, auth, id, data)
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+
+    pub fn delete_webhook(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        warp::path!("api" / "v1" / "admin" / "webhooks" / i64)
+            .and(warp::delete())
+            .and(auth::with_permission(Permission::ManageSettings))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(|id: i64, auth: Auth, conn: DbConnection| async move {
+                super::delete_webhook(conn, auth, id)
+                    .await
+                    .map_err(reject::custom)
+            })
+    }
+
+    pub fn get_webhook_deliveries(
+        conn: DbConnection,
+    ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+        #[derive(Deserialize)]
+        struct Params {
+            limit: Option<i64>,
+        }
+
+        warp::path!("api" / "v1" / "admin" / "webhooks" / i64 / "deliveries")
+            .and(warp::get())
+            .and(warp::query::<Params>())
+            .and(auth::with_permission(Permission::ManageSettings))
+            .and(with_state::<DbConnection>(conn))
+            .and_then(
+                |id: i64, Params { limit }: Params, auth: Auth, conn: DbConnection| async move {
+                    super::get_webhook_deliveries(conn, auth, id, limit.unwrap_or(100))
+                        .await
+                        .map_err(reject::custom)
+                },
+            )
+    }
+}
+
+/// Body of a request adding a webhook.
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    /// Url events are posted to.
+    pub url: String,
+    /// Events to send to the webhook, all of them if empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Key used to sign payloads, generated if not set.
+    pub secret: Option<String>,
+}
+
+fn invalid(description: &str) -> errors::DimError {
+    errors::DimError::MissingFieldInBody {
+        description: description.into(),
+    }
+}
+
+fn validate(url: Option<&str>, events: Option<&[String]>) -> Result<(), errors::DimError> {
+    if let Some(url) = url {
+        match reqwest::Url::parse(url) {
+            Ok(x) if matches!(x.scheme(), "http" | "https") => {}
+            _ => return Err(invalid("url must be a valid http(s) url")),
+        }
+    }
+
+    let unknown = events
+        .unwrap_or_default()
+        .iter()
+        .find(|x| !webhooks::EVENTS.contains(&x.as_str()));
+
+    if let Some(event) = unknown {
+        return Err(invalid(&format!(
+            "unknown event {}, expected one of [{}]",
+            event,
+            webhooks::EVENTS.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Method mapped to `GET /api/v1/admin/webhooks` returns all the webhooks. Secrets are never
+/// returned.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+pub async fn get_webhooks(
+    conn: DbConnection,
+    _user: Auth,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+
+    Ok(reply::json(&Webhook::get_all(&mut tx).await?))
+}
+
+/// Method mapped to `POST /api/v1/admin/webhooks` registers a webhook server events get posted
+/// to. The response holds the secret payloads are signed with, it cant be retrieved later on.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `data` - the webhook to add
+pub async fn add_webhook(
+    conn: DbConnection,
+    _user: Auth,
+    data: NewWebhook,
+) -> Result<impl warp::Reply, errors::DimError> {
+    validate(Some(&data.url), Some(&data.events))?;
+
+    let secret = match data.secret {
+        Some(x) if x.is_empty() => return Err(invalid("secret must not be empty")),
+        Some(x) => x,
+        None => webhooks::generate_secret(),
+    };
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    let id = InsertableWebhook {
+        url: data.url,
+        secret: secret.clone(),
+        events: data.events,
+    }
+    .insert(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(reply::json(&json!({ "id": id, "secret": secret })))
+}
+
+/// Method mapped to `PATCH /api/v1/admin/webhooks/<id>` changes the url or events of a webhook,
+/// or enables and disables it.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `id` - id of the webhook
+/// * `data` - the fields to change
+pub async fn patch_webhook(
+    conn: DbConnection,
+    _user: Auth,
+    id: i64,
+    data: UpdateWebhook,
+) -> Result<impl warp::Reply, errors::DimError> {
+    validate(data.url.as_deref(), data.events.as_deref())?;
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    Webhook::get(&mut tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    data.update(&mut tx, id).await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `DELETE /api/v1/admin/webhooks/<id>` removes a webhook along with its
+/// deliveries.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `id` - id of the webhook
+pub async fn delete_webhook(
+    conn: DbConnection,
+    _user: Auth,
+    id: i64,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    if Webhook::delete(&mut tx, id).await? < 1 {
+        return Err(errors::DimError::NotFoundError);
+    }
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Method mapped to `GET /api/v1/admin/webhooks/<id>/deliveries` returns the most recent
+/// deliveries to a webhook, newest first, along with their status.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `_user` - Auth middleware
+/// * `id` - id of the webhook
+/// * `limit` - max number of deliveries to return, defaults to 100
+pub async fn get_webhook_deliveries(
+    conn: DbConnection,
+    _user: Auth,
+    id: i64,
+    limit: i64,
+) -> Result<impl warp::Reply, errors::DimError> {
+    let mut tx = conn.read().begin().await?;
+
+    Webhook::get(&mut tx, id)
+        .await
+        .map_err(|_| errors::DimError::NotFoundError)?;
+
+    Ok(reply::json(
+        &WebhookDelivery::get_of_webhook(&mut tx, id, limit).await?,
+    ))
+}
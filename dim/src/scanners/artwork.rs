@@ -0,0 +1,442 @@
+//! Artwork extracted from the media files themselves, used for media which neither the metadata
+//! provider nor sidecar files have artwork for.
+//!
+//! Cover art embedded into the file (mp4 cover atoms, mkv `cover.jpg` attachments) is preferred,
+//! portrait covers are used as posters and landscape ones as backdrops. Anything still missing is
+//! filled in with a frame grabbed from the video, skipping frames which are black or otherwise
+//! blank such as fades and title cards.
+
+use super::nfo::path_id;
+use super::ApiEpisode;
+use super::ApiMedia;
+use super::ApiSeason;
+
+use crate::core::METADATA_PATH;
+use crate::jobs;
+use crate::streaming::ffprobe::FFPWrapper;
+use crate::streaming::ffprobe::FFProbeCtx;
+use crate::streaming::ffprobe::Stream;
+use crate::streaming::FFMPEG_BIN;
+use crate::streaming::FFPROBE_BIN;
+
+use database::mediafile::MediaFile;
+use database::mediafile::UpdateMediaFile;
+use database::DbConnection;
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use image::GenericImageView;
+use image::ImageFormat;
+
+use tokio::task::spawn_blocking;
+use tracing::debug;
+use tracing::error;
+use tracing::warn;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Points in the video, as a fraction of its duration, frames are grabbed from. The first and
+/// last parts are skipped as they usually hold logos and credits.
+const FRAME_OFFSETS: &[f64] = &[0.2, 0.35, 0.5, 0.65, 0.1];
+/// Offset in seconds frames are grabbed from when the duration of the video is unknown.
+const FALLBACK_OFFSET: f64 = 30.0;
+/// Frames darker than this average luma are considered black.
+const MIN_LUMA: f64 = 24.0;
+/// Frames with less contrast than this between their darkest and brightest parts are considered
+/// blank.
+const MIN_CONTRAST: u8 = 32;
+/// How long ffmpeg may take to extract a single image before it is killed.
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Artwork extracted from a media file, as the names of the files in the metadata directory.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LocalArtwork {
+    pub poster: Option<String>,
+    pub backdrop: Option<String>,
+}
+
+/// Fills in the poster and backdrop of `media` with artwork extracted from `file` if it has none.
+///
+/// # Arguments
+/// * `media` - the metadata `file` was matched to.
+/// * `file` - path to the media file.
+pub async fn fill_missing(media: &mut ApiMedia, file: &str) {
+    if media.poster_file.is_some() && media.backdrop_file.is_some() {
+        return;
+    }
+
+    let artwork = extract_async(file).await;
+
+    if media.poster_file.is_none() {
+        media.poster_file = artwork.poster;
+    }
+
+    if media.backdrop_file.is_none() {
+        media.backdrop_file = artwork.backdrop;
+    }
+}
+
+/// Fills in the still of the episode `file` is matched to with a frame of `file` if it has none.
+/// Seasons and episodes the metadata doesnt know about are added so that they get a still too.
+///
+/// # Arguments
+/// * `media` - the tv show `file` was matched to.
+/// * `file` - path to the episode.
+/// * `season` - season number of the episode.
+/// * `episode` - episode number of the episode.
+pub async fn fill_missing_still(media: &mut ApiMedia, file: &str, season: u64, episode: u64) {
+    let has_still = media
+        .seasons
+        .iter()
+        .find(|x| x.season_number == season)
+        .and_then(|x| x.episodes.iter().find(|x| x.episode == Some(episode)))
+        .map_or(false, |x| x.still_file.is_some());
+
+    if has_still {
+        return;
+    }
+
+    let still_file = match extract_async(file).await.backdrop {
+        Some(x) => x,
+        None => return,
+    };
+
+    let season = match media.seasons.iter().position(|x| x.season_number == season) {
+        Some(idx) => &mut media.seasons[idx],
+        None => {
+            media.seasons.push(ApiSeason {
+                id: 0,
+                name: None,
+                poster_path: None,
+                poster_file: None,
+                season_number: season,
+                episodes: vec![],
+            });
+            media.seasons.last_mut().unwrap()
+        }
+    };
+
+    match season
+        .episodes
+        .iter_mut()
+        .find(|x| x.episode == Some(episode))
+    {
+        Some(x) => x.still_file = Some(still_file),
+        None => season.episodes.push(ApiEpisode {
+            id: 0,
+            name: None,
+            overview: None,
+            episode: Some(episode),
+            still: None,
+            still_file: Some(still_file),
+        }),
+    }
+}
+
+/// Extracts artwork for `file`, which couldnt be matched to any metadata, and stores it on the
+/// mediafile so that it still has a poster and backdrop when listed with the unmatched files.
+///
+/// # Arguments
+/// * `conn` - db connection
+/// * `file` - the mediafile which couldnt be matched.
+pub async fn store_unmatched(conn: &DbConnection, file: &MediaFile) {
+    if file.poster_file.is_some() && file.backdrop_file.is_some() {
+        return;
+    }
+
+    let artwork = extract_async(&file.target_file).await;
+
+    if artwork == LocalArtwork::default() {
+        return;
+    }
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = match database::write_tx(&mut lock).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!(reason = ?e, "Failed to create transaction.");
+            return;
+        }
+    };
+
+    let update = UpdateMediaFile {
+        poster_file: artwork.poster,
+        backdrop_file: artwork.backdrop,
+        ..Default::default()
+    };
+
+    if let Err(e) = update.update(&mut tx, file.id).await {
+        warn!(reason = ?e, mediafile_id = file.id, "Failed to store artwork of unmatched file");
+        return;
+    }
+
+    if let Err(e) = tx.commit().await {
+        error!(reason = ?e, "Failed to commit transaction.");
+    }
+}
+
+async fn extract_async(file: &str) -> LocalArtwork {
+    let meta_path = match METADATA_PATH.get() {
+        Some(x) => PathBuf::from(x),
+        None => return LocalArtwork::default(),
+    };
+
+    let file = PathBuf::from(file);
+
+    spawn_blocking(move || extract(&file, &meta_path))
+        .await
+        .unwrap_or_default()
+}
+
+/// Extracts the artwork of the media file `file` into `meta_path`. Artwork which was already
+/// extracted and is newer than `file` is reused.
+pub fn extract(file: &Path, meta_path: &Path) -> LocalArtwork {
+    let id = path_id(file);
+    let poster_name = format!("local-{:x}-poster.jpg", id);
+    let backdrop_name = format!("local-{:x}-backdrop.jpg", id);
+
+    let is_fresh = |name: &str| {
+        let target = meta_path.join(name);
+        match (fs::metadata(file), fs::metadata(target)) {
+            (Ok(source), Ok(target)) => match (source.modified(), target.modified()) {
+                (Ok(source), Ok(target)) => target >= source,
+                _ => false,
+            },
+            _ => false,
+        }
+    };
+
+    if is_fresh(&poster_name) && is_fresh(&backdrop_name) {
+        return LocalArtwork {
+            poster: Some(poster_name),
+            backdrop: Some(backdrop_name),
+        };
+    }
+
+    let probe = match FFProbeCtx::new(&FFPROBE_BIN).get_meta(file.to_string_lossy()) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(file = ?file, reason = ?e, "Failed to probe file for artwork");
+            return LocalArtwork::default();
+        }
+    };
+
+    let scratch = meta_path.join(format!("local-{:x}.part", id));
+    let covers = probe
+        .get_cover_art()
+        .into_iter()
+        .filter_map(|x| extract_cover(file, x, &scratch))
+        .collect::<Vec<_>>();
+    let _ = fs::remove_file(&scratch);
+
+    let (mut poster, mut backdrop) = pick_covers(covers);
+
+    if poster.is_none() || backdrop.is_none() {
+        if let Some(frame) = grab_frame(file, &probe, &scratch) {
+            poster = poster.or_else(|| Some(crop_to_aspect(&frame, 2, 3)));
+            backdrop = backdrop.or(Some(frame));
+        }
+        let _ = fs::remove_file(&scratch);
+    }
+
+    let save = |image: Option<DynamicImage>, name: String| {
+        let image = DynamicImage::ImageRgb8(image?.to_rgb8());
+
+        match image.save_with_format(meta_path.join(&name), ImageFormat::Jpeg) {
+            Ok(_) => Some(name),
+            Err(e) => {
+                warn!(file = ?file, reason = ?e, "Failed to store extracted artwork");
+                None
+            }
+        }
+    };
+
+    let artwork = LocalArtwork {
+        poster: save(poster, poster_name),
+        backdrop: save(backdrop, backdrop_name),
+    };
+
+    debug!(file = ?file, artwork = ?artwork, "Extracted local artwork");
+
+    artwork
+}
+
+/// Sorts the covers into a poster and backdrop based on their orientation.
+fn pick_covers(covers: Vec<DynamicImage>) -> (Option<DynamicImage>, Option<DynamicImage>) {
+    let mut poster = None;
+    let mut backdrop = None;
+
+    for cover in covers {
+        let (width, height) = cover.dimensions();
+
+        if height > width {
+            poster = poster.or(Some(cover));
+        } else {
+            backdrop = backdrop.or(Some(cover));
+        }
+    }
+
+    (poster, backdrop)
+}
+
+/// Writes the cover art stream `stream` of `file` to `scratch` and decodes it.
+fn extract_cover(file: &Path, stream: &Stream, scratch: &Path) -> Option<DynamicImage> {
+    let mut cmd = Command::new(*FFMPEG_BIN);
+    cmd.args(["-v", "error", "-y"]);
+
+    if stream.is_image_attachment() {
+        // ffmpeg complains about the missing output file but dumps the attachment regardless.
+        cmd.arg(format!("-dump_attachment:{}", stream.index))
+            .arg(scratch)
+            .arg("-i")
+            .arg(file);
+    } else {
+        cmd.arg("-i")
+            .arg(file)
+            .args(["-map", &format!("0:{}", stream.index)])
+            .args(["-frames:v", "1", "-c", "copy", "-f", "image2"])
+            .arg(scratch);
+    }
+
+    run(cmd)?;
+    decode(scratch)
+}
+
+/// Grabs a representative frame out of the video `file` into `scratch` and decodes it. Frames
+/// are tried at several offsets until one which isnt blank is found.
+fn grab_frame(file: &Path, probe: &FFPWrapper, scratch: &Path) -> Option<DynamicImage> {
+    let video = probe.get_primary("video")?;
+
+    frame_offsets(probe.get_duration())
+        .into_iter()
+        .find_map(|offset| {
+            let mut cmd = Command::new(*FFMPEG_BIN);
+            cmd.args(["-v", "error", "-y"])
+                .args(["-ss", &format!("{:.3}", offset)])
+                .arg("-i")
+                .arg(file)
+                .args(["-map", &format!("0:{}", video.index)])
+                // picks the most representative out of the next few frames.
+                .args(["-vf", "thumbnail=24", "-frames:v", "1"])
+                .args(["-c:v", "png", "-f", "image2"])
+                .arg(scratch);
+
+            run(cmd)?;
+            decode(scratch).filter(|x| !is_blank(x))
+        })
+}
+
+fn run(mut cmd: Command) -> Option<()> {
+    let output = match jobs::output(cmd.stdin(Stdio::null()), FFMPEG_TIMEOUT) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(reason = %e, "Failed to run ffmpeg while extracting artwork");
+            return None;
+        }
+    };
+
+    if !output.stderr.is_empty() {
+        debug!(
+            stderr = %String::from_utf8_lossy(&output.stderr),
+            "ffmpeg reported errors while extracting artwork"
+        );
+    }
+
+    Some(())
+}
+
+fn decode(path: &Path) -> Option<DynamicImage> {
+    let data = fs::read(path).ok()?;
+    image::load_from_memory(&data).ok()
+}
+
+/// Returns the offsets in seconds frames should be grabbed from for a video lasting `duration`
+/// seconds.
+fn frame_offsets(duration: Option<i32>) -> Vec<f64> {
+    match duration.filter(|x| *x > 0) {
+        Some(duration) => FRAME_OFFSETS.iter().map(|x| x * duration as f64).collect(),
+        None => vec![FALLBACK_OFFSET, 0.0],
+    }
+}
+
+/// Returns whether `image` is black or of a single color, ie a fade or a title card.
+fn is_blank(image: &DynamicImage) -> bool {
+    let luma = image.resize(64, 64, FilterType::Triangle).to_luma8();
+
+    let (min, max, sum) = luma.pixels().fold((u8::MAX, u8::MIN, 0u64), |acc, x| {
+        (acc.0.min(x[0]), acc.1.max(x[0]), acc.2 + x[0] as u64)
+    });
+    let mean = sum as f64 / (luma.width() * luma.height()).max(1) as f64;
+
+    mean < MIN_LUMA || max - min < MIN_CONTRAST
+}
+
+/// Crops the center of `image` to the aspect ratio `width`:`height`.
+fn crop_to_aspect(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (w, h) = image.dimensions();
+
+    let (crop_w, crop_h) = if w * height > h * width {
+        (h * width / height, h)
+    } else {
+        (w, w * height / width)
+    };
+
+    image.crop_imm((w - crop_w) / 2, (h - crop_h) / 2, crop_w, crop_h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgb;
+    use image::RgbImage;
+
+    #[test]
+    fn blank_frames() {
+        let black = DynamicImage::ImageRgb8(RgbImage::new(320, 180));
+        assert!(is_blank(&black));
+
+        let card = DynamicImage::ImageRgb8(RgbImage::from_pixel(320, 180, Rgb([200, 30, 30])));
+        assert!(is_blank(&card));
+
+        let frame = DynamicImage::ImageRgb8(RgbImage::from_fn(320, 180, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        }));
+        assert!(!is_blank(&frame));
+    }
+
+    #[test]
+    fn crops_posters() {
+        let frame = DynamicImage::new_rgb8(1920, 1080);
+        let poster = crop_to_aspect(&frame, 2, 3);
+        assert_eq!(poster.dimensions(), (720, 1080));
+
+        let tall = DynamicImage::new_rgb8(1000, 2000);
+        assert_eq!(crop_to_aspect(&tall, 2, 3).dimensions(), (1000, 1500));
+    }
+
+    #[test]
+    fn picks_covers() {
+        let (poster, backdrop) = pick_covers(vec![
+            DynamicImage::new_rgb8(1280, 720),
+            DynamicImage::new_rgb8(600, 900),
+            DynamicImage::new_rgb8(300, 450),
+        ]);
+
+        assert_eq!(poster.unwrap().dimensions(), (600, 900));
+        assert_eq!(backdrop.unwrap().dimensions(), (1280, 720));
+        assert_eq!(pick_covers(vec![]).0, None);
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(frame_offsets(Some(1000))[0], 200.0);
+        assert_eq!(frame_offsets(Some(0)), vec![FALLBACK_OFFSET, 0.0]);
+        assert_eq!(frame_offsets(None), vec![FALLBACK_OFFSET, 0.0]);
+    }
+}
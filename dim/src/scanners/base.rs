@@ -10,6 +10,7 @@ use tracing::instrument;
 use tracing::warn;
 use tracing::Instrument;
 
+use database::library::Library;
 use database::library::MediaType;
 use database::mediafile::InsertableMediaFile;
 use database::mediafile::MediaFile;
@@ -17,8 +18,12 @@ use database::mediafile::UpdateMediaFile;
 use database::DbConnection;
 
 use crate::core::EventTx;
+use crate::scanners::artwork;
 use crate::scanners::movie::MovieMatcher;
-use crate::scanners::tmdb::Tmdb;
+use crate::scanners::music::MusicMatcher;
+use crate::scanners::nfo;
+use crate::scanners::provider::provider_for;
+use crate::scanners::provider::MetadataProvider;
 use crate::scanners::tv_show::TvShowMatcher;
 use crate::streaming::ffprobe::FFProbeCtx;
 use crate::streaming::FFPROBE_BIN;
@@ -29,6 +34,8 @@ use torrent_name_parser::Metadata;
 
 use serde::Serialize;
 
+use std::sync::Arc;
+
 use tokio::task::spawn_blocking;
 
 use async_trait::async_trait;
@@ -51,6 +58,12 @@ pub enum ScannerError {
     UnknownError,
     #[error(display = "Database error why={}", _0)]
     DatabaseError(String),
+    #[error(display = "A scan of this library is already running")]
+    AlreadyScanning,
+    #[error(display = "The file is already mounted")]
+    AlreadyMounted,
+    #[error(display = "The scan was cancelled")]
+    Cancelled,
 }
 
 impl From<database::DatabaseError> for ScannerError {
@@ -82,12 +95,12 @@ impl MetadataExtractor {
     }
 
     #[handler]
-    #[instrument(skip(self, library_id, _media_type))]
+    #[instrument(skip(self, library_id, media_type))]
     pub async fn mount_file(
         &mut self,
         file: PathBuf,
         library_id: i64,
-        _media_type: MediaType,
+        media_type: MediaType,
     ) -> Result<MediaFile, ScannerError> {
         let target_file = file.to_str().unwrap().to_owned();
 
@@ -111,14 +124,47 @@ impl MetadataExtractor {
             MediaFile::get_by_file(&mut tx, &target_file_clone).await
         };
 
-        if let Ok(_media_file) = res {
-            debug!(
-                file = ?file.to_string_lossy(),
-                library_id = library_id,
-                "File already exists in the db",
-            );
+        let (file_size, file_mtime) = file_stat(&file);
+
+        if let Ok(media_file) = res {
+            // files mounted before we tracked size and mtime have nothing to compare against so we
+            // just record them for the next scan.
+            let untracked = media_file.file_size.is_none() && media_file.file_mtime.is_none();
+
+            if untracked
+                || (media_file.file_size == file_size && media_file.file_mtime == file_mtime)
+            {
+                debug!(
+                    file = ?file.to_string_lossy(),
+                    library_id = library_id,
+                    "File already exists in the db",
+                );
+
+                if untracked {
+                    let update_mediafile = UpdateMediaFile {
+                        file_size,
+                        file_mtime,
+                        ..Default::default()
+                    };
+
+                    let mut lock = self.conn.writer().lock_owned().await;
+                    let mut tx = database::write_tx(&mut lock)
+                        .await
+                        .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
+                    update_mediafile.update(&mut tx, media_file.id).await?;
+                    tx.commit()
+                        .await
+                        .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
+                }
+
+                return Err(ScannerError::AlreadyMounted);
+            }
 
-            return Err(ScannerError::UnknownError);
+            // the file has changed since we last saw it so we re-probe it. The file keeps its
+            // match as the filename hasnt changed.
+            reprobe_file(&self.conn, &media_file, file_size, file_mtime).await?;
+
+            return Err(ScannerError::AlreadyMounted);
         }
 
         // we clone so that we can strip the extension.
@@ -140,14 +186,20 @@ impl MetadataExtractor {
         let meta_from_string =
             move || Metadata::from(&clone).map_err(|_| ScannerError::FilenameParserError);
 
-        let metadata = match spawn_blocking(meta_from_string)
-            .instrument(debug_span!("ParseFilename"))
-            .await
-        {
-            Ok(x) => x?,
-            Err(e) => {
-                error!(e = ?e, "Metadata::from possibly panicked");
-                return Err(ScannerError::UnknownError);
+        // Music files are identified by their tags rather than their filenames, which the parser
+        // usually fails on.
+        let metadata = if let MediaType::Music = media_type {
+            None
+        } else {
+            match spawn_blocking(meta_from_string)
+                .instrument(debug_span!("ParseFilename"))
+                .await
+            {
+                Ok(x) => Some(x?),
+                Err(e) => {
+                    error!(e = ?e, "Metadata::from possibly panicked");
+                    return Err(ScannerError::UnknownError);
+                }
             }
         };
 
@@ -164,15 +216,44 @@ impl MetadataExtractor {
             return Err(ScannerError::FFProbeError);
         };
 
+        let (raw_name, raw_year, mut season, mut episode) = match metadata {
+            Some(metadata) => (
+                metadata.title().to_owned(),
+                metadata.year().map(|x| x as i64),
+                metadata.season().map(|x| x as i64),
+                metadata.episode().map(|x| x as i64),
+            ),
+            // for music we store the disc and track number in place of season and episode.
+            None => {
+                let tags = ffprobe_data.get_audio_tags();
+                (
+                    tags.title.unwrap_or(file_name_clone),
+                    tags.year,
+                    tags.disc,
+                    tags.track,
+                )
+            }
+        };
+
+        // Files in mixed libraries are classified by whether they have a episode number. Anime
+        // releases often lack the `SxxExx` markers, so we give anitomy a go on files that dont look
+        // like movies either, ie have no year.
+        if matches!(media_type, MediaType::Mixed) && episode.is_none() && raw_year.is_none() {
+            if let Some((anitomy_season, anitomy_episode)) = anitomy_episode(&file).await {
+                season = Some(anitomy_season);
+                episode = Some(anitomy_episode);
+            }
+        }
+
         let media_file = InsertableMediaFile {
             library_id,
             media_id: None,
             target_file: target_file.to_string(),
 
-            raw_name: metadata.title().to_owned(),
-            raw_year: metadata.year().map(|x| x as i64),
-            season: metadata.season().map(|x| x as i64),
-            episode: metadata.episode().map(|x| x as i64),
+            raw_name,
+            raw_year,
+            season,
+            episode,
 
             quality: ffprobe_data.get_height().map(|x| x.to_string()),
             codec: ffprobe_data.get_video_codec(),
@@ -191,6 +272,8 @@ impl MetadataExtractor {
                 .as_deref()
                 .and_then(crate::utils::lang_from_iso639)
                 .map(ToString::to_string),
+            file_size,
+            file_mtime,
         };
 
         let mediafile = {
@@ -223,18 +306,89 @@ impl MetadataExtractor {
             file = ?&target_file,
             library_id = library_id,
             id = mediafile.id,
-            season = metadata.season().unwrap_or(0),
-            episode = metadata.episode().unwrap_or(0),
+            season = mediafile.season.unwrap_or(0),
+            episode = mediafile.episode.unwrap_or(0),
         );
 
         Ok(mediafile)
     }
 }
 
+/// Returns the size in bytes and the modification time as a unix timestamp of `file`.
+pub fn file_stat(file: &Path) -> (Option<i64>, Option<i64>) {
+    let metadata = match std::fs::metadata(file) {
+        Ok(x) => x,
+        Err(_) => return (None, None),
+    };
+
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|x| x.as_secs() as i64);
+
+    (Some(metadata.len() as i64), mtime)
+}
+
+/// Runs ffprobe against a mediafile whose file has changed on disk and updates the stream
+/// information we have stored for it.
+#[instrument(skip(conn, media_file), fields(id = media_file.id))]
+async fn reprobe_file(
+    conn: &DbConnection,
+    media_file: &MediaFile,
+    file_size: Option<i64>,
+    file_mtime: Option<i64>,
+) -> Result<(), ScannerError> {
+    let target_file = media_file.target_file.clone();
+    let ffprobe_data = move || FFProbeCtx::new(&FFPROBE_BIN).get_meta(&target_file);
+    let ffprobe_data = if let Ok(Ok(data)) = spawn_blocking(ffprobe_data).await {
+        data
+    } else {
+        error!(
+            file = ?media_file.target_file,
+            "Couldnt extract media information with ffprobe",
+        );
+        return Err(ScannerError::FFProbeError);
+    };
+
+    let update_mediafile = UpdateMediaFile {
+        quality: ffprobe_data.get_height().map(|x| x.to_string()),
+        codec: ffprobe_data.get_video_codec(),
+        container: ffprobe_data.get_container(),
+        audio: ffprobe_data
+            .get_primary_codec("audio")
+            .map(ToOwned::to_owned),
+        duration: ffprobe_data.get_duration().map(|x| x as i64),
+        corrupt: ffprobe_data.is_corrupt(),
+        channels: ffprobe_data.get_primary_channels(),
+        profile: ffprobe_data.get_video_profile(),
+        audio_language: ffprobe_data
+            .get_audio_lang()
+            .or_else(|| ffprobe_data.get_video_lang())
+            .as_deref()
+            .and_then(crate::utils::lang_from_iso639)
+            .map(ToString::to_string),
+        file_size,
+        file_mtime,
+        ..Default::default()
+    };
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock)
+        .await
+        .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
+    update_mediafile.update(&mut tx, media_file.id).await?;
+    tx.commit()
+        .await
+        .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
+
+    info!(file = ?media_file.target_file, "Re-probed changed file");
+
+    Ok(())
+}
+
 #[actor]
 pub struct MetadataMatcher {
-    pub movie_tmdb: Tmdb,
-    pub tv_tmdb: Tmdb,
     pub conn: DbConnection,
     pub event_tx: EventTx,
 }
@@ -242,29 +396,34 @@ pub struct MetadataMatcher {
 #[actor]
 impl MetadataMatcher {
     pub fn new(conn: DbConnection, event_tx: EventTx) -> Self {
-        Self {
-            conn,
-            event_tx,
-            movie_tmdb: Tmdb::new("38c372f5bc572c8aadde7a802638534e".into(), MediaType::Movie),
-            tv_tmdb: Tmdb::new("38c372f5bc572c8aadde7a802638534e".into(), MediaType::Tv),
-        }
+        Self { conn, event_tx }
     }
 
     #[handler]
     pub async fn match_movie(&mut self, media: MediaFile) -> Result<(), ScannerError> {
-        let result = match self
-            .movie_tmdb
-            .search(media.raw_name.clone(), media.raw_year.map(|x| x as i32))
+        // sidecar metadata always takes precedence over online providers.
+        let path = PathBuf::from(&media.target_file);
+        if let Ok(Some(result)) = spawn_blocking(move || nfo::movie_metadata(&path)).await {
+            return self.match_movie_to_result(media, result).await;
+        }
+
+        let provider = library_provider(&self.conn, media.library_id, MediaType::Movie).await?;
+
+        let mut result = match provider
+            .search(&media.raw_name, media.raw_year.map(|x| x as i32))
             .await
         {
             Ok(v) => v,
             Err(e) => {
-                error!(media = ?media, reason = ?e, "Could not match movie to tmdb");
+                error!(media = ?media, reason = ?e, "Could not match movie to external metadata");
+                artwork::store_unmatched(&self.conn, &media).await;
 
                 return Err(ScannerError::UnknownError);
             }
         };
 
+        provider.populate_certification(&mut result).await;
+
         self.match_movie_to_result(media, result).await
     }
 
@@ -283,10 +442,41 @@ impl MetadataMatcher {
         Ok(())
     }
 
+    #[handler]
+    pub async fn match_music(&mut self, media: MediaFile) -> Result<(), ScannerError> {
+        let target_file = media.target_file.clone();
+        let ffprobe_data = move || FFProbeCtx::new(&FFPROBE_BIN).get_meta(&target_file);
+        let tags = match spawn_blocking(ffprobe_data).await {
+            Ok(Ok(x)) => x.get_audio_tags(),
+            _ => {
+                error!(media = ?media, "Couldnt read the tags of a track with ffprobe");
+                return Err(ScannerError::FFProbeError);
+            }
+        };
+
+        let matcher = MusicMatcher {
+            conn: &self.conn,
+            event_tx: &self.event_tx,
+        };
+
+        matcher.match_to_tags(tags, &media).await;
+        Ok(())
+    }
+
     #[handler]
     pub async fn match_tv(&mut self, media: MediaFile) -> Result<(), ScannerError> {
         let mut media = media;
 
+        if let Some(result) = tv_sidecar(&self.conn, &mut media).await? {
+            let matcher = TvShowMatcher {
+                conn: &self.conn,
+                event_tx: &self.event_tx,
+            };
+
+            matcher.match_to_result(result, &media).await;
+            return Ok(());
+        }
+
         let path = Path::new(&media.target_file);
         let filename = path
             .file_name()
@@ -305,16 +495,17 @@ impl MetadataMatcher {
             Ok(v) | Err(v) => v,
         };
 
-        let mut result = self
-            .tv_tmdb
-            .search(media.raw_name.clone(), media.raw_year.map(|x| x as i32))
+        let provider = library_provider(&self.conn, media.library_id, MediaType::Tv).await?;
+
+        let mut result = provider
+            .search(&media.raw_name, media.raw_year.map(|x| x as i32))
             .await;
 
         if let Some(x) = els.get(ElementCategory::AnimeTitle) {
             if result.is_err() {
                 // NOTE: If we got here then we assume that the file uses common anime release naming schemes.
                 // Thus we prioritise metadata extracted by anitomy.
-                result = self.tv_tmdb.search(x.to_string(), None).await;
+                result = provider.search(x, None).await;
 
                 // NOTE: Some releases dont include season number, so we just assume its the first one.
                 let anitomy_episode = els
@@ -353,7 +544,9 @@ impl MetadataMatcher {
         let result = match result {
             Ok(v) => v,
             Err(e) => {
-                error!(media = ?media, reason = ?e, "Could not match tv show to tmdb");
+                error!(media = ?media, reason = ?e, "Could not match tv show to external metadata");
+                artwork::store_unmatched(&self.conn, &media).await;
+
                 return Err(ScannerError::UnknownError);
             }
         };
@@ -383,27 +576,9 @@ impl MetadataMatcher {
             .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
         drop(lock);
 
-        let mut seasons: Vec<super::ApiSeason> = self
-            .tv_tmdb
-            .get_seasons_for(result.id)
-            .await
-            .unwrap_or_default()
-            .into_iter()
-            .map(Into::into)
-            .collect();
-
-        for season in seasons.iter_mut() {
-            season.episodes = self
-                .tv_tmdb
-                .get_episodes_for(result.id, season.season_number)
-                .await
-                .unwrap_or_default()
-                .into_iter()
-                .map(Into::into)
-                .collect();
-        }
-
-        result.seasons = seasons;
+        let provider = library_provider(&self.conn, media.library_id, MediaType::Tv).await?;
+        provider.populate_seasons(&mut result).await;
+        provider.populate_certification(&mut result).await;
 
         let matcher = TvShowMatcher {
             conn: &self.conn,
@@ -415,6 +590,97 @@ impl MetadataMatcher {
     }
 }
 
+/// Returns the metadata provider configured for the library `library_id`.
+pub async fn library_provider(
+    conn: &DbConnection,
+    library_id: i64,
+    media_type: MediaType,
+) -> Result<Arc<dyn MetadataProvider>, ScannerError> {
+    let mut tx = conn
+        .read()
+        .begin()
+        .await
+        .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
+
+    let library = Library::get_one(&mut tx, library_id).await?;
+
+    Ok(provider_for(library.metadata_provider, media_type))
+}
+
+/// Builds the metadata for the episode `media` out of its nfo sidecars if the show has any. If the
+/// episode has its own nfo file, the season and episode number of `media` are updated to the ones
+/// found in it.
+async fn tv_sidecar(
+    conn: &DbConnection,
+    media: &mut MediaFile,
+) -> Result<Option<ApiMedia>, ScannerError> {
+    let path = PathBuf::from(&media.target_file);
+    let season = media.season.map(|x| x as u64);
+    let episode = media.episode.map(|x| x as u64);
+
+    let sidecar = match spawn_blocking(move || nfo::tv_metadata(&path, season, episode)).await {
+        Ok(Some(x)) => x,
+        _ => return Ok(None),
+    };
+
+    if sidecar.season.is_some() || sidecar.episode.is_some() {
+        let mut lock = conn.writer().lock_owned().await;
+        let mut tx = database::write_tx(&mut lock)
+            .await
+            .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
+
+        let update_mediafile = UpdateMediaFile {
+            season: sidecar.season.map(|x| x as i64),
+            episode: sidecar.episode.map(|x| x as i64),
+            ..Default::default()
+        };
+
+        update_mediafile.update(&mut tx, media.id).await?;
+
+        tx.commit()
+            .await
+            .map_err(|e| ScannerError::DatabaseError(format!("{:?}", e)))?;
+
+        media.season = sidecar.season.map(|x| x as i64).or(media.season);
+        media.episode = sidecar.episode.map(|x| x as i64).or(media.episode);
+    }
+
+    // the sidecar always carries exactly one season, so files without a season number end up in
+    // the first one.
+    if media.season.is_none() {
+        media.season = Some(1);
+    }
+
+    Ok(Some(sidecar.show))
+}
+
+/// Parses the season and episode number out of the filename of `file` with anitomy. Files without
+/// a season number are assumed to be in the first season.
+async fn anitomy_episode(file: &Path) -> Option<(i64, i64)> {
+    let filename = file.file_name()?.to_str()?.to_string();
+
+    let els: Elements = match spawn_blocking(move || {
+        let mut anitomy = Anitomy::new();
+        anitomy.parse(filename.as_str())
+    })
+    .await
+    .ok()?
+    {
+        Ok(v) | Err(v) => v,
+    };
+
+    let episode = els
+        .get(ElementCategory::EpisodeNumber)
+        .and_then(|x| x.parse::<i64>().ok())?;
+
+    let season = els
+        .get(ElementCategory::AnimeSeason)
+        .and_then(|x| x.parse::<i64>().ok())
+        .unwrap_or(1);
+
+    Some((season, episode))
+}
+
 #[instrument(skip(media, tx))]
 pub async fn patch_tv_metadata(
     media: &mut MediaFile,
@@ -468,3 +734,30 @@ pub async fn patch_tv_metadata(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn anitomy_classification() {
+        let cases = [
+            ("[HorribleSubs] Dorohedoro - 07 [1080p].mkv", Some((1, 7))),
+            (
+                "[SubsPlease] Mushishi Season 2 - 05 (720p).mkv",
+                Some((2, 5)),
+            ),
+            // files without a episode number are left to be matched as movies.
+            ("Big Buck Bunny.mkv", None),
+        ];
+
+        for (file, expected) in cases.iter() {
+            assert_eq!(
+                anitomy_episode(Path::new(file)).await,
+                *expected,
+                "{}",
+                file
+            );
+        }
+    }
+}
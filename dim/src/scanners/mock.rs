@@ -0,0 +1,239 @@
+use super::provider::MetadataProvider;
+use super::provider::ProviderError;
+use super::ApiEpisode;
+use super::ApiMedia;
+use super::ApiSeason;
+
+use database::library::MediaType;
+
+use async_trait::async_trait;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+/// `MockProvider` is a metadata provider which never touches the network. Lookups are first
+/// resolved against the fixtures registered with [`MockProvider::with_media`], if none match a
+/// result is derived from the query itself so that every file still gets matched.
+#[derive(Clone, Debug)]
+pub struct MockProvider {
+    media_type: MediaType,
+    fixtures: Vec<ApiMedia>,
+}
+
+impl MockProvider {
+    pub fn new(media_type: MediaType) -> Self {
+        Self {
+            media_type,
+            fixtures: Vec::new(),
+        }
+    }
+
+    /// Register a fixture that will be returned for queries matching its title.
+    pub fn with_media(mut self, media: ApiMedia) -> Self {
+        self.fixtures.push(media);
+        self
+    }
+
+    fn synthesize(&self, title: &str, year: Option<i32>) -> ApiMedia {
+        let mut hasher = DefaultHasher::new();
+        title.to_lowercase().hash(&mut hasher);
+        year.hash(&mut hasher);
+
+        ApiMedia {
+            // keep the id within the range of a i32 as thats what most routes accept.
+            id: hasher.finish() % i32::MAX as u64,
+            title: title.to_string(),
+            release_date: year.map(|x| format!("{:04}-01-01", x)),
+            overview: None,
+            poster_path: None,
+            backdrop_path: None,
+            poster_file: None,
+            backdrop_file: None,
+            genres: Vec::new(),
+            rating: None,
+            certification: None,
+            seasons: Vec::new(),
+        }
+    }
+
+    fn find(&self, id: u64) -> Option<&ApiMedia> {
+        self.fixtures.iter().find(|x| x.id == id)
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for MockProvider {
+    async fn search_many(
+        &self,
+        title: &str,
+        year: Option<i32>,
+    ) -> Result<Vec<ApiMedia>, ProviderError> {
+        let year_matches = |media: &ApiMedia| {
+            year.is_none()
+                || media
+                    .release_date
+                    .as_ref()
+                    .map_or(true, |x| x.starts_with(&format!("{:04}", year.unwrap())))
+        };
+
+        let mut results = self
+            .fixtures
+            .iter()
+            .filter(|x| x.title.to_lowercase().contains(&title.to_lowercase()))
+            .filter(|x| year_matches(x))
+            .cloned()
+            .map(|mut x| {
+                // seasons are only ever returned by `seasons_for`.
+                x.seasons.clear();
+                x
+            })
+            .collect::<Vec<_>>();
+
+        if results.is_empty() && !title.trim().is_empty() {
+            results.push(self.synthesize(title.trim(), year));
+        }
+
+        Ok(results)
+    }
+
+    async fn search_by_id(&self, id: u64) -> Result<ApiMedia, ProviderError> {
+        self.find(id)
+            .cloned()
+            .map(|mut x| {
+                x.seasons.clear();
+                x
+            })
+            .ok_or(ProviderError::NotFound)
+    }
+
+    async fn seasons_for(&self, id: u64) -> Result<Vec<ApiSeason>, ProviderError> {
+        if !matches!(self.media_type, MediaType::Tv) {
+            return Err(ProviderError::UnsupportedMediaType);
+        }
+
+        Ok(self
+            .find(id)
+            .map(|x| {
+                x.seasons
+                    .iter()
+                    .cloned()
+                    .map(|mut x| {
+                        x.episodes.clear();
+                        x
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn episodes_for(&self, id: u64, season: u64) -> Result<Vec<ApiEpisode>, ProviderError> {
+        if !matches!(self.media_type, MediaType::Tv) {
+            return Err(ProviderError::UnsupportedMediaType);
+        }
+
+        Ok(self
+            .find(id)
+            .and_then(|x| x.seasons.iter().find(|x| x.season_number == season))
+            .map(|x| x.episodes.clone())
+            .unwrap_or_default())
+    }
+
+    async fn genre(&self, _id: u64) -> Result<String, ProviderError> {
+        Err(ProviderError::NotFound)
+    }
+
+    async fn certification_for(&self, id: u64) -> Result<Option<String>, ProviderError> {
+        Ok(self.find(id).and_then(|x| x.certification.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanse() -> ApiMedia {
+        ApiMedia {
+            id: 63639,
+            title: "The Expanse".into(),
+            release_date: Some("2015-12-14".into()),
+            overview: Some("Hundreds of years in the future...".into()),
+            poster_path: None,
+            backdrop_path: None,
+            poster_file: None,
+            backdrop_file: None,
+            genres: vec!["Drama".into()],
+            rating: Some(8),
+            certification: Some("TV-14".into()),
+            seasons: vec![ApiSeason {
+                id: 1,
+                name: Some("Season 1".into()),
+                poster_path: None,
+                poster_file: None,
+                season_number: 1,
+                episodes: vec![ApiEpisode {
+                    id: 2,
+                    name: Some("Dulcinea".into()),
+                    overview: None,
+                    episode: Some(1),
+                    still: None,
+                    still_file: None,
+                }],
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn search_prefers_fixtures() {
+        let provider = MockProvider::new(MediaType::Tv).with_media(expanse());
+
+        let result = provider.search("the expanse", Some(2015)).await.unwrap();
+        assert_eq!(result.id, 63639);
+        assert!(result.seasons.is_empty());
+
+        let result = provider.search("the expanse", Some(2001)).await.unwrap();
+        assert_ne!(result.id, 63639);
+        assert_eq!(result.release_date, Some("2001-01-01".into()));
+    }
+
+    #[tokio::test]
+    async fn search_is_deterministic() {
+        let provider = MockProvider::new(MediaType::Movie);
+
+        let a = provider.search("Blade Runner", Some(1982)).await.unwrap();
+        let b = provider.search("blade runner", Some(1982)).await.unwrap();
+
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.title, "Blade Runner");
+        assert!(provider.search("  ", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn populate_seasons() {
+        let provider = MockProvider::new(MediaType::Tv).with_media(expanse());
+
+        let mut result = provider.search("Expanse", None).await.unwrap();
+        provider.populate_seasons(&mut result).await;
+
+        assert_eq!(result.seasons.len(), 1);
+        assert_eq!(result.seasons[0].episodes.len(), 1);
+        assert_eq!(result.seasons[0].episodes[0].name, Some("Dulcinea".into()));
+
+        let movies = MockProvider::new(MediaType::Movie);
+        assert!(movies.seasons_for(63639).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn populate_certification() {
+        let provider = MockProvider::new(MediaType::Tv).with_media(expanse());
+
+        let mut result = provider.search("Expanse", None).await.unwrap();
+        result.certification = None;
+        provider.populate_certification(&mut result).await;
+        assert_eq!(result.certification, Some("TV-14".into()));
+
+        let mut result = provider.search("Firefly", None).await.unwrap();
+        provider.populate_certification(&mut result).await;
+        assert_eq!(result.certification, None);
+    }
+}
@@ -1,23 +1,48 @@
+pub mod artwork;
 pub mod base;
+#[cfg(any(test, feature = "mock_provider"))]
+pub mod mock;
 pub mod movie;
+pub mod music;
+pub mod nfo;
+pub mod provider;
 pub mod scanner_daemon;
 pub mod tmdb;
 pub mod tv_show;
 
 use database::library::Library;
 use database::library::MediaType;
+use database::media::Media;
+use database::mediafile::MediaFile;
 
+use tracing::error;
 use tracing::info;
 use tracing::instrument;
+use tracing::warn;
 
 use crate::core::DbConnection;
 use crate::core::EventTx;
+use crate::core::METADATA_PATH;
+use crate::trickplay;
 
+use chrono::prelude::Utc;
+use futures::StreamExt;
+use once_cell::sync::Lazy;
 use once_cell::sync::OnceCell;
+use tokio::task::spawn_blocking;
 use walkdir::WalkDir;
 
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 use std::time::Instant;
 
 use serde::Deserialize;
@@ -35,6 +60,9 @@ pub struct ApiMedia {
     pub backdrop_file: Option<String>,
     pub genres: Vec<String>,
     pub rating: Option<i32>,
+    /// Certification (age rating) of the media in the US, ie `PG-13`.
+    #[serde(default)]
+    pub certification: Option<String>,
     pub seasons: Vec<ApiSeason>,
 }
 
@@ -61,6 +89,185 @@ pub struct ApiEpisode {
 pub(super) static METADATA_EXTRACTOR: OnceCell<base::MetadataExtractor> = OnceCell::new();
 pub(super) static METADATA_MATCHER: OnceCell<base::MetadataMatcher> = OnceCell::new();
 pub(super) static SUPPORTED_EXTS: &[&str] = &["mp4", "mkv", "avi", "webm"];
+pub(super) static SUPPORTED_AUDIO_EXTS: &[&str] = &["mp3", "flac", "m4a", "ogg", "opus", "wav"];
+
+/// Returns the file extensions we scan for in a library of type `media_type`.
+pub(super) fn supported_exts(media_type: MediaType) -> &'static [&'static str] {
+    match media_type {
+        MediaType::Music => SUPPORTED_AUDIO_EXTS,
+        _ => SUPPORTED_EXTS,
+    }
+}
+
+/// How often a running scan reports its progress to clients.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+/// Number of files of a scan which are mounted and matched at the same time, enough to keep every
+/// worker of the extractor and the matcher busy.
+const SCAN_CONCURRENCY: usize = 10;
+/// Largest fraction of a library which is purged in one go when files go missing.
+const MAX_PURGE_FRACTION: f64 = 0.5;
+/// Number of missing files which are always purged regardless of the size of the library.
+const MIN_GUARDED_PURGE: usize = 10;
+
+/// Counters tracking how far along a scan is.
+pub struct ScanProgress {
+    discovered: AtomicU64,
+    probed: AtomicU64,
+    matched: AtomicU64,
+    failed: AtomicU64,
+    processed: AtomicU64,
+    cancelled: AtomicBool,
+    started: Instant,
+}
+
+/// Snapshot of the progress of a scan.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ScanStatus {
+    /// Number of files found in the library.
+    pub discovered: u64,
+    /// Number of files the extractor has looked at.
+    pub probed: u64,
+    /// Number of new files which were matched.
+    pub matched: u64,
+    /// Number of files which couldnt be mounted or matched.
+    pub failed: u64,
+    /// Number of files which are done, this includes files which were already up to date.
+    pub processed: u64,
+    /// Number of seconds since the scan started.
+    pub elapsed: u64,
+    /// Estimated number of seconds until the scan finishes, `None` until the first file is done.
+    pub eta: Option<u64>,
+    pub cancelled: bool,
+}
+
+impl ScanProgress {
+    fn new() -> Self {
+        Self {
+            discovered: AtomicU64::new(0),
+            probed: AtomicU64::new(0),
+            matched: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            started: Instant::now(),
+        }
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Records that a file is done, `matched` is `None` for files which were already up to date.
+    fn file_done(&self, probed: bool, matched: Option<bool>) {
+        if probed {
+            self.probed.fetch_add(1, Ordering::SeqCst);
+        }
+
+        match matched {
+            Some(true) => self.matched.fetch_add(1, Ordering::SeqCst),
+            Some(false) => self.failed.fetch_add(1, Ordering::SeqCst),
+            None => 0,
+        };
+
+        self.processed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn status(&self) -> ScanStatus {
+        let discovered = self.discovered.load(Ordering::SeqCst);
+        let processed = self.processed.load(Ordering::SeqCst);
+        let elapsed = self.started.elapsed();
+
+        let eta = if processed > 0 {
+            let per_file = elapsed.as_secs_f64() / processed as f64;
+            Some((per_file * discovered.saturating_sub(processed) as f64).round() as u64)
+        } else {
+            None
+        };
+
+        ScanStatus {
+            discovered,
+            probed: self.probed.load(Ordering::SeqCst),
+            matched: self.matched.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            processed,
+            elapsed: elapsed.as_secs(),
+            eta,
+            cancelled: self.is_cancelled(),
+        }
+    }
+
+    fn event(&self, library_id: i64) -> events::Envelope {
+        let status = self.status();
+
+        events::Envelope::owner(events::Message {
+            id: library_id,
+            event_type: events::PushEventType::EventScanProgress {
+                discovered: status.discovered,
+                probed: status.probed,
+                matched: status.matched,
+                failed: status.failed,
+                processed: status.processed,
+                eta: status.eta,
+            },
+        })
+    }
+}
+
+/// The libraries which currently have a full scan running along with the progress of the scan.
+static RUNNING_SCANS: Lazy<Mutex<HashMap<i64, Arc<ScanProgress>>>> = Lazy::new(Default::default);
+
+/// Marks a library as being scanned for as long as it is alive.
+struct ScanGuard(i64, Arc<ScanProgress>);
+
+impl ScanGuard {
+    fn acquire(library_id: i64) -> Option<Self> {
+        let mut lock = RUNNING_SCANS.lock().unwrap();
+        if lock.contains_key(&library_id) {
+            return None;
+        }
+
+        let progress = Arc::new(ScanProgress::new());
+        lock.insert(library_id, progress.clone());
+
+        Some(Self(library_id, progress))
+    }
+}
+
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        RUNNING_SCANS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Returns whether a full scan of the library `library_id` is currently running.
+pub fn is_scanning(library_id: i64) -> bool {
+    RUNNING_SCANS.lock().unwrap().contains_key(&library_id)
+}
+
+/// Returns the progress of the full scan of the library `library_id` if one is running.
+pub fn scan_status(library_id: i64) -> Option<ScanStatus> {
+    RUNNING_SCANS
+        .lock()
+        .unwrap()
+        .get(&library_id)
+        .map(|x| x.status())
+}
+
+/// Cancels the full scan of the library `library_id`. Files which are being matched are finished,
+/// the rest are skipped, see [`process_files`]. Returns `false` if the library isnt being scanned.
+pub fn cancel_scan(library_id: i64) -> bool {
+    match RUNNING_SCANS.lock().unwrap().get(&library_id) {
+        Some(x) => {
+            x.cancel();
+            true
+        }
+        None => false,
+    }
+}
 
 pub fn get_extractor(_tx: &EventTx) -> &'static base::MetadataExtractor {
     let mut handle = xtra::spawn::Tokio::Global;
@@ -84,6 +291,7 @@ pub fn get_matcher_unchecked() -> &'static base::MetadataMatcher {
 #[doc(hidden)]
 pub async fn get_subfiles(
     paths: impl Iterator<Item = impl AsRef<Path>>,
+    exts: &[&str],
 ) -> Result<Vec<PathBuf>, self::base::ScannerError> {
     let mut files = Vec::with_capacity(2048);
     for path in paths {
@@ -103,7 +311,7 @@ pub async fn get_subfiles(
                 f.path()
                     .extension()
                     .and_then(|e| e.to_str())
-                    .map_or(false, |e| SUPPORTED_EXTS.contains(&e))
+                    .map_or(false, |e| exts.contains(&e))
             })
             .map(|f| f.into_path())
             .collect();
@@ -114,12 +322,53 @@ pub async fn get_subfiles(
     Ok(files)
 }
 
-#[instrument(skip(tx, paths))]
+/// Returns the media type a file of a library of type `media_type` is matched as. Files in mixed
+/// libraries are matched as episodes if `mount_file` managed to parse a episode number out of
+/// their filename, otherwise they are matched as movies.
+fn match_type(media_type: MediaType, episode: Option<i64>) -> MediaType {
+    match media_type {
+        MediaType::Mixed if episode.is_some() => MediaType::Tv,
+        MediaType::Mixed => MediaType::Movie,
+        x => x,
+    }
+}
+
+/// Sends `mfile` to the matcher for the type it is matched as, see [`match_type`]. Returns whether
+/// the file was matched.
+pub(super) async fn match_mediafile(
+    matcher: &base::MetadataMatcher,
+    mfile: MediaFile,
+    media_type: MediaType,
+) -> bool {
+    let media_type = match_type(media_type, mfile.episode);
+
+    match media_type {
+        MediaType::Movie => matcher.match_movie(mfile).await.is_ok(),
+        MediaType::Tv => matcher.match_tv(mfile).await.is_ok(),
+        MediaType::Music => matcher.match_music(mfile).await.is_ok(),
+        MediaType::Episode | MediaType::Mixed => {
+            warn!(
+                mediafile = mfile.id,
+                media_type = ?media_type,
+                "Received a file for a library type that cannot be matched",
+            );
+
+            false
+        }
+    }
+}
+
+/// Scans the files under `paths` into the library `library_id`. If `progress` is set, progress is
+/// recorded in it and reported over `tx` periodically. Incremental rescans pass `None` as nobody
+/// is waiting on them.
+#[instrument(skip(conn, tx, paths, progress))]
 pub async fn start_custom<I, T>(
+    conn: &DbConnection,
     library_id: i64,
     tx: EventTx,
     paths: I,
     media_type: MediaType,
+    progress: Option<Arc<ScanProgress>>,
 ) -> Result<(), self::base::ScannerError>
 where
     I: Iterator<Item = T>,
@@ -127,21 +376,30 @@ where
 {
     info!(library_id = library_id, "Scanning library");
 
-    tx.send(
-        events::Message {
-            id: library_id,
-            event_type: events::PushEventType::EventStartedScanning,
-        }
-        .to_string(),
-    )
+    tx.send(events::Envelope::owner(events::Message {
+        id: library_id,
+        event_type: events::PushEventType::EventStartedScanning,
+    }))
     .unwrap();
 
     let extractor = get_extractor(&tx);
     let matcher = get_matcher(&tx);
 
-    let files = get_subfiles(paths).await?;
+    let paths = paths
+        .map(|x| x.as_ref().to_path_buf())
+        .collect::<Vec<_>>();
+
+    purge_vanished(conn, library_id, &paths).await;
+
+    let files = get_subfiles(paths.iter(), supported_exts(media_type)).await?;
+
+    let report = progress.is_some();
+    let progress = progress.unwrap_or_else(|| Arc::new(ScanProgress::new()));
 
     let total_files = files.len();
+    progress
+        .discovered
+        .store(total_files as u64, Ordering::SeqCst);
 
     info!(
         library_id = library_id,
@@ -149,54 +407,231 @@ where
         "Walked library directory",
     );
 
-    let now = Instant::now();
-    let mut futures = Vec::new();
-
-    for file in files {
-        futures.push(async move {
-            if let Ok(mfile) = extractor
-                .mount_file(file.clone(), library_id, media_type)
-                .await
-            {
-                match media_type {
-                    MediaType::Movie => {
-                        let _ = matcher.match_movie(mfile).await;
-                    }
-                    MediaType::Tv => {
-                        let _ = matcher.match_tv(mfile).await;
-                    }
-                    _ => unreachable!(),
-                }
+    let reporter = report.then(|| {
+        let tx = tx.clone();
+        let progress = progress.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+            loop {
+                interval.tick().await;
+                let _ = tx.send(progress.event(library_id));
             }
         })
-    }
+    });
 
-    futures::future::join_all(futures).await;
+    let now = Instant::now();
+
+    process_files(
+        files,
+        &progress,
+        |file| extractor.mount_file(file, library_id, media_type),
+        |mfile| match_mediafile(matcher, mfile, media_type),
+    )
+    .await;
+
+    if let Some(reporter) = reporter {
+        reporter.abort();
+        let _ = tx.send(progress.event(library_id));
+    }
 
     info!(
         library_id = library_id,
         files = total_files,
         duration = now.elapsed().as_secs(),
+        cancelled = progress.is_cancelled(),
         "Finished scanning library",
     );
 
-    tx.send(
-        events::Message {
-            id: library_id,
-            event_type: events::PushEventType::EventStoppedScanning,
-        }
-        .to_string(),
-    )
+    tx.send(events::Envelope::owner(events::Message {
+        id: library_id,
+        event_type: events::PushEventType::EventStoppedScanning,
+    }))
     .unwrap();
 
+    if progress.is_cancelled() {
+        return Err(self::base::ScannerError::Cancelled);
+    }
+
     Ok(())
 }
 
+/// Mounts each of `files` with `mount` and matches the ones which are new with `matcher`, a few at a
+/// time. Once the scan is cancelled files which werent picked up yet are skipped, as are files
+/// which were mounted in the meantime. The latter show up with the unmatched files.
+async fn process_files<T, M, MF, C, CF>(
+    files: Vec<PathBuf>,
+    progress: &ScanProgress,
+    mount: M,
+    matcher: C,
+) where
+    M: Fn(PathBuf) -> MF,
+    MF: Future<Output = Result<T, self::base::ScannerError>>,
+    C: Fn(T) -> CF,
+    CF: Future<Output = bool>,
+{
+    let mount = &mount;
+    let matcher = &matcher;
+
+    futures::stream::iter(files)
+        .map(move |file| async move {
+            if progress.is_cancelled() {
+                return;
+            }
+
+            match mount(file).await {
+                Ok(_) if progress.is_cancelled() => {}
+                Ok(mfile) => {
+                    let matched = matcher(mfile).await;
+                    progress.file_done(true, Some(matched));
+                }
+                Err(self::base::ScannerError::AlreadyMounted) => progress.file_done(true, None),
+                Err(_) => progress.file_done(false, Some(false)),
+            }
+        })
+        .buffer_unordered(SCAN_CONCURRENCY)
+        .for_each(|_| async {})
+        .await;
+}
+
+/// Removes the mediafiles of a library which are located under `paths` but no longer exist on
+/// disk, for instance because they were deleted while dim was offline. Paths which dont exist or
+/// are empty are skipped as that usually means a drive isnt mounted, in which case we dont want to
+/// throw away the whole library. For the same reason nothing is purged if a suspiciously large
+/// part of the library went missing.
+async fn purge_vanished(conn: &DbConnection, library_id: i64, paths: &[PathBuf]) {
+    let mediafiles = match conn.read().begin().await {
+        Ok(mut tx) => MediaFile::get_by_lib(&mut tx, library_id)
+            .await
+            .unwrap_or_default(),
+        Err(e) => {
+            error!(reason = ?e, "Failed to create transaction.");
+            return;
+        }
+    };
+
+    let total = mediafiles.len();
+    let files = mediafiles
+        .into_iter()
+        .map(|x| (x.id, PathBuf::from(x.target_file)))
+        .collect::<Vec<_>>();
+    let paths = paths.to_vec();
+
+    // checking every file can take a while on large libraries, so its done before taking the
+    // write lock.
+    let vanished = spawn_blocking(move || vanished_files(&paths, files))
+        .await
+        .unwrap_or_default();
+
+    if vanished.is_empty() {
+        return;
+    }
+
+    if !is_sane_purge(vanished.len(), total) {
+        warn!(
+            library_id = library_id,
+            files = vanished.len(),
+            total = total,
+            "Refusing to purge files as too much of the library went missing"
+        );
+        return;
+    }
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = match database::write_tx(&mut lock).await {
+        Ok(x) => x,
+        Err(e) => {
+            error!(reason = ?e, "Failed to create transaction.");
+            return;
+        }
+    };
+
+    for id in vanished.iter().copied() {
+        if let Err(e) = remove_mediafile(&mut tx, id).await {
+            error!(reason = ?e, id = id, "Failed to purge mediafile");
+            return;
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        error!(reason = ?e, "Failed to commit transaction.");
+        return;
+    }
+
+    info!(
+        library_id = library_id,
+        files = vanished.len(),
+        "Purged files which no longer exist"
+    );
+}
+
+/// Returns the ids of the `files` which are located under one of `paths` but no longer exist.
+/// Paths which dont exist or are empty are skipped. This does blocking io.
+fn vanished_files(paths: &[PathBuf], files: Vec<(i64, PathBuf)>) -> Vec<i64> {
+    let roots = paths
+        .iter()
+        .filter(|x| {
+            fs::read_dir(x)
+                .map(|mut x| x.next().is_some())
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+
+    if roots.is_empty() {
+        return vec![];
+    }
+
+    files
+        .into_iter()
+        .filter(|(_, path)| roots.iter().any(|x| path.starts_with(x)) && !path.exists())
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Returns whether purging `vanished` out of `total` files of a library looks like files were
+/// deleted rather than like a drive went missing.
+fn is_sane_purge(vanished: usize, total: usize) -> bool {
+    vanished <= MIN_GUARDED_PURGE || (vanished as f64) <= total as f64 * MAX_PURGE_FRACTION
+}
+
+/// Deletes the mediafile `id` along with its trickplay sheets. If the media it was matched to has
+/// no files left it is deleted too as it would otherwise be a ghost media entry.
+pub(super) async fn remove_mediafile(
+    tx: &mut database::Transaction<'_>,
+    id: i64,
+) -> Result<(), database::DatabaseError> {
+    let media = Media::get_of_mediafile(&mut *tx, id).await;
+
+    MediaFile::delete(&mut *tx, id).await?;
+
+    if let Some(meta_path) = METADATA_PATH.get() {
+        let dir = trickplay::sheets_dir(Path::new(meta_path), id);
+
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(dir = %dir.display(), reason = %e, "Failed to remove trickplay sheets.");
+            }
+        }
+    }
+
+    if let Ok(media) = media {
+        if MediaFile::get_of_media(&mut *tx, media.id).await?.is_empty() {
+            Media::delete(&mut *tx, media.id).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts a full scan of the library `id`. Only one full scan of a library can run at a time,
+/// once the scan finishes the time is recorded in `last_scanned` unless it was cancelled.
 pub async fn start(
     conn: DbConnection,
     id: i64,
     tx: EventTx,
 ) -> Result<(), self::base::ScannerError> {
+    let guard = ScanGuard::acquire(id).ok_or(self::base::ScannerError::AlreadyScanning)?;
+
     let mut tx_ = conn
         .read()
         .begin()
@@ -204,8 +639,28 @@ pub async fn start(
         .map_err(|e| self::base::ScannerError::DatabaseError(format!("{:?}", e)))?;
 
     let lib = Library::get_one(&mut tx_, id).await?;
+    drop(tx_);
+
+    start_custom(
+        &conn,
+        id,
+        tx,
+        lib.locations.into_iter(),
+        lib.media_type,
+        Some(guard.1.clone()),
+    )
+    .await?;
 
-    start_custom(id, tx, lib.locations.into_iter(), lib.media_type).await
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx_ = database::write_tx(&mut lock)
+        .await
+        .map_err(|e| self::base::ScannerError::DatabaseError(format!("{:?}", e)))?;
+    Library::set_last_scanned(&mut tx_, id, Utc::now().timestamp()).await?;
+    tx_.commit()
+        .await
+        .map_err(|e| self::base::ScannerError::DatabaseError(format!("{:?}", e)))?;
+
+    Ok(())
 }
 
 /// Function formats the path where assets are stored.
@@ -213,3 +668,129 @@ pub fn format_path(x: Option<String>) -> String {
     x.map(|x| format!("images/{}", x.trim_start_matches('/')))
         .unwrap_or_default()
 }
+
+/// Returns the extension of the artwork file `x`, defaulting to `jpg` which is what tmdb serves.
+pub fn asset_ext(x: &str) -> String {
+    std::path::Path::new(x)
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_else(|| "jpg".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_progress() {
+        let progress = ScanProgress::new();
+        progress.discovered.store(4, Ordering::SeqCst);
+        assert_eq!(progress.status().eta, None);
+
+        progress.file_done(true, Some(true));
+        progress.file_done(true, None);
+        progress.file_done(false, Some(false));
+
+        let status = progress.status();
+        assert_eq!(status.probed, 2);
+        assert_eq!(status.matched, 1);
+        assert_eq!(status.failed, 1);
+        assert_eq!(status.processed, 3);
+        assert!(status.eta.is_some());
+        assert!(!status.cancelled);
+    }
+
+    #[test]
+    fn mixed_library_routing() {
+        assert_eq!(match_type(MediaType::Mixed, Some(3)), MediaType::Tv);
+        assert_eq!(match_type(MediaType::Mixed, None), MediaType::Movie);
+
+        // only mixed libraries are routed by the episode number.
+        assert_eq!(match_type(MediaType::Movie, Some(3)), MediaType::Movie);
+        assert_eq!(match_type(MediaType::Tv, None), MediaType::Tv);
+        assert_eq!(match_type(MediaType::Music, Some(3)), MediaType::Music);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancel_stops_processing() {
+        let progress = ScanProgress::new();
+        let files = (0..100)
+            .map(|x| PathBuf::from(format!("/media/{}.mkv", x)))
+            .collect::<Vec<_>>();
+
+        let mounted = AtomicU64::new(0);
+        let matched = AtomicU64::new(0);
+        let (mounted_ref, matched_ref, progress_ref) = (&mounted, &matched, &progress);
+
+        process_files(
+            files,
+            &progress,
+            |file| async move {
+                mounted_ref.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                Ok(file)
+            },
+            |_| async move {
+                // the scan is cancelled while the third file is being matched.
+                if matched_ref.fetch_add(1, Ordering::SeqCst) == 2 {
+                    progress_ref.cancel();
+                }
+                true
+            },
+        )
+        .await;
+
+        assert_eq!(matched.load(Ordering::SeqCst), 3);
+        assert!(mounted.load(Ordering::SeqCst) <= 3 + SCAN_CONCURRENCY as u64);
+        assert_eq!(progress.status().processed, 3);
+        assert!(progress.status().cancelled);
+    }
+
+    #[test]
+    fn vanished() {
+        let root = std::env::temp_dir().join(format!("dim-purge-{}", uuid::Uuid::new_v4()));
+        let library = root.join("library");
+        let unmounted = root.join("unmounted");
+        fs::create_dir_all(&library).unwrap();
+        fs::create_dir_all(&unmounted).unwrap();
+        fs::write(library.join("kept.mkv"), b"").unwrap();
+
+        let files = vec![
+            (1, library.join("kept.mkv")),
+            (2, library.join("deleted.mkv")),
+            (3, unmounted.join("movie.mkv")),
+            (4, root.join("missing").join("movie.mkv")),
+        ];
+        let paths = vec![library, unmounted.clone(), root.join("missing")];
+
+        assert_eq!(vanished_files(&paths, files.clone()), vec![2]);
+        assert_eq!(vanished_files(&[unmounted], files), Vec::<i64>::new());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn sane_purges() {
+        assert!(is_sane_purge(3, 3));
+        assert!(is_sane_purge(50, 100));
+        assert!(!is_sane_purge(51, 100));
+        assert!(!is_sane_purge(1000, 1000));
+    }
+
+    #[test]
+    fn cancel_running_scan() {
+        assert!(!cancel_scan(-1));
+
+        let guard = ScanGuard::acquire(-1).unwrap();
+        assert!(ScanGuard::acquire(-1).is_none());
+        assert!(is_scanning(-1));
+
+        assert!(cancel_scan(-1));
+        assert!(scan_status(-1).unwrap().cancelled);
+
+        drop(guard);
+        assert!(!is_scanning(-1));
+        assert_eq!(scan_status(-1), None);
+    }
+}
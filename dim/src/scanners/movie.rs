@@ -13,6 +13,7 @@ use chrono::prelude::Utc;
 use chrono::Datelike;
 use chrono::NaiveDate;
 
+use events::Envelope;
 use events::Message;
 use events::PushEventType;
 
@@ -20,6 +21,7 @@ use tracing::error;
 use tracing::instrument;
 use tracing::warn;
 
+use super::asset_ext;
 use super::format_path;
 use crate::core::EventTx;
 use crate::fetcher::insert_into_queue;
@@ -31,9 +33,12 @@ pub struct MovieMatcher<'a> {
 
 impl<'a> MovieMatcher<'a> {
     #[instrument(skip(self, result), fields(result.id = %result.id, result.name = %result.title))]
-    pub async fn match_to_result(&self, result: super::ApiMedia, orphan: &'a MediaFile) {
+    pub async fn match_to_result(&self, mut result: super::ApiMedia, orphan: &'a MediaFile) {
         let library_id = orphan.library_id;
 
+        // done before taking the write lock as extracting artwork can take a while.
+        super::artwork::fill_missing(&mut result, &orphan.target_file).await;
+
         let mut lock = self.conn.writer().lock_owned().await;
         let mut tx = match database::write_tx(&mut lock).await {
             Ok(x) => x,
@@ -82,19 +87,21 @@ impl<'a> MovieMatcher<'a> {
         let backdrop_path = result.backdrop_path.clone();
 
         if let Some(poster_path) = poster_path.as_ref() {
-            let _ = insert_into_queue(poster_path.clone(), 3);
+            let _ = insert_into_queue(&mut *tx, poster_path.clone(), 3).await;
         }
 
         if let Some(backdrop_path) = backdrop_path.as_ref() {
-            let _ = insert_into_queue(backdrop_path.clone(), 3);
+            let _ = insert_into_queue(&mut *tx, backdrop_path.clone(), 3).await;
         }
 
-        let poster = match poster_path {
-            Some(path) => {
+        // NOTE: Local artwork (ie from nfo sidecars) has no remote url, in which case we key the
+        // asset off of the local file only.
+        let poster = match result.poster_file.clone() {
+            Some(file) => {
                 let asset = InsertableAsset {
-                    remote_url: Some(path),
-                    local_path: format_path(result.poster_file.clone()),
-                    file_ext: "jpg".into(),
+                    remote_url: poster_path,
+                    file_ext: asset_ext(&file),
+                    local_path: format_path(Some(file)),
                 }
                 .insert(&mut *tx)
                 .await;
@@ -114,12 +121,12 @@ impl<'a> MovieMatcher<'a> {
             None => None,
         };
 
-        let backdrop = match backdrop_path {
-            Some(path) => {
+        let backdrop = match result.backdrop_file.clone() {
+            Some(file) => {
                 let asset = InsertableAsset {
-                    remote_url: Some(path),
-                    local_path: format_path(result.backdrop_file.clone()),
-                    file_ext: "jpg".into(),
+                    remote_url: backdrop_path,
+                    file_ext: asset_ext(&file),
+                    local_path: format_path(Some(file)),
                 }
                 .insert(&mut *tx)
                 .await;
@@ -144,6 +151,7 @@ impl<'a> MovieMatcher<'a> {
             name,
             description: result.overview.clone(),
             rating: result.rating.map(|x| x as i64),
+            certification: result.certification.clone(),
             year,
             added: Utc::now().to_string(),
 
@@ -205,6 +213,6 @@ impl<'a> MovieMatcher<'a> {
             event_type: PushEventType::EventNewCard { lib_id },
         };
 
-        let _ = self.event_tx.send(serde_json::to_string(&event).unwrap());
+        let _ = self.event_tx.send(Envelope::library(lib_id, event));
     }
 }
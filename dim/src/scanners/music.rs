@@ -0,0 +1,217 @@
+use database::album::Album;
+use database::artist::InsertableArtist;
+use database::asset::InsertableAsset;
+use database::genre::InsertableGenre;
+use database::genre::InsertableGenreMedia;
+use database::library::MediaType;
+use database::media::InsertableMedia;
+use database::mediafile::MediaFile;
+use database::mediafile::UpdateMediaFile;
+use database::track::InsertableTrack;
+use database::DbConnection;
+
+use chrono::prelude::Utc;
+
+use events::Envelope;
+use events::Message;
+use events::PushEventType;
+
+use tokio::task::spawn_blocking;
+
+use tracing::error;
+use tracing::instrument;
+use tracing::warn;
+
+use std::path::Path;
+
+use super::asset_ext;
+use super::format_path;
+use super::nfo::find_image;
+use super::nfo::import_artwork;
+use crate::core::EventTx;
+use crate::streaming::ffprobe::AudioTags;
+
+/// `MusicMatcher` files tracks into albums and artists based on the tags of the audio files. Unlike
+/// movies and shows, music is never matched against a external provider.
+pub struct MusicMatcher<'a> {
+    pub conn: &'a DbConnection,
+    pub event_tx: &'a EventTx,
+}
+
+impl<'a> MusicMatcher<'a> {
+    #[instrument(skip(self, tags, orphan), fields(orphan.id = %orphan.id, tags.album = ?tags.album))]
+    pub async fn match_to_tags(&self, tags: AudioTags, orphan: &'a MediaFile) {
+        let library_id = orphan.library_id;
+
+        // album art usually sits next to the tracks.
+        let dir = Path::new(&orphan.target_file).parent().map(ToOwned::to_owned);
+        let cover = spawn_blocking(move || {
+            find_image(dir.as_ref()?, &["cover", "folder", "front", "album"])
+                .and_then(|x| import_artwork(&x))
+        })
+        .await
+        .ok()
+        .flatten();
+
+        let mut lock = self.conn.writer().lock_owned().await;
+        let mut tx = match database::write_tx(&mut lock).await {
+            Ok(x) => x,
+            Err(e) => {
+                error!(reason = ?e, "Failed to create transaction.");
+                return;
+            }
+        };
+
+        let (album_id, is_new) = match self.inner_match(tags, cover, orphan, &mut tx).await {
+            Ok(x) => x,
+            Err(e) => {
+                error!(reason = ?e, "Failed to match track");
+                return;
+            }
+        };
+
+        if let Err(e) = tx.commit().await {
+            error!(reason = ?e, "Failed to commit transaction.");
+            return;
+        }
+
+        if is_new {
+            self.push_event(album_id, library_id).await;
+        }
+    }
+
+    /// Links `orphan` to its album, creating the album and artists if they dont exist yet. Returns
+    /// the id of the album and whether it was newly created.
+    pub async fn inner_match(
+        &self,
+        tags: AudioTags,
+        cover: Option<String>,
+        orphan: &'a MediaFile,
+        tx: &mut database::Transaction<'_>,
+    ) -> Result<(i64, bool), super::base::ScannerError> {
+        let library_id = orphan.library_id;
+
+        let album_artist = tags.album_artist.clone().or_else(|| tags.artist.clone());
+
+        let artist_id = match album_artist.as_ref() {
+            Some(name) => Some(
+                InsertableArtist {
+                    library_id,
+                    name: name.clone(),
+                }
+                .insert(&mut *tx)
+                .await?,
+            ),
+            None => None,
+        };
+
+        // features and compilations have track artists that differ from the album artist.
+        let track_artist_id = match tags.artist.as_ref() {
+            Some(name) if Some(name) != album_artist.as_ref() => Some(
+                InsertableArtist {
+                    library_id,
+                    name: name.clone(),
+                }
+                .insert(&mut *tx)
+                .await?,
+            ),
+            _ => artist_id,
+        };
+
+        // untagged tracks are grouped by the directory they are in.
+        let album_name = tags
+            .album
+            .clone()
+            .or_else(|| {
+                Path::new(&orphan.target_file)
+                    .parent()?
+                    .file_name()?
+                    .to_str()
+                    .map(ToString::to_string)
+            })
+            .unwrap_or_else(|| "Unknown Album".into());
+
+        let (album_id, is_new) =
+            match Album::get_by_name(&mut *tx, library_id, &album_name, artist_id).await? {
+                Some(id) => (id, false),
+                None => {
+                    let poster = match cover {
+                        Some(file) => {
+                            let asset = InsertableAsset {
+                                remote_url: None,
+                                file_ext: asset_ext(&file),
+                                local_path: format_path(Some(file)),
+                            }
+                            .insert(&mut *tx)
+                            .await;
+
+                            match asset {
+                                Ok(x) => Some(x.id),
+                                Err(e) => {
+                                    warn!(
+                                        reason = ?e,
+                                        orphan_id = orphan.id,
+                                        "Failed to insert album art into db",
+                                    );
+                                    None
+                                }
+                            }
+                        }
+                        None => None,
+                    };
+
+                    let media = InsertableMedia {
+                        library_id,
+                        name: album_name,
+                        year: tags.year,
+                        added: Utc::now().to_string(),
+                        poster,
+                        media_type: MediaType::Music,
+                        ..Default::default()
+                    };
+
+                    let id = media.insert_blind(&mut *tx).await?;
+                    Album::insert(&mut *tx, id, artist_id).await?;
+
+                    (id, true)
+                }
+            };
+
+        if let Some(name) = tags.genre.clone() {
+            let genre = InsertableGenre { name };
+
+            if let Ok(x) = genre.insert(&mut *tx).await {
+                let _ = InsertableGenreMedia::insert_pair(x, album_id, &mut *tx).await;
+            }
+        }
+
+        let track = InsertableTrack {
+            album_id,
+            mediafile_id: orphan.id,
+            artist_id: track_artist_id,
+            name: tags.title.unwrap_or_else(|| orphan.raw_name.clone()),
+            track_number: tags.track,
+            disc_number: tags.disc,
+        };
+
+        track.insert(&mut *tx).await?;
+
+        let updated_mediafile = UpdateMediaFile {
+            media_id: Some(album_id),
+            ..Default::default()
+        };
+
+        updated_mediafile.update(&mut *tx, orphan.id).await?;
+
+        Ok((album_id, is_new))
+    }
+
+    async fn push_event(&self, id: i64, lib_id: i64) {
+        let event = Message {
+            id,
+            event_type: PushEventType::EventNewCard { lib_id },
+        };
+
+        let _ = self.event_tx.send(Envelope::library(lib_id, event));
+    }
+}
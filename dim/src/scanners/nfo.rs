@@ -0,0 +1,485 @@
+//! Support for Kodi style `.nfo` sidecar files and local artwork.
+//!
+//! When a media file has a sidecar next to it we build the metadata for it entirely from the local
+//! filesystem, online metadata providers are only consulted for files without one.
+//!
+//! The lookup rules are as follows:
+//! * Movies use `<file name>.nfo` or `movie.nfo` in the same directory, with artwork picked from
+//! `<file name>-poster.jpg`, `poster.jpg`, `folder.jpg` and `<file name>-fanart.jpg`, `fanart.jpg`.
+//! * Tv shows use `tvshow.nfo` in the directory of the episode or its parent (for `Season XX`
+//! directories), with artwork picked from `poster.jpg` and `fanart.jpg` in the show directory.
+//! Episodes can optionally carry their own `<file name>.nfo` and `<file name>-thumb.jpg`. Season
+//! posters are picked from `seasonXX-poster.jpg` in the show directory.
+
+use super::ApiEpisode;
+use super::ApiMedia;
+use super::ApiSeason;
+
+use crate::core::METADATA_PATH;
+
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use ring::digest;
+use tracing::warn;
+
+const IMAGE_EXTS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+/// Ids derived from paths have this bit set, which keeps them clear of the ids providers hand
+/// out while still fitting into a `i64`.
+pub(super) const LOCAL_ID_BIT: u64 = 1 << 62;
+
+/// Metadata parsed out of a single nfo file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Nfo {
+    /// Root tag of the nfo file, ie `movie`, `tvshow` or `episodedetails`.
+    pub kind: String,
+    pub title: Option<String>,
+    pub year: Option<i32>,
+    pub premiered: Option<String>,
+    pub plot: Option<String>,
+    pub rating: Option<f64>,
+    /// Certification stored in the `mpaa` tag, ie `Rated PG-13`.
+    pub certification: Option<String>,
+    pub genres: Vec<String>,
+    pub season: Option<u64>,
+    pub episode: Option<u64>,
+    pub tmdb_id: Option<u64>,
+    pub poster: Option<String>,
+    pub fanart: Option<String>,
+}
+
+impl Nfo {
+    /// Parse the contents of a nfo file. Returns `None` if the content is not a xml document.
+    pub fn parse(content: &str) -> Option<Self> {
+        // Some nfo files have a url appended after the xml document, so we cut everything after
+        // the closing root tag.
+        let content = content.trim();
+        let content = &content[..content.rfind('>').map_or(0, |x| x + 1)];
+
+        let doc = roxmltree::Document::parse(content).ok()?;
+        let root = doc.root_element();
+
+        let mut nfo = Self {
+            kind: root.tag_name().name().to_string(),
+            ..Default::default()
+        };
+
+        for node in root.children().filter(|x| x.is_element()) {
+            let text = node.text().map(str::trim).filter(|x| !x.is_empty());
+
+            match node.tag_name().name() {
+                "title" => nfo.title = text.map(ToString::to_string),
+                "year" => nfo.year = text.and_then(|x| x.parse().ok()),
+                "premiered" | "aired" | "releasedate" if nfo.premiered.is_none() => {
+                    nfo.premiered = text.map(ToString::to_string)
+                }
+                "plot" => nfo.plot = text.map(ToString::to_string),
+                "mpaa" => nfo.certification = text.map(ToString::to_string),
+                "outline" if nfo.plot.is_none() => nfo.plot = text.map(ToString::to_string),
+                "rating" => nfo.rating = nfo.rating.or_else(|| text.and_then(|x| x.parse().ok())),
+                "ratings" => {
+                    // prefer the rating marked as default, otherwise pick the first one.
+                    let ratings = node
+                        .children()
+                        .filter(|x| x.is_element() && x.tag_name().name() == "rating");
+
+                    let mut rating = None;
+                    for x in ratings {
+                        let value = x
+                            .children()
+                            .find(|x| x.tag_name().name() == "value")
+                            .and_then(|x| x.text())
+                            .and_then(|x| x.trim().parse::<f64>().ok());
+
+                        if x.attribute("default") == Some("true") {
+                            rating = value.or(rating);
+                            break;
+                        }
+
+                        rating = rating.or(value);
+                    }
+
+                    nfo.rating = rating.or(nfo.rating);
+                }
+                "genre" => {
+                    if let Some(genre) = text {
+                        // some scrapers write multiple genres into a single tag separated by `/`.
+                        nfo.genres.extend(
+                            genre
+                                .split('/')
+                                .map(str::trim)
+                                .filter(|x| !x.is_empty())
+                                .map(ToString::to_string),
+                        );
+                    }
+                }
+                "season" => nfo.season = text.and_then(|x| x.parse().ok()),
+                "episode" => nfo.episode = text.and_then(|x| x.parse().ok()),
+                "tmdbid" => nfo.tmdb_id = text.and_then(|x| x.parse().ok()),
+                "uniqueid" if node.attribute("type") == Some("tmdb") => {
+                    nfo.tmdb_id = text.and_then(|x| x.parse().ok())
+                }
+                "thumb" => match node.attribute("aspect") {
+                    Some("poster") | None if nfo.poster.is_none() => {
+                        nfo.poster = text.map(ToString::to_string)
+                    }
+                    _ => {}
+                },
+                "fanart" => {
+                    nfo.fanart = node
+                        .children()
+                        .find(|x| x.tag_name().name() == "thumb")
+                        .and_then(|x| x.text())
+                        .map(str::trim)
+                        .filter(|x| !x.is_empty())
+                        .map(ToString::to_string)
+                }
+                _ => {}
+            }
+        }
+
+        Some(nfo)
+    }
+
+    /// Read and parse the nfo file at `path`.
+    pub fn read(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+
+        match Self::parse(&content) {
+            Some(x) => Some(x),
+            None => {
+                warn!(file = ?path, "Failed to parse nfo file");
+                None
+            }
+        }
+    }
+
+    fn release_date(&self) -> Option<String> {
+        self.premiered
+            .clone()
+            .or_else(|| self.year.map(|x| format!("{:04}-01-01", x)))
+    }
+
+    fn into_api_media(self, id_seed: &Path) -> ApiMedia {
+        let release_date = self.release_date();
+
+        ApiMedia {
+            id: self.tmdb_id.unwrap_or_else(|| path_id(id_seed)),
+            title: self.title.unwrap_or_default(),
+            release_date,
+            overview: self.plot,
+            poster_path: None,
+            backdrop_path: None,
+            poster_file: None,
+            backdrop_file: None,
+            genres: self.genres,
+            rating: self.rating.map(|x| x as i32),
+            certification: self.certification,
+            seasons: Vec::new(),
+        }
+    }
+}
+
+/// Build the metadata for the movie at `file` from its sidecar files. Returns `None` if the movie
+/// has no nfo file.
+pub fn movie_metadata(file: &Path) -> Option<ApiMedia> {
+    let dir = file.parent()?;
+    let stem = file.file_stem()?.to_str()?;
+
+    let nfo_path = [dir.join(format!("{}.nfo", stem)), dir.join("movie.nfo")]
+        .iter()
+        .find(|x| x.is_file())
+        .cloned()?;
+
+    let nfo = Nfo::read(&nfo_path).filter(|x| x.kind == "movie" && x.title.is_some())?;
+
+    let poster = find_image(dir, &[&format!("{}-poster", stem), "poster", "folder", "cover"])
+        .or_else(|| nfo.poster.as_deref().and_then(|x| local_image(dir, x)));
+    let fanart = find_image(dir, &[&format!("{}-fanart", stem), "fanart", "backdrop"])
+        .or_else(|| nfo.fanart.as_deref().and_then(|x| local_image(dir, x)));
+
+    let mut media = nfo.into_api_media(dir);
+    media.poster_file = poster.and_then(|x| import_artwork(&x));
+    media.backdrop_file = fanart.and_then(|x| import_artwork(&x));
+
+    Some(media)
+}
+
+/// Metadata built from the sidecar files of a episode.
+pub struct TvSidecar {
+    /// The show with the season and episode of the file populated.
+    pub show: ApiMedia,
+    /// Season number, if the episode has its own nfo file.
+    pub season: Option<u64>,
+    /// Episode number, if the episode has its own nfo file.
+    pub episode: Option<u64>,
+}
+
+/// Build the metadata for the episode at `file` from its sidecar files. Returns `None` if the show
+/// has no `tvshow.nfo`.
+///
+/// # Arguments
+/// * `file` - path to the episode
+/// * `season` - season number parsed from the filename.
+/// * `episode` - episode number parsed from the filename.
+pub fn tv_metadata(file: &Path, season: Option<u64>, episode: Option<u64>) -> Option<TvSidecar> {
+    let episode_dir = file.parent()?;
+    let stem = file.file_stem()?.to_str()?;
+
+    let (show_dir, show_nfo) = episode_dir
+        .ancestors()
+        .take(2)
+        .map(|x| (x, x.join("tvshow.nfo")))
+        .find(|(_, x)| x.is_file())?;
+
+    let nfo = Nfo::read(&show_nfo).filter(|x| x.kind == "tvshow" && x.title.is_some())?;
+
+    let episode_nfo = Nfo::read(&episode_dir.join(format!("{}.nfo", stem)))
+        .filter(|x| x.kind == "episodedetails");
+
+    let season_number = episode_nfo
+        .as_ref()
+        .and_then(|x| x.season)
+        .or(season)
+        .unwrap_or(1);
+    let episode_number = episode_nfo.as_ref().and_then(|x| x.episode).or(episode);
+
+    let poster = find_image(show_dir, &["poster", "folder"])
+        .or_else(|| nfo.poster.as_deref().and_then(|x| local_image(show_dir, x)));
+    let fanart = find_image(show_dir, &["fanart", "backdrop"])
+        .or_else(|| nfo.fanart.as_deref().and_then(|x| local_image(show_dir, x)));
+
+    let season_poster = {
+        let name = format!("season{:02}-poster", season_number);
+        let season_dir_poster = if episode_dir != show_dir {
+            find_image(episode_dir, &["poster", "folder"])
+        } else {
+            None
+        };
+
+        find_image(show_dir, &[&name]).or(season_dir_poster)
+    };
+
+    let still = find_image(episode_dir, &[&format!("{}-thumb", stem)]).or_else(|| {
+        episode_nfo
+            .as_ref()
+            .and_then(|x| x.poster.as_deref())
+            .and_then(|x| local_image(episode_dir, x))
+    });
+
+    let mut show = nfo.into_api_media(show_dir);
+    show.poster_file = poster.and_then(|x| import_artwork(&x));
+    show.backdrop_file = fanart.and_then(|x| import_artwork(&x));
+
+    let episodes = episode_number
+        .map(|number| {
+            let (name, overview) = episode_nfo
+                .clone()
+                .map(|x| (x.title, x.plot))
+                .unwrap_or_default();
+
+            vec![ApiEpisode {
+                id: path_id(file),
+                name,
+                overview,
+                episode: Some(number),
+                still: None,
+                still_file: still.and_then(|x| import_artwork(&x)),
+            }]
+        })
+        .unwrap_or_default();
+
+    show.seasons = vec![ApiSeason {
+        id: path_id(&show_dir.join(season_number.to_string())),
+        name: Some(format!("Season {}", season_number)),
+        poster_path: None,
+        poster_file: season_poster.and_then(|x| import_artwork(&x)),
+        season_number,
+        episodes,
+    }];
+
+    Some(TvSidecar {
+        show,
+        season: episode_nfo.as_ref().and_then(|x| x.season),
+        episode: episode_nfo.as_ref().and_then(|x| x.episode),
+    })
+}
+
+/// Stable identifier derived from a path, used when the nfo doesnt carry a external id. The id
+/// is the sha256 of the path truncated to 62 bits with [`LOCAL_ID_BIT`] set, so it stays the same
+/// across restarts and versions of dim.
+pub(super) fn path_id(path: &Path) -> u64 {
+    let hash = digest::digest(&digest::SHA256, path.to_string_lossy().as_bytes());
+    let prefix = u64::from_be_bytes(hash.as_ref()[..8].try_into().unwrap());
+
+    prefix & (LOCAL_ID_BIT - 1) | LOCAL_ID_BIT
+}
+
+/// Find the first image in `dir` whose file stem is one of `names`.
+pub(super) fn find_image(dir: &Path, names: &[&str]) -> Option<PathBuf> {
+    names
+        .iter()
+        .flat_map(|name| IMAGE_EXTS.iter().map(move |ext| format!("{}.{}", name, ext)))
+        .map(|x| dir.join(x))
+        .find(|x| x.is_file())
+}
+
+/// Resolve a `<thumb>` value to a local image. Remote urls are ignored as sidecar libraries are
+/// expected to work without network access.
+fn local_image(dir: &Path, thumb: &str) -> Option<PathBuf> {
+    if thumb.contains("://") {
+        return None;
+    }
+
+    Some(dir.join(thumb)).filter(|x| x.is_file())
+}
+
+/// Copy a local image into the metadata directory so that it can be served like any other asset.
+/// Returns the name of the file in the metadata directory.
+pub(super) fn import_artwork(image: &Path) -> Option<String> {
+    let meta_path = METADATA_PATH.get()?;
+    let ext = image.extension()?.to_str()?.to_lowercase();
+    let name = format!("local-{:x}.{}", path_id(image), ext);
+
+    let target = Path::new(meta_path).join(&name);
+
+    let is_stale = match (fs::metadata(image), fs::metadata(&target)) {
+        (Ok(source), Ok(target)) => match (source.modified(), target.modified()) {
+            (Ok(source), Ok(target)) => source > target,
+            _ => true,
+        },
+        _ => true,
+    };
+
+    if is_stale {
+        if let Err(e) = fs::copy(image, &target) {
+            warn!(file = ?image, reason = ?e, "Failed to import local artwork");
+            return None;
+        }
+    }
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_movie() {
+        let nfo = Nfo::parse(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>
+            <movie>
+                <title>Blade Runner</title>
+                <year>1982</year>
+                <plot>A blade runner must pursue and terminate four replicants.</plot>
+                <mpaa>Rated R</mpaa>
+                <ratings>
+                    <rating name="imdb" max="10">
+                        <value>8.1</value>
+                    </rating>
+                    <rating name="themoviedb" max="10" default="true">
+                        <value>7.9</value>
+                    </rating>
+                </ratings>
+                <genre>Science Fiction / Thriller</genre>
+                <genre>Drama</genre>
+                <uniqueid type="tmdb">78</uniqueid>
+                <thumb aspect="poster">poster.jpg</thumb>
+                <fanart><thumb>http://example.com/fanart.jpg</thumb></fanart>
+            </movie>"#,
+        )
+        .unwrap();
+
+        assert_eq!(nfo.kind, "movie");
+        assert_eq!(nfo.title.as_deref(), Some("Blade Runner"));
+        assert_eq!(nfo.year, Some(1982));
+        assert_eq!(nfo.rating, Some(7.9));
+        assert_eq!(nfo.genres, vec!["Science Fiction", "Thriller", "Drama"]);
+        assert_eq!(nfo.tmdb_id, Some(78));
+        assert_eq!(nfo.poster.as_deref(), Some("poster.jpg"));
+        assert_eq!(nfo.fanart.as_deref(), Some("http://example.com/fanart.jpg"));
+
+        let media = nfo.into_api_media(Path::new("/movies/blade runner"));
+        assert_eq!(media.id, 78);
+        assert_eq!(media.release_date.as_deref(), Some("1982-01-01"));
+        assert_eq!(media.rating, Some(7));
+        assert_eq!(media.certification.as_deref(), Some("Rated R"));
+    }
+
+    #[test]
+    fn parse_episode() {
+        let nfo = Nfo::parse(
+            r#"<episodedetails>
+                <title>Dulcinea</title>
+                <season>1</season>
+                <episode>1</episode>
+                <aired>2015-12-14</aired>
+                <rating>7.6</rating>
+            </episodedetails>"#,
+        )
+        .unwrap();
+
+        assert_eq!(nfo.kind, "episodedetails");
+        assert_eq!(nfo.season, Some(1));
+        assert_eq!(nfo.episode, Some(1));
+        assert_eq!(nfo.premiered.as_deref(), Some("2015-12-14"));
+        assert_eq!(nfo.rating, Some(7.6));
+    }
+
+    #[test]
+    fn stable_path_ids() {
+        let id = path_id(Path::new("/media/movies/Big Buck Bunny.mkv"));
+
+        // must never change, the id names the artwork extracted from the file.
+        assert_eq!(id, 6289110794409017726);
+        assert!(id & LOCAL_ID_BIT != 0);
+        assert!(id <= i64::MAX as u64);
+        assert_ne!(id, path_id(Path::new("/media/movies/Sintel.mkv")));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Nfo::parse("https://www.themoviedb.org/movie/78").is_none());
+    }
+
+    #[test]
+    fn tv_sidecar() {
+        let root = std::env::temp_dir().join(format!("dim-nfo-{}", std::process::id()));
+        let season_dir = root.join("The Expanse").join("Season 01");
+        fs::create_dir_all(&season_dir).unwrap();
+
+        fs::write(
+            root.join("The Expanse").join("tvshow.nfo"),
+            "<tvshow><title>The Expanse</title><premiered>2015-12-14</premiered>\
+             <genre>Drama</genre></tvshow>",
+        )
+        .unwrap();
+
+        fs::write(
+            season_dir.join("expanse.s01e02.nfo"),
+            "<episodedetails><title>The Big Empty</title><season>1</season>\
+             <episode>2</episode></episodedetails>",
+        )
+        .unwrap();
+
+        let file = season_dir.join("expanse.s01e02.mkv");
+        let sidecar = tv_metadata(&file, Some(3), Some(9)).unwrap();
+
+        assert_eq!(sidecar.show.title, "The Expanse");
+        assert_eq!(sidecar.show.genres, vec!["Drama"]);
+        assert_eq!(sidecar.season, Some(1));
+        assert_eq!(sidecar.episode, Some(2));
+
+        let season = &sidecar.show.seasons[0];
+        assert_eq!(season.season_number, 1);
+        assert_eq!(season.episodes[0].name.as_deref(), Some("The Big Empty"));
+        assert_eq!(season.episodes[0].episode, Some(2));
+
+        assert!(movie_metadata(&file).is_none());
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,136 @@
+#[cfg(feature = "mock_provider")]
+use super::mock::MockProvider;
+use super::tmdb::Tmdb;
+use super::tmdb::TmdbError;
+use super::ApiEpisode;
+use super::ApiMedia;
+use super::ApiSeason;
+
+use database::library::MediaType;
+use database::library::ProviderKind;
+
+use async_trait::async_trait;
+use err_derive::Error;
+use serde::Serialize;
+
+use once_cell::sync::Lazy;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// API key used to query tmdb when the user hasn't configured their own.
+pub const DEFAULT_TMDB_API_KEY: &str = "38c372f5bc572c8aadde7a802638534e";
+
+#[derive(Debug, Error, Serialize)]
+pub enum ProviderError {
+    #[error(display = "No results are found")]
+    NoResults { query: String, year: Option<i32> },
+    #[error(display = "The requested item could not be found")]
+    NotFound,
+    #[error(display = "The provider cannot supply metadata for this media type")]
+    UnsupportedMediaType,
+    /// Converted with the `From` impl in `tmdb.rs`, which maps some tmdb errors onto the
+    /// variants above.
+    #[error(display = "Tmdb returned an error: {}", _0)]
+    Tmdb(#[error(source, no_from)] TmdbError),
+}
+
+/// A `MetadataProvider` is a source of external metadata, such as tmdb. A provider instance is
+/// bound to a single media type, for instance a provider returned by
+/// `provider_for(ProviderKind::Tmdb, MediaType::Tv)` will only ever return tv shows.
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    /// Search for media by title and optionally year, returning the best match.
+    async fn search(&self, title: &str, year: Option<i32>) -> Result<ApiMedia, ProviderError> {
+        self.search_many(title, year)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| ProviderError::NoResults {
+                query: title.to_string(),
+                year,
+            })
+    }
+
+    /// Search for media by title and optionally year, returning all matches ordered by relevancy.
+    async fn search_many(
+        &self,
+        title: &str,
+        year: Option<i32>,
+    ) -> Result<Vec<ApiMedia>, ProviderError>;
+
+    /// Fetch media by its id within the provider.
+    async fn search_by_id(&self, id: u64) -> Result<ApiMedia, ProviderError>;
+
+    /// Fetch all the seasons of the tv show `id`. The seasons returned will not have their
+    /// episodes populated.
+    async fn seasons_for(&self, id: u64) -> Result<Vec<ApiSeason>, ProviderError>;
+
+    /// Fetch all the episodes for season `season` of the tv show `id`.
+    async fn episodes_for(&self, id: u64, season: u64) -> Result<Vec<ApiEpisode>, ProviderError>;
+
+    /// Resolve the name of the genre `id`.
+    async fn genre(&self, id: u64) -> Result<String, ProviderError>;
+
+    /// Fetch the certification of the media `id`, ie `PG-13`. Returns `None` if the media hasnt
+    /// been rated.
+    async fn certification_for(&self, id: u64) -> Result<Option<String>, ProviderError>;
+
+    /// Populate the certification of `media` if the search results didnt include it.
+    async fn populate_certification(&self, media: &mut ApiMedia) {
+        if media.certification.is_none() {
+            media.certification = self.certification_for(media.id).await.ok().flatten();
+        }
+    }
+
+    /// Populate `media` with all of its seasons and their episodes. Seasons and episodes which
+    /// cannot be fetched are skipped.
+    async fn populate_seasons(&self, media: &mut ApiMedia) {
+        let mut seasons = self.seasons_for(media.id).await.unwrap_or_default();
+
+        for season in seasons.iter_mut() {
+            season.episodes = self
+                .episodes_for(media.id, season.season_number)
+                .await
+                .unwrap_or_default();
+        }
+
+        media.seasons = seasons;
+    }
+}
+
+/// Get the metadata provider of kind `kind` which will return media of type `media_type`.
+/// Providers are constructed once and then shared.
+pub fn provider_for(kind: ProviderKind, media_type: MediaType) -> Arc<dyn MetadataProvider> {
+    type ProviderCache = HashMap<(ProviderKind, MediaType, String), Arc<dyn MetadataProvider>>;
+    static PROVIDERS: Lazy<Mutex<ProviderCache>> = Lazy::new(Default::default);
+
+    let api_key = match kind {
+        ProviderKind::Tmdb => crate::get_global_settings()
+            .tmdb_api_key
+            .unwrap_or_else(|| DEFAULT_TMDB_API_KEY.to_string()),
+        #[cfg(feature = "mock_provider")]
+        ProviderKind::Mock => String::new(),
+    };
+
+    let mut lock = PROVIDERS.lock().unwrap();
+
+    lock.entry((kind, media_type, api_key.clone()))
+        .or_insert_with(|| match kind {
+            ProviderKind::Tmdb => Arc::new(Tmdb::new(api_key, media_type)),
+            #[cfg(feature = "mock_provider")]
+            ProviderKind::Mock => Arc::new(MockProvider::new(media_type)),
+        })
+        .clone()
+}
+
+/// Parses the `media_type` query argument routes use to select which kind of media to search for
+/// with a provider.
+pub fn parse_media_type(media_type: &str) -> Option<MediaType> {
+    match media_type.to_lowercase().as_ref() {
+        "movie" => Some(MediaType::Movie),
+        "tv" => Some(MediaType::Tv),
+        _ => None,
+    }
+}
@@ -6,7 +6,6 @@ use std::time::Duration;
 
 use database::library::Library;
 use database::library::MediaType;
-use database::media::Media;
 use database::mediafile::MediaFile;
 use database::mediafile::UpdateMediaFile;
 use database::DbConnection;
@@ -89,7 +88,9 @@ impl FsWatcher {
             && path
                 .extension()
                 .and_then(|e| e.to_str())
-                .map_or(false, |e| super::SUPPORTED_EXTS.contains(&e))
+                .map_or(false, |e| {
+                    super::supported_exts(self.media_type).contains(&e)
+                })
         {
             let extractor = super::get_extractor(&self.tx);
             let matcher = super::get_matcher(&self.tx);
@@ -98,23 +99,17 @@ impl FsWatcher {
                 .mount_file(path.clone(), self.library_id, self.media_type)
                 .await
             {
-                match self.media_type {
-                    MediaType::Movie => {
-                        let _ = matcher.match_movie(mfile).await;
-                    }
-                    MediaType::Tv => {
-                        let _ = matcher.match_tv(mfile).await;
-                    }
-                    _ => unreachable!(),
-                }
+                super::match_mediafile(matcher, mfile, self.media_type).await;
             }
         } else if path.is_dir() {
             if let Some(x) = path.to_str() {
                 let _ = super::start_custom(
+                    &self.conn,
                     self.library_id,
                     self.tx.clone(),
                     IntoIterator::into_iter([x]),
                     self.media_type,
+                    None,
                 )
                 .await;
             }
@@ -145,26 +140,11 @@ impl FsWatcher {
         };
 
         if let Ok(media_file) = MediaFile::get_by_file(&mut tx, path).await {
-            let media = Media::get_of_mediafile(&mut tx, media_file.id).await;
-
-            if let Err(e) = MediaFile::delete(&mut tx, media_file.id).await {
+            if let Err(e) = super::remove_mediafile(&mut tx, media_file.id).await {
                 error!(reason = ?e, "Failed to remove mediafile");
                 return;
             }
 
-            // if we have a media with no mediafiles we want to purge it as it is a ghost media
-            // entry.
-            if let Ok(media) = media {
-                if let Ok(media_files) = MediaFile::get_of_media(&mut tx, media.id).await {
-                    if media_files.is_empty() {
-                        if let Err(e) = Media::delete(&mut tx, media.id).await {
-                            error!(reason = ?e, "Failed to delete ghost media");
-                            return;
-                        }
-                    }
-                }
-            }
-
             if let Err(e) = tx.commit().await {
                 error!(reason = ?e, "Failed to commit transaction.");
             }
@@ -16,6 +16,13 @@ use futures::StreamExt;
 use tokio::sync::RwLock;
 
 use async_recursion::async_recursion;
+use async_trait::async_trait;
+
+use super::provider::MetadataProvider;
+use super::provider::ProviderError;
+use super::ApiEpisode;
+use super::ApiMedia;
+use super::ApiSeason;
 
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
@@ -59,23 +66,10 @@ impl Tmdb {
         }
     }
 
-    pub async fn search(
-        &mut self,
-        title: String,
-        year: Option<i32>,
-    ) -> Result<super::ApiMedia, TmdbError> {
-        self.search_by_name(title.clone(), year, None)
-            .await?
-            .first()
-            .cloned()
-            .map(Into::into)
-            .ok_or(TmdbError::NoResults { query: title, year })
-    }
-
-    pub async fn search_by_id(&mut self, id: i32) -> Result<Media, TmdbError> {
+    pub async fn get_by_id(&self, id: u64) -> Result<Media, TmdbError> {
         let args = vec![
-        ("api_key".to_string(), self.api_key.clone()),
-        ("language".to_string(), "en-US".into()),
+            ("api_key".to_string(), self.api_key.clone()),
+            ("language".to_string(), "en-US".into()),
         ];
 
         let url = format!("{}/{}/{}", self.base, self.media_type, id);
@@ -132,7 +126,7 @@ impl Tmdb {
 
     #[async_recursion]
     pub async fn search_by_name(
-        &mut self,
+        &self,
         title: String,
         year: Option<i32>,
         max_tries: Option<usize>,
@@ -199,14 +193,7 @@ impl Tmdb {
             let ids = media.genre_ids.clone().unwrap_or_default();
             media.genres = stream::iter(ids)
                 .filter_map(|x| {
-                    let client = ClientBuilder::new().user_agent(APP_USER_AGENT);
-                    let mut this = Tmdb {
-                        api_key: self.api_key.clone(),
-                        client: client.build().unwrap(),
-                        base: self.base.clone(),
-                        media_type: self.media_type,
-                    };
-
+                    let this = self.clone();
                     async move { this.get_genre_detail(x).await.ok().map(|x| x.name) }
                 })
                 .collect::<Vec<String>>()
@@ -222,7 +209,7 @@ impl Tmdb {
         Ok(result)
     }
 
-    pub async fn get_seasons_for(&mut self, id: u64) -> Result<Vec<Season>, TmdbError> {
+    pub async fn get_seasons_for(&self, id: u64) -> Result<Vec<Season>, TmdbError> {
         let args = vec![("api_key".to_string(), self.api_key.clone())];
 
         let req = self
@@ -246,7 +233,7 @@ impl Tmdb {
     }
 
     pub async fn get_episodes_for(
-        &mut self,
+        &self,
         id: u64,
         season: u64,
     ) -> Result<Vec<Episode>, TmdbError> {
@@ -272,7 +259,62 @@ impl Tmdb {
             .ok_or(TmdbError::NoEpisodesFound { id, season })
     }
 
-    pub async fn get_genre_detail(&mut self, genre_id: u64) -> Result<Genre, TmdbError> {
+    /// Fetch the US certification of the media `id`. Movies can have a different certification
+    /// per release, in which case the first non-empty one is picked.
+    pub async fn get_certification(&self, id: u64) -> Result<Option<String>, TmdbError> {
+        let args = vec![("api_key".to_string(), self.api_key.clone())];
+
+        let endpoint = match self.media_type {
+            MediaType::Tv => "content_ratings",
+            _ => "release_dates",
+        };
+
+        let url = format!("{}/{}/{}/{}", self.base, self.media_type, id, endpoint);
+        let req = self
+            .client
+            .get(url)
+            .query(&args)
+            .send()
+            .await
+            .map_err(|_| TmdbError::ReqwestError)?;
+
+        #[derive(Deserialize)]
+        struct Release {
+            certification: Option<String>,
+        }
+
+        #[derive(Deserialize)]
+        struct Country {
+            iso_3166_1: String,
+            // set for tv shows
+            rating: Option<String>,
+            // set for movies
+            #[serde(default)]
+            release_dates: Vec<Release>,
+        }
+
+        #[derive(Deserialize)]
+        struct Wrapper {
+            results: Vec<Country>,
+        }
+
+        let country = req
+            .json::<Wrapper>()
+            .await
+            .map_err(|_| TmdbError::DeserializationError)?
+            .results
+            .into_iter()
+            .find(|x| x.iso_3166_1 == "US");
+
+        Ok(country.and_then(|x| {
+            x.rating
+                .into_iter()
+                .chain(x.release_dates.into_iter().filter_map(|x| x.certification))
+                .find(|x| !x.trim().is_empty())
+        }))
+    }
+
+    pub async fn get_genre_detail(&self, genre_id: u64) -> Result<Genre, TmdbError> {
         lazy_static::lazy_static! {
             static ref __CACHE: Arc<RwLock<HashMap<MediaType, Vec<Genre>>>> = Arc::new(RwLock::new(HashMap::new()));
         }
@@ -320,6 +362,67 @@ impl Tmdb {
             .ok_or(TmdbError::NoGenreFound { id: genre_id })
     }
 }
+#[async_trait]
+impl MetadataProvider for Tmdb {
+    async fn search_many(
+        &self,
+        title: &str,
+        year: Option<i32>,
+    ) -> Result<Vec<ApiMedia>, ProviderError> {
+        Ok(self
+            .search_by_name(title.to_string(), year, None)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn search_by_id(&self, id: u64) -> Result<ApiMedia, ProviderError> {
+        let mut media: ApiMedia = self.get_by_id(id).await?.into();
+        self.populate_certification(&mut media).await;
+
+        Ok(media)
+    }
+
+    async fn seasons_for(&self, id: u64) -> Result<Vec<ApiSeason>, ProviderError> {
+        Ok(self
+            .get_seasons_for(id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn episodes_for(&self, id: u64, season: u64) -> Result<Vec<ApiEpisode>, ProviderError> {
+        Ok(self
+            .get_episodes_for(id, season)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn genre(&self, id: u64) -> Result<String, ProviderError> {
+        Ok(self.get_genre_detail(id).await?.name)
+    }
+
+    async fn certification_for(&self, id: u64) -> Result<Option<String>, ProviderError> {
+        Ok(self.get_certification(id).await?)
+    }
+}
+
+impl From<TmdbError> for ProviderError {
+    fn from(e: TmdbError) -> Self {
+        match e {
+            TmdbError::NoResults { query, year } => Self::NoResults { query, year },
+            TmdbError::NoSeasonsFound { .. }
+            | TmdbError::NoEpisodesFound { .. }
+            | TmdbError::NoGenreFound { .. } => Self::NotFound,
+            e => Self::Tmdb(e),
+        }
+    }
+}
+
 /*
 
  {
@@ -395,6 +498,7 @@ impl From<Media> for super::ApiMedia {
             backdrop_file: this.backdrop_path,
             genres: this.genres,
             rating: this.vote_average.map(|x| x as i32),
+            certification: None,
             seasons: Vec::new(),
         }
     }
@@ -462,7 +566,7 @@ impl From<Episode> for super::ApiEpisode {
 mod tests {
     use super::*;
 
-    const API_KEY: &str = "38c372f5bc572c8aadde7a802638534e";
+    const API_KEY: &str = crate::scanners::provider::DEFAULT_TMDB_API_KEY;
 
     // #[test]
     // fn test_search_by_name() {
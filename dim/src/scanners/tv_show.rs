@@ -6,6 +6,7 @@ use database::DbConnection;
 use database::episode::InsertableEpisode;
 use database::library::MediaType;
 use database::media::InsertableMedia;
+use database::media::Media;
 use database::mediafile::MediaFile;
 use database::mediafile::UpdateMediaFile;
 use database::movie::InsertableMovie;
@@ -16,6 +17,7 @@ use chrono::prelude::Utc;
 use chrono::Datelike;
 use chrono::NaiveDate;
 
+use events::Envelope;
 use events::Message;
 use events::PushEventType;
 
@@ -26,6 +28,7 @@ use tracing::instrument;
 use tracing::warn;
 use tracing::Instrument;
 
+use super::asset_ext;
 use super::format_path;
 use crate::core::EventTx;
 use crate::fetcher::insert_into_queue;
@@ -37,8 +40,30 @@ pub struct TvShowMatcher<'a> {
 
 impl<'a> TvShowMatcher<'a> {
     #[instrument(skip(self, result, orphan), fields(result.id = %result.id, result.name = %result.title, orphan.id = %orphan.id))]
-    pub async fn match_to_result(&self, result: super::ApiMedia, orphan: &'a MediaFile) {
+    pub async fn match_to_result(&self, mut result: super::ApiMedia, orphan: &'a MediaFile) {
         let library_id = orphan.library_id;
+
+        // shows keep the artwork they were first matched with, so its only extracted for the first
+        // episode of a show rather than for every single one.
+        let is_known = match self.conn.read().begin().await {
+            Ok(mut tx) => Media::get_by_name_and_lib(&mut tx, library_id, &result.title)
+                .await
+                .is_ok(),
+            Err(_) => false,
+        };
+
+        if !is_known {
+            super::artwork::fill_missing(&mut result, &orphan.target_file).await;
+        }
+
+        super::artwork::fill_missing_still(
+            &mut result,
+            &orphan.target_file,
+            orphan.season.unwrap_or(0) as u64,
+            orphan.episode.unwrap_or(0) as u64,
+        )
+        .await;
+
         let mut lock = self.conn.writer().lock_owned().await;
         let mut tx = match database::write_tx(&mut lock)
             .await
@@ -88,19 +113,19 @@ impl<'a> TvShowMatcher<'a> {
         let backdrop_path = result.backdrop_path.clone();
 
         if let Some(poster_path) = poster_path.as_ref() {
-            let _ = insert_into_queue(poster_path.clone(), 3).await;
+            let _ = insert_into_queue(&mut *tx, poster_path.clone(), 3).await;
         }
 
         if let Some(backdrop_path) = backdrop_path.as_ref() {
-            let _ = insert_into_queue(backdrop_path.clone(), 3).await;
+            let _ = insert_into_queue(&mut *tx, backdrop_path.clone(), 3).await;
         }
 
-        let poster = match poster_path {
-            Some(path) => {
+        let poster = match result.poster_file.clone() {
+            Some(file) => {
                 let asset = InsertableAsset {
-                    remote_url: Some(path),
-                    local_path: format_path(result.poster_file.clone()),
-                    file_ext: "jpg".into(),
+                    remote_url: poster_path,
+                    file_ext: asset_ext(&file),
+                    local_path: format_path(Some(file)),
                 }
                 .insert(&mut *tx)
                 .await;
@@ -121,12 +146,12 @@ impl<'a> TvShowMatcher<'a> {
             None => None,
         };
 
-        let backdrop = match backdrop_path {
-            Some(path) => {
+        let backdrop = match result.backdrop_file.clone() {
+            Some(file) => {
                 let asset = InsertableAsset {
-                    remote_url: Some(path),
-                    local_path: format_path(result.backdrop_file.clone()),
-                    file_ext: "jpg".into(),
+                    remote_url: backdrop_path,
+                    file_ext: asset_ext(&file),
+                    local_path: format_path(Some(file)),
                 }
                 .insert(&mut *tx)
                 .await;
@@ -152,6 +177,7 @@ impl<'a> TvShowMatcher<'a> {
             library_id: orphan.library_id,
             description: result.overview.clone(),
             rating: result.rating.map(|x| x as i64),
+            certification: result.certification.clone(),
             added: Utc::now().to_string(),
             poster,
             backdrop,
@@ -206,15 +232,15 @@ impl<'a> TvShowMatcher<'a> {
         let poster_file = season.and_then(|x| x.poster_path.clone());
 
         if let Some(x) = poster_file.as_ref() {
-            let _ = insert_into_queue(x.clone(), 2).await;
+            let _ = insert_into_queue(&mut *tx, x.clone(), 2).await;
         }
 
-        let season_poster = match poster_file {
-            Some(path) => {
+        let season_poster = match season.and_then(|x| x.poster_file.clone()) {
+            Some(file) => {
                 let asset = InsertableAsset {
-                    remote_url: Some(path),
-                    local_path: format_path(season.and_then(|x| x.poster_file.clone())),
-                    file_ext: "jpg".into(),
+                    remote_url: poster_file,
+                    file_ext: asset_ext(&file),
+                    local_path: format_path(Some(file)),
                 }
                 .insert(&mut *tx)
                 .await;
@@ -264,15 +290,15 @@ impl<'a> TvShowMatcher<'a> {
         let still = search_ep.as_ref().and_then(|x| x.still.clone());
 
         if let Some(x) = still.as_ref() {
-            let _ = insert_into_queue(x.clone(), 1).await;
+            let _ = insert_into_queue(&mut *tx, x.clone(), 1).await;
         }
 
-        let backdrop = match still {
-            Some(path) => {
+        let backdrop = match search_ep.and_then(|x| x.still_file.clone()) {
+            Some(file) => {
                 let asset = InsertableAsset {
-                    remote_url: Some(path),
-                    local_path: format_path(search_ep.and_then(|x| x.still_file.clone()).clone()),
-                    file_ext: "jpg".into(),
+                    remote_url: still,
+                    file_ext: asset_ext(&file),
+                    local_path: format_path(Some(file)),
                 }
                 .insert(&mut *tx)
                 .await;
@@ -316,6 +342,8 @@ impl<'a> TvShowMatcher<'a> {
                     .map(|x| x.overview.clone())
                     .unwrap_or_default(),
                 backdrop,
+                // episodes arent rated separately, they inherit the certification of the show.
+                certification: media.certification.clone(),
                 ..Default::default()
             },
         };
@@ -365,6 +393,6 @@ impl<'a> TvShowMatcher<'a> {
             event_type: PushEventType::EventNewCard { lib_id },
         };
 
-        let _ = self.event_tx.send(serde_json::to_string(&event).unwrap());
+        let _ = self.event_tx.send(Envelope::library(lib_id, event));
     }
 }
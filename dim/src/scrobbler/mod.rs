@@ -0,0 +1,906 @@
+//! Scrobblers report what users are watching to external services. Playback events are queued
+//! by [`ScrobbleDispatcher`], resolved against the database and then sent to every sink the user
+//! has configured. Every sink gets its own delivery task so a slow service doesnt hold up the
+//! others. Deliveries that fail because the service is unreachable are retried with a backoff.
+
+use crate::core::DbConnection;
+
+use database::episode::Episode;
+use database::library::MediaType;
+use database::media::Media;
+use database::mediafile::MediaFile;
+use database::progress::Progress;
+use database::scrobbler::Scrobbler;
+use database::season::Season;
+use database::user::User;
+use database::watch_history::SESSION_GAP;
+
+use async_trait::async_trait;
+use auth::Permission;
+use auth::Role;
+use chrono::Utc;
+use err_derive::Error;
+use serde::Serialize;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+pub mod trakt;
+pub mod webhook;
+
+/// How long a single request to a sink may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay before the first retry of a failed delivery, doubled on every further attempt.
+const RETRY_BASE: Duration = Duration::from_secs(30);
+/// Number of times a delivery is attempted before it is dropped.
+const MAX_ATTEMPTS: u32 = 5;
+/// Number of deliveries kept for retrying, the oldest ones are dropped first.
+const MAX_QUEUED: usize = 1000;
+/// Time after which a playback session we havent heard from is considered over.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(SESSION_GAP as u64);
+/// Number of tracked playback states or streams after which we start dropping the ones of ended
+/// sessions.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// The playback state a event reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrobbleKind {
+    Start,
+    Pause,
+    Stop,
+    /// The media was played through, sent instead of `Stop` at the end of a session.
+    Complete,
+}
+
+/// Information about the show a episode belongs to.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScrobbleShow {
+    pub id: i64,
+    pub name: String,
+    pub year: Option<i64>,
+}
+
+/// The media a event is about.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScrobbleMedia {
+    pub id: i64,
+    pub name: String,
+    pub year: Option<i64>,
+    pub media_type: MediaType,
+    /// Duration in seconds, `0` if unknown.
+    pub duration: i64,
+    /// Only set for episodes.
+    pub show: Option<ScrobbleShow>,
+    pub season: Option<i64>,
+    pub episode: Option<i64>,
+}
+
+/// A playback event as it is sent to sinks.
+#[derive(Clone, Debug, Serialize)]
+pub struct ScrobbleEvent {
+    pub kind: ScrobbleKind,
+    pub username: String,
+    pub media: ScrobbleMedia,
+    /// Playback position in seconds.
+    pub position: i64,
+    /// Playback position as a percentage of the duration, `0` if the duration is unknown.
+    pub progress: f64,
+    /// Timestamp of when the event happened.
+    pub timestamp: i64,
+}
+
+/// The types of sinks a user can configure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkKind {
+    /// Posts every event as json to a url.
+    Webhook,
+    /// Reports playback to a Trakt compatible api.
+    Trakt,
+}
+
+impl SinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Webhook => "webhook",
+            Self::Trakt => "trakt",
+        }
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "webhook" => Ok(Self::Webhook),
+            "trakt" => Ok(Self::Trakt),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error(display = "The request failed: {}", _0)]
+    Request(#[error(source)] reqwest::Error),
+    #[error(display = "The service responded with {}", _0)]
+    Status(reqwest::StatusCode),
+    #[error(display = "The scrobbler is misconfigured: {}", _0)]
+    Misconfigured(&'static str),
+}
+
+impl SinkError {
+    /// Returns whether the delivery might succeed if attempted again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Request(_) => true,
+            Self::Status(status) => {
+                status.is_server_error()
+                    || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    || *status == reqwest::StatusCode::REQUEST_TIMEOUT
+            }
+            Self::Misconfigured(_) => false,
+        }
+    }
+}
+
+/// A external service playback events are delivered to.
+#[async_trait]
+pub trait ScrobbleSink: Send + Sync {
+    async fn scrobble(&self, event: &ScrobbleEvent) -> Result<(), SinkError>;
+}
+
+/// Function builds the sink described by a user's scrobbler configuration.
+pub fn sink_for(
+    client: &reqwest::Client,
+    config: &Scrobbler,
+) -> Result<Box<dyn ScrobbleSink>, SinkError> {
+    let kind = config
+        .kind
+        .parse::<SinkKind>()
+        .map_err(|_| SinkError::Misconfigured("unknown kind"))?;
+
+    Ok(match kind {
+        SinkKind::Webhook => Box::new(webhook::WebhookSink::new(
+            client.clone(),
+            config.url.clone(),
+            config.token.clone(),
+        )),
+        SinkKind::Trakt => Box::new(trakt::TraktSink::new(
+            client.clone(),
+            config.url.clone(),
+            config
+                .client_id
+                .clone()
+                .ok_or(SinkError::Misconfigured("missing client id"))?,
+            config
+                .token
+                .clone()
+                .ok_or(SinkError::Misconfigured("missing token"))?,
+        )),
+    })
+}
+
+/// Function returns whether `ip` can be reached from the internet. Loopback, private, link-local
+/// and otherwise reserved addresses are not.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // shared address space used for carrier-grade NAT.
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+
+            let first = ip.segments()[0];
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local addresses.
+                || (first & 0xfe00) == 0xfc00
+                // link-local addresses.
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Function resolves the host of `url` and returns whether every address it resolves to can be
+/// reached from the internet. Hosts which dont resolve are not considered public.
+pub async fn is_public_url(url: &reqwest::Url) -> bool {
+    resolve_public(url).await.is_some()
+}
+
+/// Function returns a client which only connects to the addresses the host of `url` currently
+/// resolves to, so that the host cant be pointed at a internal service once it was checked.
+/// Returns `None` if the host doesnt resolve or any of its addresses isnt public.
+pub async fn public_client(url: &reqwest::Url, timeout: Duration) -> Option<reqwest::Client> {
+    let (host, addrs) = resolve_public(url).await?;
+
+    client_builder(timeout)
+        .resolve_to_addrs(&host, &addrs)
+        .build()
+        .ok()
+}
+
+/// Function returns a builder for clients sending requests to urls supplied by users. Redirects
+/// arent followed as they could lead anywhere, including to internal services.
+pub fn client_builder(timeout: Duration) -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+}
+
+async fn resolve_public(url: &reqwest::Url) -> Option<(String, Vec<SocketAddr>)> {
+    let host = url
+        .host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let addrs = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .ok()?
+        .collect::<Vec<_>>();
+
+    if addrs.is_empty() || !addrs.iter().all(|x| is_public_ip(x.ip())) {
+        return None;
+    }
+
+    Some((host, addrs))
+}
+
+/// A event which failed to be delivered to a scrobbler.
+struct Retry {
+    scrobbler: i64,
+    event: ScrobbleEvent,
+    attempts: u32,
+    due: Instant,
+}
+
+/// Deliveries waiting to be attempted again, ordered by when they were queued.
+#[derive(Default)]
+struct RetryQueue {
+    queue: VecDeque<Retry>,
+}
+
+impl RetryQueue {
+    /// Queues a delivery which failed for the `attempts` time. Returns `false` if the delivery
+    /// has been attempted too many times and was dropped.
+    fn push(&mut self, scrobbler: i64, event: ScrobbleEvent, attempts: u32, now: Instant) -> bool {
+        if attempts >= MAX_ATTEMPTS {
+            return false;
+        }
+
+        if self.queue.len() >= MAX_QUEUED {
+            self.queue.pop_front();
+        }
+
+        self.queue.push_back(Retry {
+            scrobbler,
+            event,
+            attempts,
+            due: now + RETRY_BASE * 2u32.pow(attempts - 1),
+        });
+
+        true
+    }
+
+    /// Removes and returns all the deliveries which are due.
+    fn take_due(&mut self, now: Instant) -> Vec<Retry> {
+        let (due, pending): (VecDeque<_>, VecDeque<_>) =
+            self.queue.drain(..).partition(|x| x.due <= now);
+        self.queue = pending;
+        due.into()
+    }
+}
+
+/// A playback event as reported by the routes, before it is resolved against the database.
+struct ScrobbleRequest {
+    kind: ScrobbleKind,
+    username: String,
+    media_id: i64,
+    /// Playback position in seconds, looked up from the users progress if not known.
+    position: Option<i64>,
+    timestamp: i64,
+}
+
+/// A running stream.
+struct Stream {
+    username: String,
+    media_id: i64,
+    /// When the stream was started or playback of its media was last reported.
+    last_active: Instant,
+}
+
+#[derive(Default)]
+struct Playing {
+    /// Every running stream, streams which are never stopped are dropped once they have been
+    /// inactive for [`SESSION_TIMEOUT`].
+    streams: HashMap<Uuid, Stream>,
+    /// Last state reported for every media a user is playing and when it was last reported, used
+    /// to drop repeated events.
+    states: HashMap<(String, i64), (ScrobbleKind, Instant)>,
+}
+
+impl Playing {
+    /// Drops the states and streams of sessions we havent heard from in a while.
+    fn prune(&mut self, now: Instant) {
+        self.states
+            .retain(|_, (_, at)| now.duration_since(*at) < SESSION_TIMEOUT);
+        self.streams
+            .retain(|_, x| now.duration_since(x.last_active) < SESSION_TIMEOUT);
+    }
+}
+
+/// Handle used to report playback events, events are delivered in the background.
+#[derive(Clone)]
+pub struct ScrobbleDispatcher {
+    tx: UnboundedSender<ScrobbleRequest>,
+    playing: Arc<Mutex<Playing>>,
+}
+
+impl ScrobbleDispatcher {
+    /// Creates a new dispatcher and spawns the task delivering its events.
+    pub fn new(conn: DbConnection) -> Self {
+        let (tx, rx) = unbounded_channel();
+        let client = client_builder(REQUEST_TIMEOUT).build().unwrap_or_default();
+
+        tokio::spawn(run(conn, client, rx));
+
+        Self {
+            tx,
+            playing: Default::default(),
+        }
+    }
+
+    /// Method reports a change in the playback state of a media. Reporting the same state twice
+    /// in a row is a no-op, which lets clients report their state along with every progress
+    /// update. Once a media has been completed nothing else is reported until the session ends.
+    ///
+    /// # Arguments
+    /// * `kind` - the new playback state.
+    /// * `username` - the user playing the media.
+    /// * `media_id` - id of the media.
+    /// * `position` - playback position in seconds if known.
+    pub fn report(&self, kind: ScrobbleKind, username: &str, media_id: i64, position: Option<i64>) {
+        let now = Instant::now();
+
+        {
+            let mut lock = self.playing.lock().unwrap();
+            let key = (username.to_string(), media_id);
+
+            // states we havent heard about in a while belong to a session that has since ended.
+            let last = lock
+                .states
+                .get(&key)
+                .filter(|(_, at)| now.duration_since(*at) < SESSION_TIMEOUT)
+                .map(|(x, _)| *x);
+
+            let send = match (kind, last) {
+                (_, Some(ScrobbleKind::Complete)) | (ScrobbleKind::Stop, None) => false,
+                (kind, last) => last != Some(kind),
+            };
+
+            if kind == ScrobbleKind::Stop {
+                lock.states.remove(&key);
+            } else if last == Some(ScrobbleKind::Complete) {
+                lock.states.insert(key, (ScrobbleKind::Complete, now));
+            } else {
+                lock.states.insert(key, (kind, now));
+            }
+
+            for stream in lock.streams.values_mut() {
+                if stream.username == username && stream.media_id == media_id {
+                    stream.last_active = now;
+                }
+            }
+
+            if lock.states.len() > PRUNE_THRESHOLD || lock.streams.len() > PRUNE_THRESHOLD {
+                lock.prune(now);
+            }
+
+            if !send {
+                return;
+            }
+        }
+
+        let _ = self.tx.send(ScrobbleRequest {
+            kind,
+            username: username.to_string(),
+            media_id,
+            position,
+            timestamp: Utc::now().timestamp(),
+        });
+    }
+
+    /// Method reports that a user started streaming a media.
+    ///
+    /// # Arguments
+    /// * `gid` - id of the stream.
+    /// * `username` - the user streaming.
+    /// * `media_id` - id of the media being streamed.
+    pub fn stream_started(&self, gid: Uuid, username: &str, media_id: i64) {
+        self.playing.lock().unwrap().streams.insert(
+            gid,
+            Stream {
+                username: username.to_string(),
+                media_id,
+                last_active: Instant::now(),
+            },
+        );
+
+        self.report(ScrobbleKind::Start, username, media_id, None);
+    }
+
+    /// Method reports that a stream has been stopped.
+    ///
+    /// # Arguments
+    /// * `gid` - id of the stream.
+    pub fn stream_stopped(&self, gid: &Uuid) {
+        let stream = self.playing.lock().unwrap().streams.remove(gid);
+
+        if let Some(stream) = stream {
+            self.report(ScrobbleKind::Stop, &stream.username, stream.media_id, None);
+        }
+    }
+}
+
+/// A event to be delivered to a scrobbler.
+struct Delivery {
+    config: Scrobbler,
+    event: ScrobbleEvent,
+    /// Number of times the delivery has already been attempted.
+    attempts: u32,
+    /// Whether the scrobbler may point at a private address, see [`may_use_private`].
+    allow_private: bool,
+}
+
+/// Function delivers events sent through `rx` until every dispatcher has been dropped.
+async fn run(
+    conn: DbConnection,
+    client: reqwest::Client,
+    mut rx: UnboundedReceiver<ScrobbleRequest>,
+) {
+    let retries = Arc::new(Mutex::new(RetryQueue::default()));
+    let mut sinks = HashMap::new();
+    let mut interval = tokio::time::interval(RETRY_BASE / 2);
+
+    loop {
+        tokio::select! {
+            request = rx.recv() => {
+                let request = match request {
+                    Some(x) => x,
+                    None => break,
+                };
+
+                match dispatch(&conn, request).await {
+                    Ok(deliveries) => {
+                        for delivery in deliveries {
+                            send(&mut sinks, &client, &retries, delivery);
+                        }
+                    }
+                    Err(e) => warn!(reason = ?e, "Failed to dispatch a scrobble."),
+                }
+            }
+            _ = interval.tick() => {
+                let due = retries.lock().unwrap().take_due(Instant::now());
+
+                for retry in due {
+                    if let Some(delivery) = redeliver(&conn, retry).await {
+                        send(&mut sinks, &client, &retries, delivery);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Function hands a delivery to the task of its scrobbler, spawning the task if needed. Events
+/// are delivered to a scrobbler in the order they were sent.
+fn send(
+    sinks: &mut HashMap<i64, UnboundedSender<Delivery>>,
+    client: &reqwest::Client,
+    retries: &Arc<Mutex<RetryQueue>>,
+    delivery: Delivery,
+) {
+    let id = delivery.config.id;
+
+    if !matches!(sinks.get(&id), Some(x) if !x.is_closed()) {
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(run_sink(client.clone(), retries.clone(), rx));
+        sinks.insert(id, tx);
+    }
+
+    let _ = sinks[&id].send(delivery);
+}
+
+/// Function delivers the events of a single scrobbler one after the other.
+async fn run_sink(
+    client: reqwest::Client,
+    retries: Arc<Mutex<RetryQueue>>,
+    mut rx: UnboundedReceiver<Delivery>,
+) {
+    while let Some(delivery) = rx.recv().await {
+        deliver(&client, &retries, delivery).await;
+    }
+}
+
+async fn dispatch(
+    conn: &DbConnection,
+    request: ScrobbleRequest,
+) -> Result<Vec<Delivery>, database::DatabaseError> {
+    let mut tx = conn.read().begin().await?;
+
+    let scrobblers = Scrobbler::get_enabled_of_user(&mut tx, &request.username).await?;
+    if scrobblers.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let allow_private = may_use_private(&mut tx, &request.username).await;
+    let event = build_event(&mut tx, request).await?;
+
+    Ok(scrobblers
+        .into_iter()
+        .map(|config| Delivery {
+            config,
+            event: event.clone(),
+            attempts: 0,
+            allow_private,
+        })
+        .collect())
+}
+
+async fn redeliver(conn: &DbConnection, retry: Retry) -> Option<Delivery> {
+    let mut tx = conn.read().begin().await.ok()?;
+    let config = Scrobbler::get(&mut tx, retry.scrobbler).await;
+
+    // scrobblers which have been removed or disabled in the meantime dont get retried.
+    match config {
+        Ok(config) if config.enabled => Some(Delivery {
+            allow_private: may_use_private(&mut tx, &config.username).await,
+            config,
+            event: retry.event,
+            attempts: retry.attempts,
+        }),
+        _ => None,
+    }
+}
+
+/// Function returns whether the scrobblers of `username` may point at private addresses. Only
+/// users who are allowed to change the server settings can set those up.
+async fn may_use_private(tx: &mut database::Transaction<'_>, username: &str) -> bool {
+    match User::get(&mut *tx, username).await {
+        Ok(user) => user
+            .roles
+            .iter()
+            .filter_map(|x| x.parse::<Role>().ok())
+            .any(|x| x.permissions().contains(&Permission::ManageSettings)),
+        Err(_) => false,
+    }
+}
+
+async fn deliver(client: &reqwest::Client, retries: &Mutex<RetryQueue>, delivery: Delivery) {
+    let Delivery {
+        config,
+        event,
+        attempts,
+        allow_private,
+    } = delivery;
+
+    // the address is checked again on every delivery as the host might resolve to something else
+    // by now.
+    let client = match reqwest::Url::parse(&config.url) {
+        Ok(_) if allow_private => Some(client.clone()),
+        Ok(url) => public_client(&url, REQUEST_TIMEOUT).await,
+        Err(_) => None,
+    };
+
+    let result = match client.map(|x| sink_for(&x, &config)) {
+        Some(Ok(sink)) => sink.scrobble(&event).await,
+        Some(Err(e)) => Err(e),
+        None => Err(SinkError::Misconfigured("url must point to a public host")),
+    };
+
+    if let Err(e) = result {
+        let queued = e.is_retryable()
+            && retries
+                .lock()
+                .unwrap()
+                .push(config.id, event, attempts + 1, Instant::now());
+
+        warn!(
+            scrobbler = config.id,
+            reason = %e,
+            retrying = queued,
+            "Failed to deliver a scrobble."
+        );
+    }
+}
+
+async fn build_event(
+    tx: &mut database::Transaction<'_>,
+    request: ScrobbleRequest,
+) -> Result<ScrobbleEvent, database::DatabaseError> {
+    let media = Media::get(&mut *tx, request.media_id).await?;
+    let duration = MediaFile::get_largest_duration(&mut *tx, media.id)
+        .await
+        .unwrap_or(0);
+
+    let position = match request.position {
+        Some(x) => x,
+        None => {
+            Progress::get_for_media_user(&mut *tx, request.username.clone(), media.id)
+                .await?
+                .delta
+        }
+    };
+
+    let (show, season, episode) = if media.media_type == MediaType::Episode {
+        let episode = Episode::get_by_id(&mut *tx, media.id).await?;
+        let season = Season::get_by_id(&mut *tx, episode.seasonid).await?;
+        let show = Media::get(&mut *tx, season.tvshowid).await?;
+
+        (
+            Some(ScrobbleShow {
+                id: show.id,
+                name: show.name,
+                year: show.year,
+            }),
+            Some(season.season_number),
+            Some(episode.episode),
+        )
+    } else {
+        (None, None, None)
+    };
+
+    let progress = if duration > 0 {
+        (position as f64 / duration as f64 * 100.0).clamp(0.0, 100.0)
+    } else {
+        0.0
+    };
+
+    Ok(ScrobbleEvent {
+        kind: request.kind,
+        username: request.username,
+        media: ScrobbleMedia {
+            id: media.id,
+            name: media.name,
+            year: media.year,
+            media_type: media.media_type,
+            duration,
+            show,
+            season,
+            episode,
+        },
+        position,
+        progress,
+        timestamp: request.timestamp,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use warp::http::HeaderMap;
+    use warp::http::StatusCode;
+    use warp::Filter;
+
+    use std::net::SocketAddr;
+
+    /// Function starts a local http server standing in for a external service. Every request it
+    /// receives is sent through the returned channel and answered with `status`.
+    pub(crate) fn stand_in(
+        status: StatusCode,
+    ) -> (
+        SocketAddr,
+        UnboundedReceiver<(String, HeaderMap, serde_json::Value)>,
+    ) {
+        let (tx, rx) = unbounded_channel();
+
+        let route = warp::path::full()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::json())
+            .map(move |path: warp::path::FullPath, headers, body| {
+                let _ = tx.send((path.as_str().to_string(), headers, body));
+                warp::reply::with_status(warp::reply(), status)
+            });
+
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (addr, rx)
+    }
+
+    pub(crate) fn movie_event(kind: ScrobbleKind) -> ScrobbleEvent {
+        ScrobbleEvent {
+            kind,
+            username: "test".into(),
+            media: ScrobbleMedia {
+                id: 1,
+                name: "Blade Runner".into(),
+                year: Some(1982),
+                media_type: MediaType::Movie,
+                duration: 7020,
+                show: None,
+                season: None,
+                episode: None,
+            },
+            position: 3510,
+            progress: 50.0,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let mut queue = RetryQueue::default();
+        let now = Instant::now();
+
+        assert!(queue.push(1, movie_event(ScrobbleKind::Stop), 1, now));
+        assert!(queue.push(2, movie_event(ScrobbleKind::Stop), 2, now));
+        assert!(!queue.push(3, movie_event(ScrobbleKind::Stop), MAX_ATTEMPTS, now));
+
+        assert!(queue.take_due(now).is_empty());
+
+        let due = queue.take_due(now + RETRY_BASE);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].scrobbler, 1);
+
+        let due = queue.take_due(now + RETRY_BASE * 2);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].scrobbler, 2);
+    }
+
+    #[test]
+    fn drops_repeated_states() {
+        let (tx, mut rx) = unbounded_channel();
+        let dispatcher = ScrobbleDispatcher {
+            tx,
+            playing: Default::default(),
+        };
+
+        let mut sent = || {
+            let mut kinds = vec![];
+            while let Ok(x) = rx.try_recv() {
+                kinds.push(x.kind);
+            }
+            kinds
+        };
+
+        // stopping something that isnt playing is ignored.
+        dispatcher.report(ScrobbleKind::Stop, "test", 1, None);
+        assert!(sent().is_empty());
+
+        dispatcher.report(ScrobbleKind::Start, "test", 1, Some(0));
+        dispatcher.report(ScrobbleKind::Start, "test", 1, Some(10));
+        dispatcher.report(ScrobbleKind::Pause, "test", 1, Some(20));
+        dispatcher.report(ScrobbleKind::Start, "test", 1, Some(20));
+        assert_eq!(
+            sent(),
+            vec![
+                ScrobbleKind::Start,
+                ScrobbleKind::Pause,
+                ScrobbleKind::Start
+            ]
+        );
+
+        // nothing but the completion is reported until the session is stopped.
+        dispatcher.report(ScrobbleKind::Complete, "test", 1, Some(95));
+        dispatcher.report(ScrobbleKind::Start, "test", 1, Some(99));
+        dispatcher.report(ScrobbleKind::Stop, "test", 1, Some(100));
+        assert_eq!(sent(), vec![ScrobbleKind::Complete]);
+
+        let gid = Uuid::new_v4();
+        dispatcher.stream_started(gid, "test", 1);
+        dispatcher.stream_stopped(&gid);
+        assert_eq!(sent(), vec![ScrobbleKind::Start, ScrobbleKind::Stop]);
+    }
+
+    #[test]
+    fn drops_inactive_streams() {
+        let now = Instant::now();
+        let mut playing = Playing::default();
+
+        playing.streams.insert(
+            Uuid::new_v4(),
+            Stream {
+                username: "test".into(),
+                media_id: 1,
+                last_active: now,
+            },
+        );
+
+        playing.prune(now + SESSION_TIMEOUT / 2);
+        assert_eq!(playing.streams.len(), 1);
+
+        playing.prune(now + SESSION_TIMEOUT);
+        assert!(playing.streams.is_empty());
+    }
+
+    #[test]
+    fn public_ips() {
+        assert!(is_public_ip("1.1.1.1".parse().unwrap()));
+        assert!(is_public_ip("2606:4700:4700::1111".parse().unwrap()));
+
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn private_targets() {
+        for url in ["http://127.0.0.1:1/", "http://localhost/", "http://[::1]/"] {
+            let url = reqwest::Url::parse(url).unwrap();
+            assert!(
+                public_client(&url, REQUEST_TIMEOUT).await.is_none(),
+                "{}",
+                url
+            );
+        }
+
+        let route = warp::path!("redirect")
+            .map(|| warp::redirect::temporary(warp::http::Uri::from_static("http://127.0.0.1:1/")));
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        // redirects are handed back rather than followed.
+        let client = client_builder(REQUEST_TIMEOUT).build().unwrap();
+        let response = client
+            .get(format!("http://{}/redirect", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(SinkError::Status(reqwest::StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(SinkError::Status(reqwest::StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!SinkError::Status(reqwest::StatusCode::UNAUTHORIZED).is_retryable());
+        assert!(!SinkError::Misconfigured("missing token").is_retryable());
+    }
+}
@@ -0,0 +1,179 @@
+use super::ScrobbleEvent;
+use super::ScrobbleKind;
+use super::ScrobbleSink;
+use super::SinkError;
+
+use database::library::MediaType;
+
+use async_trait::async_trait;
+use serde_json::json;
+use serde_json::Value;
+
+/// Base url of the Trakt api, used unless the user points the sink at a compatible service.
+pub const DEFAULT_URL: &str = "https://api.trakt.tv";
+const API_VERSION: &str = "2";
+
+/// Sink which reports playback to the scrobble endpoints of a Trakt compatible api. Media are
+/// identified by their title and year as we dont store the ids Trakt knows them by.
+pub struct TraktSink {
+    client: reqwest::Client,
+    url: String,
+    client_id: String,
+    token: String,
+}
+
+impl TraktSink {
+    pub fn new(client: reqwest::Client, url: String, client_id: String, token: String) -> Self {
+        Self {
+            client,
+            url,
+            client_id,
+            token,
+        }
+    }
+}
+
+/// Function builds the body of a scrobble request, returns `None` for media Trakt doesnt track.
+fn scrobble_body(event: &ScrobbleEvent) -> Option<Value> {
+    let media = &event.media;
+
+    match media.media_type {
+        MediaType::Movie => Some(json!({
+            "movie": {
+                "title": media.name,
+                "year": media.year,
+            },
+            "progress": event.progress,
+        })),
+        MediaType::Episode => {
+            let show = media.show.as_ref()?;
+
+            Some(json!({
+                "show": {
+                    "title": show.name,
+                    "year": show.year,
+                },
+                "episode": {
+                    "season": media.season?,
+                    "number": media.episode?,
+                },
+                "progress": event.progress,
+            }))
+        }
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl ScrobbleSink for TraktSink {
+    async fn scrobble(&self, event: &ScrobbleEvent) -> Result<(), SinkError> {
+        let body = match scrobble_body(event) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        // trakt marks a media as watched when it is stopped past 80%.
+        let action = match event.kind {
+            ScrobbleKind::Start => "start",
+            ScrobbleKind::Pause => "pause",
+            ScrobbleKind::Stop | ScrobbleKind::Complete => "stop",
+        };
+
+        let response = self
+            .client
+            .post(format!(
+                "{}/scrobble/{}",
+                self.url.trim_end_matches('/'),
+                action
+            ))
+            .header("trakt-api-version", API_VERSION)
+            .header("trakt-api-key", &self.client_id)
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()
+            .await
+            .map_err(SinkError::Request)?;
+
+        // trakt responds with a conflict if the media has just been scrobbled already.
+        let status = response.status();
+        if !status.is_success() && status != reqwest::StatusCode::CONFLICT {
+            return Err(SinkError::Status(status));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrobbler::tests::movie_event;
+    use crate::scrobbler::tests::stand_in;
+    use crate::scrobbler::ScrobbleShow;
+
+    use warp::http::StatusCode;
+
+    fn sink(addr: std::net::SocketAddr) -> TraktSink {
+        TraktSink::new(
+            reqwest::Client::new(),
+            format!("http://{}/", addr),
+            "client".into(),
+            "token".into(),
+        )
+    }
+
+    #[tokio::test]
+    async fn scrobbles_movie() {
+        let (addr, mut requests) = stand_in(StatusCode::CREATED);
+
+        sink(addr)
+            .scrobble(&movie_event(ScrobbleKind::Complete))
+            .await
+            .unwrap();
+
+        let (path, headers, body) = requests.recv().await.unwrap();
+        assert_eq!(path, "/scrobble/stop");
+        assert_eq!(headers["trakt-api-key"], "client");
+        assert_eq!(headers["trakt-api-version"], API_VERSION);
+        assert_eq!(headers["authorization"], "Bearer token");
+        assert_eq!(body["movie"]["title"], "Blade Runner");
+        assert_eq!(body["movie"]["year"], 1982);
+        assert_eq!(body["progress"], 50.0);
+    }
+
+    #[tokio::test]
+    async fn scrobbles_episode() {
+        let (addr, mut requests) = stand_in(StatusCode::CONFLICT);
+
+        let mut event = movie_event(ScrobbleKind::Pause);
+        event.media.media_type = MediaType::Episode;
+        event.media.name = "Pilot".into();
+        event.media.show = Some(ScrobbleShow {
+            id: 2,
+            name: "Twin Peaks".into(),
+            year: Some(1990),
+        });
+        event.media.season = Some(1);
+        event.media.episode = Some(1);
+
+        // a conflict means the episode was already scrobbled which isnt a failure.
+        sink(addr).scrobble(&event).await.unwrap();
+
+        let (path, _, body) = requests.recv().await.unwrap();
+        assert_eq!(path, "/scrobble/pause");
+        assert_eq!(body["show"]["title"], "Twin Peaks");
+        assert_eq!(body["episode"]["season"], 1);
+        assert_eq!(body["episode"]["number"], 1);
+    }
+
+    #[tokio::test]
+    async fn skips_music() {
+        let (addr, mut requests) = stand_in(StatusCode::CREATED);
+
+        let mut event = movie_event(ScrobbleKind::Start);
+        event.media.media_type = MediaType::Music;
+
+        sink(addr).scrobble(&event).await.unwrap();
+        assert!(requests.try_recv().is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use super::ScrobbleEvent;
+use super::ScrobbleSink;
+use super::SinkError;
+
+use async_trait::async_trait;
+
+/// Sink which posts every event as json to a url. If a token is configured it is sent as a
+/// bearer token so that the receiving end can authenticate us.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl WebhookSink {
+    pub fn new(client: reqwest::Client, url: String, token: Option<String>) -> Self {
+        Self { client, url, token }
+    }
+}
+
+#[async_trait]
+impl ScrobbleSink for WebhookSink {
+    async fn scrobble(&self, event: &ScrobbleEvent) -> Result<(), SinkError> {
+        let mut request = self.client.post(&self.url).json(event);
+
+        if let Some(token) = self.token.as_ref() {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await.map_err(SinkError::Request)?;
+
+        if !response.status().is_success() {
+            return Err(SinkError::Status(response.status()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrobbler::tests::movie_event;
+    use crate::scrobbler::tests::stand_in;
+    use crate::scrobbler::ScrobbleKind;
+
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn posts_event() {
+        let (addr, mut requests) = stand_in(StatusCode::OK);
+        let sink = WebhookSink::new(
+            reqwest::Client::new(),
+            format!("http://{}/hook", addr),
+            Some("secret".into()),
+        );
+
+        sink.scrobble(&movie_event(ScrobbleKind::Start))
+            .await
+            .unwrap();
+
+        let (path, headers, body) = requests.recv().await.unwrap();
+        assert_eq!(path, "/hook");
+        assert_eq!(headers["authorization"], "Bearer secret");
+        assert_eq!(body["kind"], "start");
+        assert_eq!(body["media"]["name"], "Blade Runner");
+        assert_eq!(body["position"], 3510);
+    }
+
+    #[tokio::test]
+    async fn reports_failures() {
+        let (addr, _requests) = stand_in(StatusCode::SERVICE_UNAVAILABLE);
+        let sink = WebhookSink::new(reqwest::Client::new(), format!("http://{}/", addr), None);
+
+        let err = sink
+            .scrobble(&movie_event(ScrobbleKind::Stop))
+            .await
+            .unwrap_err();
+        assert!(err.is_retryable());
+    }
+}
@@ -7,14 +7,21 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use serde::Serialize;
+use std::fmt::Write;
 use xmlwriter::*;
 
-#[derive(Debug, Clone, Copy, Serialize)]
+/// Prefix under which all stream data is served. Used as the `BaseURL` for DASH and as the prefix
+/// for the absolute URIs we write into HLS playlists.
+const STREAM_BASE: &str = "/api/v1/stream/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ContentType {
     Video,
     Audio,
     Subtitle,
+    /// Thumbnail sprite sheets used for seek previews.
+    Image,
 }
 
 impl std::fmt::Display for ContentType {
@@ -26,6 +33,7 @@ impl std::fmt::Display for ContentType {
                 ContentType::Audio => "audio",
                 ContentType::Subtitle => "subtitle",
                 ContentType::Video => "video",
+                ContentType::Image => "image",
             }
         )
     }
@@ -49,6 +57,9 @@ pub struct VirtualManifest {
     pub label: String,
     pub lang: Option<String>,
     pub target_duration: u32,
+    /// Number of columns and rows of thumbnails in a sheet of a image track.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiles: Option<(i64, i64)>,
 }
 
 impl VirtualManifest {
@@ -74,6 +85,7 @@ impl VirtualManifest {
             label: String::new(),
             lang: None,
             target_duration: 5,
+            tiles: None,
         }
     }
 
@@ -143,9 +155,15 @@ impl VirtualManifest {
         self
     }
 
+    pub fn set_tiles(mut self, columns: i64, rows: i64) -> Self {
+        self.tiles = Some((columns, rows));
+        self
+    }
+
     pub fn compile(&self, w: &mut XmlWriter, start_num: u64) {
         match self.content_type {
             ContentType::Subtitle => self.compile_sub(w),
+            ContentType::Image => self.compile_image(w),
             _ => self.compile_av(w, start_num),
         }
     }
@@ -213,6 +231,135 @@ impl VirtualManifest {
         }
     }
 
+    /// Writes the `#EXT-X-MEDIA` tag describing this track within the HLS master playlist. Only
+    /// audio and subtitle tracks are renditions, video tracks are written as variants by
+    /// [`compile_hls_variant`](Self::compile_hls_variant).
+    fn compile_hls_rendition(&self, gid: &Uuid, is_default: bool) -> String {
+        let (kind, group) = match self.content_type {
+            ContentType::Audio => ("AUDIO", HLS_AUDIO_GROUP),
+            ContentType::Subtitle => ("SUBTITLES", HLS_SUBTITLE_GROUP),
+            ContentType::Video | ContentType::Image => {
+                unreachable!("only audio and subtitle tracks are hls renditions")
+            }
+        };
+
+        let mut tag = format!(
+            "#EXT-X-MEDIA:TYPE={},GROUP-ID=\"{}\",NAME=\"{}\"",
+            kind,
+            group,
+            hls_quote(&self.label)
+        );
+
+        if let Some(lang) = self.lang.as_ref() {
+            let _ = write!(tag, ",LANGUAGE=\"{}\"", hls_quote(lang));
+        }
+
+        let _ = write!(
+            tag,
+            ",DEFAULT={},AUTOSELECT=YES,URI=\"{}\"",
+            if is_default { "YES" } else { "NO" },
+            self.hls_playlist_uri(gid)
+        );
+
+        tag
+    }
+
+    /// Writes the `#EXT-X-STREAM-INF` tag and uri for a video track.
+    fn compile_hls_variant(
+        &self,
+        gid: &Uuid,
+        audio: Option<&VirtualManifest>,
+        has_subtitles: bool,
+    ) -> String {
+        let bandwidth = self.bandwidth + audio.map(|x| x.bandwidth).unwrap_or(0);
+        let codecs = match audio {
+            Some(audio) => format!("{},{}", self.codecs, audio.codecs),
+            None => self.codecs.clone(),
+        };
+
+        let mut tag = format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\"",
+            bandwidth, codecs
+        );
+
+        if audio.is_some() {
+            let _ = write!(tag, ",AUDIO=\"{}\"", HLS_AUDIO_GROUP);
+        }
+
+        if has_subtitles {
+            let _ = write!(tag, ",SUBTITLES=\"{}\"", HLS_SUBTITLE_GROUP);
+        }
+
+        let _ = write!(tag, "\n{}", self.hls_playlist_uri(gid));
+
+        tag
+    }
+
+    /// Compiles the HLS media playlist for this track.
+    ///
+    /// # Arguments
+    /// * `duration` - duration of the whole virtual manifest in seconds.
+    /// * `start_num` - first chunk number.
+    pub fn compile_hls_playlist(&self, duration: u64, start_num: u64) -> String {
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n");
+
+        // subtitles are served as a single webvtt file spanning the whole duration of the stream.
+        if matches!(self.content_type, ContentType::Subtitle) {
+            let _ = writeln!(playlist, "#EXT-X-TARGETDURATION:{}", duration.max(1));
+            playlist.push_str("#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n");
+            let _ = writeln!(playlist, "#EXTINF:{}.000,", duration);
+            let _ = writeln!(playlist, "{}{}", STREAM_BASE, self.chunk_path);
+            playlist.push_str("#EXT-X-ENDLIST\n");
+
+            return playlist;
+        }
+
+        let target_duration = self.target_duration.max(1) as u64;
+
+        let _ = writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target_duration);
+        let _ = writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:{}", start_num);
+        playlist.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+
+        if let Some(init) = self.init_seg.as_ref() {
+            let _ = writeln!(
+                playlist,
+                "#EXT-X-MAP:URI=\"{}{}?start_num={}\"",
+                STREAM_BASE, init, start_num
+            );
+        }
+
+        let first_segment_start = start_num * target_duration;
+        let mut elapsed = first_segment_start;
+        let mut number = start_num;
+
+        while elapsed < duration {
+            let segment_duration = target_duration.min(duration - elapsed);
+            let _ = writeln!(playlist, "#EXTINF:{}.000,", segment_duration);
+            let _ = writeln!(
+                playlist,
+                "{}{}",
+                STREAM_BASE,
+                self.chunk_path.replace("$Number$", &number.to_string())
+            );
+
+            elapsed += segment_duration;
+            number += 1;
+        }
+
+        playlist.push_str("#EXT-X-ENDLIST\n");
+
+        playlist
+    }
+
+    fn hls_playlist_uri(&self, gid: &Uuid) -> String {
+        format!(
+            "{}{}/{}/playlist.m3u8",
+            STREAM_BASE,
+            gid.to_hyphenated(),
+            self.id
+        )
+    }
+
     fn compile_sub(&self, w: &mut XmlWriter) {
         w.start_element("AdapationSet");
         w.write_attribute("mimeType", &self.mime);
@@ -236,6 +383,49 @@ impl VirtualManifest {
         w.end_element();
         w.end_element();
     }
+
+    /// Writes a thumbnail track as described by the DASH-IF IOP, every segment is a sheet of
+    /// thumbnails covering `target_duration` seconds. Sheets dont depend on where playback
+    /// starts, so unlike other tracks they are always numbered from zero.
+    fn compile_image(&self, w: &mut XmlWriter) {
+        w.start_element("AdaptationSet");
+        w.write_attribute("contentType", &self.content_type.to_string());
+        w.write_attribute("id", &self.set_id);
+        w.write_attribute("mimeType", &self.mime);
+
+        w.start_element("SegmentTemplate");
+        w.write_attribute("timescale", &1);
+        w.write_attribute("duration", &self.target_duration);
+        w.write_attribute("media", &self.chunk_path);
+        w.write_attribute("startNumber", &0);
+        w.end_element();
+
+        w.start_element("Representation");
+        w.write_attribute("id", &self.id);
+        w.write_attribute("bandwidth", &self.bandwidth);
+
+        for (k, v) in self.args.iter() {
+            w.write_attribute(k, v);
+        }
+
+        if let Some((columns, rows)) = self.tiles {
+            w.start_element("EssentialProperty");
+            w.write_attribute("schemeIdUri", "http://dashif.org/thumbnail_tile");
+            w.write_attribute("value", &format!("{}x{}", columns, rows));
+            w.end_element();
+        }
+
+        w.end_element();
+        w.end_element();
+    }
+}
+
+const HLS_AUDIO_GROUP: &str = "audio";
+const HLS_SUBTITLE_GROUP: &str = "subs";
+
+/// Quoted-string attributes in m3u8 playlists cannot contain double quotes or line breaks.
+fn hls_quote(s: &str) -> String {
+    s.replace('"', "'").replace(&['\r', '\n'][..], " ")
 }
 
 pub struct StreamTracking {
@@ -318,7 +508,7 @@ impl StreamTracking {
         w.start_element("Period");
         w.write_attribute("duration", &duration);
         w.start_element("BaseURL");
-        w.write_text("/api/v1/stream/");
+        w.write_text(STREAM_BASE);
         w.end_element();
 
         for track in manifests {
@@ -328,6 +518,100 @@ impl StreamTracking {
         Some(w.end_document())
     }
 
+    /// Compiles the virtual manifest for `gid` into a HLS master playlist. Video tracks are
+    /// written as variants while audio and subtitle tracks are written as renditions referenced by
+    /// the variants.
+    pub async fn compile_hls(&self, gid: &Uuid) -> Option<String> {
+        let lock = self.streaming_sessions.read().await;
+        let manifests = lock.get(gid)?;
+
+        let by_type = |content_type: ContentType| {
+            manifests
+                .iter()
+                .filter(move |x| x.content_type == content_type)
+        };
+
+        let mut videos = by_type(ContentType::Video).collect::<Vec<_>>();
+        let audios = by_type(ContentType::Audio).collect::<Vec<_>>();
+        let subtitles = by_type(ContentType::Subtitle).collect::<Vec<_>>();
+
+        if videos.is_empty() && audios.is_empty() {
+            return None;
+        }
+
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+
+        // Only a single rendition within a group may be marked as default.
+        let default_audio = audios
+            .iter()
+            .position(|x| x.is_default)
+            .unwrap_or_default();
+
+        // audio-only streams have no video variants, in which case the audio tracks become the
+        // variants themselves.
+        for (idx, audio) in audios.iter().enumerate().filter(|_| !videos.is_empty()) {
+            let _ = writeln!(
+                playlist,
+                "{}",
+                audio.compile_hls_rendition(gid, idx == default_audio)
+            );
+        }
+
+        let default_subtitle = subtitles.iter().position(|x| x.is_default);
+
+        for (idx, subtitle) in subtitles.iter().enumerate() {
+            let _ = writeln!(
+                playlist,
+                "{}",
+                subtitle.compile_hls_rendition(gid, Some(idx) == default_subtitle)
+            );
+        }
+
+        let primary_audio = audios.get(default_audio).copied();
+
+        // Players pick the first variant listed as the starting rendition, so the default video
+        // track goes first.
+        videos.sort_by_key(|x| !x.is_default);
+
+        for video in videos.iter() {
+            let _ = writeln!(
+                playlist,
+                "{}",
+                video.compile_hls_variant(gid, primary_audio, !subtitles.is_empty())
+            );
+        }
+
+        if videos.is_empty() {
+            for audio in audios.iter() {
+                let _ = writeln!(
+                    playlist,
+                    "{}",
+                    audio.compile_hls_variant(gid, None, !subtitles.is_empty())
+                );
+            }
+        }
+
+        Some(playlist)
+    }
+
+    /// Compiles the HLS media playlist for the track `id` within the virtual manifest `gid`.
+    pub async fn compile_hls_playlist(
+        &self,
+        gid: &Uuid,
+        id: &str,
+        start_num: u64,
+    ) -> Option<String> {
+        let lock = self.streaming_sessions.read().await;
+        let manifests = lock.get(gid)?;
+        let duration = manifests.iter().find_map(|x| x.duration)? as u64;
+
+        manifests
+            .iter()
+            .filter(|x| x.content_type != ContentType::Image)
+            .find(|x| x.id == id)
+            .map(|x| x.compile_hls_playlist(duration, start_num))
+    }
+
     pub async fn compile_only(
         &self,
         gid: &Uuid,
@@ -353,3 +637,197 @@ impl Clone for StreamTracking {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(id: &str, bandwidth: u64, is_default: bool) -> VirtualManifest {
+        VirtualManifest::new(
+            id.into(),
+            format!("{}/data/$Number$.m4s", id),
+            Some(format!("{}/data/init.mp4", id)),
+            ContentType::Video,
+        )
+        .set_mime("video/mp4")
+        .set_duration(Some(12))
+        .set_codecs("avc1.64001f")
+        .set_bandwidth(bandwidth)
+        .set_is_default(is_default)
+    }
+
+    fn audio(id: &str, lang: &str, is_default: bool) -> VirtualManifest {
+        VirtualManifest::new(
+            id.into(),
+            format!("{}/data/$Number$.m4s", id),
+            Some(format!("{}/data/init.mp4", id)),
+            ContentType::Audio,
+        )
+        .set_mime("audio/mp4")
+        .set_duration(Some(12))
+        .set_codecs("mp4a.40.2")
+        .set_bandwidth(128_000)
+        .set_is_default(is_default)
+        .set_label(lang.to_uppercase())
+        .set_lang(Some(lang.into()))
+    }
+
+    fn subtitle(id: &str, label: &str) -> VirtualManifest {
+        VirtualManifest::new(
+            id.into(),
+            format!("{}/data/stream.vtt", id),
+            None,
+            ContentType::Subtitle,
+        )
+        .set_mime("text/vtt")
+        .set_bandwidth(1024)
+        .set_label(label.into())
+        .set_lang(Some("eng".into()))
+    }
+
+    async fn track_all(gid: &Uuid, manifests: Vec<VirtualManifest>) -> StreamTracking {
+        let tracking = StreamTracking::default();
+
+        for manifest in manifests {
+            tracking.insert(gid, manifest).await;
+        }
+
+        tracking
+    }
+
+    #[tokio::test]
+    async fn hls_master_playlist() {
+        let gid = Uuid::nil();
+        let tracking = track_all(
+            &gid,
+            vec![
+                video("low", 1_000_000, false),
+                video("high", 3_000_000, true),
+                audio("eng", "eng", false),
+                audio("fre", "fre", true),
+                subtitle("sub", "English \"SDH\""),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            tracking.compile_hls(&gid).await.unwrap(),
+            concat!(
+                "#EXTM3U\n",
+                "#EXT-X-VERSION:7\n",
+                "#EXT-X-INDEPENDENT-SEGMENTS\n",
+                "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"ENG\",LANGUAGE=\"eng\",",
+                "DEFAULT=NO,AUTOSELECT=YES,",
+                "URI=\"/api/v1/stream/00000000-0000-0000-0000-000000000000/eng/playlist.m3u8\"\n",
+                "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"FRE\",LANGUAGE=\"fre\",",
+                "DEFAULT=YES,AUTOSELECT=YES,",
+                "URI=\"/api/v1/stream/00000000-0000-0000-0000-000000000000/fre/playlist.m3u8\"\n",
+                "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"English 'SDH'\",",
+                "LANGUAGE=\"eng\",DEFAULT=NO,AUTOSELECT=YES,",
+                "URI=\"/api/v1/stream/00000000-0000-0000-0000-000000000000/sub/playlist.m3u8\"\n",
+                "#EXT-X-STREAM-INF:BANDWIDTH=3128000,CODECS=\"avc1.64001f,mp4a.40.2\",",
+                "AUDIO=\"audio\",SUBTITLES=\"subs\"\n",
+                "/api/v1/stream/00000000-0000-0000-0000-000000000000/high/playlist.m3u8\n",
+                "#EXT-X-STREAM-INF:BANDWIDTH=1128000,CODECS=\"avc1.64001f,mp4a.40.2\",",
+                "AUDIO=\"audio\",SUBTITLES=\"subs\"\n",
+                "/api/v1/stream/00000000-0000-0000-0000-000000000000/low/playlist.m3u8\n",
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn hls_audio_only_master_playlist() {
+        let gid = Uuid::nil();
+        let tracking = track_all(&gid, vec![audio("eng", "eng", true)]).await;
+
+        assert_eq!(
+            tracking.compile_hls(&gid).await.unwrap(),
+            concat!(
+                "#EXTM3U\n",
+                "#EXT-X-VERSION:7\n",
+                "#EXT-X-INDEPENDENT-SEGMENTS\n",
+                "#EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\n",
+                "/api/v1/stream/00000000-0000-0000-0000-000000000000/eng/playlist.m3u8\n",
+            )
+        );
+
+        // subtitles alone cant be played.
+        let tracking = track_all(&gid, vec![subtitle("sub", "English")]).await;
+        assert!(tracking.compile_hls(&gid).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn hls_media_playlists() {
+        let gid = Uuid::nil();
+        let tracking = track_all(
+            &gid,
+            vec![
+                video("video", 1_000_000, true),
+                audio("eng", "eng", true),
+                subtitle("sub", "English"),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            tracking
+                .compile_hls_playlist(&gid, "video", 0)
+                .await
+                .unwrap(),
+            concat!(
+                "#EXTM3U\n",
+                "#EXT-X-VERSION:7\n",
+                "#EXT-X-TARGETDURATION:5\n",
+                "#EXT-X-MEDIA-SEQUENCE:0\n",
+                "#EXT-X-PLAYLIST-TYPE:VOD\n",
+                "#EXT-X-INDEPENDENT-SEGMENTS\n",
+                "#EXT-X-MAP:URI=\"/api/v1/stream/video/data/init.mp4?start_num=0\"\n",
+                "#EXTINF:5.000,\n",
+                "/api/v1/stream/video/data/0.m4s\n",
+                "#EXTINF:5.000,\n",
+                "/api/v1/stream/video/data/1.m4s\n",
+                "#EXTINF:2.000,\n",
+                "/api/v1/stream/video/data/2.m4s\n",
+                "#EXT-X-ENDLIST\n",
+            )
+        );
+
+        // playback started at the second segment.
+        assert_eq!(
+            tracking.compile_hls_playlist(&gid, "eng", 1).await.unwrap(),
+            concat!(
+                "#EXTM3U\n",
+                "#EXT-X-VERSION:7\n",
+                "#EXT-X-TARGETDURATION:5\n",
+                "#EXT-X-MEDIA-SEQUENCE:1\n",
+                "#EXT-X-PLAYLIST-TYPE:VOD\n",
+                "#EXT-X-INDEPENDENT-SEGMENTS\n",
+                "#EXT-X-MAP:URI=\"/api/v1/stream/eng/data/init.mp4?start_num=1\"\n",
+                "#EXTINF:5.000,\n",
+                "/api/v1/stream/eng/data/1.m4s\n",
+                "#EXTINF:2.000,\n",
+                "/api/v1/stream/eng/data/2.m4s\n",
+                "#EXT-X-ENDLIST\n",
+            )
+        );
+
+        assert_eq!(
+            tracking.compile_hls_playlist(&gid, "sub", 0).await.unwrap(),
+            concat!(
+                "#EXTM3U\n",
+                "#EXT-X-VERSION:7\n",
+                "#EXT-X-TARGETDURATION:12\n",
+                "#EXT-X-MEDIA-SEQUENCE:0\n",
+                "#EXT-X-PLAYLIST-TYPE:VOD\n",
+                "#EXTINF:12.000,\n",
+                "/api/v1/stream/sub/data/stream.vtt\n",
+                "#EXT-X-ENDLIST\n",
+            )
+        );
+
+        assert!(tracking
+            .compile_hls_playlist(&gid, "missing", 0)
+            .await
+            .is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::process::Command;
 
@@ -17,6 +18,8 @@ struct FFPStream {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Stream {
     pub index: i64,
+    /// Empty for attachments ffmpeg doesnt know the codec of.
+    #[serde(default)]
     pub codec_name: String,
     pub profile: Option<String>,
     pub codec_type: String,
@@ -58,6 +61,22 @@ impl Stream {
     pub fn get_title(&self) -> Option<String> {
         self.tags.as_ref()?.title.clone()
     }
+
+    pub fn is_attached_pic(&self) -> bool {
+        self.disposition
+            .as_ref()
+            .map_or(false, |x| x.attached_pic == 1)
+    }
+
+    /// Whether this stream is a image attached to a matroska file, ie `cover.jpg`.
+    pub fn is_image_attachment(&self) -> bool {
+        self.codec_type == "attachment"
+            && self
+                .tags
+                .as_ref()
+                .and_then(|x| x.mimetype.as_deref())
+                .map_or(false, |x| x.starts_with("image/"))
+    }
 }
 
 impl From<Stream> for nightfall::profiles::InputCtx {
@@ -94,8 +113,13 @@ pub struct Tags {
     statistics_writing_date_utc_eng: Option<String>,
     #[serde(rename = "_STATISTICS_TAGS-eng")]
     statistics_tags_eng: Option<String>,
-    filename: Option<String>,
-    mimetype: Option<String>,
+    /// Name of the attached file, only set on attachments.
+    pub filename: Option<String>,
+    /// Mime type of the attached file, only set on attachments.
+    pub mimetype: Option<String>,
+    /// Any other tags, for instance vorbis comments of ogg streams.
+    #[serde(flatten)]
+    pub other: HashMap<String, String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -109,6 +133,9 @@ struct Format {
     pub duration: String,
     pub size: String,
     pub bit_rate: String,
+    /// Container level tags, ie ID3 tags or MP4 atoms.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
 }
 
 pub struct FFProbeCtx {
@@ -239,6 +266,36 @@ impl FFPWrapper {
             .ok()
     }
 
+    /// Returns the music tags of this file. Tag names differ between containers, so lookups are
+    /// done case insensitively across the container tags and the tags of the primary audio stream.
+    pub fn get_audio_tags(&self) -> AudioTags {
+        let mut tags = HashMap::new();
+
+        if let Some(stream) = self.get_primary("audio").and_then(|x| x.tags.as_ref()) {
+            tags.extend(
+                stream
+                    .other
+                    .iter()
+                    .map(|(k, v)| (k.to_lowercase(), v.clone())),
+            );
+
+            if let Some(title) = stream.title.clone() {
+                tags.insert("title".to_string(), title);
+            }
+        }
+
+        if let Some(ctx) = self.ffpstream.as_ref() {
+            tags.extend(
+                ctx.format
+                    .tags
+                    .iter()
+                    .map(|(k, v)| (k.to_lowercase(), v.clone())),
+            );
+        }
+
+        AudioTags::from_map(tags)
+    }
+
     pub fn is_corrupt(&self) -> Option<bool> {
         Some(self.corrupt.unwrap_or(false))
     }
@@ -247,11 +304,26 @@ impl FFPWrapper {
         Some(!self.find_by_type(codec_type).is_empty())
     }
 
+    /// Returns the cover art embedded into the file, either as a attached picture (mp4, mp3) or
+    /// as a image attachment (mkv).
+    pub fn get_cover_art(&self) -> Vec<&Stream> {
+        if let Some(x) = self.ffpstream.as_ref() {
+            x.streams
+                .iter()
+                .filter(|x| x.is_attached_pic() || x.is_image_attachment())
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Returns all streams of type `codec_type`. Cover art embedded into audio files shows up as a
+    /// video stream, such streams are never returned.
     pub fn find_by_type(&self, codec_type: &str) -> Vec<&Stream> {
         if let Some(x) = self.ffpstream.as_ref() {
             x.streams
                 .iter()
-                .filter(|x| x.codec_type == *codec_type)
+                .filter(|x| x.codec_type == *codec_type && !x.is_attached_pic())
                 .collect()
         } else {
             Vec::new()
@@ -259,6 +331,45 @@ impl FFPWrapper {
     }
 }
 
+/// Music metadata read from the tags of a audio file.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct AudioTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i64>,
+    pub track: Option<i64>,
+    pub disc: Option<i64>,
+}
+
+impl AudioTags {
+    fn from_map(mut tags: HashMap<String, String>) -> Self {
+        let mut take = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|x| tags.remove(*x))
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+        };
+
+        // numbers are usually formatted as `3/12`, dates as `2004` or `2004-05-01`.
+        let number = |x: String| x.split('/').next()?.trim().parse::<i64>().ok();
+        let year = |x: String| x.get(..4)?.parse::<i64>().ok();
+
+        Self {
+            title: take(&["title"]),
+            artist: take(&["artist"]),
+            album_artist: take(&["album_artist", "albumartist", "album artist"]),
+            album: take(&["album"]),
+            genre: take(&["genre"]),
+            year: take(&["date", "year", "originaldate"]).and_then(year),
+            track: take(&["track", "tracknumber"]).and_then(number),
+            disc: take(&["disc", "discnumber"]).and_then(number),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq, Serialize)]
 pub struct Disposition {
     pub default: i64,
@@ -270,4 +381,85 @@ pub struct Disposition {
     pub forced: i64,
     pub hearing_impaired: i64,
     pub visual_impaired: i64,
+    /// Set on video streams which are actually embedded cover art.
+    #[serde(default)]
+    pub attached_pic: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audio_tags() {
+        let tags = vec![
+            ("TITLE", "Harder, Better, Faster, Stronger"),
+            ("ARTIST", "Daft Punk"),
+            ("album", "Discovery"),
+            ("date", "2001-03-12"),
+            ("track", "4/14"),
+            ("disc", "1"),
+            ("genre", " "),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+        .collect();
+
+        let tags = AudioTags::from_map(tags);
+
+        assert_eq!(tags.title.as_deref(), Some("Harder, Better, Faster, Stronger"));
+        assert_eq!(tags.artist.as_deref(), Some("Daft Punk"));
+        assert_eq!(tags.album_artist, None);
+        assert_eq!(tags.year, Some(2001));
+        assert_eq!(tags.track, Some(4));
+        assert_eq!(tags.disc, Some(1));
+        assert_eq!(tags.genre, None);
+    }
+
+    #[test]
+    fn cover_art() {
+        let disposition = r#"{"default": 0, "dub": 0, "original": 0, "comment": 0, "lyrics": 0,
+            "karaoke": 0, "forced": 0, "hearing_impaired": 0, "visual_impaired": 0,
+            "attached_pic": 1}"#;
+
+        let json = format!(
+            r#"{{
+                "streams": [
+                    {{"index": 0, "codec_name": "h264", "codec_type": "video", "width": 1920}},
+                    {{"index": 1, "codec_name": "mjpeg", "codec_type": "video",
+                        "disposition": {}}},
+                    {{"index": 2, "codec_type": "attachment",
+                        "tags": {{"filename": "cover.jpg", "mimetype": "image/jpeg"}}}},
+                    {{"index": 3, "codec_name": "ttf", "codec_type": "attachment",
+                        "tags": {{"filename": "font.ttf", "mimetype": "font/ttf"}}}}
+                ],
+                "format": {{
+                    "filename": "movie.mkv", "nb_streams": 4, "nb_programs": 0,
+                    "format_name": "matroska,webm", "format_long_name": "Matroska / WebM",
+                    "start_time": "0.000000", "duration": "5400.000000", "size": "1024",
+                    "bit_rate": "8000"
+                }}
+            }}"#,
+            disposition
+        );
+
+        let probe = FFPWrapper {
+            ffpstream: Some(serde_json::from_str(&json).unwrap()),
+            corrupt: None,
+        };
+
+        let covers = probe.get_cover_art();
+        assert_eq!(
+            covers.iter().map(|x| x.index).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            covers[1].tags.as_ref().unwrap().filename.as_deref(),
+            Some("cover.jpg")
+        );
+
+        // cover art never counts as the video stream.
+        assert_eq!(probe.find_by_type("video").len(), 1);
+        assert_eq!(probe.get_width(), Some(1920));
+    }
 }
@@ -0,0 +1,150 @@
+use crate::errors::DimError;
+use crate::routes::admin;
+use crate::tests::auth;
+use crate::tests::insert_user;
+use crate::tests::test_db;
+
+use database::library::InsertableLibrary;
+use database::library::MediaType;
+use database::session::InsertableSession;
+use database::session::Session;
+use database::user::User;
+use database::DbConnection;
+
+use events::Audience;
+use events::PushEventType;
+
+use tokio::sync::mpsc::unbounded_channel;
+
+async fn insert_library(conn: &DbConnection) -> i64 {
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await.unwrap();
+
+    let id = InsertableLibrary {
+        name: "Movies".into(),
+        locations: vec!["/movies".into()],
+        media_type: MediaType::Movie,
+        metadata_provider: Default::default(),
+        scan_interval: None,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    tx.commit().await.unwrap();
+
+    id
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn password_reset_revokes_sessions() {
+    let db = test_db().await;
+    insert_user(&db.conn, "admin", "admin").await;
+    insert_user(&db.conn, "user", "user").await;
+
+    {
+        let mut lock = db.conn.writer().lock_owned().await;
+        let mut tx = database::write_tx(&mut lock).await.unwrap();
+
+        InsertableSession {
+            username: "user".into(),
+            refresh_token: "refresh".into(),
+            device: None,
+            ttl: 60,
+        }
+        .insert(&mut tx)
+        .await
+        .unwrap();
+
+        tx.commit().await.unwrap();
+    }
+
+    let result = admin::update_user(
+        db.conn.clone(),
+        auth("admin"),
+        "user".into(),
+        None,
+        Some(true),
+    )
+    .await;
+    assert!(result.is_ok());
+
+    let mut tx = db.conn.read().begin().await.unwrap();
+    let sessions = Session::get_all_of_user(&mut tx, "user").await.unwrap();
+    assert!(sessions.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_owners_restrict_owners() {
+    let db = test_db().await;
+    insert_user(&db.conn, "admin", "admin").await;
+    insert_user(&db.conn, "owner", "owner").await;
+    let library = insert_library(&db.conn).await;
+
+    let (event_tx, mut event_rx) = unbounded_channel();
+
+    let result = admin::set_user_libraries(
+        db.conn.clone(),
+        event_tx,
+        auth("admin"),
+        "owner".into(),
+        Some(vec![library]),
+    )
+    .await;
+    assert!(matches!(result, Err(DimError::Unauthorized)));
+    assert!(event_rx.try_recv().is_err());
+
+    let result = admin::set_user_certification(
+        db.conn.clone(),
+        auth("admin"),
+        "owner".into(),
+        Some("PG".into()),
+        None,
+    )
+    .await;
+    assert!(matches!(result, Err(DimError::Unauthorized)));
+
+    let mut tx = db.conn.read().begin().await.unwrap();
+    let libraries = User::get_library_access(&mut tx, "owner").await.unwrap();
+    assert_eq!(libraries, None);
+
+    let prefs = User::get(&mut tx, "owner").await.unwrap().prefs;
+    assert_eq!(prefs.max_certification, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restricting_notifies_clients() {
+    let db = test_db().await;
+    insert_user(&db.conn, "admin", "admin").await;
+    insert_user(&db.conn, "user", "user").await;
+    let library = insert_library(&db.conn).await;
+    let (event_tx, mut event_rx) = unbounded_channel();
+
+    let result = admin::set_user_libraries(
+        db.conn.clone(),
+        event_tx.clone(),
+        auth("admin"),
+        "ghost".into(),
+        Some(vec![library]),
+    )
+    .await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+    assert!(event_rx.try_recv().is_err());
+
+    let result = admin::set_user_libraries(
+        db.conn.clone(),
+        event_tx,
+        auth("admin"),
+        "user".into(),
+        Some(vec![library]),
+    )
+    .await;
+    assert!(result.is_ok());
+
+    let envelope = event_rx.try_recv().unwrap();
+    assert!(matches!(envelope.audience, Audience::User(x) if x == "user"));
+    assert!(matches!(
+        envelope.message.event_type,
+        PushEventType::EventLibraryAccessChanged { libraries: Some(x) } if x == vec![library]
+    ));
+}
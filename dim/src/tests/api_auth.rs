@@ -1,5 +1,181 @@
+use crate::rate_limit::LoginLimiter;
 use crate::routes::auth::*;
+use crate::routes::scrobbler;
+use crate::routes::settings;
+use crate::tests::insert_user;
+use crate::tests::test_db;
+
+use database::user::Login;
+
+use warp::http::StatusCode;
 use warp::test::request;
+use warp::Filter;
+use warp::Rejection;
+use warp::Reply;
 
 #[test]
 fn _test() {}
+
+async fn recover_api_key(rejection: Rejection) -> Result<StatusCode, Rejection> {
+    match rejection.find::<::auth::JWTError>() {
+        Some(::auth::JWTError::ApiKeyNotAllowed) => Ok(StatusCode::FORBIDDEN),
+        _ => Err(rejection),
+    }
+}
+
+/// Returns whether `filter` rejects a request to `path` because it was made with a API key.
+async fn rejects_api_key<F>(filter: F, method: &str, path: &str) -> bool
+where
+    F: Filter<Error = Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: Reply + Send,
+{
+    let response = request()
+        .method(method)
+        .path(path)
+        .header(::auth::API_KEY_HEADER, "dim_key")
+        .reply(&filter.recover(recover_api_key))
+        .await;
+
+    response.status() == StatusCode::FORBIDDEN
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn account_routes_reject_api_keys() {
+    let db = test_db().await;
+    let conn = &db.conn;
+
+    assert!(
+        rejects_api_key(
+            filters::get_sessions(conn.clone()),
+            "GET",
+            "/api/v1/auth/sessions"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::revoke_all_sessions(conn.clone()),
+            "DELETE",
+            "/api/v1/auth/sessions"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::revoke_session(conn.clone()),
+            "DELETE",
+            "/api/v1/auth/sessions/id"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::get_api_keys(conn.clone()),
+            "GET",
+            "/api/v1/auth/keys"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::create_api_key(conn.clone()),
+            "POST",
+            "/api/v1/auth/keys"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::delete_api_key(conn.clone()),
+            "DELETE",
+            "/api/v1/auth/keys/1"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::user_change_password(conn.clone()),
+            "PATCH",
+            "/api/v1/auth/password"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::user_change_username(conn.clone()),
+            "PATCH",
+            "/api/v1/auth/username"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::user_delete_self(conn.clone()),
+            "DELETE",
+            "/api/v1/user/delete"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            filters::user_upload_avatar(conn.clone()),
+            "POST",
+            "/api/v1/user/avatar"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            settings::filters::get_user_settings(conn.clone()),
+            "GET",
+            "/api/v1/user/settings"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            scrobbler::filters::get_scrobblers(conn.clone()),
+            "GET",
+            "/api/v1/user/scrobblers"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            scrobbler::filters::add_scrobbler(conn.clone()),
+            "POST",
+            "/api/v1/user/scrobblers"
+        )
+        .await
+    );
+    assert!(
+        rejects_api_key(
+            scrobbler::filters::delete_scrobbler(conn.clone()),
+            "DELETE",
+            "/api/v1/user/scrobblers/1"
+        )
+        .await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_existing_users_get_locked() {
+    let db = test_db().await;
+    insert_user(&db.conn, "user", "user").await;
+    let limiter = LoginLimiter::default();
+
+    for username in ["ghost", "user"] {
+        for _ in 0..10 {
+            let new_login = Login {
+                username: username.into(),
+                password: "wrong".into(),
+                invite_token: None,
+            };
+
+            let _ = login(new_login, None, None, limiter.clone(), db.conn.clone()).await;
+        }
+    }
+
+    assert!(limiter.check_user("ghost", None).is_ok());
+    assert!(limiter.check_user("user", None).is_err());
+}
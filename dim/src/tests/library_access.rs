@@ -0,0 +1,168 @@
+use crate::errors::DimError;
+use crate::routes::media;
+use crate::routes::mediafile;
+use crate::routes::music;
+use crate::routes::tv;
+use crate::scrobbler::ScrobbleDispatcher;
+use crate::tests::auth;
+use crate::tests::setup;
+use crate::tests::test_db;
+
+use database::user::UpdateableUser;
+use database::user::User;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tv_seasons() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result = tv::get_tv_seasons(db.conn.clone(), hidden.show, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = tv::get_tv_seasons(db.conn.clone(), hidden.show, auth("unrestricted")).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn season_by_id() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result = tv::get_season_by_id(db.conn.clone(), hidden.season, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = tv::get_season_by_id(db.conn.clone(), hidden.season, auth("unrestricted")).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn season_episodes() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result = tv::get_season_episodes(db.conn.clone(), hidden.season, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result =
+        tv::get_season_episodes(db.conn.clone(), hidden.season, auth("unrestricted")).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn artists() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result = music::get_artists(db.conn.clone(), hidden.library, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::LibraryNotFound)));
+
+    let result = music::get_artists(db.conn.clone(), hidden.library, auth("unrestricted")).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn artist_by_id() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result = music::get_artist_by_id(db.conn.clone(), hidden.artist, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result =
+        music::get_artist_by_id(db.conn.clone(), hidden.artist, auth("unrestricted")).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn album_by_id() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result = music::get_album_by_id(db.conn.clone(), hidden.album, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = music::get_album_by_id(db.conn.clone(), hidden.album, auth("unrestricted")).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn track_by_id() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result = music::get_track_by_id(db.conn.clone(), hidden.track, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = music::get_track_by_id(db.conn.clone(), hidden.track, auth("unrestricted")).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mediafile_info() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+
+    let result =
+        mediafile::get_mediafile_info(db.conn.clone(), hidden.mediafile, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result =
+        mediafile::get_mediafile_info(db.conn.clone(), hidden.mediafile, auth("unrestricted"))
+            .await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn media_progress() {
+    let db = test_db().await;
+    let hidden = setup(&db.conn).await;
+    let scrobbler = ScrobbleDispatcher::new(db.conn.clone());
+
+    let result = media::map_progress(
+        db.conn.clone(),
+        hidden.show,
+        10,
+        None,
+        auth("restricted"),
+        scrobbler.clone(),
+    )
+    .await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = media::set_watched(db.conn.clone(), hidden.show, true, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = media::get_media_history(db.conn.clone(), hidden.show, auth("restricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = media::set_watched(db.conn.clone(), hidden.show, true, auth("unrestricted")).await;
+    assert!(result.is_ok());
+
+    // the show isnt rated, so it is hidden once the user has a certification limit.
+    {
+        let mut lock = db.conn.writer().lock_owned().await;
+        let mut tx = database::write_tx(&mut lock).await.unwrap();
+        let mut prefs = User::get(&mut tx, "unrestricted").await.unwrap().prefs;
+        prefs.max_certification = Some("PG-13".into());
+
+        UpdateableUser { prefs: Some(prefs) }
+            .update(&mut tx, "unrestricted")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    let result = media::map_progress(
+        db.conn.clone(),
+        hidden.show,
+        10,
+        None,
+        auth("unrestricted"),
+        scrobbler,
+    )
+    .await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+
+    let result = media::get_media_history(db.conn.clone(), hidden.show, auth("unrestricted")).await;
+    assert!(matches!(result, Err(DimError::NotFoundError)));
+}
@@ -1,2 +1,192 @@
 // NOTE: Might want to add a v1 module.
+pub mod admin;
 pub mod api_auth;
+pub mod library_access;
+
+use auth::Wrapper as Auth;
+
+use database::album::Album;
+use database::artist::InsertableArtist;
+use database::episode::InsertableEpisode;
+use database::library::InsertableLibrary;
+use database::library::MediaType;
+use database::media::InsertableMedia;
+use database::mediafile::InsertableMediaFile;
+use database::season::InsertableSeason;
+use database::track::InsertableTrack;
+use database::tv::TVShow;
+use database::user::InsertableUser;
+use database::user::Login;
+use database::user::User;
+use database::DbConnection;
+
+use std::path::PathBuf;
+use std::sync::Once;
+
+/// A database stored in a temporary file, which is removed once the test is done.
+pub struct TestDb {
+    pub conn: DbConnection,
+    path: PathBuf,
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub async fn test_db() -> TestDb {
+    let path = std::env::temp_dir().join(format!("dim-test-{}.db", uuid::Uuid::new_v4()));
+    let conn = database::get_conn_file(&path).await.unwrap();
+
+    TestDb { conn, path }
+}
+
+/// Returns a access token for `user`.
+pub fn auth(user: &str) -> Auth {
+    static KEY: Once = Once::new();
+    KEY.call_once(|| auth::set_jwt_key(auth::generate_key()));
+
+    let token = auth::jwt_generate(user.into(), vec!["user".into()], String::new());
+    Auth(auth::jwt_check(token).unwrap())
+}
+
+/// Creates the user `username` with the role `role`, their password is `password`.
+pub async fn insert_user(conn: &DbConnection, username: &str, role: &str) {
+    // hashing passwords properly takes ages in debug builds.
+    database::user::set_hash_iterations(1);
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await.unwrap();
+
+    InsertableUser {
+        username: username.into(),
+        password_hash: database::user::hash("password"),
+        roles: vec![role.into()],
+        prefs: Default::default(),
+        claimed_invite: Login::new_invite(&mut tx).await.unwrap(),
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    tx.commit().await.unwrap();
+}
+
+/// Media in a library the user `restricted` cant see.
+pub struct Hidden {
+    pub library: i64,
+    pub show: i64,
+    pub season: i64,
+    pub artist: i64,
+    pub album: i64,
+    pub track: i64,
+    pub mediafile: i64,
+}
+
+/// Creates the users `restricted`, who can only see a empty library, and `unrestricted`, along
+/// with a tv show and a album in a library only `unrestricted` can see.
+pub async fn setup(conn: &DbConnection) -> Hidden {
+    for username in ["restricted", "unrestricted"] {
+        insert_user(conn, username, "user").await;
+    }
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await.unwrap();
+
+    let library = |name: &str, media_type| InsertableLibrary {
+        name: name.into(),
+        locations: vec![format!("/{}", name)],
+        media_type,
+        metadata_provider: Default::default(),
+        scan_interval: None,
+    };
+
+    let visible = library("visible", MediaType::Tv)
+        .insert(&mut tx)
+        .await
+        .unwrap();
+    let hidden = library("hidden", MediaType::Mixed)
+        .insert(&mut tx)
+        .await
+        .unwrap();
+
+    User::set_library_access(&mut tx, "restricted", Some(&[visible]))
+        .await
+        .unwrap();
+
+    let media = |name: &str, media_type| InsertableMedia {
+        library_id: hidden,
+        name: name.into(),
+        added: "Test".into(),
+        media_type,
+        ..Default::default()
+    };
+
+    let show = media("Show", MediaType::Tv).insert(&mut tx).await.unwrap();
+    TVShow::insert(&mut tx, show).await.unwrap();
+
+    let season = InsertableSeason {
+        season_number: 1,
+        ..Default::default()
+    }
+    .insert(&mut tx, show)
+    .await
+    .unwrap();
+
+    InsertableEpisode {
+        media: media("Episode", MediaType::Episode),
+        seasonid: season,
+        episode: 1,
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let artist = InsertableArtist {
+        library_id: hidden,
+        name: "Artist".into(),
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let album = media("Album", MediaType::Music)
+        .insert_blind(&mut tx)
+        .await
+        .unwrap();
+    Album::insert(&mut tx, album, Some(artist)).await.unwrap();
+
+    let mediafile = InsertableMediaFile {
+        library_id: hidden,
+        target_file: "/hidden/track.flac".into(),
+        raw_name: "Track".into(),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    let track = InsertableTrack {
+        album_id: album,
+        mediafile_id: mediafile,
+        artist_id: Some(artist),
+        name: "Track".into(),
+        ..Default::default()
+    }
+    .insert(&mut tx)
+    .await
+    .unwrap();
+
+    tx.commit().await.unwrap();
+
+    Hidden {
+        library: hidden,
+        show,
+        season,
+        artist,
+        album,
+        track,
+        mediafile,
+    }
+}
@@ -0,0 +1,344 @@
+//! Thumbnail sprite sheets used for seek previews. A background job grabs a thumbnail every
+//! [`INTERVAL`] seconds out of every video file, tiles them into sheets and writes a WebVTT
+//! thumbnail track pointing into the sheets. Everything is stored in
+//! `{metadata}/trickplay/{mediafile_id}`, the layout of the sheets is kept in the database so
+//! that it can be written into DASH manifests.
+//!
+//! Episodes which still have no thumbnail once their sheets are generated, ie because they were
+//! matched before we extracted artwork from files, get one along the way.
+
+use crate::core::DbConnection;
+use crate::jobs;
+use crate::jobs::Next;
+use crate::scanners::artwork;
+use crate::scanners::asset_ext;
+use crate::scanners::format_path;
+use crate::streaming::FFMPEG_BIN;
+
+use database::asset::InsertableAsset;
+use database::library::MediaType;
+use database::media::Media;
+use database::media::UpdateMedia;
+use database::trickplay::InsertableTrickplay;
+use database::trickplay::Trickplay;
+use database::trickplay::TrickplayTarget;
+
+use err_derive::Error;
+use tracing::{debug, instrument, warn};
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Seconds between two thumbnails.
+pub const INTERVAL: i64 = 10;
+/// Width of a single thumbnail, the height follows the aspect ratio of the video.
+const THUMBNAIL_WIDTH: u32 = 320;
+/// Number of thumbnails per row of a sheet.
+const TILE_COLUMNS: i64 = 10;
+/// Number of thumbnails per column of a sheet.
+const TILE_ROWS: i64 = 10;
+/// Quality of the sheets, on ffmpeg's scale of 2 (best) to 31 (worst).
+const SHEET_QUALITY: u8 = 5;
+/// Name of the WebVTT thumbnail track.
+pub const VTT_FILE: &str = "thumbnails.vtt";
+/// How often we check for files without sheets once all files have them.
+const POLL_INTERVAL: Duration = Duration::from_secs(300);
+/// Number of files fetched from the database in one go.
+const BATCH_SIZE: i64 = 10;
+/// How long ffmpeg may take to generate the sheets of a file before it is killed.
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Error)]
+pub enum TrickplayError {
+    #[error(display = "An io error has occured: {}", _0)]
+    Io(#[error(source)] std::io::Error),
+    #[error(display = "ffmpeg failed: {}", _0)]
+    Ffmpeg(String),
+    #[error(display = "Failed to read the sheets: {}", _0)]
+    Image(#[error(source)] image::ImageError),
+    #[error(display = "the job panicked")]
+    Panicked,
+}
+
+/// Returns the directory the sheets of a mediafile are stored in.
+pub fn sheets_dir(meta_path: &Path, mediafile_id: i64) -> PathBuf {
+    meta_path.join("trickplay").join(mediafile_id.to_string())
+}
+
+/// Returns the name of the `n`th sheet, counting from zero.
+pub fn sheet_name(n: i64) -> String {
+    format!("sheet-{}.jpg", n)
+}
+
+/// Returns the sheet number of `file` if it is a sheet.
+pub fn parse_sheet_name(file: &str) -> Option<i64> {
+    file.strip_prefix("sheet-")?
+        .strip_suffix(".jpg")?
+        .parse()
+        .ok()
+        .filter(|x| *x >= 0)
+}
+
+/// Function runs the job generating sheets, it never returns.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `meta_path` - the metadata directory sheets are stored in.
+#[instrument(skip(conn))]
+pub async fn run(conn: DbConnection, meta_path: PathBuf) {
+    jobs::run(POLL_INTERVAL, None, || round(&conn, &meta_path)).await
+}
+
+/// Function generates the sheets of the next batch of files without any.
+async fn round(conn: &DbConnection, meta_path: &Path) -> Next {
+    let targets = match conn.read().begin().await {
+        Ok(mut tx) => Trickplay::get_outdated(&mut tx, BATCH_SIZE).await,
+        Err(e) => Err(e.into()),
+    };
+
+    let targets = match jobs::batch(targets) {
+        Ok(x) => x,
+        Err(next) => return next,
+    };
+
+    for target in targets {
+        if let Err(e) = process(conn, meta_path, target).await {
+            warn!(reason = ?e, "Failed to store sheets.");
+            return Next::Idle;
+        }
+    }
+
+    Next::Continue
+}
+
+async fn process(
+    conn: &DbConnection,
+    meta_path: &Path,
+    target: TrickplayTarget,
+) -> Result<(), database::DatabaseError> {
+    let media = {
+        let mut tx = conn.read().begin().await?;
+        Media::get_of_mediafile(&mut tx, target.mediafile_id)
+            .await
+            .ok()
+    };
+
+    // episodes without a thumbnail get one out of the file.
+    let wants_still = media.as_ref().map_or(false, |x| {
+        x.media_type == MediaType::Episode && x.backdrop_path.is_none()
+    });
+
+    let (sheets, still) = {
+        let meta_path = meta_path.to_path_buf();
+        let target = target.clone();
+
+        jobs::blocking(move || {
+            let dir = sheets_dir(&meta_path, target.mediafile_id);
+            let sheets = generate(&target, &dir);
+            let still = wants_still
+                .then(|| artwork::extract(Path::new(&target.target_file), &meta_path).backdrop)
+                .flatten();
+
+            (sheets, still)
+        })
+        .await
+        .unwrap_or_else(|| (Err(TrickplayError::Panicked), None))
+    };
+
+    let sheets = sheets.unwrap_or_else(|e| {
+        warn!(file = %target.target_file, reason = %e, "Failed to generate sheets.");
+
+        InsertableTrickplay {
+            mediafile_id: target.mediafile_id,
+            file_mtime: target.file_mtime,
+            error: Some(e.to_string()),
+            ..Default::default()
+        }
+    });
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    sheets.insert(&mut tx).await?;
+
+    if let (Some(media), Some(file)) = (media, still) {
+        let asset = InsertableAsset {
+            remote_url: None,
+            file_ext: asset_ext(&file),
+            local_path: format_path(Some(file)),
+        }
+        .insert(&mut tx)
+        .await?;
+
+        UpdateMedia {
+            backdrop: Some(asset.id),
+            ..Default::default()
+        }
+        .update(&mut tx, media.id)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Generates the sheets and thumbnail track of `target` into `dir`, replacing whatever was there.
+/// This does blocking io and should be called with `spawn_blocking`.
+pub fn generate(
+    target: &TrickplayTarget,
+    dir: &Path,
+) -> Result<InsertableTrickplay, TrickplayError> {
+    let duration = target.duration.unwrap_or_default();
+
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+
+    fs::create_dir_all(dir)?;
+
+    let filter = format!(
+        "fps=1/{},scale={}:-2,tile={}x{}",
+        INTERVAL, THUMBNAIL_WIDTH, TILE_COLUMNS, TILE_ROWS
+    );
+
+    let mut cmd = Command::new(*FFMPEG_BIN);
+    cmd.args(["-v", "error", "-y"])
+        // only decoding keyframes is much faster, thumbnails dont need to be exact.
+        .args(["-skip_frame", "nokey"])
+        .arg("-i")
+        .arg(&target.target_file)
+        // `V` skips cover art which is stored as a video stream too.
+        .args(["-map", "0:V:0", "-an", "-sn"])
+        .args(["-vf", &filter])
+        .args(["-q:v", &SHEET_QUALITY.to_string()])
+        .args(["-start_number", "0"])
+        .arg(dir.join("sheet-%d.jpg"))
+        .stdin(Stdio::null());
+
+    let output = jobs::output(&mut cmd, FFMPEG_TIMEOUT)?;
+
+    if !output.status.success() {
+        return Err(TrickplayError::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    let sheets = (0..)
+        .take_while(|x| dir.join(sheet_name(*x)).exists())
+        .count() as i64;
+
+    if sheets == 0 {
+        return Err(TrickplayError::Ffmpeg("no sheets were written".into()));
+    }
+
+    // the last sheet is padded so every sheet has the size of a full grid.
+    let (sheet_width, sheet_height) = image::image_dimensions(dir.join(sheet_name(0)))?;
+
+    let thumbnails =
+        ((duration + INTERVAL - 1) / INTERVAL).clamp(1, sheets * TILE_COLUMNS * TILE_ROWS);
+
+    let layout = InsertableTrickplay {
+        mediafile_id: target.mediafile_id,
+        interval: INTERVAL,
+        width: sheet_width as i64 / TILE_COLUMNS,
+        height: sheet_height as i64 / TILE_ROWS,
+        tile_columns: TILE_COLUMNS,
+        tile_rows: TILE_ROWS,
+        thumbnails,
+        sheets,
+        file_mtime: target.file_mtime,
+        error: None,
+    };
+
+    fs::write(dir.join(VTT_FILE), webvtt(&layout))?;
+
+    debug!(file = %target.target_file, sheets, thumbnails, "Generated sheets");
+
+    Ok(layout)
+}
+
+/// Renders the WebVTT thumbnail track of a set of sheets. Every cue points at the region of a
+/// sheet holding its thumbnail, the sheets are referenced relative to the track.
+pub fn webvtt(layout: &InsertableTrickplay) -> String {
+    let per_sheet = (layout.tile_columns * layout.tile_rows).max(1);
+    let mut vtt = String::from("WEBVTT\n");
+
+    for n in 0..layout.thumbnails {
+        let tile = n % per_sheet;
+
+        let _ = write!(
+            vtt,
+            "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+            timestamp(n * layout.interval),
+            timestamp((n + 1) * layout.interval),
+            sheet_name(n / per_sheet),
+            tile % layout.tile_columns * layout.width,
+            tile / layout.tile_columns * layout.height,
+            layout.width,
+            layout.height
+        );
+    }
+
+    vtt
+}
+
+fn timestamp(secs: i64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.000",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sheet_names() {
+        assert_eq!(parse_sheet_name(&sheet_name(12)), Some(12));
+        assert_eq!(parse_sheet_name("sheet--1.jpg"), None);
+        assert_eq!(parse_sheet_name("sheet-1.png"), None);
+        assert_eq!(parse_sheet_name("../sheet-1.jpg"), None);
+    }
+
+    #[test]
+    fn thumbnail_track() {
+        let layout = InsertableTrickplay {
+            interval: 10,
+            width: 320,
+            height: 180,
+            tile_columns: 2,
+            tile_rows: 2,
+            thumbnails: 5,
+            sheets: 2,
+            ..Default::default()
+        };
+
+        let vtt = webvtt(&layout);
+        let cues = vtt.split("\n\n").collect::<Vec<_>>();
+
+        assert_eq!(cues[0], "WEBVTT");
+        assert_eq!(cues.len(), 6);
+        assert_eq!(
+            cues[1],
+            "00:00:00.000 --> 00:00:10.000\nsheet-0.jpg#xywh=0,0,320,180"
+        );
+        assert_eq!(
+            cues[4],
+            "00:00:30.000 --> 00:00:40.000\nsheet-0.jpg#xywh=320,180,320,180"
+        );
+        assert_eq!(
+            cues[5],
+            "00:00:40.000 --> 00:00:50.000\nsheet-1.jpg#xywh=0,0,320,180\n"
+        );
+        assert_eq!(timestamp(3725), "01:02:05.000");
+    }
+}
@@ -0,0 +1,488 @@
+//! Webhooks forward server events to urls registered by admins. Every event sent over the
+//! websocket passes through [`tap`], which queues a delivery for each webhook interested in it.
+//! Deliveries are stored in the database and attempted in the background, failed ones are retried
+//! with a backoff until they run out of attempts.
+//!
+//! Payloads are signed with the secret of the webhook. The `X-Dim-Signature` header holds
+//! `sha256=<hex>`, the HMAC-SHA256 of `<X-Dim-Timestamp>.<body>`.
+
+use crate::core::DbConnection;
+use crate::scrobbler;
+
+use database::webhook::InsertableWebhookDelivery;
+use database::webhook::Webhook;
+use database::webhook::WebhookDelivery;
+
+use chrono::Utc;
+use events::Envelope;
+use events::Message;
+use ring::hmac;
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
+use serde_json::json;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Notify;
+use tracing::warn;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Events webhooks can subscribe to.
+pub const EVENTS: &[&str] = &[
+    "EventNewCard",
+    "EventNewLibrary",
+    "EventRemoveLibrary",
+    "EventStartedScanning",
+    "EventStoppedScanning",
+];
+
+/// How long a single request to a webhook may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay in seconds before the first retry of a failed delivery, doubled on every further attempt.
+const RETRY_BASE: i64 = 30;
+/// Number of times a delivery is attempted before it is marked as failed.
+pub const MAX_ATTEMPTS: i64 = 8;
+/// How often we check for deliveries which are due to be retried.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+/// Number of deliveries attempted in one go.
+const BATCH_SIZE: i64 = 50;
+
+/// Function generates a random secret for a new webhook.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .expect("Failed to generate a webhook secret.");
+
+    to_hex(&secret)
+}
+
+/// Function computes the value of the `X-Dim-Signature` header of a delivery.
+///
+/// # Arguments
+/// * `secret` - secret of the webhook.
+/// * `timestamp` - value of the `X-Dim-Timestamp` header.
+/// * `body` - the payload being sent.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{}.{}", timestamp, body).as_bytes());
+
+    format!("sha256={}", to_hex(tag.as_ref()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Function returns the delay before a delivery which failed `attempts` times is retried, or
+/// `None` if it shouldnt be retried.
+fn retry_delay(attempts: i64) -> Option<i64> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+
+    Some(RETRY_BASE * 2i64.pow(attempts.max(1) as u32 - 1))
+}
+
+/// Function spawns the webhook worker in front of `event_rx`. Every event is forwarded unchanged
+/// to the returned receiver, so it can be handed to the websocket as before.
+///
+/// # Arguments
+/// * `conn` - database connection
+/// * `event_rx` - receiving end of the channel server events are sent through.
+pub fn tap(
+    conn: DbConnection,
+    event_rx: UnboundedReceiver<Envelope>,
+) -> UnboundedReceiver<Envelope> {
+    tap_with(conn, event_rx, false)
+}
+
+/// Same as [`tap`], except that webhooks pointing at hosts which arent public are delivered to as
+/// well if `allow_private` is set.
+fn tap_with(
+    conn: DbConnection,
+    event_rx: UnboundedReceiver<Envelope>,
+    allow_private: bool,
+) -> UnboundedReceiver<Envelope> {
+    let (tx, rx) = unbounded_channel();
+    let notify = Arc::new(Notify::new());
+    let client = scrobbler::client_builder(REQUEST_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+
+    tokio::spawn(record(conn.clone(), event_rx, tx, notify.clone()));
+    tokio::spawn(run(conn, client, notify, allow_private));
+
+    rx
+}
+
+/// Function queues deliveries for the events received over `event_rx` until the channel closes.
+async fn record(
+    conn: DbConnection,
+    mut event_rx: UnboundedReceiver<Envelope>,
+    tx: UnboundedSender<Envelope>,
+    notify: Arc<Notify>,
+) {
+    while let Some(event) = event_rx.recv().await {
+        // the websocket shouldnt have to wait on the database.
+        let _ = tx.send(event.clone());
+
+        match queue_event(&conn, &event.message).await {
+            Ok(0) => {}
+            Ok(_) => notify.notify_one(),
+            Err(e) => warn!(reason = ?e, "Failed to queue webhook deliveries."),
+        }
+    }
+}
+
+/// Function queues a delivery of `event` for every enabled webhook subscribed to it, returns the
+/// number of deliveries queued. Webhooks are configured by admins, so they receive events
+/// regardless of their audience.
+async fn queue_event(
+    conn: &DbConnection,
+    event: &Message,
+) -> Result<usize, database::DatabaseError> {
+    let mut data = match serde_json::to_value(event) {
+        Ok(x) => x,
+        Err(_) => return Ok(0),
+    };
+
+    let name = match data.get("type").and_then(|x| x.as_str()) {
+        Some(x) if EVENTS.contains(&x) => x.to_string(),
+        _ => return Ok(0),
+    };
+
+    let webhooks = {
+        let mut tx = conn.read().begin().await?;
+        Webhook::get_for_event(&mut tx, &name).await?
+    };
+
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+
+    if let Some(x) = data.as_object_mut() {
+        x.remove("type");
+    }
+
+    let payload = json!({
+        "event": name,
+        "timestamp": Utc::now().timestamp(),
+        "data": data,
+    })
+    .to_string();
+
+    let mut lock = conn.writer().lock_owned().await;
+    let mut tx = database::write_tx(&mut lock).await?;
+
+    for webhook in webhooks.iter() {
+        InsertableWebhookDelivery {
+            webhook_id: webhook.id,
+            event: name.clone(),
+            payload: payload.clone(),
+        }
+        .insert(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(webhooks.len())
+}
+
+/// Function attempts deliveries whenever new ones are queued or retries become due.
+async fn run(
+    conn: DbConnection,
+    client: reqwest::Client,
+    notify: Arc<Notify>,
+    allow_private: bool,
+) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = notify.notified() => {}
+            _ = interval.tick() => {}
+        }
+
+        if let Err(e) = deliver_due(&conn, &client, allow_private).await {
+            warn!(reason = ?e, "Failed to deliver webhooks.");
+        }
+    }
+}
+
+async fn deliver_due(
+    conn: &DbConnection,
+    client: &reqwest::Client,
+    allow_private: bool,
+) -> Result<(), database::DatabaseError> {
+    loop {
+        let (deliveries, webhooks) = {
+            let mut tx = conn.read().begin().await?;
+            let deliveries = WebhookDelivery::get_due(&mut tx, BATCH_SIZE).await?;
+            if deliveries.is_empty() {
+                return Ok(());
+            }
+
+            let webhooks = Webhook::get_all(&mut tx)
+                .await?
+                .into_iter()
+                .map(|x| (x.id, x))
+                .collect::<HashMap<_, _>>();
+
+            (deliveries, webhooks)
+        };
+
+        let batch = deliveries.len() as i64;
+
+        for delivery in deliveries {
+            let result = match webhooks.get(&delivery.webhook_id) {
+                Some(webhook) if webhook.enabled => {
+                    deliver(client, webhook, &delivery, allow_private).await
+                }
+                _ => Err((None, "The webhook is disabled.".to_string())),
+            };
+
+            let mut lock = conn.writer().lock_owned().await;
+            let mut tx = database::write_tx(&mut lock).await?;
+
+            match result {
+                Ok(status) => {
+                    WebhookDelivery::set_delivered(&mut tx, delivery.id, status).await?;
+                }
+                Err((status, error)) => {
+                    let next_attempt = webhooks
+                        .get(&delivery.webhook_id)
+                        .filter(|x| x.enabled)
+                        .and_then(|_| retry_delay(delivery.attempts + 1))
+                        .map(|x| Utc::now().timestamp() + x);
+
+                    warn!(
+                        webhook = delivery.webhook_id,
+                        delivery = delivery.id,
+                        reason = %error,
+                        retrying = next_attempt.is_some(),
+                        "Failed to deliver a webhook."
+                    );
+
+                    WebhookDelivery::set_failed(&mut tx, delivery.id, status, &error, next_attempt)
+                        .await?;
+                }
+            }
+
+            tx.commit().await?;
+        }
+
+        // a full batch means there might be more deliveries due.
+        if batch < BATCH_SIZE {
+            return Ok(());
+        }
+    }
+}
+
+/// Function posts a delivery to its webhook. Returns the status code of the response, or the
+/// status code if we got one along with the reason the attempt failed. Webhooks which dont point
+/// to a public host are refused unless `allow_private` is set.
+async fn deliver(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    delivery: &WebhookDelivery,
+    allow_private: bool,
+) -> Result<i64, (Option<i64>, String)> {
+    let url = reqwest::Url::parse(&webhook.url)
+        .map_err(|_| (None, "The url of the webhook is invalid.".to_string()))?;
+
+    // the address is checked on every delivery as the host might resolve to something else by now.
+    let client = match allow_private {
+        true => client.clone(),
+        false => match scrobbler::public_client(&url, REQUEST_TIMEOUT).await {
+            Some(x) => x,
+            None => return Err((None, "The webhook doesnt point to a public host.".into())),
+        },
+    };
+
+    let timestamp = Utc::now().timestamp();
+
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Dim-Event", &delivery.event)
+        .header("X-Dim-Delivery", delivery.id)
+        .header("X-Dim-Timestamp", timestamp)
+        .header(
+            "X-Dim-Signature",
+            sign(&webhook.secret, timestamp, &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err((
+            Some(status.as_u16() as i64),
+            format!("The webhook responded with {}", status),
+        ));
+    }
+
+    Ok(status.as_u16() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrobbler::tests::stand_in;
+
+    use database::webhook::DeliveryStatus;
+    use database::webhook::InsertableWebhook;
+
+    use warp::http::StatusCode;
+
+    #[test]
+    fn signatures() {
+        // reference value computed with `openssl dgst -sha256 -hmac secret`.
+        assert_eq!(
+            sign("secret", 1638316800, "{}"),
+            "sha256=58843ef3c3c0bc710a0044131639551aa2bb415997f03b45b1653439b005fd56"
+        );
+        assert_ne!(sign("secret", 1, "{}"), sign("secret", 2, "{}"));
+        assert_ne!(sign("secret", 1, "{}"), sign("other", 1, "{}"));
+        assert_eq!(generate_secret().len(), 64);
+    }
+
+    #[test]
+    fn backoff() {
+        assert_eq!(retry_delay(1), Some(RETRY_BASE));
+        assert_eq!(retry_delay(3), Some(RETRY_BASE * 4));
+        assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delivers_events() {
+        let (addr, mut requests) = stand_in(StatusCode::OK);
+        let conn = database::get_conn_memory().await.unwrap();
+
+        {
+            let mut lock = conn.writer().lock_owned().await;
+            let mut tx = database::write_tx(&mut lock).await.unwrap();
+            InsertableWebhook {
+                url: format!("http://{}/hook", addr),
+                secret: "secret".into(),
+                events: vec!["EventNewLibrary".into()],
+            }
+            .insert(&mut tx)
+            .await
+            .unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        // the stand in listens on localhost.
+        let (event_tx, event_rx) = unbounded_channel();
+        let mut forwarded = tap_with(conn.clone(), event_rx, true);
+
+        let card = events::Message {
+            id: 1,
+            event_type: events::PushEventType::EventNewCard { lib_id: 1 },
+        };
+        let library = events::Message {
+            id: 2,
+            event_type: events::PushEventType::EventNewLibrary,
+        };
+        event_tx.send(Envelope::broadcast(card.clone())).unwrap();
+        event_tx
+            .send(Envelope::user("test", library.clone()))
+            .unwrap();
+
+        // every event still makes it to the websocket.
+        let forwarded_card = forwarded.recv().await.unwrap();
+        assert_eq!(forwarded_card.message.to_string(), card.to_string());
+        let forwarded_library = forwarded.recv().await.unwrap();
+        assert_eq!(forwarded_library.message.to_string(), library.to_string());
+        assert_eq!(
+            forwarded_library.audience,
+            events::Audience::User("test".into())
+        );
+
+        let (path, headers, body) = requests.recv().await.unwrap();
+        assert_eq!(path, "/hook");
+        assert_eq!(headers["x-dim-event"], "EventNewLibrary");
+        assert_eq!(body["event"], "EventNewLibrary");
+        assert_eq!(body["data"]["id"], 2);
+
+        let timestamp = headers["x-dim-timestamp"].to_str().unwrap();
+        assert_eq!(
+            headers["x-dim-signature"],
+            sign("secret", timestamp.parse().unwrap(), &body.to_string())
+        );
+
+        // the delivery is recorded once the response has been handled.
+        for _ in 0..50 {
+            let mut tx = conn.read().begin().await.unwrap();
+            let deliveries = WebhookDelivery::get_of_webhook(&mut tx, 1, 10)
+                .await
+                .unwrap();
+            drop(tx);
+
+            if deliveries[0].status == DeliveryStatus::Delivered {
+                assert_eq!(deliveries.len(), 1);
+                assert_eq!(deliveries[0].response_code, Some(200));
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("The delivery was never marked as delivered.");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refuses_private_hosts() {
+        let (addr, mut requests) = stand_in(StatusCode::OK);
+        let conn = database::get_conn_memory().await.unwrap();
+
+        {
+            let mut lock = conn.writer().lock_owned().await;
+            let mut tx = database::write_tx(&mut lock).await.unwrap();
+            InsertableWebhook {
+                url: format!("http://{}/hook", addr),
+                secret: "secret".into(),
+                events: vec!["EventNewLibrary".into()],
+            }
+            .insert(&mut tx)
+            .await
+            .unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        let (event_tx, event_rx) = unbounded_channel();
+        let _forwarded = tap(conn.clone(), event_rx);
+
+        let library = events::Message {
+            id: 2,
+            event_type: events::PushEventType::EventNewLibrary,
+        };
+        event_tx.send(Envelope::owner(library)).unwrap();
+
+        for _ in 0..50 {
+            let mut tx = conn.read().begin().await.unwrap();
+            let deliveries = WebhookDelivery::get_of_webhook(&mut tx, 1, 10)
+                .await
+                .unwrap();
+            drop(tx);
+
+            if matches!(deliveries.first(), Some(x) if x.error.is_some()) {
+                assert_eq!(deliveries[0].response_code, None);
+                assert!(requests.try_recv().is_err());
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("The delivery was never refused.");
+    }
+}
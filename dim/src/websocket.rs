@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::hash::Hash;
 use std::net::SocketAddr;
 
@@ -12,8 +13,16 @@ use warp::Filter;
 use futures::prelude::*;
 use futures::stream::SplitSink;
 
+use crate::core::DbConnection;
 use crate::routes;
 
+use database::user::User;
+
+use events::Audience;
+use events::Envelope;
+use events::EventCategory;
+use events::PushEventType;
+
 pub enum CtrlEvent<A, M>
 where
     A: Hash + Eq,
@@ -22,6 +31,8 @@ where
         addr: A,
         sink: SplitSink<WebSocket, Message>,
         auth: Box<auth::Wrapper>,
+        /// Libraries the user is restricted to, `None` if they can see every library.
+        libraries: Option<HashSet<i64>>,
     },
 
     Forget {
@@ -33,7 +44,18 @@ where
         message: M,
     },
 
-    SendAll(M),
+    /// Sends a message to every tracked peer it is meant for.
+    Dispatch(Envelope),
+
+    Subscribe {
+        addr: A,
+        categories: Vec<EventCategory>,
+    },
+
+    Unsubscribe {
+        addr: A,
+        categories: Vec<EventCategory>,
+    },
 }
 
 pub trait IntoCtrlEvent<A, M>: Sync + Send + Clone + 'static
@@ -43,12 +65,59 @@ where
     fn into_ctrl_event(self) -> CtrlEvent<A, M>;
 }
 
-impl<A> IntoCtrlEvent<A, String> for String
+impl<A> IntoCtrlEvent<A, String> for Envelope
 where
     A: Hash + Eq,
 {
     fn into_ctrl_event(self) -> CtrlEvent<A, String> {
-        CtrlEvent::SendAll(self)
+        CtrlEvent::Dispatch(self)
+    }
+}
+
+/// What we know about the user behind a peer, used to decide which events it receives.
+#[derive(Clone, Debug)]
+struct Subscriber {
+    username: String,
+    owner: bool,
+    /// Libraries the user is restricted to, `None` if they can see every library. Read when the
+    /// peer connects and kept up to date by [`Subscriber::update_access`].
+    libraries: Option<HashSet<i64>>,
+    /// Categories of events the peer wants to receive, all of them by default.
+    categories: HashSet<EventCategory>,
+}
+
+impl Subscriber {
+    fn new(auth: &auth::Wrapper, libraries: Option<HashSet<i64>>) -> Self {
+        Self {
+            username: auth.get_user(),
+            owner: auth.0.claims.get_roles().contains(&auth::Role::Owner),
+            libraries,
+            categories: EventCategory::ALL.iter().copied().collect(),
+        }
+    }
+
+    /// Updates the libraries the user is restricted to if `envelope` tells us they changed.
+    fn update_access(&mut self, envelope: &Envelope) {
+        let libraries = match &envelope.message.event_type {
+            PushEventType::EventLibraryAccessChanged { libraries } => libraries,
+            _ => return,
+        };
+
+        if matches!(&envelope.audience, Audience::User(x) if *x == self.username) {
+            self.libraries = libraries.as_ref().map(|x| x.iter().copied().collect());
+        }
+    }
+
+    /// Returns whether the message in `envelope` should be sent to this peer.
+    fn wants(&self, envelope: &Envelope) -> bool {
+        envelope
+            .audience
+            .includes(&self.username, self.owner, self.libraries.as_ref())
+            && envelope
+                .message
+                .event_type
+                .category()
+                .map_or(true, |x| self.categories.contains(&x))
     }
 }
 
@@ -68,16 +137,29 @@ where
             discard.clear();
 
             match ev {
-                CtrlEvent::Track { addr, sink, auth } => {
-                    peers.insert(addr, (sink, auth));
+                CtrlEvent::Track {
+                    addr,
+                    sink,
+                    auth,
+                    libraries,
+                } => {
+                    peers.insert(addr, (sink, Subscriber::new(&auth, libraries)));
                 }
 
                 CtrlEvent::Forget { ref addr } => {
                     peers.remove(addr);
                 }
 
-                CtrlEvent::SendAll(body) => {
-                    for (addr, (sink, _)) in peers.iter_mut() {
+                CtrlEvent::Dispatch(envelope) => {
+                    let body = envelope.message.to_string();
+
+                    for (addr, (sink, subscriber)) in peers.iter_mut() {
+                        subscriber.update_access(&envelope);
+
+                        if !subscriber.wants(&envelope) {
+                            continue;
+                        }
+
                         let result = sink.send(Message::text(body.clone())).await;
 
                         if result.is_err() {
@@ -97,6 +179,20 @@ where
                         }
                     }
                 }
+
+                CtrlEvent::Subscribe { addr, categories } => {
+                    if let Some((_, subscriber)) = peers.get_mut(&addr) {
+                        subscriber.categories.extend(categories);
+                    }
+                }
+
+                CtrlEvent::Unsubscribe { addr, categories } => {
+                    if let Some((_, subscriber)) = peers.get_mut(&addr) {
+                        for category in categories {
+                            subscriber.categories.remove(&category);
+                        }
+                    }
+                }
             };
         }
     }
@@ -106,12 +202,37 @@ where
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum ClientActions {
-    Authenticate { token: String },
+    Authenticate {
+        token: String,
+    },
+    /// Start receiving events of these categories.
+    Subscribe {
+        categories: Vec<EventCategory>,
+    },
+    /// Stop receiving events of these categories.
+    Unsubscribe {
+        categories: Vec<EventCategory>,
+    },
+}
+
+/// Function returns the libraries `username` is restricted to. Users whose restrictions cant be
+/// read are treated as if they couldnt see any library.
+async fn library_access(conn: &DbConnection, username: &str) -> Option<HashSet<i64>> {
+    let access = match conn.read().begin().await {
+        Ok(mut tx) => User::get_library_access(&mut tx, username).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match access {
+        Ok(x) => x.map(|x| x.into_iter().collect()),
+        Err(_) => Some(HashSet::new()),
+    }
 }
 
 pub fn event_socket(
     rt_handle: Handle,
-    mut event_rx: UnboundedReceiver<String>,
+    conn: DbConnection,
+    mut event_rx: UnboundedReceiver<Envelope>,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     let (i_tx, i_rx) = unbounded_channel::<CtrlEvent<SocketAddr, String>>();
 
@@ -133,11 +254,13 @@ pub fn event_socket(
         .and(warp::filters::addr::remote())
         .and(routes::global_filters::with_state(i_tx))
         .and(routes::global_filters::with_state(rt_handle))
+        .and(routes::global_filters::with_state(conn))
         .and(warp::ws())
         .map(
             |addr: Option<SocketAddr>,
              i_tx: UnboundedSender<CtrlEvent<SocketAddr, String>>,
              rt_handle: Handle,
+             conn: DbConnection,
              ws: warp::ws::Ws| {
                 ws.on_upgrade(move |websocket| async move {
                     let addr = match addr {
@@ -153,11 +276,14 @@ pub fn event_socket(
                             if let Ok(ClientActions::Authenticate { token }) =
                                 serde_json::from_slice(x.as_bytes())
                             {
-                                if let Ok(token_data) = auth::jwt_check(token) {
+                                if let Ok(auth) = auth::authenticate_session(&token).await {
+                                    let libraries = library_access(&conn, auth.user_ref()).await;
+
                                     let _ = i_tx.send(CtrlEvent::Track {
                                         addr,
                                         sink: ws_tx,
-                                        auth: Box::new(auth::Wrapper(token_data)),
+                                        auth: Box::new(auth),
+                                        libraries,
                                     });
 
                                     let _ = i_tx.send(CtrlEvent::SendTo {
@@ -185,6 +311,7 @@ pub fn event_socket(
                     }
 
                     let m_tx = m_tx.clone();
+                    let ctrl_tx = i_tx.clone();
 
                     rt_handle.spawn(async move {
                         while let Some(Ok(message)) = ws_rx.next().await {
@@ -204,10 +331,26 @@ pub fn event_socket(
                             }
 
                             message = m_rx.recv() => {
-                                let (_addr, _message) = match message {
+                                let (addr, message) = match message {
                                     Some(p) => p,
                                     None => break 'outer,
                                 };
+
+                                if !message.is_text() {
+                                    continue;
+                                }
+
+                                let event = match serde_json::from_slice(message.as_bytes()) {
+                                    Ok(ClientActions::Subscribe { categories }) => {
+                                        CtrlEvent::Subscribe { addr, categories }
+                                    }
+                                    Ok(ClientActions::Unsubscribe { categories }) => {
+                                        CtrlEvent::Unsubscribe { addr, categories }
+                                    }
+                                    _ => continue,
+                                };
+
+                                let _ = ctrl_tx.send(event);
                             }
 
                             else => break 'outer,
@@ -219,3 +362,133 @@ pub fn event_socket(
             },
         )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(audience: events::Audience, event_type: PushEventType) -> Envelope {
+        Envelope {
+            audience,
+            message: events::Message { id: 1, event_type },
+        }
+    }
+
+    #[test]
+    fn routes_by_audience() {
+        let user = Subscriber {
+            username: "user".into(),
+            owner: false,
+            libraries: Some([1].iter().copied().collect()),
+            categories: EventCategory::ALL.iter().copied().collect(),
+        };
+        let owner = Subscriber {
+            username: "owner".into(),
+            owner: true,
+            libraries: None,
+            ..user.clone()
+        };
+
+        let all = envelope(events::Audience::All, PushEventType::EventNewLibrary);
+        assert!(user.wants(&all));
+        assert!(owner.wants(&all));
+
+        let owners = envelope(events::Audience::Owner, PushEventType::EventStartedScanning);
+        assert!(!user.wants(&owners));
+        assert!(owner.wants(&owners));
+
+        let own = envelope(
+            events::Audience::User("user".into()),
+            PushEventType::EventStreamIsReady,
+        );
+        assert!(user.wants(&own));
+        assert!(!owner.wants(&own));
+
+        let visible = envelope(
+            events::Audience::Library(1),
+            PushEventType::EventNewCard { lib_id: 1 },
+        );
+        assert!(user.wants(&visible));
+        assert!(owner.wants(&visible));
+
+        let hidden = envelope(
+            events::Audience::Library(2),
+            PushEventType::EventNewCard { lib_id: 2 },
+        );
+        assert!(!user.wants(&hidden));
+        assert!(owner.wants(&hidden));
+    }
+
+    #[test]
+    fn routes_by_category() {
+        let mut user = Subscriber {
+            username: "user".into(),
+            owner: false,
+            libraries: None,
+            categories: EventCategory::ALL.iter().copied().collect(),
+        };
+        user.categories.remove(&EventCategory::Stream);
+
+        let stream = envelope(
+            events::Audience::User("user".into()),
+            PushEventType::EventStreamIsReady,
+        );
+        let card = envelope(
+            events::Audience::All,
+            PushEventType::EventNewCard { lib_id: 1 },
+        );
+        assert!(!user.wants(&stream));
+        assert!(user.wants(&card));
+
+        // events without a category cant be unsubscribed from.
+        user.categories.clear();
+        assert!(user.wants(&envelope(events::Audience::All, PushEventType::EventAuthOk)));
+    }
+
+    #[test]
+    fn reloads_library_access() {
+        let mut user = Subscriber {
+            username: "user".into(),
+            owner: false,
+            libraries: None,
+            categories: EventCategory::ALL.iter().copied().collect(),
+        };
+        let card = envelope(
+            events::Audience::Library(2),
+            PushEventType::EventNewCard { lib_id: 2 },
+        );
+        assert!(user.wants(&card));
+
+        let restrict = |username: &str, libraries| {
+            envelope(
+                events::Audience::User(username.into()),
+                PushEventType::EventLibraryAccessChanged { libraries },
+            )
+        };
+
+        // restrictions of other users are left alone.
+        user.update_access(&restrict("other", Some(vec![1])));
+        assert!(user.wants(&card));
+
+        user.update_access(&restrict("user", Some(vec![1])));
+        assert!(!user.wants(&card));
+
+        user.update_access(&restrict("user", None));
+        assert!(user.wants(&card));
+    }
+
+    #[test]
+    fn parses_subscriptions() {
+        let action = serde_json::from_str::<ClientActions>(
+            r#"{"type": "unsubscribe", "categories": ["stream", "scan"]}"#,
+        )
+        .unwrap();
+
+        match action {
+            ClientActions::Unsubscribe { categories } => {
+                assert_eq!(categories, vec![EventCategory::Stream, EventCategory::Scan])
+            }
+            _ => panic!("Expected a unsubscribe action."),
+        }
+    }
+}
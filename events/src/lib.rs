@@ -1,9 +1,11 @@
+use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 /// Struct encompasses a message we are trying to relay to a client from somehwere within dim. It
 /// holds an id and a event_type field.
-#[derive(Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Message {
     /// Field id, can hold anything and the client usually discriminates its meaning based on the
     /// event_type. For example within dim, sometimes it can be the library_id or media_id or
@@ -21,7 +23,7 @@ impl ToString for Message {
 }
 
 /// Enum holds all event types used within dim that are dispatched over ws.
-#[derive(Serialize)]
+#[derive(Clone, Debug, Serialize)]
 #[serde(tag = "type")]
 pub enum PushEventType {
     /// A new media card has been added to the database
@@ -40,8 +42,137 @@ pub enum PushEventType {
     EventStartedScanning,
     /// A library has finished scanning.
     EventStoppedScanning,
+    /// Periodic progress report of a running library scan.
+    EventScanProgress {
+        /// Number of files found in the library.
+        discovered: u64,
+        /// Number of files the extractor has looked at.
+        probed: u64,
+        /// Number of new files which were matched.
+        matched: u64,
+        /// Number of files which couldnt be mounted or matched.
+        failed: u64,
+        /// Number of files which are done, this includes files which were already up to date.
+        processed: u64,
+        /// Estimated number of seconds until the scan finishes.
+        eta: Option<u64>,
+    },
+    /// The libraries the user can see have changed, `libraries` holds the ones they are now
+    /// restricted to, `None` if they can see every library.
+    EventLibraryAccessChanged { libraries: Option<Vec<i64>> },
     /// Tell client auth is ok
     EventAuthOk,
     /// Tell client their token is wrong or missing
     EventAuthErr,
 }
+
+impl PushEventType {
+    /// Method returns the category clients subscribe to in order to receive this event. Events
+    /// without a category are always delivered.
+    pub fn category(&self) -> Option<EventCategory> {
+        match self {
+            Self::EventNewCard { .. } | Self::EventRemoveCard => Some(EventCategory::Media),
+            Self::EventNewLibrary
+            | Self::EventRemoveLibrary
+            | Self::EventLibraryAccessChanged { .. } => Some(EventCategory::Library),
+            Self::EventStartedScanning
+            | Self::EventStoppedScanning
+            | Self::EventScanProgress { .. } => Some(EventCategory::Scan),
+            Self::EventStreamIsReady | Self::EventStreamStats(_) => Some(EventCategory::Stream),
+            Self::EventAuthOk | Self::EventAuthErr => None,
+        }
+    }
+}
+
+/// Groups of events clients can subscribe and unsubscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventCategory {
+    /// Media cards being added or removed.
+    Media,
+    /// Libraries being added or removed.
+    Library,
+    /// Libraries being scanned.
+    Scan,
+    /// The state of the streams of a user.
+    Stream,
+}
+
+impl EventCategory {
+    /// All the categories, clients are subscribed to all of them when they connect.
+    pub const ALL: [Self; 4] = [Self::Media, Self::Library, Self::Scan, Self::Stream];
+}
+
+/// Enum describes which clients a message should be delivered to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Audience {
+    /// Every authenticated client.
+    All,
+    /// Only the clients of users holding the owner role.
+    Owner,
+    /// Only the clients of the user with this username.
+    User(String),
+    /// Only the clients of users who can see the library with this id.
+    Library(i64),
+}
+
+impl Audience {
+    /// Method returns whether a client of `username` belongs to this audience.
+    ///
+    /// # Arguments
+    /// * `username` - the user the client is authenticated as.
+    /// * `owner` - whether the user holds the owner role.
+    /// * `libraries` - the libraries the user is restricted to, `None` if they can see every
+    ///   library.
+    pub fn includes(&self, username: &str, owner: bool, libraries: Option<&HashSet<i64>>) -> bool {
+        match self {
+            Self::All => true,
+            Self::Owner => owner,
+            Self::User(x) => x == username,
+            Self::Library(id) => libraries.map(|x| x.contains(id)).unwrap_or(true),
+        }
+    }
+}
+
+/// Struct holds a message along with the clients it should be delivered to. This is what gets
+/// sent over the event channel.
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    pub audience: Audience,
+    pub message: Message,
+}
+
+impl Envelope {
+    /// Creates a envelope delivering `message` to every client.
+    pub fn broadcast(message: Message) -> Self {
+        Self {
+            audience: Audience::All,
+            message,
+        }
+    }
+
+    /// Creates a envelope delivering `message` only to the clients of owners.
+    pub fn owner(message: Message) -> Self {
+        Self {
+            audience: Audience::Owner,
+            message,
+        }
+    }
+
+    /// Creates a envelope delivering `message` only to the clients of users who can see the library
+    /// `library_id`.
+    pub fn library(library_id: i64, message: Message) -> Self {
+        Self {
+            audience: Audience::Library(library_id),
+            message,
+        }
+    }
+
+    /// Creates a envelope delivering `message` only to the clients of `username`.
+    pub fn user(username: impl Into<String>, message: Message) -> Self {
+        Self {
+            audience: Audience::User(username.into()),
+            message,
+        }
+    }
+}